@@ -0,0 +1,274 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! An audit log of the privileged actions taken on the host.
+//!
+//! When an audit log file is set with [`set_audit_log`], a record is appended to it for every
+//! command which changes the host, such as creating or destroying a jail, running a script in a
+//! jail, or changing a ZFS dataset. Commands which only read state, such as `iocage list` or
+//! `zfs get`, are not recorded.
+//!
+//! Each record is a line holding a JSON object with the fields:
+//!
+//! * `timestamp`: the time the command finished, in RFC 3339 format in UTC
+//! * `user`: the invoking user, taken from `SUDO_USER` when run with `sudo`, otherwise `USER`
+//! * `uid`: the effective user ID of the process
+//! * `jail`: the name of the jail being acted on, if any
+//! * `argv`: the exact program and arguments which were run
+//! * `result`: `success`, `exit code N`, `terminated by signal`, or `failed to spawn`
+//!
+//! Once a jail is provisioned, a record of the changes made to its iocage properties after it was
+//! created is also appended. It has the same `timestamp`, `user`, `uid`, and `jail` fields, with
+//! a `property_changes` field in place of `argv` and `result` which holds an array of objects with
+//! the `property` name and its `before` and `after` values (`null` when unset).
+//!
+//! Since a command is recorded once it has run, [`check_audit_log`] should be called before any
+//! commands are run so that an audit log which can't be written is found before the host is
+//! changed. A record which then can't be written is logged as a warning, rather than turning a
+//! command which changed the host into a failure.
+
+use super::{cmd_argv, host, redact, Error, PropertyChange, Result};
+use log::warn;
+use serde::Serialize;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static::lazy_static! {
+    static ref AUDIT_LOG: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+    static ref JAIL: Mutex<Option<String>> = Mutex::new(None);
+}
+
+/// `iocage` subcommands which only read state.
+const IOCAGE_READ_ONLY: &[&str] = &["--version", "df", "get", "list", "snaplist"];
+
+/// `zfs` subcommands which only read state.
+const ZFS_READ_ONLY: &[&str] = &["get", "list"];
+
+/// Sets the file to which audit records are appended, or disables the audit log if `None`.
+///
+/// The file and its parent directories are created as needed when the first record is written.
+pub fn set_audit_log(path: Option<PathBuf>) {
+    *lock(&AUDIT_LOG) = path;
+}
+
+/// Checks that records can be appended to the audit log, if it is enabled, creating the file and
+/// its parent directories as needed.
+///
+/// # Errors
+///
+/// Returns an `Err` if the audit log could not be opened for appending.
+pub fn check_audit_log() -> Result<()> {
+    match audit_log() {
+        Some(path) => open(&path)
+            .map(|_| ())
+            .map_err(|err| Error::WriteAuditLog(path, err)),
+        None => Ok(()),
+    }
+}
+
+/// Returns the file to which audit records are appended, if the audit log is enabled.
+pub fn audit_log() -> Option<PathBuf> {
+    lock(&AUDIT_LOG).clone()
+}
+
+/// The jail named in audit records while it is in scope.
+#[derive(Debug)]
+pub(crate) struct JailScope {
+    previous: Option<String>,
+}
+
+impl JailScope {
+    /// Names the given jail, if any, in audit records until the returned scope is dropped.
+    pub(crate) fn enter(jail_name: Option<&str>) -> Self {
+        let previous = std::mem::replace(&mut *lock(&JAIL), jail_name.map(str::to_string));
+        Self { previous }
+    }
+
+    /// Renames the jail in scope, such as once iocage has named a jail which it created.
+    pub(crate) fn rename(&self, jail_name: &str) {
+        *lock(&JAIL) = Some(jail_name.to_string());
+    }
+}
+
+impl Drop for JailScope {
+    fn drop(&mut self) {
+        *lock(&JAIL) = self.previous.take();
+    }
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    timestamp: String,
+    user: Option<String>,
+    uid: u32,
+    jail: Option<String>,
+    argv: Vec<String>,
+    result: &'a str,
+}
+
+/// Appends a record of a command and its outcome to the audit log, if it is enabled and the
+/// command changes the host.
+pub(crate) fn record(cmd: &Command, status: Option<&ExitStatus>) {
+    let path = match audit_log() {
+        Some(path) => path,
+        None => return,
+    };
+    let argv = cmd_argv(cmd)
+        .iter()
+        .map(|arg| redact(arg))
+        .collect::<Vec<_>>();
+    if !is_mutating(&argv) {
+        return;
+    }
+
+    let result = result(status);
+    let record = Record {
+        timestamp: timestamp(SystemTime::now()),
+        user: env::var("SUDO_USER").or_else(|_| env::var("USER")).ok(),
+        uid: host::effective_uid(),
+        jail: jail(),
+        argv,
+        result: &result,
+    };
+
+    write(&path, &record);
+}
+
+#[derive(Serialize)]
+struct PropertyRecord<'a> {
+    timestamp: String,
+    user: Option<String>,
+    uid: u32,
+    jail: Option<String>,
+    property_changes: &'a [PropertyChange],
+}
+
+/// Appends a record of the changes made to the iocage properties of the jail in scope to the
+/// audit log, if it is enabled.
+pub(crate) fn record_property_changes(changes: &[PropertyChange]) {
+    let path = match audit_log() {
+        Some(path) => path,
+        None => return,
+    };
+
+    let record = PropertyRecord {
+        timestamp: timestamp(SystemTime::now()),
+        user: env::var("SUDO_USER").or_else(|_| env::var("USER")).ok(),
+        uid: host::effective_uid(),
+        jail: jail(),
+        property_changes: changes,
+    };
+
+    write(&path, &record);
+}
+
+/// Returns the name of the jail in scope, if any.
+pub(crate) fn jail() -> Option<String> {
+    lock(&JAIL).clone()
+}
+
+/// Describes the outcome of a command, or that it failed to spawn when `status` is `None`.
+pub(crate) fn result(status: Option<&ExitStatus>) -> String {
+    match status {
+        Some(status) if status.success() => String::from("success"),
+        Some(status) => match status.code() {
+            Some(code) => format!("exit code {}", code),
+            None => String::from("terminated by signal"),
+        },
+        None => String::from("failed to spawn"),
+    }
+}
+
+/// Returns whether or not a command, given as its program and arguments, changes the host.
+fn is_mutating(argv: &[String]) -> bool {
+    let program = argv[0].rsplit('/').next().unwrap_or_default();
+    // Skip iocage's global flags, such as `--debug`, to find its subcommand
+    let subcommand = argv[1..]
+        .iter()
+        .find(|arg| !arg.starts_with('-') || *arg == "--version")
+        .map(String::as_str)
+        .unwrap_or_default();
+
+    match program {
+        "iocage" => !IOCAGE_READ_ONLY.contains(&subcommand),
+        "zfs" => !ZFS_READ_ONLY.contains(&subcommand),
+        // `ifconfig` only reads state when given no more than an interface and address family
+        "ifconfig" => !(argv.len() <= 2 || (argv.len() == 3 && argv[2].starts_with("inet"))),
+        "ipfw" => !matches!(subcommand, "list" | "show"),
+        "pfctl" => !argv.iter().any(|arg| arg == "-s"),
+        "pkg" => !matches!(subcommand, "info" | "query" | "rquery" | "search"),
+        "kldstat" | "netstat" | "sysctl" => false,
+        _ => true,
+    }
+}
+
+/// Appends a record to the audit log as a line of JSON, logging a warning if it can't be written.
+fn write<T: Serialize>(path: &Path, record: &T) {
+    let mut line = serde_json::to_string(record).expect("audit records always serialize");
+    line.push('\n');
+
+    // A single write keeps records from concurrent runs on separate lines
+    if let Err(err) = open(path).and_then(|mut file| file.write_all(line.as_bytes())) {
+        warn!(
+            "could not write audit record; path={}, error={}",
+            path.display(),
+            err
+        );
+    }
+}
+
+/// Opens a file for appending, creating the file and its parent directories if needed.
+fn open(path: &Path) -> io::Result<fs::File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)
+}
+
+/// Formats a time as an RFC 3339 timestamp in UTC with second precision.
+pub(crate) fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Converts days since the epoch to a civil date, after Howard Hinnant's `civil_from_days`
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
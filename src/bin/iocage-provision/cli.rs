@@ -2,17 +2,29 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use clap::{AppSettings, Clap};
+use clap::{AppSettings, Clap, FromArgMatches, IntoApp};
+use iocage_provision::dns::DnsRegistration;
+use iocage_provision::recipe::Recipe;
+use iocage_provision::{
+    parse, IdConflictPolicy, IfExistsPolicy, MonitoringAgent, Phase, SnapshotSchedule,
+    SshHostKeyType, SysvIpc,
+};
 use ipnet::IpNet;
 use std::net::IpAddr;
+use std::path::PathBuf;
 use std::str;
 
 lazy_static::lazy_static! {
     /// The computed default value for the gateway option.
-    static ref DEFAULT_GATEWAY: String = default_gateway();
+    ///
+    /// The value is only computed when first needed and is then shared for the rest of the run.
+    static ref DEFAULT_GATEWAY: Result<IpAddr, String> =
+        iocage_provision::netstat_gateway_addr().map_err(|err| err.to_string());
 
     /// The computed default value for the release option.
-    static ref DEFAULT_RELEASE: String = default_release();
+    ///
+    /// The value is only computed when first needed and is then shared for the rest of the run.
+    static ref DEFAULT_RELEASE: String = iocage_provision::default_release();
 }
 
 const AFTER_HELP: &str =
@@ -27,7 +39,15 @@ const AFTER_LONG_HELP: &str = concat!(
 
 /// Parse, validate, and return the CLI arguments as a typed struct.
 pub(crate) fn parse() -> Args {
-    Args::parse()
+    // The jail name and IP address are required unless a subcommand is given, which the derived
+    // parser cannot express for `Option` fields. With --uuid only the IP address is given, which
+    // is parsed as the first positional argument (see `Args::uuid`).
+    let app = Args::into_app()
+        .mut_arg("NAME", |arg| arg.required_unless_present("uuid"))
+        .mut_arg("IP", |arg| arg.required_unless_present("uuid"));
+    let matches = app.get_matches();
+
+    Args::from_arg_matches(&matches)
 }
 
 /// Creates an iocage based FreeBSD jail.
@@ -42,6 +62,8 @@ pub(crate) fn parse() -> Args {
 #[derive(Clap, Debug)]
 #[clap(
     global_setting(AppSettings::UnifiedHelpMessage),
+    setting(AppSettings::ArgsNegateSubcommands),
+    setting(AppSettings::SubcommandsNegateReqs),
     max_term_width = 100,
     author = concat!("\nAuthor: ", env!("CARGO_PKG_AUTHORS"), "\n\n"),
     version = BuildInfo::version_short(),
@@ -50,49 +72,848 @@ pub(crate) fn parse() -> Args {
     after_long_help = AFTER_LONG_HELP,
 )]
 pub(crate) struct Args {
+    /// Adds the jail's name and address to the host's /etc/hosts.
+    ///
+    /// If this flag is set, then once the jail is provisioned a marked entry for it is added to
+    /// the host's /etc/hosts, replacing any earlier one, and the jail's /etc/hosts is seeded with
+    /// the host's own entries and those of the other jails added this way. The entry is removed
+    /// when the jail is destroyed. Useful in small environments without internal DNS.
+    #[clap(long)]
+    pub(crate) add_hosts_entry: bool,
+
+    /// File to which an audit record of every change made to the host is appended.
+    ///
+    /// Each record is a line of JSON holding the time, the invoking user (from SUDO_USER when run
+    /// with sudo), the jail, the exact command run, and its result. Commands which only read
+    /// state are not recorded. The subcommands always use the default file.
+    #[clap(
+        long,
+        value_name = "FILE",
+        default_value = "/var/log/iocage-provision/audit.log"
+    )]
+    pub(crate) audit_log: PathBuf,
+
+    /// Command which the automation user may run as root through sudo.
+    ///
+    /// The command must be given with an absolute path and may include arguments. When this
+    /// option is not used, the automation user is not granted any sudo access. This option may be
+    /// provided multiple times.
+    #[clap(
+        long,
+        number_of_values = 1,
+        value_name = "CMD",
+        parse(try_from_str = parse_sudo_command),
+        requires = "automation-user"
+    )]
+    pub(crate) automation_command: Vec<String>,
+
+    /// SSH public key file on the host to authorize for the automation user.
+    ///
+    /// The key is installed as the only entry of the automation user's `authorized_keys` file.
+    #[clap(long, value_name = "FILE", requires = "automation-user")]
+    pub(crate) automation_key: Option<PathBuf>,
+
+    /// Creates a non-interactive automation user in the jail, separate from --user.
+    ///
+    /// When this option is used, a user with a disabled password is created in the jail for use by
+    /// configuration management tools such as Ansible. The user can only log in with the SSH key
+    /// given by --automation-key and can only use sudo for the commands given by
+    /// --automation-command.
+    #[clap(long, value_name = "NAME", requires = "automation-key")]
+    pub(crate) automation_user: Option<String>,
+
+    /// PEM encoded CA certificate file on the host to trust in the jail.
+    ///
+    /// When this option is used, the certificate is added to the jail's trust store before any
+    /// packages are installed, which are installed after the jail is created, and the
+    /// `ca_root_nss` package is installed. Useful when packages or other resources are fetched over
+    /// HTTPS from servers using an internal PKI. This option may be provided multiple times.
+    #[clap(long, number_of_values = 1, value_name = "FILE")]
+    pub(crate) ca_cert: Vec<PathBuf>,
+
+    /// Checkpoints the iocage datasets before making any changes.
+    ///
+    /// If this flag is set, then a recursive ZFS snapshot of the active pool's iocage datasets is
+    /// taken before the jail is created. The name of the checkpoint is printed and the entire
+    /// provisioning run can later be undone with the `rollback-checkpoint` subcommand.
+    #[clap(long)]
+    pub(crate) checkpoint: bool,
+
+    /// Environment variable set for every command this program runs. [example: LC_ALL=C.UTF-8]
+    ///
+    /// The variables are set for iocage and every other command run on the host, in addition to
+    /// `PYTHONUNBUFFERED=true` which keeps iocage's output from being buffered. A variable given
+    /// here overrides the default of the same name. Useful for wrapped iocage builds which need
+    /// extra settings or a custom locale. This option may be provided multiple times.
+    #[clap(
+        long,
+        number_of_values = 1,
+        value_name = "KEY=VALUE",
+        parse(try_from_str = parse_exec_env)
+    )]
+    pub(crate) child_env: Vec<(String, String)>,
+
+    #[clap(subcommand)]
+    pub(crate) command: Option<Command>,
+
+    /// Copies the host's locally trusted CA certificates into the jail.
+    ///
+    /// If this flag is set, then the `ca_root_nss` package is installed and all certificates in
+    /// the host's `/usr/local/etc/ssl/certs` directory are added to the jail's trust store.
+    #[clap(long)]
+    pub(crate) copy_ca_certs: bool,
+
+    /// Copies the host user's supplementary group memberships into the jail.
+    ///
+    /// When this flag is set, each supplementary group of the --user on the host is created in
+    /// the jail (with the same gid where possible) and the user is added to it, in addition to the
+    /// `wheel` group.
+    #[clap(long, requires = "USER")]
+    pub(crate) copy_groups: bool,
+
+    /// Host CPUs to restrict the jail's processes to [example: 0-3].
+    ///
+    /// The CPUs are given as a cpuset(1) CPU list of numbers and ranges, such as `0-3` or
+    /// `0,2,4-7`. Every CPU must be less than the value of the host's `hw.ncpu` sysctl, otherwise
+    /// the command will result in an error and the jail will not be created.
+    #[clap(long, value_name = "LIST", parse(try_from_str = parse_cpuset))]
+    pub(crate) cpuset: Option<String>,
+
+    /// Entry to add to the root user's crontab in the jail. [example: '@daily /root/backup.sh']
+    ///
+    /// The value is a complete crontab line made up of a schedule (either five time and date
+    /// fields or an `@` special string such as `@daily`) followed by a command. This option may be
+    /// provided multiple times to add several entries.
+    #[clap(
+        long,
+        number_of_values = 1,
+        value_name = "SCHEDULE COMMAND",
+        parse(try_from_str = parse_cron_entry)
+    )]
+    pub(crate) cron: Vec<String>,
+
+    /// Pool or dataset the jail is expected to be created beneath. [example: tank]
+    ///
+    /// iocage always creates jails beneath the `iocage` dataset of its activated pool (see
+    /// `iocage activate`). When this option is used, the activated pool is checked before the
+    /// jail is created, and if it does not match then the command will result in an error and the
+    /// jail will not be created. A pool name, `POOL/iocage`, or `POOL/iocage/jails` are accepted.
+    #[clap(long, value_name = "DATASET")]
+    pub(crate) dataset_parent: Option<String>,
+
+    /// Creates the jail without a package list and installs every package afterwards.
+    ///
+    /// If this flag is set, then `iocage create` doesn't install any packages. Once the jail has
+    /// been created, pkg is bootstrapped and all packages are installed in the jail with the
+    /// --exec-env variables (such as proxy settings) and any --pkg-repo-dir repository, and are
+    /// retried with --retries. Useful for a fast and reliable `iocage create` and for handling
+    /// packages the same way as in later steps.
+    #[clap(long)]
+    pub(crate) defer_packages: bool,
+
+    /// Existing jail which must be started before this jail at host boot.
+    ///
+    /// When this option is used, the named jail must already exist, otherwise the command will
+    /// result in an error and the jail will not be created. This option may be provided multiple
+    /// times.
+    #[clap(long, number_of_values = 1, value_name = "JAIL")]
+    pub(crate) depends: Vec<String>,
+
+    /// Description of the jail. [example: "billing API, owner: platform team"]
+    ///
+    /// When this option is used, the description is stored in the jail's iocage `notes` property
+    /// and is displayed by the `list` subcommand.
+    #[clap(long, value_name = "TEXT")]
+    pub(crate) description: Option<String>,
+
+    /// Maximum disk space for the jail's dataset. [example: 20G]
+    ///
+    /// When this option is used, the size is set as the ZFS `quota` of the jail's dataset right
+    /// after the jail is created, so that a misbehaving jail can't fill the pool.
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_zfs_size))]
+    pub(crate) disk_quota: Option<String>,
+
+    /// Disk space guaranteed to the jail's dataset. [example: 5G]
+    ///
+    /// When this option is used, the size is set as the ZFS `refreservation` of the jail's
+    /// dataset right after the jail is created, so that other datasets can't take the space the
+    /// jail needs.
+    #[clap(long, value_name = "SIZE", parse(try_from_str = parse_zfs_size))]
+    pub(crate) disk_reserve: Option<String>,
+
+    /// Verifies that the jail can reach its pkg mirror before installing packages.
+    ///
+    /// If this flag is set, then once the jail has been created, resolving the pkg mirror and
+    /// connecting to its HTTPS port are checked from inside the jail, failing fast with a
+    /// networking error. Packages are installed after this check rather than while the jail is
+    /// created. Useful for catching VNET, default route, and resolver misconfigurations early.
+    #[clap(long, conflicts_with = "offline")]
+    pub(crate) dns_check: bool,
+
+    /// Re-runs this program as root with doas when not already running as root.
+    ///
+    /// The command line is preserved. doas resets the environment, so any environment variables
+    /// which are needed, such as those named by --secret-env, must be kept by doas.conf(5) with
+    /// `keepenv` or `setenv`. A variable named by --secret-env which is missing once running as
+    /// root is an error.
+    #[clap(long, global = true, conflicts_with = "sudo")]
+    pub(crate) doas: bool,
+
+    /// File to which a Terraform description of the provisioned jail is written.
+    ///
+    /// The file holds a JSON object of the jail's name, uuid, IP address, release, and --tag
+    /// labels, in the format of the output of a program for Terraform's `external` data source.
+    /// Useful for recording jails in an infrastructure-as-code repository.
+    #[clap(long, value_name = "FILE")]
+    pub(crate) emit_terraform: Option<PathBuf>,
+
+    /// Provisions a disposable jail which expires a day after it is provisioned.
+    ///
+    /// If this flag is set, then the jail expires as with --expires-in, after a day unless
+    /// --expires-in is also given. Combined with `--recipe ci-runner`, useful for jails used as CI
+    /// build environments.
+    #[clap(long)]
+    pub(crate) ephemeral: bool,
+
+    /// Environment variable exported in every script run in the jail. [example: ASSUME_ALWAYS_YES=yes]
+    ///
+    /// The variables are set at the top of each script which is run in the jail while
+    /// provisioning it, such as the package installation and --user-data `runcmd` commands, so
+    /// that settings like proxies or install tokens don't have to be embedded in the scripts.
+    /// This option may be provided multiple times.
+    #[clap(
+        long,
+        number_of_values = 1,
+        value_name = "KEY=VALUE",
+        parse(try_from_str = parse_exec_env)
+    )]
+    pub(crate) exec_env: Vec<(String, String)>,
+
+    /// Command to run on the host after the jail starts.
+    ///
+    /// This option may be provided multiple times, in which case the commands are run in order
+    /// and stop at the first failure.
+    #[clap(long, number_of_values = 1, value_name = "COMMAND")]
+    pub(crate) exec_poststart: Vec<String>,
+
+    /// Command to run on the host after the jail stops.
+    ///
+    /// This option may be provided multiple times, in which case the commands are run in order
+    /// and stop at the first failure.
+    #[clap(long, number_of_values = 1, value_name = "COMMAND")]
+    pub(crate) exec_poststop: Vec<String>,
+
+    /// Command to run on the host before the jail starts.
+    ///
+    /// Useful for host-side actions such as setting up a VPN tunnel that the jail requires. This
+    /// option may be provided multiple times, in which case the commands are run in order and
+    /// stop at the first failure.
+    #[clap(long, number_of_values = 1, value_name = "COMMAND")]
+    pub(crate) exec_prestart: Vec<String>,
+
+    /// Command to run on the host before the jail stops.
+    ///
+    /// This option may be provided multiple times, in which case the commands are run in order
+    /// and stop at the first failure.
+    #[clap(long, number_of_values = 1, value_name = "COMMAND")]
+    pub(crate) exec_prestop: Vec<String>,
+
+    /// Environment variable, exported like --exec-env, whose value is a secret retrieved by name.
+    /// [example: INSTALL_TOKEN=install-token]
+    ///
+    /// The named secret is retrieved from the --secret-source when the jail is provisioned, so
+    /// that passwords, keys, and tokens are kept out of manifests and command lines. Its value is
+    /// masked in all output. This option may be provided multiple times.
+    #[clap(
+        long,
+        number_of_values = 1,
+        value_name = "KEY=SECRET",
+        parse(try_from_str = parse_exec_env)
+    )]
+    pub(crate) exec_secret: Vec<(String, String)>,
+
+    /// Time after which the jail expires. [example: 72h]
+    ///
+    /// When this option is used, the time at which the jail expires is printed and recorded in
+    /// the state file, and the jail is destroyed by the first run of `destroy --expired` after
+    /// that time. The duration is a whole number followed by a unit of `s`, `m`, `h`, `d`, or `w`.
+    #[clap(long, value_name = "DURATION", parse(try_from_str = parse::duration))]
+    pub(crate) expires_in: Option<u64>,
+
+    /// Routing table (FIB) for the jail's processes to use.
+    ///
+    /// Useful on hosts with multiple routing tables. The FIB must be less than the value of the
+    /// host's `net.fibs` sysctl, otherwise the command will result in an error and the jail will
+    /// not be created.
+    #[clap(long, value_name = "N")]
+    pub(crate) fib: Option<u32>,
+
+    /// Script on the host to run once on the jail's next start.
+    ///
+    /// When this option is used, the script is installed in the jail along with an rc.d service
+    /// which runs it the next time the jail starts, rather than while the jail is provisioned, so
+    /// that it runs in the jail's final runtime environment. This option may be provided multiple
+    /// times, in which case the scripts are run in order.
+    #[clap(long, number_of_values = 1, value_name = "FILE")]
+    pub(crate) firstboot: Vec<PathBuf>,
+
+    /// Loads the rules suggested by the host firewall check into pf.
+    ///
+    /// Before the jail is created, the rules of pf or ipfw, when enabled on the host, are checked
+    /// for a default block without a rule which passes the jail's traffic, and for a private jail
+    /// subnet which isn't translated to the host's address. Each problem is reported as a warning
+    /// with a suggested rule. When this flag is set, the suggested pf rules are instead added to
+    /// `/etc/pf.iocage-provision.conf` and loaded into the `iocage-provision` anchor, which
+    /// `/etc/pf.conf` must reference with `nat-anchor`, `anchor`, and `load anchor` lines.
+    #[clap(long)]
+    pub(crate) fix_firewall: bool,
+
     /// IP address of the default gateway route for a VNET.
     ///
     /// This address is used when setting up the VNET networking of the jail. If not provided the
     /// default value will be the address corresponding to the default route on the underlying host
     /// as determined by using the `netstat` program.
+    #[clap(short = 'g', long, rename_all = "screaming-snake")]
+    pub(crate) gateway: Option<IpAddr>,
+
+    /// Interval in seconds between "still running" lines while a long-running step is silent.
+    ///
+    /// When no progress bar is shown, steps such as creating a thick jail or installing many
+    /// packages can go minutes without output, which CI systems may take for a hung job. A line
+    /// naming the step and its elapsed time is printed whenever the step has been silent for this
+    /// long. A value of 0 disables the heartbeat.
+    #[clap(long, value_name = "SECS", default_value = "60")]
+    pub(crate) heartbeat: u64,
+
+    /// Creates a dedicated ZFS dataset for the home directory of the --user.
+    ///
+    /// When this flag is set, a dataset is created beneath the jail's dataset and mounted at the
+    /// user's home directory (`/home/NAME` unless --home-dir is used) so that it can be
+    /// snapshotted, sent, or given a quota independently of the jail.
+    #[clap(long, requires = "USER")]
+    pub(crate) home_dataset: bool,
+
+    /// Home directory of the --user in the jail. [example: /home/jdoe]
+    ///
+    /// When this option is not used, the `pw` default of `/home/NAME` is used.
+    #[clap(long, value_name = "DIR", requires = "USER")]
+    pub(crate) home_dir: Option<String>,
+
+    /// Permissions mode of the --user's home directory in octal. [example: 0700]
+    ///
+    /// When this option is not used, the `pw` default mode is used.
+    #[clap(
+        long,
+        value_name = "MODE",
+        parse(try_from_str = parse_mode),
+        requires = "USER"
+    )]
+    pub(crate) home_mode: Option<u32>,
+
+    /// Idle priority at which to run the commands this program runs, from 0 to 31. [example: 31]
+    ///
+    /// iocage, pkg, and every other command run on the host, along with the processes they start,
+    /// only run when no other process on the host is ready to, as with `idprio(1)`. Useful to keep
+    /// provisioning, such as fetching releases and installing packages, from slowing down the
+    /// services on a busy host. Lower values run ahead of higher ones.
+    #[clap(long, value_name = "PRIO", parse(try_from_str = parse_idprio))]
+    pub(crate) idprio: Option<u16>,
+
+    /// How to handle a jail with the same name already existing.
+    ///
+    /// Before the jail is created, its name is checked against the existing jails. When a jail
+    /// with the same name exists, the provisioning either fails naming the existing jail's UUID
+    /// and state (`fail`), leaves the existing jail untouched (`skip`), or destroys the existing
+    /// jail and provisions a new one in its place (`replace`).
     #[clap(
-        short = 'g',
         long,
-        default_value = &DEFAULT_GATEWAY,
-        rename_all = "screaming-snake",
+        value_name = "POLICY",
+        default_value = "fail",
+        possible_values = &["fail", "skip", "replace"]
     )]
-    pub(crate) gateway: IpAddr,
+    pub(crate) if_exists: IfExistsPolicy,
+
+    /// Installs missing host prerequisites from packages, after confirmation.
+    ///
+    /// When this flag is set and iocage is not installed on the host, the iocage package for the
+    /// newest available Python flavor (such as `py311-iocage`) is shown and installed with `pkg`
+    /// once confirmed, rather than failing because iocage can't be run.
+    #[clap(long)]
+    pub(crate) install_prereqs: bool,
+
+    /// Host network interface for a shared-IP jail or bridge uplink. [example: em0]
+    ///
+    /// The jail's address is added to this interface with --vnet-off, and this interface is added
+    /// to the host bridge with --setup-bridge. When this option is not used, the interface of the
+    /// default route on the underlying host is used, as determined by using the `netstat` program.
+    #[clap(long, value_name = "IFACE")]
+    pub(crate) interface: Option<String>,
 
     /// IP address & subnet mask for the jail instance. [example: 10.200.0.50/24]
     ///
     /// The IP address and the subnet mask are both required for the value to be considered valid.
     #[clap(index = 2, rename_all = "screaming-snake")]
-    pub(crate) ip: IpNet,
+    pub(crate) ip: Option<IpNet>,
+
+    /// Prints a JSON report of the provisioned jail to the standard output stream.
+    ///
+    /// When this flag is set, all progress output is written to the standard error stream so that
+    /// the report can be consumed by other programs.
+    #[clap(long)]
+    pub(crate) json: bool,
+
+    /// Writes a structured record of the run to a JSON file.
+    ///
+    /// Independently of the console output, the file records the jail's spec with all resolved
+    /// defaults, every command which was run with its times, exit code, and (truncated) output,
+    /// and the final result, whether the jail was provisioned or an error occurred. Useful for
+    /// auditing a run later or attaching to a ticket when provisioning fails.
+    #[clap(long, value_name = "FILE")]
+    pub(crate) json_log: Option<PathBuf>,
+
+    /// File to which `known_hosts` lines for the jail's SSH host keys are appended.
+    ///
+    /// Each line names the jail by both its name and IP address. The file is created if it does
+    /// not exist.
+    #[clap(long, value_name = "FILE", requires = "ssh")]
+    pub(crate) known_hosts: Option<PathBuf>,
+
+    /// Locale of the --user in the jail. [example: en_US.UTF-8]
+    ///
+    /// When this option is used, the locale (and the character set it names) is set in the user's
+    /// `~/.login_conf` file so that it applies to all login sessions.
+    #[clap(long, value_name = "LOCALE", requires = "USER")]
+    pub(crate) lang: Option<String>,
+
+    /// Starts an interactive shell in the jail once it has been provisioned.
+    ///
+    /// When this flag is set, a login shell is started in the new jail as the --user, if one was
+    /// given, or as root otherwise.
+    #[clap(long, conflicts_with = "json")]
+    pub(crate) login: bool,
+
+    /// Login class of the --user in the jail.
+    ///
+    /// The login class must be defined in the jail's `/etc/login.conf` file.
+    #[clap(long, value_name = "CLASS", requires = "USER")]
+    pub(crate) login_class: Option<String>,
+
+    /// make.conf file on the host to install in the jail.
+    ///
+    /// When this option is used, the file is installed as `/etc/make.conf` in the jail so that
+    /// build jails get consistent compiler and port option settings.
+    #[clap(long, value_name = "FILE")]
+    pub(crate) make_conf: Option<PathBuf>,
+
+    /// One minute load average of the host above which it is too busy to provision the jail.
+    ///
+    /// The load average is checked before the jail is created. When it is too high, a warning is
+    /// reported, or provisioning waits for the load to drop with --wait-for-capacity.
+    #[clap(long, value_name = "LOAD")]
+    pub(crate) max_load: Option<f64>,
+
+    /// Free memory of the host, in MiB, below which it is too busy to provision the jail.
+    ///
+    /// Free and inactive memory are checked before the jail is created. When there is too little,
+    /// a warning is reported, or provisioning waits for memory to be freed with
+    /// --wait-for-capacity.
+    #[clap(long, value_name = "MIB")]
+    pub(crate) min_free_memory: Option<u64>,
+
+    /// Strips the jail's base system down to a minimal one.
+    ///
+    /// When this option is used, the test suite, 32-bit libraries, debug symbols, documentation,
+    /// and (unless --ports is used) the compiler toolchain are removed after the jail is created,
+    /// and sendmail is disabled. Only FreeBSD 12, 13, and 14 releases are supported.
+    #[clap(long)]
+    pub(crate) minimal: bool,
+
+    /// Monitoring agent to install and enable in the jail.
+    ///
+    /// When this option is used, the agent's package is installed with the jail's other packages,
+    /// the agent is enabled and started, and its endpoint (such as the node exporter's scrape URL)
+    /// is printed and included in the JSON report, so that the jail can be added to monitoring as
+    /// soon as it exists. The Zabbix agent requires --monitoring-server.
+    #[clap(
+        long,
+        value_name = "AGENT",
+        possible_values = &["node-exporter", "zabbix-agent"]
+    )]
+    pub(crate) monitoring: Option<MonitoringAgent>,
+
+    /// Server which the monitoring agent answers. [example: zabbix.internal]
+    ///
+    /// For the Zabbix agent, this is the Zabbix server (or comma separated servers) which may
+    /// query the agent and to which active checks are sent.
+    #[clap(long, value_name = "HOST", requires = "monitoring")]
+    pub(crate) monitoring_server: Option<String>,
 
     /// Name for the jail instance [example: myjail]
     #[clap(index = 1, rename_all = "screaming-snake")]
-    pub(crate) name: String,
+    pub(crate) name: Option<String>,
+
+    /// Seconds to wait for the jail's network to be ready after it is created.
+    ///
+    /// Once the jail has been created and started, a default route and resolving the pkg mirror
+    /// are polled from inside the jail before anything is run in it, so that later steps don't
+    /// race the jail's network startup. Resolving is not waited for with `--offline`. A value of
+    /// 0 disables the wait.
+    #[clap(long, value_name = "SECS", default_value = "60")]
+    pub(crate) network_timeout: u64,
+
+    /// Nice value at which to run the commands this program runs, from -20 to 20. [example: 10]
+    ///
+    /// iocage, pkg, and every other command run on the host, along with the processes they start,
+    /// are given the nice value, as with `nice(1)`. Higher values give the commands a smaller
+    /// share of the CPU when the host is busy. May be combined with --idprio.
+    #[clap(long, value_name = "N", allow_hyphen_values = true, parse(try_from_str = parse_nice))]
+    pub(crate) nice: Option<i32>,
+
+    /// Does not create a home directory for the --user.
+    #[clap(long, requires = "USER", conflicts_with_all = &["home-mode", "home-dataset"])]
+    pub(crate) no_create_home: bool,
+
+    /// Does not set `PYTHONUNBUFFERED=true` for the commands this program runs.
+    ///
+    /// Only the variables given with --child-env are then added to the environment of each
+    /// command. Useful when iocage is not a Python program, such as a wrapped build of it.
+    #[clap(long)]
+    pub(crate) no_default_child_env: bool,
+
+    /// Enables and starts an NTP service.
+    ///
+    /// If this flag is set, then the `ntpd` service is enabled on boot, configured to step the
+    /// clock on start, and is started on first boot. Useful to avoid clock drift related problems
+    /// such as TLS certificate validation failures and skewed log timestamps.
+    #[clap(long)]
+    pub(crate) ntp: bool,
+
+    /// Disables all remote package repositories in the jail.
+    ///
+    /// If this flag is set, then packages are only installed from the local package repository
+    /// given with `--pkg-repo-dir`, allowing jails to be provisioned on networks with no Internet
+    /// access. Note that the chosen release must already be fetched on the host.
+    #[clap(long, requires = "pkg-repo-dir")]
+    pub(crate) offline: bool,
+
+    /// Sends periodic reports to log files rather than by mail.
+    ///
+    /// If this flag is set, then the daily, weekly, and monthly periodic(8) reports as well as
+    /// the daily security report are written to log files under `/var/log` in the jail rather
+    /// than being mailed to the root user.
+    #[clap(long)]
+    pub(crate) periodic_log: bool,
+
+    /// Local package repository directory on the host to use in the jail.
+    ///
+    /// When this option is used, the directory is mounted read-only into the jail, pkg is
+    /// configured to use it as a repository with a higher priority than the default, and all
+    /// packages are installed from it after the jail is created. The directory must contain a
+    /// repository as built by `pkg repo` or poudriere.
+    #[clap(long, value_name = "DIR")]
+    pub(crate) pkg_repo_dir: Option<PathBuf>,
+
+    /// Builds the jail's userland from FreeBSD pkgbase packages (experimental).
+    ///
+    /// If this flag is set, then rather than a release fetched by iocage, the jail is created from
+    /// a userland built by installing base system packages for the --release from the official
+    /// pkgbase repository. The userland is built once and reused for later jails with the same
+    /// release and packages.
+    #[clap(long)]
+    pub(crate) pkgbase: bool,
+
+    /// Base system package to install with --pkgbase. [example: FreeBSD-runtime]
+    ///
+    /// When this option is not used, a minimal set of runtime, rc, utilities, and pkg bootstrap
+    /// packages is installed. This option may be provided multiple times.
+    #[clap(long, number_of_values = 1, value_name = "PKG", requires = "pkgbase")]
+    pub(crate) pkgbase_package: Vec<String>,
+
+    /// Existing package list JSON file to give to `iocage create` as is.
+    ///
+    /// When this option is used, the file is checked to be a JSON object with a `pkgs` array of
+    /// package names and is passed to iocage in place of a generated package list, without being
+    /// rewritten. Any packages required by other options which are not in the file are installed
+    /// after the jail has been created.
+    #[clap(long, value_name = "PATH")]
+    pub(crate) pkglist: Option<PathBuf>,
+
+    /// Mounts the host's ports tree into the jail.
+    ///
+    /// If this flag is set, then the host's `/usr/ports` directory is mounted read-only into the
+    /// jail, the `portmaster` package is installed, and ports build directories are configured
+    /// under `/var/ports` in the jail.
+    #[clap(long)]
+    pub(crate) ports: bool,
+
+    /// Ports distfiles directory on the host to share with the jail.
+    ///
+    /// When this option is used, the directory is mounted read-write into the jail so that
+    /// downloaded source archives can be shared between the host and other jails.
+    #[clap(long, value_name = "DIR", requires = "ports")]
+    pub(crate) ports_distfiles: Option<PathBuf>,
+
+    /// Ports packages directory on the host to share with the jail.
+    ///
+    /// When this option is used, the directory is mounted read-write into the jail so that
+    /// packages built from ports are available outside of the jail.
+    #[clap(long, value_name = "DIR", requires = "ports")]
+    pub(crate) ports_packages: Option<PathBuf>,
+
+    /// Prints the fully resolved jail configuration as JSON and exits without provisioning.
+    ///
+    /// The printed configuration includes computed defaults such as the gateway and release, and
+    /// any properties copied with --same-as, which helps when debugging which value is used.
+    #[clap(long, conflicts_with_all = &["json", "login"])]
+    pub(crate) print_config: bool,
+
+    /// Start priority of the jail at host boot, lower values start earlier. [default: 99]
+    ///
+    /// Useful to ensure that, for example, database jails are started before application jails.
+    #[clap(long, value_name = "N")]
+    pub(crate) priority: Option<u32>,
+
+    /// Silences output.
+    ///
+    /// A -q option silences the steps of provisioning and the output of the commands they run,
+    /// leaving warnings and errors, and -qq also silences warnings. The JSON report of --json is
+    /// still printed.
+    #[clap(
+        short = 'q',
+        long = "quiet",
+        parse(from_occurrences),
+        conflicts_with = "verbose"
+    )]
+    pub(crate) quiet: usize,
+
+    /// Preset for a common kind of jail to apply.
+    ///
+    /// A recipe adds to the jail's other options: it sets iocage properties which aren't set
+    /// otherwise, installs packages, creates a tuned dataset for the service's data, and
+    /// initializes and starts the service. The `postgres` recipe enables System V shared memory
+    /// and semaphores, installs the PostgreSQL server, mounts a dataset tuned for the database at
+    /// /var/db/postgres, and initializes and starts the server. The `ci-runner` recipe installs
+    /// the packages commonly needed to build software in CI, such as git and gmake. This option
+    /// may be provided multiple times.
+    #[clap(
+        long,
+        number_of_values = 1,
+        value_name = "NAME",
+        possible_values = &["ci-runner", "postgres"]
+    )]
+    pub(crate) recipe: Vec<Recipe>,
+
+    /// Zone in which to register the jail's name. [example: jails.example.com:ns1:/etc/jails.key]
+    ///
+    /// When this option is used, once the jail is provisioned an address record for
+    /// `NAME.ZONE` is replaced with the jail's address by a dynamic DNS update (RFC 2136) sent to
+    /// the server with `nsupdate`, signed with the TSIG key in the key file. A jail destroyed by
+    /// the `apply` or `prune` subcommands is removed from the zone.
+    #[clap(long, value_name = "ZONE:SERVER:KEYFILE")]
+    pub(crate) register_dns: Option<DnsRegistration>,
 
     /// FreeBSD release to use for the jail instance.
     ///
     /// If not provided, the default value will be the same release version that is running on the
     /// underlying host system. For example if `uname -r` returns `11.2-STABLE`, then the default
-    /// value would be `11.2-RELEASE`.
+    /// value would be `11.2-RELEASE`. A release of `path:PATH` names a locally built userland
+    /// directory (such as the DESTDIR of `make installworld`) or a tarball of one, which is
+    /// imported as a release named after the directory or tarball if it hasn't been already.
+    #[clap(short = 'R', long, rename_all = "screaming-snake")]
+    pub(crate) release: Option<String>,
+
+    /// Repairs stale iocage state which makes creating the jail fail, then retries it once.
+    ///
+    /// When creating the jail fails because an earlier jail with the same name is still dying, its
+    /// dataset was left without a configuration, or a VNET interface of a removed jail is left on
+    /// the host, the earlier jail is force stopped and waited on, the stale interfaces are
+    /// destroyed, and the unconfigured dataset is destroyed before creating the jail again.
+    #[clap(long)]
+    pub(crate) repair: bool,
+
+    /// Requires running as root rather than accepting delegated jail permissions.
+    ///
+    /// By default, a user other than root may manage jails when iocage can list the jails and the
+    /// iocage dataset is writable, as on hosts which delegate jail management with `zfs allow`.
+    #[clap(long, global = true)]
+    pub(crate) require_root: bool,
+
+    /// Number of times to retry a network dependent step after a transient failure.
+    ///
+    /// Package installation is retried with an exponential backoff starting at --retry-delay.
+    /// Failures which can't succeed on a later attempt, such as invalid input or a jail name
+    /// which is already used, are never retried.
+    #[clap(long, value_name = "N", default_value = "0")]
+    pub(crate) retries: u32,
+
+    /// Delay in seconds before the first retry, doubling before each further retry.
+    #[clap(long, value_name = "SECS", default_value = "5")]
+    pub(crate) retry_delay: u64,
+
+    /// Existing jail whose configuration is used as a base for the jail instance.
+    ///
+    /// When this option is used, the properties of the named jail are copied to the new jail,
+    /// except for those which identify it such as its name, addresses, and notes. The release of
+    /// the named jail is also used unless `--release` is provided. Any other explicitly provided
+    /// options take precedence over the copied properties.
+    #[clap(long, value_name = "JAIL")]
+    pub(crate) same_as: Option<String>,
+
+    /// Environment variable holding a secret value to mask in all output (can be repeated).
+    ///
+    /// The value of the variable is masked wherever it would appear in logs, errors, and JSON
+    /// output, for example a token which is part of another option such as --smtp-relay.
+    #[clap(long, number_of_values = 1, value_name = "VAR")]
+    pub(crate) secret_env: Vec<String>,
+
+    /// Source from which the secrets named by --exec-secret are retrieved.
+    ///
+    /// The source is one of `env` to read each secret from the environment variable with its
+    /// name, `file:DIR` to read it from the file with its name in DIR, or `exec:PROGRAM` to run
+    /// PROGRAM with its name as the only argument and read it from the program's output.
     #[clap(
-        short = 'R',
         long,
-        default_value = &DEFAULT_RELEASE,
-        rename_all = "screaming-snake",
+        value_name = "SOURCE",
+        default_value = "env",
+        parse(try_from_str = parse_secret_source)
     )]
-    pub(crate) release: String,
+    pub(crate) secret_source: String,
+
+    /// Kernel security level of the jail, from -1 (insecure) to 3 (network secure).
+    ///
+    /// A raised security level prevents processes in the jail from, for example, modifying
+    /// immutable files even as root. See security(7) for details of each level.
+    #[clap(
+        long,
+        value_name = "N",
+        allow_hyphen_values = true,
+        parse(try_from_str = parse_securelevel)
+    )]
+    pub(crate) securelevel: Option<i32>,
+
+    /// Sets up the host bridge for VNET jails when it is missing.
+    ///
+    /// When this flag is set, the `bridge0` interface is created if it doesn't exist and the
+    /// uplink interface (see --interface) is added to it, before the jail is created. The bridge
+    /// and its members are also added to the host's `/etc/rc.conf` so that they persist across a
+    /// reboot.
+    #[clap(long, conflicts_with = "vnet-off")]
+    pub(crate) setup_bridge: bool,
+
+    /// Mail server to relay all of the jail's mail through. [example: mail.internal:25]
+    ///
+    /// When this option is used, the jail's mailer is configured to send all mail via the given
+    /// smarthost, with an optional port, and the local mail server daemons are disabled. Useful
+    /// so that output from periodic reports and cron jobs actually reaches an administrator. An
+    /// IPv6 address with a port must be enclosed in brackets, such as `[2001:db8::25]:587`.
+    #[clap(long, value_name = "HOST[:PORT]")]
+    pub(crate) smtp_relay: Option<String>,
+
+    /// Snapshots of the jail's dataset to keep. [example: daily:7,weekly:4]
+    ///
+    /// When this option is used, the jail's dataset is registered with the host's snapshot tools
+    /// so that it is backed up from the start: the zfs-auto-snapshot properties for the listed
+    /// intervals (hourly, daily, weekly, and monthly) are set, and a stanza with the snapshot
+    /// counts is added to the sanoid configuration if one exists on the host.
+    #[clap(long, value_name = "SCHEDULE")]
+    pub(crate) snapshot_schedule: Option<SnapshotSchedule>,
+
+    /// src.conf file on the host to install in the jail.
+    ///
+    /// When this option is used, the file is installed as `/etc/src.conf` in the jail.
+    #[clap(long, value_name = "FILE")]
+    pub(crate) src_conf: Option<PathBuf>,
 
     /// Installs and sets up an SSH service.
     ///
     /// If this flag is set, then SSH software is installed, enabled on boot and is started on
     /// first boot. Useful for jails that required remote administration, remote file copying, etc.
+    /// The SHA256 fingerprints of the jail's SSH host keys are printed once the service has
+    /// started.
     #[clap(short = 's', long)]
     pub(crate) ssh: bool,
 
+    /// Type of SSH host key to generate, disabling all other types. [example: ed25519]
+    ///
+    /// When this option is used, only the given host key types are generated and offered by the
+    /// SSH service, which is useful when a policy dictates the permitted key algorithms. This
+    /// option may be repeated to allow several types.
+    #[clap(
+        long,
+        value_name = "TYPE",
+        number_of_values = 1,
+        possible_values = &["rsa", "ecdsa", "ed25519"],
+        requires = "ssh"
+    )]
+    pub(crate) ssh_hostkey: Vec<SshHostKeyType>,
+
+    /// Directory on the host containing existing SSH host keys to install in the jail.
+    ///
+    /// When this option is used, all `ssh_host_*_key` and `ssh_host_*_key.pub` files in the
+    /// directory are installed before the SSH service first starts. This lets a rebuilt jail keep
+    /// its previous host keys so that clients don't see key change warnings.
+    #[clap(long, value_name = "DIR", requires = "ssh")]
+    pub(crate) ssh_hostkeys_dir: Option<PathBuf>,
+
+    /// Stops provisioning after the named phase.
+    ///
+    /// The jail is provisioned in phases: `create` (creating the jail and setting its ZFS
+    /// properties), `network` (waiting for its network and installing packages), `users`
+    /// (creating the --user and --automation-user), and `services` (configuring services, SSH,
+    /// user-data, and first boot scripts). When this option is used, the jail is left in the state
+    /// reached at the end of the named phase, such as to build a template from a jail with its
+    /// packages but without users, or to hand the jail over to another tool.
+    #[clap(
+        long,
+        value_name = "PHASE",
+        possible_values = &["create", "network", "users", "services"]
+    )]
+    pub(crate) stop_after: Option<Phase>,
+
+    /// Treat warnings as errors.
+    ///
+    /// Non-fatal issues, such as a user's login shell which isn't installed in the jail or a step
+    /// which only succeeded after being retried, are normally reported as warnings. With this
+    /// flag the first warning fails the provisioning instead.
+    #[clap(long)]
+    pub(crate) strict: bool,
+
+    /// Re-runs this program as root with sudo when not already running as root.
+    ///
+    /// The command line is preserved, as are the environment variables which affect this
+    /// program's output and those named by --secret-env, provided the sudoers policy allows them
+    /// to be kept.
+    #[clap(long, global = true)]
+    pub(crate) sudo: bool,
+
+    /// Remote log host to forward the jail's syslog messages to. [example: loghost:514]
+    ///
+    /// When this option is used, the syslog service in the new jail is configured to forward all
+    /// messages to the given host, with an optional port. The service is restarted so that
+    /// forwarding is active from the moment the jail is provisioned.
+    #[clap(long, value_name = "HOST[:PORT]")]
+    pub(crate) syslog_remote: Option<String>,
+
+    /// System V IPC access mode for the jail.
+    ///
+    /// When this option is used, System V message queues, semaphores, and shared memory are
+    /// either given a separate namespace for the jail (`new`), shared with the host (`inherit`),
+    /// or disabled (`disable`). Database servers such as PostgreSQL commonly require System V IPC.
+    #[clap(long, value_name = "MODE", possible_values = &["new", "inherit", "disable"])]
+    pub(crate) sysvipc: Option<SysvIpc>,
+
+    /// Label describing the jail. [example: env=prod]
+    ///
+    /// Tags are included in the JSON report and in the --emit-terraform output. This option may
+    /// be provided multiple times.
+    #[clap(
+        long,
+        number_of_values = 1,
+        value_name = "KEY=VALUE",
+        parse(try_from_str = parse_tag)
+    )]
+    pub(crate) tag: Vec<(String, String)>,
+
     /// Installs a thick jail rather than a clone.
     ///
     /// If this flag is set, then a so-called "thick jail" is installed, which is a jail that is
@@ -101,6 +922,42 @@ pub(crate) struct Args {
     #[clap(short = 'T', long = "thickjail")]
     pub(crate) thick_jail: bool,
 
+    /// Prefixes each line of output with an RFC 3339 timestamp in UTC.
+    ///
+    /// Steps, warnings, and the output of the commands they run keep their usual format with the
+    /// time at which each line was printed in front, which is useful when capturing output into a
+    /// ticket. Progress bars are not shown when this flag is set.
+    #[clap(long, global = true)]
+    pub(crate) timestamps: bool,
+
+    /// Size limited tmpfs mount in the jail. [example: /tmp=512m]
+    ///
+    /// When this option is used, a tmpfs file system is mounted at the path in the jail, limited
+    /// to the size if one is given (with an optional k, m, g, or t suffix), so that temporary
+    /// files such as those in /tmp or /var/run don't add write load to the pool. This option may
+    /// be provided multiple times.
+    #[clap(
+        long,
+        number_of_values = 1,
+        value_name = "PATH[=SIZE]",
+        parse(try_from_str = parse_tmpfs)
+    )]
+    pub(crate) tmpfs: Vec<String>,
+
+    /// How to handle the user's uid or gid already being used in the jail.
+    ///
+    /// Before the --user is created, the jail is checked for an existing user with the same uid
+    /// and an existing group with the same gid. When one is found, the provisioning either fails
+    /// (`fail`), uses the existing user or group (`reuse`), or creates the user or group with the
+    /// next free id in the jail (`next-free`).
+    #[clap(
+        long,
+        value_name = "POLICY",
+        default_value = "fail",
+        possible_values = &["fail", "reuse", "next-free"]
+    )]
+    pub(crate) uid_conflict: IdConflictPolicy,
+
     /// User to create in jail instance (based on host system's information).
     ///
     /// When this option is used, a user account will be created in the new jail with settings
@@ -110,29 +967,449 @@ pub(crate) struct Args {
     #[clap(short = 'u', long, rename_all = "screaming-snake")]
     pub(crate) user: Option<String>,
 
+    /// cloud-config user-data file on the host to apply to the jail.
+    ///
+    /// When this option is used, user-data written for cloud-init is translated into this tool's
+    /// own steps: its `packages` are installed with the jail's other packages, and then its
+    /// `users` are created, its `write_files` are written, and its `runcmd` commands are run, in
+    /// that order. Any other cloud-config key results in an error and the jail will not be
+    /// created.
+    #[clap(long, value_name = "FILE")]
+    pub(crate) user_data: Option<PathBuf>,
+
+    /// Lets iocage name the jail instance with a short UUID.
+    ///
+    /// When this flag is set, the NAME argument is omitted and only the IP address is given. The
+    /// name iocage generated for the jail is printed and included in the JSON report. Useful when
+    /// jails are tracked by an external inventory rather than by name.
+    #[clap(long)]
+    pub(crate) uuid: bool,
+
     /// Sets the verbosity mode.
     ///
-    /// Multiple -v options increase verbosity. The maximum is 3.
+    /// Multiple -v options increase verbosity. The maximum is 3: -v logs the details of each step,
+    /// -vv also logs each command which is run, and -vvv also logs each script which is run in the
+    /// jail along with its exported environment. When output is to a terminal and no -v option is
+    /// given, long-running steps such as creating the jail and installing packages show a progress
+    /// bar rather than streaming their output.
     #[clap(short = 'v', long = "verbose", parse(from_occurrences))]
     pub(crate) verbose: usize,
+
+    /// Passes iocage's own debug flag to the iocage invocations whose output is streamed.
+    ///
+    /// Invocations whose output is parsed, such as listing jails, are not affected. Useful when
+    /// debugging a failure inside of iocage. Combined with -vvv, the scripts run in the jail are
+    /// also logged, with the contents of private keys redacted.
+    #[clap(long)]
+    pub(crate) verbose_iocage: bool,
+
+    /// Creates a shared-IP jail which uses the host's network stack rather than a VNET.
+    ///
+    /// When this flag is set, the jail's address is added to a host interface (see --interface)
+    /// and the jail has no default route of its own, so --gateway is not used. An SSH service in
+    /// the jail only listens on the jail's address. Services on the host which listen on all
+    /// addresses, such as the host's own SSH service, should be bound to the host's addresses so
+    /// that they don't also answer on the jail's address.
+    #[clap(long, conflicts_with = "GATEWAY")]
+    pub(crate) vnet_off: bool,
+
+    /// Prepares the jail to run a VPN such as WireGuard.
+    ///
+    /// If this flag is set, then the jail may use tun devices (through iocage's devfs ruleset)
+    /// and raw sockets, the `wireguard-tools` package is installed, and IPv4 and IPv6 forwarding
+    /// are enabled in the jail. Forwarding can't be enabled in a shared-IP jail (see --vnet-off),
+    /// so a warning is given instead. A WireGuard interface also requires the host to have the
+    /// `if_wg` kernel module loaded.
+    #[clap(long)]
+    pub(crate) vpn_ready: bool,
+
+    /// Time to wait for a busy host to have capacity before the jail is created. [example: 30m]
+    ///
+    /// When the host is too busy according to --min-free-memory or --max-load, provisioning is
+    /// deferred until it has capacity, and fails once this time has passed. Without this option
+    /// a busy host is only reported as a warning. The duration is a whole number followed by a
+    /// unit of `s`, `m`, `h`, `d`, or `w`.
+    #[clap(long, value_name = "DURATION", parse(try_from_str = parse::duration))]
+    pub(crate) wait_for_capacity: Option<u64>,
+
+    /// ZFS property to set on the jail's dataset. [example: compression=zstd]
+    ///
+    /// When this option is used, the property is set on the jail's dataset right after the jail
+    /// is created, such as `atime=off` to disable access time updates. This option may be
+    /// provided multiple times.
+    #[clap(
+        long,
+        number_of_values = 1,
+        value_name = "PROPERTY=VALUE",
+        parse(try_from_str = parse_zfs_prop)
+    )]
+    pub(crate) zfs_opt: Vec<String>,
+}
+
+// Note: a doc comment on this type would replace the program's about text in the help output
+//
+// Subcommands which are run instead of provisioning a jail.
+#[derive(Clap, Debug)]
+pub(crate) enum Command {
+    /// Applies the actions needed to make the managed jails match a manifest.
+    ///
+    /// The planned actions are printed and must be confirmed before they are applied, unless
+    /// --auto-approve is given. Modified jails are destroyed and provisioned again.
+    Apply {
+        /// Path to the TOML manifest file.
+        #[clap(rename_all = "screaming-snake")]
+        file: PathBuf,
+
+        /// Applies the planned actions without asking for confirmation.
+        #[clap(long)]
+        auto_approve: bool,
+    },
+
+    /// Destroys the managed jails which have expired.
+    ///
+    /// The jails to destroy are printed and must be confirmed before they are destroyed, unless
+    /// --auto-approve is given, which is suited to running from cron. Only jails provisioned by
+    /// this program with an expiry, such as with --expires-in or --ephemeral, are ever destroyed.
+    Destroy {
+        /// Destroys the managed jails whose expiry has passed, which is currently required.
+        #[allow(dead_code)]
+        #[clap(long, parse(from_flag), required = true)]
+        expired: bool,
+
+        /// Destroys the jails without asking for confirmation.
+        #[clap(long, conflicts_with = "dry-run")]
+        auto_approve: bool,
+
+        /// Prints the jails which would be destroyed without destroying them.
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Keeps the data datasets of destroyed jails, such as home directory and recipe datasets.
+        ///
+        /// Kept datasets are unmounted and moved beneath the `iocage-provision/kept/NAME`
+        /// dataset of the active pool.
+        #[clap(long)]
+        keep_data: bool,
+    },
+
+    /// Lists all jails along with their descriptions.
+    List,
+
+    /// Prints the actions needed to make the managed jails match a manifest.
+    ///
+    /// Declared jails which don't exist are created, managed jails whose declared spec changed
+    /// are modified, and managed jails which are no longer declared are destroyed. Managed jails
+    /// are those provisioned by this program, as recorded in its state file.
+    Plan {
+        /// Path to the TOML manifest file.
+        #[clap(rename_all = "screaming-snake")]
+        file: PathBuf,
+    },
+
+    /// Destroys the managed jails which are no longer declared in a manifest.
+    ///
+    /// The jails to destroy are printed and must be confirmed before they are destroyed, unless
+    /// --auto-approve is given. Only jails provisioned by this program are ever destroyed.
+    Prune {
+        /// Path to the TOML manifest file.
+        #[clap(rename_all = "screaming-snake")]
+        file: PathBuf,
+
+        /// Destroys the jails without asking for confirmation.
+        #[clap(long)]
+        auto_approve: bool,
+
+        /// Keeps the data datasets of destroyed jails, such as home directory and recipe datasets.
+        ///
+        /// Kept datasets are unmounted and moved beneath the `iocage-provision/kept/NAME`
+        /// dataset of the active pool.
+        #[clap(long)]
+        keep_data: bool,
+    },
+
+    /// Renames a jail, keeping what this program tracks about it consistent.
+    ///
+    /// A running jail is stopped while it is renamed and started again afterwards. When the jail
+    /// was provisioned by this program, its record in the state file moves to the new name and
+    /// its /etc/hosts entry and DNS registration, if it has them, are replaced with ones for the
+    /// new name.
+    Rename {
+        /// Current name of the jail.
+        #[clap(rename_all = "screaming-snake")]
+        old: String,
+
+        /// New name of the jail.
+        #[clap(rename_all = "screaming-snake")]
+        new: String,
+
+        /// Also sets the jail's hostname to its new name.
+        #[clap(long)]
+        hostname: bool,
+    },
+
+    /// Rolls back the iocage datasets to a checkpoint.
+    ///
+    /// All jails and datasets created after the checkpoint was taken are destroyed, the iocage
+    /// release, template, and configuration datasets are rolled back, and the checkpoint is
+    /// removed. Jails which existed before the checkpoint was taken are left untouched.
+    RollbackCheckpoint {
+        /// Name of the checkpoint as printed when it was taken.
+        #[clap(rename_all = "screaming-snake")]
+        checkpoint: String,
+    },
+
+    /// Sets iocage properties of all managed jails.
+    ///
+    /// Useful for fleet-wide changes, such as a new resolver or default route after a network
+    /// change. Managed jails are those provisioned by this program, as recorded in its state file.
+    /// The result for each jail is printed, and a failure for one jail doesn't stop the others
+    /// from being changed. Properties which identify a jail, such as `ip4_addr`, can't be set.
+    /// Most properties take effect the next time a jail is started.
+    Set {
+        /// iocage property to set, such as `resolver="nameserver 10.0.0.53"`.
+        #[clap(
+            value_name = "KEY=VALUE",
+            required = true,
+            parse(try_from_str = parse_shared_property)
+        )]
+        props: Vec<String>,
+
+        /// Sets the properties of all managed jails, which is currently required.
+        #[allow(dead_code)]
+        #[clap(long, parse(from_flag), required = true)]
+        all_managed: bool,
+
+        /// Prints the jails which would be changed without changing them.
+        #[clap(long)]
+        dry_run: bool,
+    },
+
+    /// Changes the address of a jail, and optionally its default route.
+    ///
+    /// The address is changed on the same interface as before and a running jail is restarted.
+    /// When the jail was provisioned by this program, its record in the state file is updated and
+    /// its /etc/hosts entry and DNS registration, if it has them, are replaced with ones for the
+    /// new address. A manifest which declares the jail should be updated to match, otherwise the
+    /// next `apply` provisions the jail again with its declared address.
+    SetIp {
+        /// Name of the jail.
+        #[clap(rename_all = "screaming-snake")]
+        name: String,
+
+        /// New IP address & subnet mask of the jail. [example: 10.0.1.9/24]
+        #[clap(rename_all = "screaming-snake")]
+        ip: IpNet,
+
+        /// New IP address of the default gateway route of a VNET jail.
+        #[clap(long)]
+        gateway: Option<IpAddr>,
+    },
+
+    /// Prints the JSON Schema of the manifest format.
+    Schema,
+
+    /// Checks that a manifest file is valid without provisioning any jails.
+    ///
+    /// Errors report the line and key of the offending value along with a suggestion for
+    /// misspelled keys and values.
+    Validate {
+        /// Path to the TOML manifest file.
+        #[clap(rename_all = "screaming-snake")]
+        file: PathBuf,
+    },
+
+    /// Checks that the managed jails which start at boot came up after the host booted.
+    ///
+    /// Each managed jail whose `boot` property is on is checked to be running, to answer a ping
+    /// at its address and, when it has an SSH service, to accept connections on port 22. Failed
+    /// checks are retried until the timeout runs out, then the result for each jail is printed
+    /// and the program exits with an error if any jail failed. Suited to running from rc.local or
+    /// a cron `@reboot` entry.
+    VerifyBoot {
+        /// Time to keep retrying failed checks while jails are still starting. [example: 10m]
+        ///
+        /// The duration is a whole number followed by a unit of `s`, `m`, `h`, `d`, or `w`.
+        #[clap(
+            long,
+            value_name = "DURATION",
+            default_value = "5m",
+            parse(try_from_str = parse::duration)
+        )]
+        timeout: u64,
+    },
+}
+
+/// Parses and validates a crontab entry consisting of a schedule and a command.
+fn parse_cron_entry(s: &str) -> Result<String, String> {
+    let fields = s.split_whitespace().collect::<Vec<_>>();
+    let schedule_len = match fields.first() {
+        Some(field) if field.starts_with('@') => 1,
+        Some(_) => 5,
+        None => return Err(String::from("cron entry must not be empty")),
+    };
+
+    if fields.len() > schedule_len {
+        Ok(s.trim().to_string())
+    } else {
+        Err(format!(
+            "cron entry must be a schedule followed by a command; entry={}",
+            s
+        ))
+    }
+}
+
+/// Parses and validates a cpuset(1) CPU list.
+fn parse_cpuset(s: &str) -> Result<String, String> {
+    match parse::cpu_list(s) {
+        Some(_) => Ok(s.trim().to_string()),
+        None => Err(format!(
+            "cpuset must be a list of CPU numbers and ranges such as 0-3 or 0,2; cpuset={}",
+            s
+        )),
+    }
+}
+
+/// Parses and validates an octal file permissions mode.
+fn parse_mode(s: &str) -> Result<u32, String> {
+    match u32::from_str_radix(s, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!(
+            "mode must be an octal value of 0 to 7777; mode={}",
+            s
+        )),
+    }
+}
+
+/// Parses and validates an idle priority.
+fn parse_idprio(s: &str) -> Result<u16, String> {
+    match s.parse() {
+        Ok(prio) if prio <= 31 => Ok(prio),
+        _ => Err(format!(
+            "idle priority must be between 0 and 31; value={}",
+            s
+        )),
+    }
+}
+
+/// Parses and validates a nice value.
+fn parse_nice(s: &str) -> Result<i32, String> {
+    match s.parse() {
+        Ok(nice) if (-20..=20).contains(&nice) => Ok(nice),
+        _ => Err(format!(
+            "nice value must be between -20 and 20; value={}",
+            s
+        )),
+    }
+}
+
+/// Parses and validates a jail security level.
+fn parse_securelevel(s: &str) -> Result<i32, String> {
+    match s.parse() {
+        Ok(level) if (-1..=3).contains(&level) => Ok(level),
+        _ => Err(format!("securelevel must be between -1 and 3; value={}", s)),
+    }
+}
+
+/// Parses and validates a command which may be run through sudo.
+fn parse_sudo_command(s: &str) -> Result<String, String> {
+    if s.trim_start().starts_with('/') {
+        Ok(s.trim().to_string())
+    } else {
+        Err(format!(
+            "command must start with an absolute path; command={}",
+            s
+        ))
+    }
+}
+
+/// Parses and validates an environment variable for scripts run in the jail or commands run on
+/// the host.
+fn parse_exec_env(s: &str) -> Result<(String, String), String> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if iocage_provision::script::is_env_name(key) => {
+            Ok((key.to_string(), value.to_string()))
+        }
+        _ => Err(format!(
+            "environment variable must be KEY=VALUE with a valid KEY; value={}",
+            s
+        )),
+    }
+}
+
+/// Parses and validates a secret source.
+fn parse_secret_source(s: &str) -> Result<String, String> {
+    iocage_provision::secret::parse_source(s).map(|_| s.to_string())
+}
+
+/// Parses and validates an iocage property assignment which may be shared by several jails.
+fn parse_shared_property(s: &str) -> Result<String, String> {
+    let mut parts = s.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(key), Some(_)) if iocage_provision::is_identity_property(key) => Err(format!(
+            "property identifies a jail and can't be set on several jails; property={}",
+            key
+        )),
+        (Some(key), Some(_)) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+            Ok(s.to_string())
+        }
+        _ => Err(format!("property must be KEY=VALUE; value={}", s)),
+    }
+}
+
+/// Parses and validates a tag.
+fn parse_tag(s: &str) -> Result<(String, String), String> {
+    match s.find('=') {
+        Some(idx) if idx > 0 => Ok((s[..idx].to_string(), s[idx + 1..].to_string())),
+        _ => Err(format!("tag must be KEY=VALUE; value={}", s)),
+    }
+}
+
+/// Parses and validates a ZFS property assignment.
+fn parse_zfs_prop(s: &str) -> Result<String, String> {
+    match s.find('=') {
+        Some(idx) if idx > 0 && !s[..idx].contains(char::is_whitespace) => Ok(s.to_string()),
+        _ => Err(format!("zfs property must be PROPERTY=VALUE; value={}", s)),
+    }
+}
+
+/// Parses and validates a ZFS size.
+fn parse_zfs_size(s: &str) -> Result<String, String> {
+    if iocage_provision::parse::is_zfs_size(s) {
+        Ok(s.to_string())
+    } else {
+        Err(format!(
+            "size must be a number with a K, M, G, T, or P suffix; value={}",
+            s
+        ))
+    }
+}
+
+/// Parses and validates a tmpfs mount.
+fn parse_tmpfs(s: &str) -> Result<String, String> {
+    iocage_provision::parse::tmpfs(s).map(|_| s.to_string())
 }
 
 /// A default gateway value.
-fn default_gateway() -> String {
-    iocage_provision::netstat_gateway_addr()
-        .unwrap_or_else(|err| {
-            clap::Error::with_description(
-                format!("could not determine default gateway; err={}", err),
-                clap::ErrorKind::Io,
-            )
-            .exit()
-        })
-        .to_string()
+///
+/// This is only called when the `--gateway` option was not provided, so a host without a default
+/// IPv4 route only results in an error when a gateway is actually required.
+pub(crate) fn default_gateway() -> IpAddr {
+    DEFAULT_GATEWAY.clone().unwrap_or_else(|err| {
+        clap::Error::with_description(
+            format!(
+                "could not determine default gateway, provide one with --gateway; err={}\n",
+                err
+            ),
+            clap::ErrorKind::MissingRequiredArgument,
+        )
+        .exit()
+    })
 }
 
 /// A default release value.
-fn default_release() -> String {
-    iocage_provision::default_release()
+pub(crate) fn default_release() -> String {
+    DEFAULT_RELEASE.clone()
 }
 
 /// Build time metadata.
@@ -152,12 +1429,17 @@ impl BuildInfo {
 
 pub(crate) mod util {
     use chrono::{SecondsFormat, Utc};
+    use iocage_provision::Verbosity;
     use std::env;
     use std::panic;
+    use std::sync::atomic::{AtomicBool, Ordering};
 
     /// The logger.
     const LOGGER: &Logger = &Logger;
 
+    /// Whether informational records are written to the standard error stream.
+    static INFO_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
     /// A custom and minimal `Log` implementation.
     ///
     /// This logger writes either to the standard output stream or standard error stream, depending
@@ -173,11 +1455,21 @@ pub(crate) mod util {
         }
 
         fn log(&self, record: &log::Record) {
-            if log::max_level() == log::LevelFilter::Info {
+            let message = iocage_provision::redact(&record.args().to_string());
+            // Unless logging in detail, records are printed plainly as the steps of provisioning
+            if log::max_level() <= log::LevelFilter::Info {
+                let stamp = if iocage_provision::timestamps() {
+                    format!("{} ", Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true))
+                } else {
+                    String::new()
+                };
                 match record.level() {
-                    log::Level::Info => println!("  - {}", record.args()),
-                    log::Level::Warn => eprintln!("!!! {}", record.args()),
-                    log::Level::Error => eprintln!("xxx {}", record.args()),
+                    log::Level::Info if INFO_TO_STDERR.load(Ordering::Relaxed) => {
+                        eprintln!("{}  - {}", stamp, message)
+                    }
+                    log::Level::Info => println!("{}  - {}", stamp, message),
+                    log::Level::Warn => eprintln!("{}!!! {}", stamp, message),
+                    log::Level::Error => eprintln!("{}xxx {}", stamp, message),
                     _ => unreachable!("illegal log level"),
                 }
             } else {
@@ -188,13 +1480,13 @@ pub(crate) mod util {
                 };
 
                 match record.level() {
-                    log::Level::Info => {
+                    log::Level::Info if !INFO_TO_STDERR.load(Ordering::Relaxed) => {
                         println!(
                             "{} {:<5} [{}] {}",
                             Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
                             record.level(),
                             location,
-                            record.args()
+                            message
                         );
                     }
                     _ => {
@@ -203,7 +1495,7 @@ pub(crate) mod util {
                             Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
                             record.level(),
                             location,
-                            record.args()
+                            message
                         );
                     }
                 }
@@ -215,17 +1507,21 @@ pub(crate) mod util {
         }
     }
 
-    /// Sets up and initializes the logger.
-    pub(crate) fn init_logger_with_verbosity(verbosity: usize) {
+    /// Sets up and initializes the logger, and the library's output, with the given verbosity.
+    pub(crate) fn init_logger(verbosity: Verbosity) {
         log::set_logger(LOGGER).expect("error setting logger");
 
-        match verbosity {
-            0 => log::set_max_level(log::LevelFilter::Info),
-            1 => log::set_max_level(log::LevelFilter::Debug),
-            v if v >= 2 => log::set_max_level(log::LevelFilter::Trace),
-            _ => {}
-        }
-        log::debug!("verbosity={}", verbosity);
+        log::set_max_level(verbosity.level_filter());
+        iocage_provision::set_verbosity(verbosity);
+        log::debug!("verbosity={:?}", verbosity);
+    }
+
+    /// Sets whether all progress output, of both the logger and the library, is written to the
+    /// standard error stream rather than standard output, which is then left for machine readable
+    /// output.
+    pub(crate) fn set_progress_to_stderr(enabled: bool) {
+        INFO_TO_STDERR.store(enabled, Ordering::Relaxed);
+        iocage_provision::set_progress_to_stderr(enabled);
     }
 
     /// Wires up a human-first experience if the program panics unexpectedly and also supports the
@@ -244,6 +1540,9 @@ pub(crate) mod util {
         if env::var("RUST_BACKTRACE").is_err() {
             let default_hook = panic::take_hook();
 
+            // `PanicInfo` is deprecated for `PanicHookInfo` in newer releases, which is newer than
+            // the minimum supported Rust version
+            #[allow(deprecated)]
             panic::set_hook(Box::new(move |info: &panic::PanicInfo| {
                 // First call the default hook that prints to standard error
                 default_hook(info);
@@ -256,3 +1555,26 @@ pub(crate) mod util {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Args;
+    use clap::Clap;
+    use iocage_provision::recipe::Recipe;
+
+    #[test]
+    fn test_recipe() {
+        let args = Args::try_parse_from(&[
+            "iocage-provision",
+            "--recipe",
+            "ci-runner",
+            "--recipe",
+            "postgres",
+            "jail",
+            "10.0.0.2/24",
+        ])
+        .unwrap();
+
+        assert_eq!(vec![Recipe::CiRunner, Recipe::Postgres], args.recipe);
+    }
+}
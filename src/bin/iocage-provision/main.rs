@@ -2,28 +2,611 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use iocage_provision::{JailSpec, Manifest, Plan, Verbosity};
 use log::debug;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::process;
+use std::time::{Duration, SystemTime};
 
 mod cli;
 
-fn main() -> Result<()> {
+/// Environment variables which are kept when re-running this program as root.
+const ESCALATION_ENV: &[&str] = &["RUST_BACKTRACE", "TERM"];
+
+fn main() {
     cli::util::setup_panic_hooks();
 
+    // Errors are printed as they would be when returned from `main`, but with secrets masked
+    if let Err(err) = run() {
+        eprintln!("Error: {}", iocage_provision::redact(&format!("{:?}", err)));
+        if let Some(hint) = err
+            .chain()
+            .find_map(|err| err.downcast_ref::<iocage_provision::Error>())
+            .and_then(iocage_provision::Error::remediation)
+        {
+            eprintln!("\nTry: {}", iocage_provision::redact(&hint));
+        }
+        process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
     let args = cli::parse();
-    cli::util::init_logger_with_verbosity(args.verbose);
+    let verbosity = Verbosity::from_flags(args.verbose, args.quiet);
+    cli::util::init_logger(verbosity);
+    // Secret values may also be given in other arguments, so they are masked before any logging
+    for var in &args.secret_env {
+        if let Ok(secret) = env::var(var) {
+            iocage_provision::register_secret(&secret);
+        }
+    }
     debug!("parsed cli arguments; args={:?}", args);
+    iocage_provision::set_iocage_debug(args.verbose_iocage);
+    iocage_provision::set_child_env(args.child_env.clone(), !args.no_default_child_env);
+    iocage_provision::set_child_priority(iocage_provision::ChildPriority {
+        nice: args.nice,
+        idprio: args.idprio,
+    });
+    iocage_provision::secret::set_secret_source(
+        iocage_provision::secret::parse_source(&args.secret_source).map_err(anyhow::Error::msg)?,
+    );
+    iocage_provision::set_heartbeat_interval(args.heartbeat);
+    iocage_provision::set_timestamps(args.timestamps);
+    iocage_provision::set_audit_log(Some(args.audit_log.clone()));
+    iocage_provision::set_record_state(true);
+    if args.sudo {
+        escalate("sudo", &args.secret_env)?;
+    } else if args.doas {
+        escalate("doas", &args.secret_env)?;
+        // doas resets the environment, so the secrets are only present when doas.conf keeps them
+        for var in &args.secret_env {
+            if env::var_os(var).is_none() {
+                bail!(
+                    "secret environment variable is not set after re-running with doas, keep it \
+                    with `permit keepenv` or `permit setenv {{ {} }}` in doas.conf(5); var={}",
+                    var,
+                    var
+                );
+            }
+        }
+    }
+    // Progress bars redraw lines, so fall back to streaming output when it isn't a terminal
+    iocage_provision::set_progress_bars(
+        verbosity == Verbosity::Normal && is_terminal(&io::stdout()),
+    );
+    // Children run in their own process groups, so an interrupt must be forwarded to them
+    iocage_provision::terminate_on_signal().context("failed to install signal handlers")?;
+    if args.install_prereqs {
+        install_prereqs()?;
+    }
+
+    match args.command {
+        Some(cli::Command::Apply {
+            ref file,
+            auto_approve,
+        }) => {
+            ensure_privileged(args.require_root)?;
+            apply(file, auto_approve)?;
+        }
+        Some(cli::Command::Destroy {
+            expired: _,
+            auto_approve,
+            dry_run,
+            keep_data,
+        }) => {
+            ensure_privileged(args.require_root)?;
+            destroy_expired(auto_approve, dry_run, keep_data)?;
+        }
+        Some(cli::Command::List) => {
+            ensure_privileged(args.require_root)?;
+            list()?;
+        }
+        Some(cli::Command::Plan { ref file }) => {
+            ensure_privileged(args.require_root)?;
+            print!("{}", plan(file)?);
+        }
+        Some(cli::Command::Prune {
+            ref file,
+            auto_approve,
+            keep_data,
+        }) => {
+            ensure_privileged(args.require_root)?;
+            prune(file, auto_approve, keep_data)?;
+        }
+        Some(cli::Command::Rename {
+            ref old,
+            ref new,
+            hostname,
+        }) => {
+            ensure_privileged(args.require_root)?;
+            iocage_provision::rename_jail(old, new, hostname)?;
+        }
+        Some(cli::Command::RollbackCheckpoint { ref checkpoint }) => {
+            ensure_privileged(args.require_root)?;
+            iocage_provision::rollback_checkpoint(checkpoint)?;
+        }
+        Some(cli::Command::Set {
+            ref props,
+            all_managed: _,
+            dry_run,
+        }) => {
+            ensure_privileged(args.require_root)?;
+            set_all_managed(props, dry_run)?;
+        }
+        Some(cli::Command::SetIp {
+            ref name,
+            ip,
+            gateway,
+        }) => {
+            ensure_privileged(args.require_root)?;
+            iocage_provision::set_jail_ip(name, ip, gateway)?;
+        }
+        Some(cli::Command::Schema) => print!("{}", iocage_provision::MANIFEST_SCHEMA),
+        Some(cli::Command::Validate { ref file }) => validate(file)?,
+        Some(cli::Command::VerifyBoot { timeout }) => {
+            ensure_privileged(args.require_root)?;
+            verify_boot(timeout)?;
+        }
+        None => {
+            if !args.print_config {
+                ensure_privileged(args.require_root)?;
+            }
+            provision(args)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Ensures that the current user may manage jails, requiring `root` if `require_root` is set and
+/// otherwise also accepting a user with delegated jail permissions.
+fn ensure_privileged(require_root: bool) -> Result<()> {
+    if require_root {
+        iocage_provision::ensure_root()?;
+    } else {
+        iocage_provision::ensure_privileged()?;
+    }
+    // Commands are audited once they have run, so the audit log is checked before running any
+    iocage_provision::check_audit_log()?;
+
+    Ok(())
+}
+
+/// Re-runs this program as root with an escalation tool (i.e. `sudo` or `doas`), unless it is
+/// already running as root.
+///
+/// The command line arguments are preserved. With `sudo`, the allowed environment variables and
+/// the variables holding secrets are kept as well.
+fn escalate(tool: &str, secret_env: &[String]) -> Result<()> {
+    if iocage_provision::ensure_root().is_ok() {
+        return Ok(());
+    }
+
+    let exe = env::current_exe().context("could not determine the path of this program")?;
+    let mut cmd = process::Command::new(tool);
+    if tool == "sudo" {
+        let keep = ESCALATION_ENV
+            .iter()
+            .copied()
+            .chain(secret_env.iter().map(String::as_str))
+            .filter(|var| env::var_os(var).is_some())
+            .collect::<Vec<_>>();
+        if !keep.is_empty() {
+            cmd.arg(format!("--preserve-env={}", keep.join(",")));
+        }
+    }
+    cmd.arg(exe).args(env::args_os().skip(1));
+    debug!("re-running as root; cmd={:?}", cmd);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+
+        // Only returns if the program could not be run
+        let err = cmd.exec();
+        Err(err).with_context(|| format!("failed to re-run as root with {}", tool))
+    }
+    #[cfg(not(unix))]
+    bail!(
+        "re-running as root is only supported on Unix; tool={}",
+        tool
+    )
+}
+
+fn list() -> Result<()> {
+    let jails = iocage_provision::list_jails()?;
+
+    let rows = jails
+        .iter()
+        .map(|jail| {
+            [
+                jail.name.as_str(),
+                jail.state.as_str(),
+                jail.release.as_str(),
+                jail.ip4.as_str(),
+                jail.description.as_deref().unwrap_or("-"),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let header = ["NAME", "STATE", "RELEASE", "IP4", "DESCRIPTION"];
+
+    let mut widths = header.iter().map(|col| col.len()).collect::<Vec<_>>();
+    for row in &rows {
+        for (width, col) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(col.len());
+        }
+    }
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(col, width)| format!("{:<width$}", col, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    }
+
+    Ok(())
+}
+
+fn plan(file: &Path) -> Result<Plan> {
+    let manifest = Manifest::load(file, cli::default_gateway, cli::default_release)?;
+
+    Ok(iocage_provision::plan(manifest.jails)?)
+}
+
+fn apply(file: &Path, auto_approve: bool) -> Result<()> {
+    let plan = plan(file)?;
+    print!("{}", plan);
+    if plan.is_empty() {
+        return Ok(());
+    }
+
+    if !auto_approve {
+        confirm("\nApply these actions?", "apply cancelled")?;
+    }
+    iocage_provision::apply(plan, false)?;
+
+    Ok(())
+}
+
+fn prune(file: &Path, auto_approve: bool, keep_data: bool) -> Result<()> {
+    let manifest = Manifest::load(file, cli::default_gateway, cli::default_release)?;
+    let plan = iocage_provision::prune_plan(&manifest.jails)?;
+    if plan.is_empty() {
+        println!("No managed jails to prune.");
+        return Ok(());
+    }
+    for action in &plan.actions {
+        println!("{} {}", action.symbol(), action);
+    }
+
+    if !auto_approve {
+        confirm("\nDestroy these jails?", "prune cancelled")?;
+    }
+    iocage_provision::apply(plan, keep_data)?;
+
+    Ok(())
+}
+
+fn destroy_expired(auto_approve: bool, dry_run: bool, keep_data: bool) -> Result<()> {
+    let plan = iocage_provision::expired_plan(SystemTime::now())?;
+    if plan.is_empty() {
+        println!("No expired jails to destroy.");
+        return Ok(());
+    }
+    for action in &plan.actions {
+        println!("{} {}", action.symbol(), action);
+    }
+    if dry_run {
+        return Ok(());
+    }
 
-    iocage_provision::ensure_root()?;
-    iocage_provision::provision_jail(
-        &args.name,
-        &args.ip,
-        &args.gateway,
-        &args.release,
-        args.thick_jail,
-        args.user.as_deref(),
-        args.ssh,
+    if !auto_approve {
+        confirm("\nDestroy these jails?", "destroy cancelled")?;
+    }
+    iocage_provision::apply(plan, keep_data)?;
+
+    Ok(())
+}
+
+fn set_all_managed(props: &[String], dry_run: bool) -> Result<()> {
+    let jails = iocage_provision::managed_jails()?;
+    if jails.is_empty() {
+        println!("No managed jails to change.");
+        return Ok(());
+    }
+    if dry_run {
+        for jail in &jails {
+            println!("~ {}: {}", jail, props.join(" "));
+        }
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for jail in &jails {
+        match iocage_provision::set_properties(jail, props) {
+            Ok(()) => println!("{}: ok", jail),
+            Err(err) => {
+                failed += 1;
+                println!("{}: failed: {}", jail, err);
+            }
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "failed to set properties of {} of {} managed jails",
+            failed,
+            jails.len()
+        );
+    }
+
+    Ok(())
+}
+
+fn verify_boot(timeout: u64) -> Result<()> {
+    let checks = iocage_provision::verify_boot(Duration::from_secs(timeout))?;
+    if checks.is_empty() {
+        println!("No managed jails start at boot.");
+        return Ok(());
+    }
+
+    let mut failed = 0;
+    for check in &checks {
+        if check.passed() {
+            println!("{}: ok", check.name);
+        } else {
+            failed += 1;
+            println!(
+                "{}: failed: {}",
+                check.name,
+                check
+                    .failures
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+    if failed > 0 {
+        bail!(
+            "{} of {} managed jails did not come up after boot",
+            failed,
+            checks.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Installs the packages providing any missing host prerequisites, once the user confirms them.
+fn install_prereqs() -> Result<()> {
+    let missing = iocage_provision::missing_prereqs()?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    println!("The following host packages will be installed:");
+    for prereq in &missing {
+        println!("  {} (provides {})", prereq.package, prereq.program);
+    }
+
+    confirm(
+        "\nInstall these packages?",
+        "installation of prerequisites cancelled",
     )?;
+    iocage_provision::install_prereqs(&missing)?;
 
     Ok(())
 }
+
+fn confirm(question: &str, cancelled: &'static str) -> Result<()> {
+    print!("{} Only 'yes' will be accepted: ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    if answer.trim() != "yes" {
+        bail!(cancelled);
+    }
+
+    Ok(())
+}
+
+fn validate(file: &Path) -> Result<()> {
+    // Host defaults aren't needed to check a manifest, so unspecified values stand in for them
+    let manifest = Manifest::load(file, || IpAddr::V4(Ipv4Addr::UNSPECIFIED), String::new)?;
+    println!(
+        "{}: valid manifest with {} jail(s)",
+        file.display(),
+        manifest.jails.len()
+    );
+
+    Ok(())
+}
+
+fn provision(mut args: cli::Args) -> Result<()> {
+    if args.json_log.is_some() {
+        iocage_provision::start_run_log();
+    }
+    // Both values are required by the CLI parser when no subcommand is given, except with --uuid
+    // where the only positional argument, parsed as the name, is the IP address
+    let (name, ip) = match (args.uuid, args.name.take(), args.ip) {
+        (false, Some(name), Some(ip)) => (name, ip),
+        (true, Some(ip), None) => (String::new(), ip.parse().context("invalid IP argument")?),
+        (true, Some(_), Some(_)) => bail!("a NAME can't be given with --uuid"),
+        (true, None, _) => bail!("the IP argument is required"),
+        _ => unreachable!("name and ip are required by the cli parser"),
+    };
+
+    let mut base_props = match args.same_as {
+        Some(ref jail) => iocage_provision::same_as_properties(jail)?,
+        None => BTreeMap::new(),
+    };
+    // Defaults are only computed when no explicit value (or base jail's release) is available
+    let base_release = base_props.remove("release");
+    let release = args
+        .release
+        .or(base_release)
+        .unwrap_or_else(cli::default_release);
+    // A shared-IP jail has no default route of its own, so no gateway is needed
+    let gateway = if args.vnet_off {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    } else {
+        args.gateway.unwrap_or_else(cli::default_gateway)
+    };
+
+    let mut spec = JailSpec::new(name, ip, gateway, release);
+    spec.thick_jail = args.thick_jail;
+    spec.pkgbase = args.pkgbase;
+    spec.pkgbase_packages = args.pkgbase_package;
+    spec.minimal = args.minimal;
+    spec.vnet_off = args.vnet_off;
+    spec.interface = args.interface;
+    spec.setup_bridge = args.setup_bridge;
+    spec.fix_firewall = args.fix_firewall;
+    spec.user = args.user;
+    spec.copy_groups = args.copy_groups;
+    spec.id_conflict = args.uid_conflict;
+    spec.home_dir = args.home_dir;
+    spec.home_mode = args.home_mode;
+    spec.create_home = !args.no_create_home;
+    spec.home_dataset = args.home_dataset;
+    spec.login_class = args.login_class;
+    spec.lang = args.lang;
+    spec.automation_user = args.automation_user;
+    spec.automation_key = args.automation_key;
+    spec.automation_commands = args.automation_command;
+    spec.ssh_service = args.ssh;
+    spec.ssh_host_key_types = args.ssh_hostkey;
+    spec.ssh_host_keys_dir = args.ssh_hostkeys_dir;
+    spec.ntp_service = args.ntp;
+    spec.monitoring = args.monitoring;
+    spec.monitoring_server = args.monitoring_server;
+    spec.syslog_remote = args.syslog_remote;
+    spec.cron_entries = args.cron;
+    spec.periodic_log = args.periodic_log;
+    spec.smtp_relay = args.smtp_relay;
+    spec.copy_ca_certs = args.copy_ca_certs;
+    spec.ca_certs = args.ca_cert;
+    spec.pkg_repo_dir = args.pkg_repo_dir;
+    spec.pkglist = args.pkglist;
+    spec.offline = args.offline;
+    spec.dns_check = args.dns_check;
+    spec.defer_packages = args.defer_packages;
+    spec.network_timeout = args.network_timeout;
+    spec.register_dns = args.register_dns;
+    spec.ports = args.ports;
+    spec.ports_distfiles_dir = args.ports_distfiles;
+    spec.ports_packages_dir = args.ports_packages;
+    spec.make_conf = args.make_conf;
+    spec.firstboot = args.firstboot;
+    spec.user_data = args.user_data;
+    spec.exec_env = args.exec_env.into_iter().collect();
+    spec.exec_secrets = args.exec_secret.into_iter().collect();
+    spec.src_conf = args.src_conf;
+    spec.priority = args.priority;
+    spec.depends = args.depends;
+    spec.exec_prestart = args.exec_prestart;
+    spec.exec_poststart = args.exec_poststart;
+    spec.exec_prestop = args.exec_prestop;
+    spec.expires_in = args.expires_in;
+    spec.exec_poststop = args.exec_poststop;
+    spec.fib = args.fib;
+    spec.cpuset = args.cpuset;
+    spec.securelevel = args.securelevel;
+    spec.sysvipc = args.sysvipc;
+    spec.recipes = args.recipe;
+    spec.vpn_ready = args.vpn_ready;
+    spec.disk_quota = args.disk_quota;
+    spec.disk_reserve = args.disk_reserve;
+    spec.snapshot_schedule = args.snapshot_schedule;
+    spec.zfs_props = args.zfs_opt;
+    spec.tmpfs = args.tmpfs;
+    spec.dataset_parent = args.dataset_parent;
+    spec.checkpoint = args.checkpoint;
+    spec.ephemeral = args.ephemeral;
+    spec.if_exists = args.if_exists;
+    spec.uuid_name = args.uuid;
+    spec.repair = args.repair;
+    spec.retries = args.retries;
+    spec.retry_delay = args.retry_delay;
+    spec.min_free_memory = args.min_free_memory;
+    spec.max_load = args.max_load;
+    spec.wait_for_capacity = args.wait_for_capacity;
+    spec.stop_after = args.stop_after;
+    spec.strict = args.strict;
+    spec.base_props = base_props;
+    spec.description = args.description;
+    spec.tags = args.tag.into_iter().collect();
+    spec.hosts_entry = args.add_hosts_entry;
+    spec.known_hosts = args.known_hosts;
+    for var in &args.secret_env {
+        match env::var(var) {
+            Ok(secret) => spec.secrets.push(secret),
+            Err(_) => bail!("secret environment variable is not set; var={}", var),
+        }
+    }
+    for secret in &spec.secrets {
+        iocage_provision::register_secret(secret);
+    }
+
+    if args.print_config {
+        println!(
+            "{}",
+            iocage_provision::redact(&serde_json::to_string_pretty(&spec)?)
+        );
+        return Ok(());
+    }
+
+    if args.json {
+        cli::util::set_progress_to_stderr(true);
+        iocage_provision::set_progress_bars(
+            iocage_provision::verbosity() == Verbosity::Normal && is_terminal(&io::stderr()),
+        );
+    }
+    let result = iocage_provision::provision_jail(&spec);
+    let logged = match args.json_log {
+        Some(ref file) => iocage_provision::write_run_log(file, &spec, result.as_ref())
+            .with_context(|| format!("failed to write run log; file={}", file.display())),
+        None => Ok(()),
+    };
+    // An error provisioning the jail takes precedence over an error writing its run log
+    let report = result?;
+    logged?;
+    if args.json {
+        println!(
+            "{}",
+            iocage_provision::redact(&serde_json::to_string_pretty(&report)?)
+        );
+    }
+    if let Some(ref file) = args.emit_terraform {
+        let terraform = iocage_provision::emitters::terraform(&report);
+        fs::write(file, iocage_provision::redact(&terraform).as_bytes()).with_context(|| {
+            format!("failed to write terraform output; file={}", file.display())
+        })?;
+    }
+    if args.login {
+        // The jail may have been named by iocage and an existing user may have been reused
+        let user = report.user.as_deref().or(spec.user.as_deref());
+        iocage_provision::login_shell(&report.name, user)?;
+    }
+
+    Ok(())
+}
+
+/// Returns whether or not a standard stream is a terminal.
+#[cfg(unix)]
+fn is_terminal<S: std::os::unix::io::AsRawFd>(stream: &S) -> bool {
+    nix::unistd::isatty(stream.as_raw_fd()).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_terminal<S>(_stream: &S) -> bool {
+    false
+}
@@ -0,0 +1,117 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Bounded capture of command output.
+//!
+//! Output which is captured for reports and errors, rather than parsed, is kept in a
+//! [`RingBuffer`] which holds only the end of the output, so that a chatty command such as a large
+//! package installation can't grow memory without bound in a long-running process.
+
+/// Maximum number of bytes of output kept for each captured stream.
+pub(crate) const CAPTURE_LIMIT: usize = 64 * 1024;
+
+/// A buffer of lines of output which keeps only the most recent bytes, counting those dropped.
+#[derive(Debug)]
+pub(crate) struct RingBuffer {
+    limit: usize,
+    buf: String,
+    dropped: usize,
+}
+
+impl RingBuffer {
+    /// Returns an empty buffer which keeps at most `limit` bytes of output.
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            buf: String::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Appends a line of output, dropping the oldest output once the buffer is full.
+    pub(crate) fn push_line(&mut self, line: &str) {
+        self.buf.push_str(line);
+        self.buf.push('\n');
+        // Trimming once twice the limit is reached keeps appending amortized constant time
+        if self.buf.len() > 2 * self.limit {
+            self.trim();
+        }
+    }
+
+    /// Returns the kept output, preceded by a marker line with the number of bytes dropped, if any.
+    pub(crate) fn into_string(mut self) -> String {
+        self.trim();
+        if self.dropped == 0 {
+            self.buf
+        } else {
+            format!(
+                "[... {} bytes of output truncated ...]\n{}",
+                self.dropped, self.buf
+            )
+        }
+    }
+
+    /// Drops the oldest output beyond the limit, starting the kept output at a line where possible.
+    fn trim(&mut self) {
+        if self.buf.len() <= self.limit {
+            return;
+        }
+        let mut start = self.buf.len() - self.limit;
+        while !self.buf.is_char_boundary(start) {
+            start += 1;
+        }
+        if start > 0 && self.buf.as_bytes()[start - 1] != b'\n' {
+            if let Some(idx) = self.buf[start..].find('\n') {
+                if start + idx + 1 < self.buf.len() {
+                    start += idx + 1;
+                }
+            }
+        }
+        self.dropped += start;
+        self.buf.drain(..start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingBuffer;
+
+    fn captured(limit: usize, lines: &[&str]) -> String {
+        let mut buf = RingBuffer::new(limit);
+        for line in lines {
+            buf.push_line(line);
+        }
+        buf.into_string()
+    }
+
+    #[test]
+    fn test_push_line_at_limit() {
+        assert_eq!("abc\ndef\n", captured(8, &["abc", "def"]));
+        assert_eq!(
+            "[... 4 bytes of output truncated ...]\ndefg\n",
+            captured(8, &["abc", "defg"])
+        );
+    }
+
+    #[test]
+    fn test_line_longer_than_limit() {
+        assert_eq!(
+            "[... 5 bytes of output truncated ...]\nfgh\n",
+            captured(4, &["abcdefgh"])
+        );
+        // The kept output never starts in the middle of a character
+        assert_eq!(
+            "[... 5 bytes of output truncated ...]\n\n",
+            captured(3, &["é€"])
+        );
+    }
+
+    #[test]
+    fn test_into_string_after_wrapping() {
+        assert_eq!(
+            "[... 8 bytes of output truncated ...]\ne\nf\n",
+            captured(4, &["a", "b", "c", "d", "e", "f"])
+        );
+    }
+}
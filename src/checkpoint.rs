@@ -0,0 +1,214 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checkpoints of the iocage datasets which allow a provisioning run to be rolled back.
+//!
+//! A checkpoint is a recursive ZFS snapshot of the `iocage` dataset of the active pool, taken
+//! before any changes are made. Rolling back destroys every jail and dataset created after the
+//! checkpoint (for example a new jail or a newly fetched release) and rolls back the remaining
+//! non-jail datasets (releases, templates, and iocage's own configuration). Jails which existed
+//! before the checkpoint are left untouched.
+
+use super::{cmd_stdout, iocage_pool, run_and_indent, run_iocage_destroy, Error, Result};
+use log::info;
+use std::collections::BTreeSet;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prefix of the snapshot name used for checkpoints.
+const CHECKPOINT_PREFIX: &str = "iocage-provision-";
+
+/// Creates a checkpoint of the iocage datasets in the given pool and returns its name.
+///
+/// # Errors
+///
+/// Returns an `Err` if the snapshot could not be created.
+pub(crate) fn create(pool: &str) -> Result<String> {
+    let name = format!(
+        "{}{}",
+        CHECKPOINT_PREFIX,
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+    );
+    let snapshot = format!("{}@{}", iocroot(pool), name);
+
+    let mut cmd = Command::new("zfs");
+    cmd.arg("snapshot").arg("-r").arg(&snapshot);
+    run_and_indent(cmd).map_err(|err| Error::ZfsSnapshot(snapshot, err))?;
+
+    Ok(name)
+}
+
+/// Rolls back the iocage datasets in the active pool to a checkpoint and removes the checkpoint.
+///
+/// # Errors
+///
+/// Returns an `Err` if:
+///
+/// * The active iocage pool could not be determined
+/// * The checkpoint does not exist
+/// * A snapshot taken after the checkpoint has clones which would not be destroyed, in which case
+///   nothing is changed
+/// * Any jail or dataset could not be destroyed or rolled back
+pub fn rollback_checkpoint(checkpoint: &str) -> Result<()> {
+    let pool = iocage_pool()?;
+    let iocroot = iocroot(&pool);
+    let jails_root = format!("{}/jails/", iocroot);
+
+    section!("Rolling back to checkpoint '{}'", checkpoint);
+
+    let datasets = list_datasets(&iocroot, "filesystem")?;
+    let suffix = format!("@{}", checkpoint);
+    let checkpointed = list_datasets(&iocroot, "snapshot")?
+        .into_iter()
+        .filter_map(|snapshot| snapshot.strip_suffix(&suffix).map(str::to_string))
+        .collect::<BTreeSet<_>>();
+    if !checkpointed.contains(&iocroot) {
+        return Err(Error::CheckpointNotFound(checkpoint.to_string()));
+    }
+
+    // Only the top-most datasets created after the checkpoint need to be destroyed as their
+    // descendants are destroyed along with them
+    let created = datasets
+        .iter()
+        .filter(|dataset| !checkpointed.contains(*dataset))
+        .filter(|dataset| match dataset.rfind('/') {
+            Some(idx) => checkpointed.contains(&dataset[..idx]),
+            None => false,
+        })
+        .collect::<Vec<_>>();
+    ensure_no_clones(&iocroot, checkpoint, &checkpointed, &created)?;
+    for dataset in created {
+        match dataset.strip_prefix(&jails_root) {
+            Some(jail_name) if !jail_name.contains('/') => {
+                info!("Destroying jail '{}'", jail_name);
+                run_iocage_destroy(jail_name)?;
+            }
+            _ => {
+                info!("Destroying dataset '{}'", dataset);
+                let mut cmd = Command::new("zfs");
+                cmd.arg("destroy").arg("-r").arg(dataset);
+                run_and_indent(cmd).map_err(|err| Error::ZfsDestroy(dataset.clone(), err))?;
+            }
+        }
+    }
+
+    for dataset in checkpointed.iter().filter(|d| !d.starts_with(&jails_root)) {
+        info!("Rolling back dataset '{}'", dataset);
+        let mut cmd = Command::new("zfs");
+        cmd.arg("rollback")
+            .arg("-r")
+            .arg(format!("{}{}", dataset, suffix));
+        run_and_indent(cmd).map_err(|err| Error::ZfsRollback(dataset.clone(), err))?;
+    }
+
+    info!("Removing checkpoint '{}'", checkpoint);
+    let snapshot = format!("{}{}", iocroot, suffix);
+    let mut cmd = Command::new("zfs");
+    cmd.arg("destroy").arg("-r").arg(&snapshot);
+    run_and_indent(cmd).map_err(|err| Error::ZfsDestroy(snapshot, err))?;
+
+    section!("Rolled back to checkpoint '{}' successfully", checkpoint);
+
+    Ok(())
+}
+
+/// Ensures that rolling back to a checkpoint won't fail part way on a snapshot, taken after the
+/// checkpoint, which has clones that are not destroyed along with the datasets created since.
+///
+/// `zfs rollback -r` destroys the later snapshots of a dataset but refuses to destroy a snapshot
+/// with clones, so this is checked before anything is destroyed.
+///
+/// # Errors
+///
+/// Returns an `Err` if the snapshots could not be listed or a later snapshot has such clones.
+fn ensure_no_clones(
+    iocroot: &str,
+    checkpoint: &str,
+    checkpointed: &BTreeSet<String>,
+    created: &[&String],
+) -> Result<()> {
+    let jails_root = format!("{}/jails/", iocroot);
+    let suffix = format!("@{}", checkpoint);
+    let is_destroyed = |dataset: &str| {
+        created.iter().any(|created| {
+            dataset == created.as_str() || dataset.starts_with(&format!("{}/", created))
+        })
+    };
+
+    let mut cmd = Command::new("zfs");
+    cmd.arg("list")
+        .arg("-H")
+        .arg("-r")
+        .arg("-o")
+        .arg("name,clones")
+        .arg("-s")
+        .arg("createtxg")
+        .arg("-t")
+        .arg("snapshot")
+        .arg(iocroot);
+    let stdout = cmd_stdout(cmd).map_err(Error::ZfsList)?;
+
+    let mut after_checkpoint = false;
+    for line in stdout.lines() {
+        let mut fields = line.split('\t');
+        let snapshot = fields.next().unwrap_or_default();
+        if snapshot.ends_with(&suffix) {
+            after_checkpoint = true;
+            continue;
+        }
+        let dataset = match snapshot.find('@') {
+            Some(idx) if after_checkpoint => &snapshot[..idx],
+            _ => continue,
+        };
+        if !checkpointed.contains(dataset) || dataset.starts_with(&jails_root) {
+            continue;
+        }
+        let clones = fields
+            .next()
+            .unwrap_or_default()
+            .split(',')
+            .filter(|clone| !clone.is_empty() && *clone != "-" && !is_destroyed(clone))
+            .collect::<Vec<_>>();
+        if !clones.is_empty() {
+            return Err(Error::CheckpointClones(
+                snapshot.to_string(),
+                clones.join(","),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the name of the iocage dataset in the given pool.
+fn iocroot(pool: &str) -> String {
+    format!("{}/iocage", pool)
+}
+
+/// Returns the names of all datasets of a type (i.e. `"filesystem"` or `"snapshot"`) beneath and
+/// including the given dataset.
+///
+/// # Errors
+///
+/// Returns an `Err` if the `zfs` program fails.
+fn list_datasets(root: &str, kind: &str) -> Result<Vec<String>> {
+    let mut cmd = Command::new("zfs");
+    cmd.arg("list")
+        .arg("-H")
+        .arg("-r")
+        .arg("-o")
+        .arg("name")
+        .arg("-t")
+        .arg(kind)
+        .arg(root);
+
+    Ok(cmd_stdout(cmd)
+        .map_err(Error::ZfsList)?
+        .lines()
+        .map(str::to_string)
+        .collect())
+}
@@ -0,0 +1,139 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Registration of a jail's name in DNS with RFC 2136 dynamic updates.
+//!
+//! A [`DnsRegistration`] names a zone, the server which accepts updates for it, and a TSIG key
+//! file. Once a jail is provisioned, an address record (`A` or `AAAA`) for `<jail>.<zone>` is
+//! replaced with the jail's address, and the record is deleted when the jail is destroyed by a
+//! manifest. The updates are sent with the host's `nsupdate` program (from the `bind-tools`
+//! package).
+
+use super::{spawn_and_indent_with_stdin, CmdError, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Write;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str;
+
+/// Time to live, in seconds, of a registered address record.
+pub const TTL: u32 = 300;
+
+/// A zone in which jails are registered, with the server and key used to update it.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DnsRegistration {
+    /// Zone in which the jail's name is registered, such as `jails.example.com`.
+    pub zone: String,
+    /// Server which accepts dynamic updates for the zone.
+    pub server: String,
+    /// Path of the TSIG key file on the host with which updates are signed.
+    pub key: PathBuf,
+}
+
+impl DnsRegistration {
+    /// Returns the fully qualified name of the given jail in the zone.
+    pub fn fqdn(&self, jail_name: &str) -> String {
+        format!("{}.{}.", jail_name, self.zone.trim_end_matches('.'))
+    }
+}
+
+impl fmt::Display for DnsRegistration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.zone, self.server, self.key.display())
+    }
+}
+
+impl str::FromStr for DnsRegistration {
+    type Err = String;
+
+    /// Parses a registration of the form `ZONE:SERVER:KEYFILE`, where the server may be an IPv6
+    /// address.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("registration must be ZONE:SERVER:KEYFILE; value={}", s);
+        let mut parts = s.splitn(2, ':');
+        let zone = parts.next().ok_or_else(invalid)?;
+        let rest = parts.next().ok_or_else(invalid)?;
+        let mut parts = rest.rsplitn(2, ':');
+        let key = parts.next().ok_or_else(invalid)?;
+        let server = parts.next().ok_or_else(invalid)?;
+        let server = server.trim_start_matches('[').trim_end_matches(']');
+        if [zone, server, key]
+            .iter()
+            .any(|part| part.is_empty() || part.contains(char::is_whitespace))
+        {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            zone: zone.to_string(),
+            server: server.to_string(),
+            key: PathBuf::from(key),
+        })
+    }
+}
+
+/// Returns the `nsupdate` commands which replace the address records of a jail's name with the
+/// given address, or which delete them when there is no address.
+pub fn update(registration: &DnsRegistration, jail_name: &str, addr: Option<IpAddr>) -> String {
+    let fqdn = registration.fqdn(jail_name);
+    let mut src = format!(
+        "server {}\nzone {}\n",
+        registration.server,
+        registration.zone.trim_end_matches('.')
+    );
+    src.push_str(&format!("update delete {} A\n", fqdn));
+    src.push_str(&format!("update delete {} AAAA\n", fqdn));
+    if let Some(addr) = addr {
+        let rtype = if addr.is_ipv4() { "A" } else { "AAAA" };
+        src.push_str(&format!("update add {} {} {} {}\n", fqdn, TTL, rtype, addr));
+    }
+    src.push_str("send\n");
+    src
+}
+
+/// Registers a jail's name with its address in the registration's zone.
+///
+/// # Errors
+///
+/// Returns an `Err` if the update could not be sent or was refused.
+pub(crate) fn register(
+    registration: &DnsRegistration,
+    jail_name: &str,
+    addr: IpAddr,
+) -> Result<()> {
+    nsupdate(registration, &update(registration, jail_name, Some(addr)))
+        .map_err(|err| Error::DnsUpdate(registration.fqdn(jail_name), err))
+}
+
+/// Removes a jail's name from the registration's zone.
+///
+/// # Errors
+///
+/// Returns an `Err` if the update could not be sent or was refused.
+#[cfg(feature = "manifest")]
+pub(crate) fn deregister(registration: &DnsRegistration, jail_name: &str) -> Result<()> {
+    nsupdate(registration, &update(registration, jail_name, None))
+        .map_err(|err| Error::DnsUpdate(registration.fqdn(jail_name), err))
+}
+
+/// Sends `nsupdate` commands, signed with the registration's key.
+fn nsupdate(registration: &DnsRegistration, src: &str) -> std::result::Result<(), CmdError> {
+    let mut cmd = Command::new("nsupdate");
+    cmd.arg("-k").arg(&registration.key);
+
+    let (status, _, _) = spawn_and_indent_with_stdin(cmd, |mut stdin| {
+        stdin
+            .write_all(src.as_bytes())
+            .map_err(CmdError::StdinWrite)
+    })?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(CmdError::Failed(status.code().unwrap_or(-1)))
+    }
+}
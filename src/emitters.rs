@@ -0,0 +1,52 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Emitters of descriptions of a provisioned jail for use by other tools.
+//!
+//! Each function renders a [`ProvisionReport`] in a format which another tool can consume
+//! directly, so that a jail created by this crate can be recorded alongside the rest of an
+//! infrastructure.
+
+use super::ProvisionReport;
+use std::collections::BTreeMap;
+
+/// Returns a JSON object describing the jail which follows the protocol of Terraform's `external`
+/// data source.
+///
+/// The `external` data source only accepts an object of string values, so the jail's tags are
+/// included as a JSON encoded object in the `tags` value, which can be decoded in a configuration
+/// with `jsondecode(data.external.jail.result.tags)`. The other values are the jail's `name`,
+/// `uuid`, `ip` (with its prefix length), `address` (without its prefix length), and `release`.
+pub fn terraform(report: &ProvisionReport) -> String {
+    let mut object = BTreeMap::new();
+    object.insert("name", report.name.clone());
+    object.insert("uuid", report.uuid.clone());
+    object.insert("ip", report.ip.to_string());
+    object.insert("address", report.ip.addr().to_string());
+    object.insert("release", report.release.clone());
+    object.insert(
+        "tags",
+        serde_json::to_string(&report.tags).expect("string maps always serialize"),
+    );
+
+    let mut json = serde_json::to_string_pretty(&object).expect("string maps always serialize");
+    json.push('\n');
+    json
+}
+
+/// Returns a sanoid.conf stanza for the jail's dataset which keeps the snapshots of its schedule.
+///
+/// Snapshots are taken and pruned for the dataset and its descendants (such as the jail's root),
+/// and an interval which the schedule doesn't list keeps no snapshots. A jail without a schedule
+/// keeps none at all.
+pub fn sanoid(report: &ProvisionReport) -> String {
+    let schedule = report.snapshot_schedule.unwrap_or_default();
+    let mut stanza = format!("[{}]\n", report.dataset);
+    stanza.push_str("\tfrequently = 0\n");
+    for (interval, count) in schedule.intervals() {
+        stanza.push_str(&format!("\t{} = {}\n", interval, count));
+    }
+    stanza.push_str("\tyearly = 0\n\tautosnap = yes\n\tautoprune = yes\n\trecursive = yes\n");
+    stanza
+}
@@ -0,0 +1,512 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checks of the host firewall for rules which would drop a jail's traffic.
+//!
+//! When pf or ipfw is enabled on the host, its loaded rules are inspected before a jail is
+//! created. A firewall which blocks traffic by default but has no rule passing the jail's traffic,
+//! or (for pf) a jail on a private subnet which the host doesn't translate to its own address, is
+//! reported as a [`Finding`] with a rule which would resolve it.
+//!
+//! The checks are heuristics over the rules as printed by `pfctl -s rules` and `ipfw list`: rules
+//! which only pass certain protocols or ports, or which use tables, are not considered to pass the
+//! jail's traffic.
+//!
+//! The rules suggested for pf can be loaded into the [`PF_ANCHOR`] anchor, whose rules are kept
+//! in [`PF_ANCHOR_FILE`]. For the anchor to take effect and persist across a reload, the host's
+//! `/etc/pf.conf` must reference it with:
+//!
+//! ```text
+//! nat-anchor "iocage-provision"
+//! anchor "iocage-provision"
+//! load anchor "iocage-provision" from "/etc/pf.iocage-provision.conf"
+//! ```
+
+use super::{cmd_stdout, parse, run_and_indent, sysctl, Error, Result, BRIDGE};
+use ipnet::IpNet;
+use log::debug;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::Command;
+
+/// Name of the pf anchor into which suggested rules are loaded.
+pub const PF_ANCHOR: &str = "iocage-provision";
+
+/// File which holds the rules of the [`PF_ANCHOR`] anchor.
+pub const PF_ANCHOR_FILE: &str = "/etc/pf.iocage-provision.conf";
+
+/// A firewall on the host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Firewall {
+    /// The ipfw(8) firewall.
+    Ipfw,
+    /// The pf(4) packet filter.
+    Pf,
+}
+
+impl Firewall {
+    /// Returns the name of the firewall.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ipfw => "ipfw",
+            Self::Pf => "pf",
+        }
+    }
+}
+
+impl fmt::Display for Firewall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A problem with the host firewall's rules for a jail, along with a rule which would resolve it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    /// The firewall with the problem.
+    pub firewall: Firewall,
+    /// A description of the problem.
+    pub problem: String,
+    /// A rule, in the firewall's own syntax, which would resolve the problem.
+    pub rule: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}; suggested rule: {}",
+            self.firewall, self.problem, self.rule
+        )
+    }
+}
+
+/// The network of a jail, as seen by the host firewall.
+#[derive(Clone, Debug)]
+pub struct JailNet {
+    /// IP address & subnet mask of the jail.
+    pub ip: IpNet,
+    /// Whether or not the jail has a VNET network stack, whose traffic passes through an `epair`
+    /// interface and the host bridge, rather than sharing the host's network stack.
+    pub vnet: bool,
+    /// The host's uplink interface.
+    pub uplink: String,
+    /// The addresses of the host's uplink interface.
+    pub uplink_addrs: Vec<IpAddr>,
+}
+
+impl JailNet {
+    /// Returns the host interfaces which the jail's traffic is filtered on.
+    ///
+    /// An interface of `epair` stands for every `epair` interface, as in pf's interface groups.
+    fn interfaces(&self) -> Vec<&str> {
+        if self.vnet {
+            vec!["epair", BRIDGE]
+        } else {
+            vec![self.uplink.as_str()]
+        }
+    }
+
+    /// Returns whether or not an address in a rule, such as `any` or `10.0.0.0/24`, covers the
+    /// jail's address.
+    ///
+    /// The host's own addresses (ipfw's `me`) include the address of a shared-IP jail.
+    fn covers(&self, addr: &str) -> bool {
+        match addr {
+            "any" => true,
+            "me" => !self.vnet,
+            addr => match addr.parse::<IpNet>() {
+                Ok(net) => net.contains(&self.ip.addr()),
+                Err(_) => matches!(addr.parse::<IpAddr>(), Ok(addr) if addr == self.ip.addr()),
+            },
+        }
+    }
+
+    /// Returns whether or not traffic from the jail must be translated to the host's address to
+    /// reach beyond the host, that is when its address is private and not on the uplink's subnet.
+    fn needs_nat(&self) -> bool {
+        let private = match self.ip.addr() {
+            IpAddr::V4(addr) => addr.is_private(),
+            IpAddr::V6(_) => false,
+        };
+
+        private && !self.uplink_addrs.iter().any(|addr| self.ip.contains(addr))
+    }
+}
+
+/// Returns the firewalls which are enabled on the host.
+///
+/// A firewall whose kernel module isn't loaded, or whose state could not be determined, is
+/// considered to be disabled.
+pub(crate) fn enabled() -> Vec<Firewall> {
+    let mut firewalls = Vec::new();
+
+    if is_loaded(Firewall::Pf) {
+        let mut cmd = Command::new("pfctl");
+        cmd.arg("-s").arg("info");
+        match cmd_stdout(cmd) {
+            Ok(stdout) if stdout.contains("Status: Enabled") => firewalls.push(Firewall::Pf),
+            Ok(_) => {}
+            Err(err) => debug!("could not determine pf status, skipping; err={}", err),
+        }
+    }
+    if is_loaded(Firewall::Ipfw) {
+        match sysctl("net.inet.ip.fw.enable") {
+            Ok(value) if value == "1" => firewalls.push(Firewall::Ipfw),
+            Ok(_) => {}
+            Err(err) => debug!("could not determine ipfw status, skipping; err={}", err),
+        }
+    }
+
+    firewalls
+}
+
+/// Returns whether or not the kernel module of a firewall is loaded.
+///
+/// The firewall's control program is only run once its module is known to be loaded, as it
+/// otherwise prints an error of its own.
+fn is_loaded(firewall: Firewall) -> bool {
+    let mut cmd = Command::new("kldstat");
+    cmd.arg("-q").arg("-m").arg(firewall.as_str());
+    match cmd_stdout(cmd) {
+        Ok(_) => true,
+        Err(err) => {
+            debug!(
+                "firewall module not loaded; firewall={}, err={}",
+                firewall, err
+            );
+            false
+        }
+    }
+}
+
+/// Returns the addresses of a host interface, or none if they could not be read.
+pub(crate) fn interface_addrs(interface: &str) -> Vec<IpAddr> {
+    let mut cmd = Command::new("ifconfig");
+    cmd.arg(interface).arg("inet");
+    match cmd_stdout(cmd) {
+        Ok(stdout) => parse::interface_addrs(&stdout),
+        Err(err) => {
+            debug!(
+                "could not read interface addresses; interface={}, err={}",
+                interface, err
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// Checks the loaded rules of the given firewalls for problems with a jail's traffic.
+///
+/// # Errors
+///
+/// Returns an `Err` if the rules of a firewall could not be read.
+pub(crate) fn check(firewalls: &[Firewall], net: &JailNet) -> Result<Vec<Finding>> {
+    let mut findings = Vec::new();
+    for firewall in firewalls {
+        match firewall {
+            Firewall::Pf => {
+                let rules = show(&["pfctl", "-s", "rules"])?;
+                let nat = show(&["pfctl", "-s", "nat"])?;
+                findings.extend(check_pf(net, &rules, &nat));
+            }
+            Firewall::Ipfw => {
+                let rules = show(&["ipfw", "list"])?;
+                findings.extend(check_ipfw(net, &rules));
+            }
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Checks pf's filter rules, as printed by `pfctl -s rules`, and translation rules, as printed by
+/// `pfctl -s nat`, for problems with a jail's traffic.
+pub fn check_pf(net: &JailNet, rules: &str, nat: &str) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let rules = rules.lines().map(PfRule::new).collect::<Vec<_>>();
+
+    let blocks_by_default = rules
+        .iter()
+        .any(|rule| rule.action == "block" && rule.on.is_none() && rule.covers_all());
+    let passes = rules.iter().any(|rule| {
+        rule.action == "pass" && rule.is_on(&net.interfaces()) && rule.covers_jail(net)
+    });
+    if blocks_by_default && !passes {
+        let rule = if net.vnet {
+            String::from("pass quick on epair all")
+        } else {
+            format!("pass quick inet from {} to any", net.ip.addr())
+        };
+        findings.push(Finding {
+            firewall: Firewall::Pf,
+            problem: String::from(
+                "blocks traffic by default and no rule passes the jail's traffic",
+            ),
+            rule,
+        });
+    }
+
+    if net.needs_nat() {
+        let translates = nat
+            .lines()
+            .map(PfRule::new)
+            .any(|rule| rule.action == "nat" && rule.covers_jail(net));
+        if !translates {
+            findings.push(Finding {
+                firewall: Firewall::Pf,
+                problem: format!(
+                    "does not translate the jail's private subnet {} to the host's address",
+                    net.ip.trunc()
+                ),
+                rule: format!(
+                    "nat on {uplink} inet from {subnet} to any -> ({uplink})",
+                    subnet = net.ip.trunc(),
+                    uplink = net.uplink,
+                ),
+            });
+        }
+    }
+
+    findings
+}
+
+/// Checks ipfw's rules, as printed by `ipfw list`, for problems with a jail's traffic.
+pub fn check_ipfw(net: &JailNet, rules: &str) -> Vec<Finding> {
+    let rules = rules.lines().map(IpfwRule::new).collect::<Vec<_>>();
+
+    // The last rule is the default rule, which denies all traffic unless the kernel was built to
+    // accept it by default
+    let denies_by_default = match rules.last() {
+        Some(rule) => {
+            matches!(rule.action, "deny" | "reset" | "unreach")
+                && rule.via.is_none()
+                && rule.is_any()
+        }
+        None => false,
+    };
+    let allows = rules.iter().any(|rule| {
+        matches!(rule.action, "allow" | "accept" | "pass" | "permit")
+            && rule.proto == "ip"
+            && rule.is_via(&net.interfaces())
+            && (net.covers(rule.from) || net.covers(rule.to))
+    });
+
+    if denies_by_default && !allows {
+        let rule = if net.vnet {
+            String::from("allow ip from any to any via epair*")
+        } else {
+            format!("allow ip from {} to any keep-state", net.ip.addr())
+        };
+        vec![Finding {
+            firewall: Firewall::Ipfw,
+            problem: String::from(
+                "denies traffic by default and no rule allows the jail's traffic",
+            ),
+            rule,
+        }]
+    } else {
+        Vec::new()
+    }
+}
+
+/// Returns the rules of the [`PF_ANCHOR`] anchor with the given rules added, given the anchor's
+/// existing rules.
+///
+/// Rules which are already present are not added again, and translation rules are placed before
+/// filter rules as pf requires.
+pub fn pf_anchor_rules(existing: &str, rules: &[&str]) -> String {
+    let mut lines = existing
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>();
+    for rule in rules {
+        if !lines.contains(rule) {
+            lines.push(rule);
+        }
+    }
+    // A stable sort keeps the order of the rules within each kind
+    lines.sort_by_key(|line| !line.starts_with("nat "));
+
+    let mut src = lines.join("\n");
+    src.push('\n');
+    src
+}
+
+/// Loads the suggested pf rules of the given findings into the [`PF_ANCHOR`] anchor, adding them
+/// to the [`PF_ANCHOR_FILE`] so that they persist.
+///
+/// Findings for other firewalls are not fixed.
+///
+/// # Errors
+///
+/// Returns an `Err` if the anchor isn't referenced by pf's main ruleset or if its rules could not
+/// be written or loaded.
+pub(crate) fn fix_pf(findings: &[Finding]) -> Result<()> {
+    let rules = findings
+        .iter()
+        .filter(|finding| finding.firewall == Firewall::Pf)
+        .map(|finding| finding.rule.as_str())
+        .collect::<Vec<_>>();
+    if rules.is_empty() {
+        return Ok(());
+    }
+
+    let anchors = show(&["pfctl", "-s", "Anchors"])?;
+    if !anchors.lines().any(|anchor| anchor.trim() == PF_ANCHOR) {
+        return Err(Error::PfAnchorMissing(PF_ANCHOR));
+    }
+
+    let path = Path::new(PF_ANCHOR_FILE);
+    let existing = match fs::read_to_string(path) {
+        Ok(existing) => existing,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(Error::WriteFirewallRules(path.to_path_buf(), err)),
+    };
+    fs::write(path, pf_anchor_rules(&existing, &rules))
+        .map_err(|err| Error::WriteFirewallRules(path.to_path_buf(), err))?;
+
+    let mut cmd = Command::new("pfctl");
+    cmd.arg("-a").arg(PF_ANCHOR).arg("-f").arg(path);
+    run_and_indent(cmd).map_err(Error::LoadFirewallRules)
+}
+
+/// Returns the standard output of a command which reads the state of a firewall.
+///
+/// # Errors
+///
+/// Returns an `Err` if the command fails.
+fn show(argv: &[&str]) -> Result<String> {
+    let mut cmd = Command::new(argv[0]);
+    cmd.args(&argv[1..]);
+
+    cmd_stdout(cmd).map_err(Error::FirewallQuery)
+}
+
+/// The parts of a pf rule, as printed by `pfctl`, which the checks consider.
+#[derive(Debug)]
+struct PfRule<'a> {
+    action: &'a str,
+    on: Option<&'a str>,
+    from: Option<&'a str>,
+    words: Vec<&'a str>,
+}
+
+impl<'a> PfRule<'a> {
+    fn new(line: &'a str) -> Self {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let after = |keyword: &str| {
+            words
+                .iter()
+                .position(|word| *word == keyword)
+                .and_then(|idx| words.get(idx + 1))
+                .copied()
+        };
+
+        Self {
+            action: words.first().copied().unwrap_or_default(),
+            on: after("on"),
+            from: after("from"),
+            words,
+        }
+    }
+
+    /// Returns whether or not the rule applies to all traffic of its interface, rather than only
+    /// to some protocols or ports.
+    fn covers_all(&self) -> bool {
+        self.words.contains(&"all")
+            && !self.words.contains(&"proto")
+            && !self.words.contains(&"port")
+    }
+
+    /// Returns whether or not the rule applies to the jail's traffic on any interface it passes.
+    fn covers_jail(&self, net: &JailNet) -> bool {
+        if self.words.contains(&"proto") || self.words.contains(&"port") {
+            return false;
+        }
+
+        self.words.contains(&"all") || matches!(self.from, Some(from) if net.covers(from))
+    }
+
+    /// Returns whether or not the rule applies on any of the given interfaces, which is so when it
+    /// applies on all interfaces.
+    fn is_on(&self, interfaces: &[&str]) -> bool {
+        match self.on {
+            Some(on) => is_interface(on, interfaces),
+            None => true,
+        }
+    }
+}
+
+/// The parts of an ipfw rule, as printed by `ipfw list`, which the checks consider.
+#[derive(Debug)]
+struct IpfwRule<'a> {
+    action: &'a str,
+    proto: &'a str,
+    from: &'a str,
+    to: &'a str,
+    via: Option<&'a str>,
+    has_port: bool,
+}
+
+impl<'a> IpfwRule<'a> {
+    fn new(line: &'a str) -> Self {
+        // A rule is its number, action, protocol, then `from SRC [PORTS] to DST [PORTS] ...`
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let word = |idx: usize| words.get(idx).copied().unwrap_or_default();
+        let position = |keyword: &str| words.iter().position(|word| *word == keyword);
+        let from = position("from");
+        let to = position("to");
+        let via = ["via", "recv", "xmit"]
+            .iter()
+            .find_map(|keyword| position(keyword))
+            .map(|idx| word(idx + 1));
+        // A port follows an address directly, before the next keyword
+        let has_port = match (from, to) {
+            (Some(from), Some(to)) => {
+                let port = words.get(to + 2);
+                to > from + 2
+                    || matches!(port, Some(word) if word.starts_with(|c: char| c.is_ascii_digit()))
+            }
+            _ => false,
+        };
+
+        Self {
+            action: word(1),
+            proto: word(2),
+            from: from.map(|idx| word(idx + 1)).unwrap_or_default(),
+            to: to.map(|idx| word(idx + 1)).unwrap_or_default(),
+            via,
+            has_port,
+        }
+    }
+
+    /// Returns whether or not the rule applies to all traffic between any addresses.
+    fn is_any(&self) -> bool {
+        self.proto == "ip" && self.from == "any" && self.to == "any" && !self.has_port
+    }
+
+    /// Returns whether or not the rule applies on any of the given interfaces, which is so when it
+    /// applies on all interfaces.
+    fn is_via(&self, interfaces: &[&str]) -> bool {
+        !self.has_port
+            && match self.via {
+                Some(via) => is_interface(via, interfaces),
+                None => true,
+            }
+    }
+}
+
+/// Returns whether or not an interface named in a rule is one of the given interfaces, where an
+/// interface of `epair` stands for every `epair` interface.
+fn is_interface(name: &str, interfaces: &[&str]) -> bool {
+    let name = name.trim_end_matches('*');
+    interfaces
+        .iter()
+        .any(|interface| name == *interface || (*interface == "epair" && name.starts_with("epair")))
+}
@@ -0,0 +1,228 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Queries of the host system's name, users, groups, kernel, and file permissions.
+//!
+//! On Unix hosts the real system databases are queried. With the `mock-host` feature,
+//! deterministic values are returned instead so that the crate's logic can be tested on any
+//! development platform:
+//!
+//! * The effective user is `root` and may write to any path
+//! * The hostname is `host.example.com`
+//! * The kernel release is `13.0-RELEASE`
+//! * Every user name exists with a uid of `1001`, a primary group named `mock` with a gid of
+//!   `1001`, no supplementary groups, and `/bin/sh` as its shell
+//!
+//! Other non-Unix targets only build with stubs where the effective user is never `root`, the
+//! hostname is empty, no path is writable, and no users or groups exist.
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+/// A user on the host system.
+#[derive(Clone, Debug)]
+pub(crate) struct User {
+    name: OsString,
+    uid: u32,
+    primary_group_id: u32,
+    shell: PathBuf,
+}
+
+impl User {
+    /// Returns the name of the user.
+    pub(crate) fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Returns the user ID (i.e. `uid`) of the user.
+    pub(crate) fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Returns the group ID (i.e. `gid`) of the user's primary group.
+    pub(crate) fn primary_group_id(&self) -> u32 {
+        self.primary_group_id
+    }
+
+    /// Returns the path of the user's login shell.
+    pub(crate) fn shell(&self) -> &Path {
+        &self.shell
+    }
+}
+
+/// A group on the host system.
+#[derive(Clone, Debug)]
+pub(crate) struct Group {
+    name: OsString,
+    gid: u32,
+}
+
+impl Group {
+    /// Returns the name of the group.
+    pub(crate) fn name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Returns the group ID (i.e. `gid`) of the group.
+    pub(crate) fn gid(&self) -> u32 {
+        self.gid
+    }
+}
+
+pub(crate) use imp::{
+    effective_uid, group_by_gid, hostname, is_writable, kernel_release, user_by_name, user_groups,
+};
+
+#[cfg(all(unix, not(feature = "mock-host")))]
+mod imp {
+    use super::{Group, User};
+    use nix::sys::utsname;
+    use nix::unistd;
+    use std::path::Path;
+    use users::os::unix::UserExt;
+
+    /// Returns the effective user ID of the current process.
+    pub(crate) fn effective_uid() -> u32 {
+        users::get_effective_uid()
+    }
+
+    /// Returns the release of the running kernel (i.e. `uname -r`).
+    pub(crate) fn kernel_release() -> String {
+        utsname::uname().release().to_string()
+    }
+
+    /// Returns the name of the host (i.e. `uname -n`).
+    pub(crate) fn hostname() -> String {
+        utsname::uname().nodename().to_string()
+    }
+
+    /// Returns whether or not the current process may write to the given path.
+    pub(crate) fn is_writable(path: &Path) -> bool {
+        unistd::access(path, unistd::AccessFlags::W_OK).is_ok()
+    }
+
+    /// Returns the user with the given name, if one exists.
+    pub(crate) fn user_by_name(name: &str) -> Option<User> {
+        users::get_user_by_name(name).map(|user| User {
+            name: user.name().to_os_string(),
+            uid: user.uid(),
+            primary_group_id: user.primary_group_id(),
+            shell: user.shell().to_path_buf(),
+        })
+    }
+
+    /// Returns the group with the given group ID, if one exists.
+    pub(crate) fn group_by_gid(gid: u32) -> Option<Group> {
+        users::get_group_by_gid(gid).map(group)
+    }
+
+    /// Returns all groups of a user, including their primary group.
+    pub(crate) fn user_groups(user: &User) -> Vec<Group> {
+        users::get_user_groups(user.name(), user.primary_group_id())
+            .unwrap_or_default()
+            .into_iter()
+            .map(group)
+            .collect()
+    }
+
+    fn group(group: users::Group) -> Group {
+        Group {
+            name: group.name().to_os_string(),
+            gid: group.gid(),
+        }
+    }
+}
+
+#[cfg(feature = "mock-host")]
+mod imp {
+    use super::{Group, User};
+    use std::path::Path;
+
+    /// The uid and gid of every mock user and their primary group.
+    const MOCK_ID: u32 = 1001;
+
+    /// Returns the effective user ID of the current process, which is always `root`.
+    pub(crate) fn effective_uid() -> u32 {
+        0
+    }
+
+    /// Returns a fixed kernel release.
+    pub(crate) fn kernel_release() -> String {
+        String::from("13.0-RELEASE")
+    }
+
+    /// Returns a fixed hostname.
+    pub(crate) fn hostname() -> String {
+        String::from("host.example.com")
+    }
+
+    /// Returns `true` as the mock user may write to any path.
+    pub(crate) fn is_writable(_path: &Path) -> bool {
+        true
+    }
+
+    /// Returns a mock user with the given name.
+    pub(crate) fn user_by_name(name: &str) -> Option<User> {
+        Some(User {
+            name: name.into(),
+            uid: MOCK_ID,
+            primary_group_id: MOCK_ID,
+            shell: "/bin/sh".into(),
+        })
+    }
+
+    /// Returns the mock group with the given group ID.
+    pub(crate) fn group_by_gid(gid: u32) -> Option<Group> {
+        Some(Group {
+            name: "mock".into(),
+            gid,
+        })
+    }
+
+    /// Returns the groups of a mock user, which is only their primary group.
+    pub(crate) fn user_groups(user: &User) -> Vec<Group> {
+        group_by_gid(user.primary_group_id()).into_iter().collect()
+    }
+}
+
+#[cfg(all(not(unix), not(feature = "mock-host")))]
+mod imp {
+    use super::{Group, User};
+    use std::path::Path;
+
+    /// Returns an effective user ID which is never `root`.
+    pub(crate) fn effective_uid() -> u32 {
+        u32::MAX
+    }
+
+    /// Returns an unknown kernel release.
+    pub(crate) fn kernel_release() -> String {
+        String::from("unknown")
+    }
+
+    /// Returns an empty hostname.
+    pub(crate) fn hostname() -> String {
+        String::new()
+    }
+
+    /// Returns `false` as no path is writable.
+    pub(crate) fn is_writable(_path: &Path) -> bool {
+        false
+    }
+
+    /// Returns `None` as no users exist.
+    pub(crate) fn user_by_name(_name: &str) -> Option<User> {
+        None
+    }
+
+    /// Returns `None` as no groups exist.
+    pub(crate) fn group_by_gid(_gid: u32) -> Option<Group> {
+        None
+    }
+
+    /// Returns no groups.
+    pub(crate) fn user_groups(_user: &User) -> Vec<Group> {
+        Vec::new()
+    }
+}
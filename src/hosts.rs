@@ -0,0 +1,142 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Management of `/etc/hosts` entries for jails, for small environments without internal DNS.
+//!
+//! Once a jail is provisioned, an entry with its name and address is added to the host's hosts
+//! file and the jail's own hosts file is seeded with entries for the host and for the other jails
+//! which were added the same way. Every line which is written is marked with a trailing comment,
+//! so that a jail's entry is replaced when it is provisioned again and removed when it is
+//! destroyed without disturbing any other line of the file.
+
+use super::{host, iocage_exec, script, Error, Result};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Path of the hosts file, on the host and in a jail.
+pub const HOSTS_FILE: &str = "/etc/hosts";
+
+/// Comment which marks every line written by this crate.
+pub const MARKER: &str = "# iocage-provision";
+
+/// Returns the marked hosts file line for a jail.
+pub fn entry(jail_name: &str, addr: IpAddr) -> String {
+    format!("{}\t{}\t{} jail={}", addr, jail_name, MARKER, jail_name)
+}
+
+/// Returns the contents of a hosts file with the jail's entry added, replacing any earlier entry
+/// for the jail.
+pub fn with_entry(contents: &str, jail_name: &str, addr: IpAddr) -> String {
+    let mut contents = without_entry(contents, jail_name);
+    contents.push_str(&entry(jail_name, addr));
+    contents.push('\n');
+    contents
+}
+
+/// Returns the contents of a hosts file with the jail's entry removed.
+pub fn without_entry(contents: &str, jail_name: &str) -> String {
+    contents
+        .lines()
+        .filter(|line| marked_jail(line) != Some(jail_name))
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// Returns the marked lines with which a jail's hosts file is seeded, given the contents of the
+/// host's hosts file.
+///
+/// These are the host's own entries, that is those with a non-loopback address which name the
+/// given hostname or its first label, and the entries of every other jail.
+pub fn seed(contents: &str, hostname: &str, jail_name: &str) -> String {
+    let short = hostname.split('.').next().unwrap_or(hostname);
+    let mut seeded = String::new();
+    for line in contents.lines() {
+        match marked_jail(line) {
+            Some(name) if name != jail_name => {
+                seeded.push_str(line);
+                seeded.push('\n');
+            }
+            Some(_) => {}
+            None => {
+                let fields = line.split('#').next().unwrap_or_default();
+                let mut fields = fields.split_whitespace();
+                let addr = match fields.next().and_then(|addr| addr.parse::<IpAddr>().ok()) {
+                    Some(addr) if !addr.is_loopback() => addr,
+                    _ => continue,
+                };
+                let names = fields.collect::<Vec<_>>();
+                if !hostname.is_empty()
+                    && names.iter().any(|name| *name == hostname || *name == short)
+                {
+                    seeded.push_str(&format!("{}\t{}\t{} host\n", addr, names.join(" "), MARKER));
+                }
+            }
+        }
+    }
+    seeded
+}
+
+/// Returns the name of the jail whose entry is on the given line, if it is one.
+fn marked_jail(line: &str) -> Option<&str> {
+    line.find(MARKER)
+        .and_then(|idx| line[idx + MARKER.len()..].trim().strip_prefix("jail="))
+}
+
+/// Adds a jail's entry to the host's hosts file.
+///
+/// # Errors
+///
+/// Returns an `Err` if the hosts file could not be read or written.
+pub(crate) fn add_host_entry(jail_name: &str, addr: IpAddr) -> Result<()> {
+    let path = Path::new(HOSTS_FILE);
+    let contents =
+        fs::read_to_string(path).map_err(|err| Error::WriteHosts(path.to_path_buf(), err))?;
+
+    fs::write(path, with_entry(&contents, jail_name, addr))
+        .map_err(|err| Error::WriteHosts(path.to_path_buf(), err))
+}
+
+/// Removes a jail's entry from the host's hosts file, if it has one.
+///
+/// # Errors
+///
+/// Returns an `Err` if the hosts file could not be read or written.
+pub(crate) fn remove_host_entry(jail_name: &str) -> Result<()> {
+    let path = Path::new(HOSTS_FILE);
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(Error::WriteHosts(path.to_path_buf(), err)),
+    };
+    if contents
+        .lines()
+        .all(|line| marked_jail(line) != Some(jail_name))
+    {
+        return Ok(());
+    }
+
+    fs::write(path, without_entry(&contents, jail_name))
+        .map_err(|err| Error::WriteHosts(path.to_path_buf(), err))
+}
+
+/// Seeds a jail's hosts file with entries for the host and the other jails in the host's hosts
+/// file.
+///
+/// # Errors
+///
+/// Returns an `Err` if the host's hosts file could not be read or the jail's could not be
+/// written.
+pub(crate) fn seed_jail(jail_name: &str) -> Result<()> {
+    let path = Path::new(HOSTS_FILE);
+    let contents =
+        fs::read_to_string(path).map_err(|err| Error::WriteHosts(path.to_path_buf(), err))?;
+
+    iocage_exec(
+        jail_name,
+        script::hosts_entries(&seed(&contents, &host::hostname(), jail_name)),
+    )
+    .map_err(Error::ExecHostsEntries)
+}
@@ -2,28 +2,57 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at https://mozilla.org/MPL/2.0/.
 
+//! Creates and provisions iocage based FreeBSD jails.
+//!
+//! The library is used by the `iocage-provision` CLI and can be embedded in other programs. When
+//! used as a library, disable the default features to avoid the CLI's dependencies:
+//!
+//! ```toml
+//! [dependencies]
+//! iocage-provision = { version = "0.2.0", default-features = false }
+//! ```
+//!
+//! The following features are available:
+//!
+//! * `application`: the dependencies of the CLI (enabled by default, implies `manifest`)
+//! * `manifest`: TOML manifests, and the state file of managed jails used by `plan`, `apply`,
+//!   and `prune_plan`. Without this feature, provisioned jails are not recorded in the state file.
+//! * `mock-host`: replaces queries of the host's users, groups, and kernel with deterministic
+//!   values so that the crate builds and its logic can be tested on non-FreeBSD development
+//!   platforms. Never enable this feature in a build which provisions real jails.
+//!
+//! Provisioning runs on FreeBSD only, but the model of a jail in the [`spec`] module, along with
+//! [`Manifest`], the [`parse`] functions, and the [`emitters`], builds on any platform so that it
+//! can be reused by cross-platform tooling such as dashboards or CI jobs which lint manifests.
+
 #![doc(html_root_url = "https://docs.rs/iocage-provision/0.2.1-dev")]
 //#![deny(missing_docs)]
 
+use host::{Group, User};
 use ipnet::IpNet;
-use log::{debug, info};
-use nix::sys::utsname;
+use log::{debug, info, trace};
+use script::{heredoc, heredoc_to_file, quote, UserHome};
+use std::collections::BTreeMap;
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
 use std::io::{self, BufRead, BufReader, Write};
 use std::net::{self, IpAddr};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{ChildStdin, Command, ExitStatus, Stdio};
 use std::result;
 use std::str;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::sync::Mutex;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use tempfile::NamedTempFile;
-use users::{os::unix::UserExt, Group, User};
+use user_data::UserData;
 
 macro_rules! section {
     ($($arg:tt)+) => (
         if log::max_level() == log::LevelFilter::Info {
-            println!("--- {}", format!($($arg)+));
+            $crate::print_progress(format_args!("--- {}", format!($($arg)+)));
         } else {
             log::info!($($arg)+);
         }
@@ -33,7 +62,7 @@ macro_rules! section {
 macro_rules! output {
     ($($arg:tt)+) => (
         if log::max_level() == log::LevelFilter::Info {
-            println!("        {}", format!($($arg)+));
+            $crate::print_progress(format_args!("        {}", format!($($arg)+)));
         } else {
             log::info!($($arg)+);
         }
@@ -43,44 +72,741 @@ macro_rules! output {
 macro_rules! eoutput {
     ($($arg:tt)+) => (
         if log::max_level() == log::LevelFilter::Info {
-            eprintln!("        {}", format!($($arg)+));
-        } else {
+            let line = format!("        {}", format!($($arg)+));
+            if !$crate::progress::capture(&line) {
+                eprintln!("{}", $crate::stamped(line));
+            }
+        } else if log::max_level() > log::LevelFilter::Info {
             log::warn!($($arg)+);
         }
     )
 }
 
+mod audit;
+mod capture;
+mod checkpoint;
+pub mod dns;
+pub mod emitters;
+pub mod firewall;
+mod host;
+pub mod hosts;
+#[cfg(feature = "manifest")]
+mod manifest;
+pub mod parse;
+mod process;
+mod progress;
+pub mod recipe;
+mod redact;
+pub mod release;
+mod rename;
+mod run_log;
+pub mod script;
+pub mod secret;
+mod set_ip;
+pub mod spec;
+#[cfg(feature = "manifest")]
+mod state;
+#[cfg(feature = "manifest")]
+mod template;
+pub mod user_data;
+#[cfg(feature = "manifest")]
+mod verify_boot;
+
+pub use audit::{audit_log, check_audit_log, set_audit_log};
+pub use checkpoint::rollback_checkpoint;
+#[cfg(feature = "manifest")]
+pub use manifest::{Manifest, ManifestError, MANIFEST_SCHEMA};
+pub use process::{
+    cancelled, set_child_env, set_child_priority, terminate_children, terminate_on_signal,
+    ChildPriority, DEFAULT_CHILD_ENV,
+};
+pub use redact::{redact, register_secret, REDACTED};
+pub use rename::rename_jail;
+pub use run_log::{start_run_log, write_run_log};
+pub use set_ip::set_jail_ip;
+pub use spec::{
+    property_changes, IdConflictPolicy, IfExistsPolicy, IocageVersion, JailInfo, JailSpec,
+    MonitoringAgent, NextSteps, Phase, PropertyChange, ProvisionReport, SnapshotSchedule,
+    SshHostKey, SshHostKeyType, StaleState, SysvIpc,
+};
+#[cfg(feature = "manifest")]
+pub use state::{
+    apply, expired_plan, managed_jails, plan, prune_plan, set_record_state, Action, Plan,
+};
+#[cfg(feature = "manifest")]
+pub use verify_boot::{verify_boot, BootCheck, BootFailure};
+
+/// Whether progress output is written to the standard error stream rather than standard output.
+static PROGRESS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Whether long-running steps show a progress bar rather than streaming their output.
+static PROGRESS_BARS: AtomicBool = AtomicBool::new(false);
+
+/// Whether iocage's own debug output is enabled for every iocage invocation.
+static IOCAGE_DEBUG: AtomicBool = AtomicBool::new(false);
+
+static TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+/// Seconds of silence after which a long-running step prints a heartbeat line, or 0 for never.
+static HEARTBEAT_SECS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static::lazy_static! {
+    /// `export` statements set at the top of every script run in a jail.
+    static ref EXEC_ENV: Mutex<String> = Mutex::new(String::new());
+}
+
+/// Directory on the host containing locally trusted CA certificates.
+const HOST_CA_CERTS_DIR: &str = "/usr/local/etc/ssl/certs";
+
+/// Directory in a jail where additional trusted CA certificates are installed.
+const JAIL_CA_CERTS_DIR: &str = "/usr/local/etc/ssl/certs";
+
+/// Directory on the host containing the ports tree.
+const HOST_PORTS_DIR: &str = "/usr/ports";
+
+/// Directory in a jail where the host's ports tree is mounted.
+const JAIL_PORTS_DIR: &str = "/usr/ports";
+
+/// Path of the sanoid configuration on the host, which is only updated if it exists.
+const SANOID_CONF: &str = "/usr/local/etc/sanoid/sanoid.conf";
+
+/// Maximum time to wait for a stopped jail to no longer be dying when repairing stale state.
+const DYING_JAIL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time between checks of the host's resources while waiting for it to have capacity.
+const CAPACITY_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Directory in a jail where ports distfiles are stored.
+const JAIL_PORTS_DISTFILES_DIR: &str = "/var/ports/distfiles";
+
+/// Directory in a jail where ports packages are stored.
+const JAIL_PORTS_PACKAGES_DIR: &str = "/var/ports/packages";
+
+/// Directory in a jail where ports are built.
+const JAIL_PORTS_WORK_DIR: &str = "/var/ports/work";
+
+/// Directory in a jail where a local package repository is mounted.
+const JAIL_PKG_REPO_DIR: &str = "/mnt/iocage-provision-pkg-repo";
+
+/// Name of the host bridge which iocage attaches VNET jails to by default.
+pub(crate) const BRIDGE: &str = "bridge0";
+
+/// iocage properties which are always set when a jail is created.
+const CREATE_PROPERTIES: &[&str] = &["vnet", "ip4_addr", "defaultrouter", "resolver", "boot"];
+
+/// iocage properties which identify a jail or describe its state and are never copied from
+/// another jail.
+const IDENTITY_PROPERTIES: &[&str] = &[
+    "CONFIG_VERSION",
+    "cloned_release",
+    "host_hostname",
+    "host_hostuuid",
+    "hostid",
+    "ip4_addr",
+    "ip6_addr",
+    "jail_zfs_dataset",
+    "jid",
+    "last_started",
+    "notes",
+    "release",
+    "state",
+    "type",
+];
+
+/// Time, in seconds, after which an ephemeral jail expires.
+pub const EPHEMERAL_TTL: u64 = 24 * 60 * 60;
+
+/// Path of the state file which records the jails managed by iocage-provision.
+pub const STATE_FILE: &str = "/var/db/iocage-provision/state.toml";
+
 /// A specialized `Result` type for this crate's operations.
 pub type Result<T> = result::Result<T, Error>;
 
 /// Error type for this crate.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    /// The interface of a jail's address could not be determined, such as when it uses DHCP.
+    #[error("could not determine the interface of the jail's address; jail={0}")]
+    AddressInterfaceUnknown(String),
+    /// A snapshot which rolling back to a checkpoint would destroy has clones which would remain.
+    #[error(
+        "checkpoint can't be rolled back as a later snapshot has clones; snapshot={0}, clones={1}"
+    )]
+    CheckpointClones(String, String),
+    /// A checkpoint was not found in the active iocage pool.
+    #[error("checkpoint not found; checkpoint={0}")]
+    CheckpointNotFound(String),
+    /// A CPU set names CPUs which the host doesn't have.
+    #[error("cpuset not available on host; cpuset={0}, hw.ncpu={1}")]
+    CpusetUnavailable(String, u32),
     #[error("could not generate json pkglist tempfile")]
     CreatePkglistJson(#[source] io::Error),
+    /// A requested dataset parent is not where iocage will create the jail.
+    #[error("jails can only be created beneath the active iocage pool; requested={0}, active={1}")]
+    DatasetParentUnsupported(String, String),
+    #[error("failed to determine the interface of the host's default route")]
+    DefaultInterface(#[source] GatewayError),
+    /// A jail named as a dependency does not exist.
+    #[error("jail dependency not found; jail={0}")]
+    DependencyNotFound(String),
+    #[error("failed to send dynamic DNS update; name={0}")]
+    DnsUpdate(String, #[source] CmdError),
+    #[error("failed to create automation user")]
+    ExecAutomationUser(#[source] IocageExecError),
+    #[error("failed to install ca certificates")]
+    ExecCaCerts(#[source] IocageExecError),
+    #[error("failed to create supplementary groups")]
+    ExecCopyGroups(#[source] IocageExecError),
     #[error("failed to create user group")]
     ExecCreateGroup(#[source] IocageExecError),
     #[error("failed to create user")]
     ExecCreateUser(#[source] IocageExecError),
+    #[error("failed to install cron entries")]
+    ExecCronEntries(#[source] IocageExecError),
+    #[error("failed to check for a file in jail")]
+    ExecFileCheck(#[source] IocageExecError),
+    #[error("failed to install first boot scripts")]
+    ExecFirstboot(#[source] IocageExecError),
+    #[error("failed to seed the jail's hosts file")]
+    ExecHostsEntries(#[source] IocageExecError),
+    #[error("failed to look up user or group ids in jail")]
+    ExecIdLookup(#[source] IocageExecError),
+    #[error("failed to enable IP forwarding in jail")]
+    ExecIpForwarding(#[source] IocageExecError),
+    #[error("failed to configure a mail relay")]
+    ExecMailRelay(#[source] IocageExecError),
+    #[error("failed to install build configuration")]
+    ExecMakeConf(#[source] IocageExecError),
+    #[error("failed to strip jail to a minimal base system")]
+    ExecMinimal(#[source] IocageExecError),
+    #[error("failed to set up the monitoring agent in jail")]
+    ExecMonitoring(#[source] IocageExecError),
+    #[error("failed to check jail networking")]
+    ExecNetworkCheck(#[source] IocageExecError),
+    #[error("failed to wait for jail networking")]
+    ExecNetworkWait(#[source] IocageExecError),
+    #[error("failed to enable an NTP service")]
+    ExecNtpService(#[source] IocageExecError),
+    #[error("failed to configure periodic output logging")]
+    ExecPeriodicLog(#[source] IocageExecError),
+    #[error("failed to bootstrap pkg")]
+    ExecPkgBootstrap(#[source] IocageExecError),
+    #[error("failed to install packages")]
+    ExecPkgInstall(#[source] IocageExecError),
+    #[error("failed to configure local package repository")]
+    ExecPkgRepo(#[source] IocageExecError),
+    #[error("failed to configure ports tree")]
+    ExecPortsConfig(#[source] IocageExecError),
+    #[error("failed to apply recipe in jail; recipe={0}")]
+    ExecRecipe(&'static str, #[source] IocageExecError),
+    #[error("failed to read SSH host keys")]
+    ExecSshHostKeys(#[source] IocageExecError),
     #[error("failed to enable an SSH service")]
     ExecSshService(#[source] IocageExecError),
     #[error("failed to prepare sudo config")]
     ExecSudoConfig(#[source] IocageExecError),
+    #[error("failed to configure syslog forwarding")]
+    ExecSyslogRemote(#[source] IocageExecError),
+    #[error("failed to apply user-data")]
+    ExecUserData(#[source] IocageExecError),
+    #[error("failed to configure user login class and locale")]
+    ExecUserLogin(#[source] IocageExecError),
+    /// A routing table (FIB) number is not available on the host.
+    #[error("fib not available on host; fib={0}, net.fibs={1}")]
+    FibUnavailable(u32, u32),
+    #[error("failed to query host firewall rules")]
+    FirewallQuery(#[source] CmdError),
+    #[error("gid is already used in jail; gid={0}, group={1}")]
+    GidConflict(u32, String),
+    /// The host stayed short of the free memory or load average required to provision a jail.
+    #[error("host is low on resources; shortage={0}, waited_secs={1}")]
+    HostBusy(String, u64),
+    #[error("failed to import local release; path={0}")]
+    ImportRelease(PathBuf, #[source] CmdError),
+    #[error("failed to install host prerequisites")]
+    InstallPrereqs(#[source] CmdError),
+    #[error("host network interface not found; interface={0}")]
+    InterfaceNotFound(String),
+    /// An environment variable for scripts run in the jail has a name which `sh` can't set.
+    #[error("invalid environment variable name; name={0}")]
+    InvalidExecEnv(String),
+    /// A jail's expiry is too far in the future to be represented.
+    #[error("invalid expiry, too far in the future; seconds={0}")]
+    InvalidExpiry(u64),
+    #[error("invalid package list file; path={0}, problem={1}")]
+    InvalidPkglist(PathBuf, String),
+    #[error("invalid tmpfs mount; {0}")]
+    InvalidTmpfs(String),
+    /// A user name for a user created in the jail is one which `pw` rejects.
+    #[error("invalid user name; name={0}")]
+    InvalidUserName(String),
     #[error("failed to create iocage jail")]
     IocageCreate(#[source] CmdError),
+    #[error("could not determine the name iocage gave the created jail")]
+    IocageCreatedName,
+    #[error("failed to install packages while creating iocage jail")]
+    IocageCreatePkgInstall(#[source] CmdError),
+    #[error("failed to destroy iocage jail; jail={0}")]
+    IocageDestroy(String, #[source] CmdError),
+    #[error("failed to add iocage fstab entry")]
+    IocageFstab(#[source] CmdError),
+    #[error("failed to get iocage jail properties; jail={0}")]
+    IocageGet(String, #[source] CmdError),
+    #[error("failed to list iocage jails")]
+    IocageList(#[source] CmdError),
+    #[error("failed to start a login shell in jail")]
+    IocageLogin(#[source] CmdError),
+    #[error("failed to determine active iocage pool")]
+    IocagePool(#[source] CmdError),
+    #[error("failed to rename iocage jail; jail={0}")]
+    IocageRename(String, #[source] CmdError),
+    #[error("failed to set iocage jail properties; jail={0}")]
+    IocageSet(String, #[source] CmdError),
+    #[error("failed to start iocage jail; jail={0}")]
+    IocageStart(String, #[source] CmdError),
+    #[error("failed to stop iocage jail; jail={0}")]
+    IocageStop(String, #[source] CmdError),
+    #[error("failed to get iocage jail uuid; jail={0}")]
+    IocageUuid(String, #[source] CmdError),
+    #[error("failed to determine iocage version")]
+    IocageVersion(#[source] CmdError),
+    /// A jail with the requested name already exists.
+    #[error("jail already exists; jail={0}, uuid={1}, state={2}")]
+    JailExists(String, String, String),
+    /// A jail to act on does not exist.
+    #[error("jail does not exist; jail={0}")]
+    JailNotFound(String),
+    /// A declared jail exists but was not provisioned by iocage-provision.
+    #[error("jail exists but is not managed by iocage-provision; jail={0}")]
+    JailNotManaged(String),
+    /// A stopped jail was still dying in the kernel when repairing stale state.
+    #[error("jail is still dying; name={0}, timeout_secs={1}")]
+    JailStillDying(String, u64),
+    #[error("failed to load firewall rules into pf anchor")]
+    LoadFirewallRules(#[source] CmdError),
+    /// A local userland to import as a release was not found.
+    #[error("local release not found; path={0}")]
+    LocalReleaseNotFound(PathBuf),
+    #[error("no minimal base system removal list for version; version={0}")]
+    MinimalUnsupported(String),
+    /// The jail could not connect to its pkg mirror, which usually means that its VNET interface,
+    /// default route, or a firewall is misconfigured.
+    #[error("jail could not connect to the https port of its pkg mirror; host={0}")]
+    MirrorUnreachable(String),
+    /// The jail could not resolve the host name of its pkg mirror, which usually means that its
+    /// resolver (`/etc/resolv.conf`) is misconfigured.
+    #[error("jail could not resolve its pkg mirror; host={0}")]
+    MirrorUnresolvable(String),
+    /// The Zabbix agent was requested without a server to answer.
+    #[error("monitoring agent requires a server; agent={0}")]
+    MonitoringServerRequired(&'static str),
+    /// The jail's network was not ready before the timeout, naming what was still being waited
+    /// for.
+    #[error("jail network was not ready before the timeout; waiting_for={0}, timeout={1}s")]
+    NetworkNotReady(String, u64),
     /// A system group ID was not found.
     #[error("system group id not found; gid={0}")]
     NoGid(u32),
+    /// The effective user is neither `root` nor delegated the permissions to manage jails.
+    #[error("root privileges or delegated jail permissions required; reason={0}")]
+    NotPrivileged(String),
     /// The effective user is not currently the `root` user.
     #[error("root privileges required")]
     NotRoot,
     /// A system user name was not found.
     #[error("system user not found; user={0}")]
     NoUser(String),
+    #[error("failed to parse iocage version; output={0}")]
+    ParseIocageVersion(String),
+    #[cfg(feature = "manifest")]
+    #[error("could not parse state file; path={0}")]
+    ParseState(PathBuf, #[source] toml::de::Error),
+    #[error("could not parse user-data file; path={0}")]
+    ParseUserData(PathBuf, #[source] user_data::UserDataError),
+    #[error("pf anchor is not referenced by the main ruleset; anchor={0}")]
+    PfAnchorMissing(&'static str),
+    #[error("failed to install pkgbase packages")]
+    PkgbaseInstall(#[source] CmdError),
+    /// A pkgbase userland was requested for a release which is not a FreeBSD version.
+    #[error("pkgbase requires a FreeBSD release version; release={0}")]
+    PkgbaseRelease(String),
+    #[error("could not write pkgbase repository configuration")]
+    PkgbaseRepoConf(#[source] io::Error),
+    #[error("failed to query the package which provides a host prerequisite")]
+    PrereqQuery(#[source] CmdError),
+    #[error("no package provides a required host program; program={0}")]
+    PrereqUnavailable(&'static str),
+    #[error("could not read host ca certificates; path={0}")]
+    ReadCaCerts(PathBuf, #[source] io::Error),
+    #[error("could not read host file; path={0}")]
+    ReadHostFile(PathBuf, #[source] io::Error),
+    #[error("could not read secret from the secret source; secret={0}")]
+    ReadSecret(String, #[source] io::Error),
+    #[error("could not read SSH host keys; path={0}")]
+    ReadSshHostKeys(PathBuf, #[source] io::Error),
+    #[error("could not read state file; path={0}")]
+    ReadState(PathBuf, #[source] io::Error),
+    #[error("failed to repair stale iocage state; step={0}")]
+    RepairStaleState(&'static str, #[source] CmdError),
+    #[error("secret not found in the secret source; secret={0}")]
+    SecretNotFound(String),
+    #[cfg(feature = "manifest")]
+    #[error("could not serialize state")]
+    SerializeState(#[source] toml::ser::Error),
+    #[error("failed to set up host bridge; bridge={0}")]
+    SetupBridge(&'static str, #[source] CmdError),
+    /// iocage failed to create the jail because of state left behind by an earlier jail or an
+    /// interrupted run.
+    #[error("iocage create failed on stale state; state={0}")]
+    StaleState(StaleState),
+    /// A warning was found while warnings are treated as errors.
+    #[error("warning treated as an error in strict mode; warning={0}")]
+    StrictWarning(String),
+    #[error("failed to read sysctl value; name={0}")]
+    Sysctl(&'static str, #[source] CmdError),
+    #[error("failed to parse sysctl value; name={0}, value={1}")]
+    SysctlParse(&'static str, String),
+    #[error("uid is already used in jail; uid={0}, user={1}")]
+    UidConflict(u32, String),
+    /// The installed iocage version doesn't accept a flag which is needed for a jail's spec.
+    #[error("iocage version does not support flag; version={0}, flag={1}")]
+    UnsupportedIocageFlag(IocageVersion, &'static str),
+    /// The installed iocage version is outside the range of versions which are supported.
+    #[error(
+        "unsupported iocage version; version={0}, supported={} to {}.x",
+        IocageVersion::MIN_SUPPORTED,
+        IocageVersion::MIN_SUPPORTED.major
+    )]
+    UnsupportedIocageVersion(IocageVersion),
+    #[error("could not write audit record; path={0}")]
+    WriteAuditLog(PathBuf, #[source] io::Error),
+    #[error("could not write firewall rules; path={0}")]
+    WriteFirewallRules(PathBuf, #[source] io::Error),
+    #[error("could not write hosts file; path={0}")]
+    WriteHosts(PathBuf, #[source] io::Error),
+    #[error("could not write known hosts file; path={0}")]
+    WriteKnownHosts(PathBuf, #[source] io::Error),
+    #[error("failed to write sanoid configuration; path={0}")]
+    WriteSanoidConf(PathBuf, #[source] io::Error),
+    #[error("could not write state file; path={0}")]
+    WriteState(PathBuf, #[source] io::Error),
+    #[error("failed to create zfs dataset; dataset={0}")]
+    ZfsCreate(String, #[source] CmdError),
+    #[error("failed to destroy zfs dataset; dataset={0}")]
+    ZfsDestroy(String, #[source] CmdError),
+    #[error("failed to get zfs property; dataset={0}")]
+    ZfsGet(String, #[source] CmdError),
+    #[error("failed to list zfs datasets")]
+    ZfsList(#[source] CmdError),
+    #[error("failed to rename zfs dataset; dataset={0}")]
+    ZfsRename(String, #[source] CmdError),
+    #[error("failed to roll back zfs dataset; dataset={0}")]
+    ZfsRollback(String, #[source] CmdError),
+    #[error("failed to set zfs dataset properties; dataset={0}")]
+    ZfsSet(String, #[source] CmdError),
+    #[error("failed to create zfs snapshot; snapshot={0}")]
+    ZfsSnapshot(String, #[source] CmdError),
+}
+
+impl Error {
+    /// Returns whether the error may be caused by a transient condition, such as an unavailable
+    /// network, and so the failed step may succeed if retried.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            // Only a command which ran and failed is considered transient, as a command which
+            // couldn't be run will fail the same way again
+            Self::ExecPkgBootstrap(IocageExecError(CmdError::Failed(_)))
+            | Self::ExecPkgInstall(IocageExecError(CmdError::Failed(_)))
+            | Self::IocageCreatePkgInstall(CmdError::Failed(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the broad category of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::CheckpointNotFound(_)
+            | Self::DependencyNotFound(_)
+            | Self::InterfaceNotFound(_)
+            | Self::JailNotFound(_)
+            | Self::LocalReleaseNotFound(_)
+            | Self::NoGid(_)
+            | Self::NoUser(_)
+            | Self::SecretNotFound(_) => ErrorKind::NotFound,
+            Self::CheckpointClones(..)
+            | Self::GidConflict(..)
+            | Self::HostBusy(..)
+            | Self::JailExists(..)
+            | Self::JailNotManaged(_)
+            | Self::JailStillDying(..)
+            | Self::StaleState(_)
+            | Self::UidConflict(..) => ErrorKind::Conflict,
+            Self::MirrorUnreachable(_)
+            | Self::MirrorUnresolvable(_)
+            | Self::NetworkNotReady(..) => ErrorKind::Network,
+            Self::NotPrivileged(_) | Self::NotRoot => ErrorKind::Permission,
+            Self::CreatePkglistJson(_)
+            | Self::PkgbaseRepoConf(_)
+            | Self::ReadCaCerts(..)
+            | Self::ReadHostFile(..)
+            | Self::ReadSecret(..)
+            | Self::ReadSshHostKeys(..)
+            | Self::ReadState(..)
+            | Self::WriteAuditLog(..)
+            | Self::WriteFirewallRules(..)
+            | Self::WriteHosts(..)
+            | Self::WriteKnownHosts(..)
+            | Self::WriteSanoidConf(..)
+            | Self::WriteState(..) => ErrorKind::Io,
+            Self::DatasetParentUnsupported(..)
+            | Self::CpusetUnavailable(..)
+            | Self::FibUnavailable(..)
+            | Self::MinimalUnsupported(_)
+            | Self::InvalidExecEnv(_)
+            | Self::InvalidExpiry(_)
+            | Self::InvalidPkglist(..)
+            | Self::InvalidTmpfs(_)
+            | Self::InvalidUserName(_)
+            | Self::MonitoringServerRequired(_)
+            | Self::PrereqUnavailable(_)
+            | Self::ParseUserData(..)
+            | Self::PfAnchorMissing(_)
+            | Self::PkgbaseRelease(_)
+            | Self::AddressInterfaceUnknown(_)
+            | Self::UnsupportedIocageFlag(..)
+            | Self::UnsupportedIocageVersion(_) => ErrorKind::Unsupported,
+            Self::StrictWarning(_) => ErrorKind::Strict,
+            Self::IocageCreatedName | Self::ParseIocageVersion(_) | Self::SysctlParse(..) => {
+                ErrorKind::Internal
+            }
+            #[cfg(feature = "manifest")]
+            Self::ParseState(..) | Self::SerializeState(_) => ErrorKind::Internal,
+            _ => ErrorKind::Command,
+        }
+    }
+
+    /// Returns a hint of what to try next to resolve the error, if one is known.
+    ///
+    /// Hints may name options of the `iocage-provision` program.
+    pub fn remediation(&self) -> Option<String> {
+        // A program which couldn't be run is the most likely cause of any command error
+        if let Some(CmdError::Spawn(program, _)) = self.cmd_error() {
+            return Some(format!("check that {} is installed and in PATH", program));
+        }
+
+        let hint = match self {
+            Self::CheckpointClones(_, clones) => format!(
+                "destroy the clones, or promote them with `zfs promote`, and re-run; clones={}",
+                clones
+            ),
+            Self::CheckpointNotFound(_) => String::from(
+                "list the available checkpoints with `zfs list -t snapshot -o name | grep @iocage-provision-`",
+            ),
+            Self::CpusetUnavailable(_, ncpu) => format!(
+                "choose a --cpuset of CPUs below {}, such as 0-{}",
+                ncpu,
+                ncpu.saturating_sub(1)
+            ),
+            Self::DatasetParentUnsupported(_, active) => format!(
+                "choose a --dataset-parent beneath the active pool '{}', or activate another pool with `iocage activate POOL`",
+                active
+            ),
+            Self::DefaultInterface(_) => String::from(
+                "give the host interface for the jail's address with --interface",
+            ),
+            Self::DependencyNotFound(jail) => format!(
+                "provision the jail '{}' first, or remove it from --depends",
+                jail
+            ),
+            Self::ExecPkgBootstrap(_) | Self::ExecPkgInstall(_) => String::from(
+                "check the jail's networking with --dns-check, or allow for an unreliable mirror with --retries",
+            ),
+            Self::FibUnavailable(_, fibs) => format!(
+                "choose a --fib below {}, or raise net.fibs in /boot/loader.conf and reboot",
+                fibs
+            ),
+            Self::GidConflict(..) | Self::UidConflict(..) => String::from(
+                "reuse the existing user or group with --uid-conflict reuse, or pick a free id with --uid-conflict next-free",
+            ),
+            Self::IocageCreate(CmdError::Failed(_)) => String::from(
+                "check that the release has been fetched with `iocage fetch -r RELEASE`, or choose another --release",
+            ),
+            Self::WriteAuditLog(..) => String::from(
+                "choose a writable file with --audit-log, or check the permissions of its directory",
+            ),
+            Self::IocageCreatePkgInstall(_) => String::from(
+                "check the jail's networking with --dns-check, or allow for an unreliable mirror with --retries, which installs packages after the jail is created",
+            ),
+            Self::HostBusy(..) => String::from(
+                "retry when the host is less busy, wait longer with --wait-for-capacity, or relax --min-free-memory and --max-load",
+            ),
+            Self::InterfaceNotFound(_) => String::from(
+                "choose an existing host interface with --interface, as listed by `ifconfig -l`",
+            ),
+            Self::AddressInterfaceUnknown(name) => format!(
+                "set the jail's address with: iocage set ip4_addr='INTERFACE|ADDRESS' {}",
+                name
+            ),
+            Self::JailNotFound(_) => String::from("list the existing jails with: iocage-provision list"),
+            Self::JailExists(..) => String::from(
+                "choose another name, or handle an existing jail with --if-exists skip or --if-exists replace",
+            ),
+            Self::JailNotManaged(jail) => format!(
+                "rename the jail in the manifest, or destroy the existing jail with `iocage destroy {}`",
+                jail
+            ),
+            Self::LocalReleaseNotFound(_) => String::from(
+                "check that the path given with --release path:PATH is a userland directory or tarball",
+            ),
+            Self::MinimalUnsupported(_) => String::from(
+                "re-run without --minimal, or choose a --release of FreeBSD 12, 13, or 14",
+            ),
+            Self::MirrorUnreachable(_) => String::from(
+                "check the host's firewall and the jail's default route (see --gateway)",
+            ),
+            Self::MirrorUnresolvable(_) => String::from(
+                "check the jail's resolver and default route, or install from a local repository with --offline and --pkg-repo-dir",
+            ),
+            Self::NetworkNotReady(..) => String::from(
+                "check the jail's default route (see --gateway) and resolver, or wait longer with --network-timeout",
+            ),
+            Self::NoUser(user) => format!(
+                "create the user '{}' on the host first, or choose another --user",
+                user
+            ),
+            Self::NotPrivileged(_) => String::from(
+                "re-run as root, such as with --sudo, or delegate the iocage dataset to this user with `zfs allow`",
+            ),
+            Self::NotRoot => String::from("re-run as root, such as with --sudo or --doas"),
+            Self::PfAnchorMissing(anchor) => format!(
+                "add 'nat-anchor \"{anchor}\"', 'anchor \"{anchor}\"', and 'load anchor \"{anchor}\" from \"{file}\"' to /etc/pf.conf and reload it with `pfctl -f /etc/pf.conf`",
+                anchor = anchor,
+                file = firewall::PF_ANCHOR_FILE
+            ),
+            Self::PkgbaseRelease(_) => String::from(
+                "give a FreeBSD version with --release, such as 14.0-RELEASE or 15.0-STABLE",
+            ),
+            #[cfg(feature = "manifest")]
+            Self::ParseState(path, _) => format!(
+                "fix the syntax of the state file '{}', or restore it from a backup",
+                path.display()
+            ),
+            Self::JailStillDying(name, _) => format!(
+                "check whether a process still holds the jail with: jls -d -j ioc-{}",
+                name.replace('.', "_")
+            ),
+            Self::StaleState(state) => format!(
+                "re-run with --repair to {} and retry creating the jail",
+                state.repair_description()
+            ),
+            Self::SecretNotFound(name) => format!(
+                "add the secret '{}' to the secret source, or choose another with --secret-source",
+                name
+            ),
+            Self::DnsUpdate(_, CmdError::Spawn(..)) => String::from(
+                "install nsupdate, which is provided by the bind-tools package",
+            ),
+            Self::DnsUpdate(..) => String::from(
+                "check that the key file is readable and that the server accepts signed updates \
+                 for the zone",
+            ),
+            Self::IocageVersion(CmdError::Spawn(..)) => String::from(
+                "install iocage, or re-run with --install-prereqs to install it from packages",
+            ),
+            Self::PrereqQuery(_) | Self::PrereqUnavailable(_) => String::from(
+                "bootstrap pkg with `pkg bootstrap` and check that the host's package repository is reachable",
+            ),
+            Self::InvalidPkglist(path, _) => format!(
+                "fix '{}' so that it is a JSON object with a 'pkgs' array of package names",
+                path.display()
+            ),
+            Self::ParseUserData(path, _) => format!(
+                "fix '{}' to use only the packages, users, write_files, and runcmd keys of cloud-config",
+                path.display()
+            ),
+            Self::ReadCaCerts(path, _)
+            | Self::ReadHostFile(path, _)
+            | Self::ReadSshHostKeys(path, _) => format!(
+                "check that '{}' exists and is readable by this user",
+                path.display()
+            ),
+            Self::StrictWarning(_) => {
+                String::from("resolve the warning, or re-run without --strict")
+            }
+            Self::UnsupportedIocageFlag(_, flag) => {
+                format!("upgrade iocage, or re-run without {}", flag)
+            }
+            Self::UnsupportedIocageVersion(_) => format!(
+                "upgrade iocage to version {} or later",
+                IocageVersion::MIN_SUPPORTED
+            ),
+            _ => return None,
+        };
+
+        Some(hint)
+    }
+
+    /// Returns the error of the command which caused this error, if any.
+    fn cmd_error(&self) -> Option<&CmdError> {
+        let mut source = std::error::Error::source(self);
+        while let Some(err) = source {
+            if let Some(cmd_error) = err.downcast_ref::<CmdError>() {
+                return Some(cmd_error);
+            }
+            source = err.source();
+        }
+
+        None
+    }
+}
+
+/// A broad category of an [`Error`], such as for deciding how to report it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// An external program, such as `iocage` or `zfs`, failed or could not be run.
+    Command,
+    /// Existing state conflicts with the request, such as a jail which already exists.
+    Conflict,
+    /// An unexpected condition, such as output of a program which could not be parsed.
+    Internal,
+    /// A file on the host could not be read or written.
+    Io,
+    /// The jail's network is not working.
+    Network,
+    /// Something the request depends on, such as a user or a checkpoint, was not found.
+    NotFound,
+    /// The current user lacks the required privileges.
+    Permission,
+    /// A warning was treated as an error in strict mode.
+    Strict,
+    /// The host or its installed programs don't support the request.
+    Unsupported,
+}
+
+impl ErrorKind {
+    /// Returns the name of this kind.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Command => "command",
+            Self::Conflict => "conflict",
+            Self::Internal => "internal",
+            Self::Io => "io",
+            Self::Network => "network",
+            Self::NotFound => "not-found",
+            Self::Permission => "permission",
+            Self::Strict => "strict",
+            Self::Unsupported => "unsupported",
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum CmdError {
+    /// A command was not spawned because the running children have been terminated.
+    #[error("command was cancelled; program={0}")]
+    Cancelled(String),
     #[error("spawned command did not start")]
     ChildWait(#[source] io::Error),
     /// A command returned a non-zero exit code and thus is considered to have failed.
@@ -118,273 +844,3023 @@ pub enum GatewayError {
     Utf8(#[source] str::Utf8Error),
 }
 
-/// Ensures that the current effective user is root.
+impl ProvisionReport {
+    /// Returns whether or not provisioning stops after the given phase, and if so completes the
+    /// report of the jail as it was left.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Err` if the changes to the jail's iocage properties could not be recorded.
+    fn stop_after(
+        &mut self,
+        spec: &JailSpec,
+        phase: Phase,
+        warnings: &mut Vec<String>,
+        created: &BTreeMap<String, String>,
+    ) -> Result<bool> {
+        if spec.stop_after != Some(phase) || phase == Phase::Services {
+            return Ok(false);
+        }
+        self.record_property_changes(created)?;
+        section!(
+            "Instance '{}' provisioned up to the '{}' phase",
+            self.name,
+            phase.as_str()
+        );
+        self.warnings = std::mem::take(warnings);
+        self.stopped_after = Some(phase);
+        Ok(true)
+    }
+
+    /// Records the changes to the jail's iocage properties since it was created, given the
+    /// properties it was created with, in the report and the audit log.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Err` if the jail's properties could not be read or the audit log could not be
+    /// written.
+    fn record_property_changes(&mut self, created: &BTreeMap<String, String>) -> Result<()> {
+        self.property_changes = property_changes(created, &iocage_properties(&self.name)?);
+        for change in &self.property_changes {
+            info!("Changed iocage property {}", change);
+        }
+
+        audit::record_property_changes(&self.property_changes);
+
+        Ok(())
+    }
+}
+
+/// Ensures that the current effective user is root.
+///
+/// # Errors
+///
+/// Returns an `Err` if the current effective `uid` is any value other than `0`.
+pub fn ensure_root() -> Result<()> {
+    if host::effective_uid() != 0 {
+        Err(Error::NotRoot)
+    } else {
+        Ok(())
+    }
+}
+
+/// Ensures that the current user may manage jails, either as `root` or through permissions which
+/// have been delegated to it.
+///
+/// Some hosts delegate jail management to a group other than `root` with `zfs allow` and jail
+/// permissions. A user other than `root` is considered to have been delegated these permissions
+/// when iocage can list the jails and the mount point of the iocage dataset is writable. As a
+/// delegated user may still lack access to the files written once a jail has changed, the audit
+/// log and the state file (when jails are recorded) are checked as well.
+///
+/// # Errors
+///
+/// Returns an `Err` if the current user is not `root` and either iocage can't list the jails, the
+/// iocage dataset is not writable, or the audit log or state file can't be written.
+pub fn ensure_privileged() -> Result<()> {
+    if ensure_root().is_ok() {
+        return Ok(());
+    }
+
+    let mut cmd = iocage_query_command();
+    cmd.arg("list").arg("-h");
+    if let Err(err) = cmd_stdout(cmd) {
+        return Err(Error::NotPrivileged(format!("iocage list failed: {}", err)));
+    }
+    let iocroot = format!("{}/iocage", iocage_pool()?);
+    let mountpoint = zfs_mountpoint(&iocroot)?;
+    if !host::is_writable(Path::new(&mountpoint)) {
+        return Err(Error::NotPrivileged(format!(
+            "iocage dataset is not writable: {}",
+            mountpoint
+        )));
+    }
+    audit::check_audit_log()?;
+    #[cfg(feature = "manifest")]
+    state::check_state_file()?;
+
+    Ok(())
+}
+
+/// Creates, starts, and sets up a new FreeBSD jail via the `iocage` program.
+///
+/// The jail is only recorded as managed in the state file when recording has been enabled with
+/// `set_record_state`.
+///
+/// # Errors
+///
+/// Returns an `Err` if a jail could not be completely provisioned successfully. Note that a
+/// failure from this function may leave behind a jail in an inconsistent state that needs to be
+/// cleaned up out of band.
+pub fn provision_jail(spec: &JailSpec) -> Result<ProvisionReport> {
+    #[cfg(feature = "manifest")]
+    let record = state::record_state();
+    #[cfg(not(feature = "manifest"))]
+    let record = false;
+
+    provision(spec, record)
+}
+
+/// Provisions a jail as [`provision_jail`] does, recording it in the state file if `record` is
+/// set.
+fn provision(spec: &JailSpec, record: bool) -> Result<ProvisionReport> {
+    let name = spec.name.as_str();
+    // A jail named by iocage is only named in audit records once it has been created
+    let audit = audit::JailScope::enter(if spec.uuid_name { None } else { Some(name) });
+    for secret in &spec.secrets {
+        register_secret(secret);
+    }
+    if let Some(key) = spec
+        .exec_env
+        .keys()
+        .chain(spec.exec_secrets.keys())
+        .find(|key| !script::is_env_name(key))
+    {
+        return Err(Error::InvalidExecEnv(key.clone()));
+    }
+    if let Some(ref user_name) = spec.automation_user {
+        if !script::is_user_name(user_name) {
+            return Err(Error::InvalidUserName(user_name.clone()));
+        }
+    }
+    let ttl = spec.expires_in.or(if spec.ephemeral {
+        Some(EPHEMERAL_TTL)
+    } else {
+        None
+    });
+    if let Some(ttl) = ttl {
+        expiry(ttl)?;
+    }
+    if spec.monitoring == Some(MonitoringAgent::ZabbixAgent) && spec.monitoring_server.is_none() {
+        return Err(Error::MonitoringServerRequired(
+            MonitoringAgent::ZabbixAgent.as_str(),
+        ));
+    }
+    let tmpfs = spec
+        .tmpfs
+        .iter()
+        .map(|mount| parse::tmpfs(mount).map_err(Error::InvalidTmpfs))
+        .collect::<Result<Vec<_>>>()?;
+    let mut exec_env = spec.exec_env.clone();
+    for (key, secret_name) in &spec.exec_secrets {
+        let value = secret::resolve(secret_name)
+            .map_err(|err| Error::ReadSecret(secret_name.clone(), err))?
+            .ok_or_else(|| Error::SecretNotFound(secret_name.clone()))?;
+        register_secret(&value);
+        exec_env.insert(key.clone(), value);
+    }
+    let _exec_env = ExecEnvScope::enter(&exec_env);
+    let user = find_user(spec.user.as_deref())?;
+    let ca_certs = read_ca_certs(spec)?;
+    let ssh_host_key_files = spec
+        .ssh_host_keys_dir
+        .as_deref()
+        .map(read_ssh_host_key_files)
+        .transpose()?
+        .unwrap_or_default();
+    let make_conf = spec.make_conf.as_deref().map(read_host_file).transpose()?;
+    let src_conf = spec.src_conf.as_deref().map(read_host_file).transpose()?;
+    let firstboot = spec
+        .firstboot
+        .iter()
+        .map(|path| read_host_file(path))
+        .collect::<Result<Vec<_>>>()?;
+    let user_data = spec.user_data.as_deref().map(read_user_data).transpose()?;
+    let user_pkglist = spec.pkglist.as_deref().map(read_pkglist).transpose()?;
+    let automation_key = spec
+        .automation_key
+        .as_deref()
+        .map(read_host_file)
+        .transpose()?;
+    if !spec.depends.is_empty() {
+        ensure_jails_exist(&spec.depends)?;
+    }
+    if let Some(fib) = spec.fib {
+        ensure_fib_available(fib)?;
+    }
+    if let Some(ref cpuset) = spec.cpuset {
+        ensure_cpuset_available(cpuset)?;
+    }
+    let iocage_version = ensure_iocage_supported()?;
+    if spec.thick_jail && !iocage_version.has_thickjail() {
+        return Err(Error::UnsupportedIocageFlag(iocage_version, "--thickjail"));
+    }
+    let pool = iocage_pool()?;
+    // A jail named by iocage can't already exist
+    let existing = if spec.uuid_name {
+        None
+    } else {
+        find_jail(name)?
+    };
+    if let Some(ref existing) = existing {
+        match spec.if_exists {
+            IfExistsPolicy::Fail => {
+                return Err(Error::JailExists(
+                    name.to_string(),
+                    jail_uuid(name)?,
+                    existing.state.clone(),
+                ));
+            }
+            IfExistsPolicy::Skip => {
+                section!("Skipping jail '{}' as it already exists", name);
+                let dataset = jail_dataset(&pool, name);
+                return Ok(ProvisionReport {
+                    name: name.to_string(),
+                    uuid: jail_uuid(name)?,
+                    root: jail_root(&dataset)?,
+                    dataset,
+                    disk_quota: None,
+                    disk_reserve: None,
+                    snapshot_schedule: None,
+                    monitoring_endpoint: None,
+                    dns_name: None,
+                    expires_at: None,
+                    ip: spec.ip,
+                    release: existing.release.clone(),
+                    user: None,
+                    tags: spec.tags.clone(),
+                    iocage_version,
+                    skipped: true,
+                    checkpoint: None,
+                    ssh_host_keys: Vec::new(),
+                    property_changes: Vec::new(),
+                    warnings: Vec::new(),
+                    stopped_after: None,
+                    next_steps: NextSteps::new(name, spec.ip.addr(), None),
+                    pkglist: String::new(),
+                });
+            }
+            IfExistsPolicy::Replace => {}
+        }
+    }
+    if let Some(ref parent) = spec.dataset_parent {
+        ensure_dataset_parent(parent, &pool)?;
+    }
+    let pkgs = packages(spec, user.as_ref(), user_data.as_ref());
+    // When a local package repository or CA certificates are used they must be installed before
+    // any packages can be, so package installation is deferred until after the jail has been
+    // created. It is also deferred when retries are requested as a failed `iocage create` can't be retried, and
+    // when the jail's networking is checked before any packages are installed, and when it is
+    // requested that the jail is created without a package list.
+    // A package list file given by the user is used as is, so any other packages are deferred.
+    let (create_pkgs, mut deferred_pkgs) = if let Some((_, ref user_pkgs)) = user_pkglist {
+        let deferred = pkgs
+            .into_iter()
+            .filter(|pkg| !user_pkgs.iter().any(|user_pkg| user_pkg == pkg))
+            .collect();
+        (Vec::new(), deferred)
+    } else if spec.defer_packages
+        || spec.pkg_repo_dir.is_some()
+        || !ca_certs.is_empty()
+        || spec.retries > 0
+        || (spec.dns_check && !spec.offline)
+    {
+        (Vec::new(), pkgs)
+    } else {
+        (pkgs, Vec::new())
+    };
+    // Packages installed by `iocage create`, which are installed after it instead if it fails
+    // to install them
+    let mut create_time_pkgs: Vec<&str> = match user_pkglist {
+        Some((_, ref user_pkgs)) => user_pkgs.iter().map(String::as_str).collect(),
+        None => create_pkgs.clone(),
+    };
+    if spec.defer_packages {
+        deferred_pkgs.splice(0..0, create_time_pkgs.drain(..));
+    }
+    let generated;
+    let (pkglist, pkglist_path) = match (spec.pkglist.as_deref(), &user_pkglist) {
+        _ if spec.defer_packages => (String::new(), None),
+        (Some(path), Some((contents, _))) => (contents.clone(), Some(path)),
+        _ => {
+            let pkglist = pkglist_json(&create_pkgs, &spec.pkglist_extra);
+            generated = create_pkglist_json(&pkglist).map_err(Error::CreatePkglistJson)?;
+            (pkglist, Some(generated.path()))
+        }
+    };
+
+    if spec.uuid_name {
+        section!("Provisioning a jail named by iocage");
+    } else {
+        section!("Provisioning a jail named '{}'", name);
+    }
+    info!("Using iocage {}", iocage_version);
+
+    let mut warnings = Vec::new();
+
+    ensure_capacity(spec, &mut warnings)?;
+
+    if spec.setup_bridge {
+        setup_bridge(&host_interface(spec)?)?;
+    }
+
+    let firewalls = firewall::enabled();
+    if !firewalls.is_empty() {
+        info!("Checking host firewall rules for the jail's traffic");
+        let uplink = host_interface(spec)?;
+        let net = firewall::JailNet {
+            ip: spec.ip,
+            vnet: !spec.vnet_off,
+            uplink_addrs: firewall::interface_addrs(&uplink),
+            uplink,
+        };
+        let mut findings = firewall::check(&firewalls, &net)?;
+        if spec.fix_firewall
+            && findings
+                .iter()
+                .any(|f| f.firewall == firewall::Firewall::Pf)
+        {
+            info!(
+                "Loading firewall rules into pf anchor '{}'",
+                firewall::PF_ANCHOR
+            );
+            firewall::fix_pf(&findings)?;
+            findings.retain(|f| f.firewall != firewall::Firewall::Pf);
+        }
+        for finding in findings {
+            warn(spec, &mut warnings, finding.to_string())?;
+        }
+    }
+
+    let checkpoint = if spec.checkpoint {
+        info!("Creating checkpoint of '{}/iocage'", pool);
+        let checkpoint = checkpoint::create(&pool)?;
+        info!(
+            "Created checkpoint '{}', roll back with: iocage-provision rollback-checkpoint {}",
+            checkpoint, checkpoint
+        );
+        Some(checkpoint)
+    } else {
+        None
+    };
+
+    if existing.is_some() {
+        info!("Destroying existing jail '{}' to replace it", name);
+        run_iocage_destroy(name)?;
+    }
+
+    let release = if spec.pkgbase {
+        release::build_pkgbase(&pool, &spec.release, &spec.pkgbase_packages)?
+    } else if let Some(path) = release::local_path(&spec.release) {
+        release::import(&pool, path)?
+    } else {
+        spec.release.clone()
+    };
+
+    let network = if spec.vnet_off {
+        JailNetwork::Shared(host_interface(spec)?)
+    } else {
+        JailNetwork::Vnet(spec.gateway)
+    };
+
+    let create_name = if spec.uuid_name { None } else { Some(name) };
+    let create = || {
+        run_iocage_create(
+            create_name,
+            &spec.ip,
+            &network,
+            &release,
+            spec.thick_jail,
+            pkglist_path,
+            &jail_properties(spec),
+        )
+    };
+    let step = progress::Step::start("Creating jail via iocage");
+    let name = match create() {
+        Err(Error::StaleState(state)) if spec.repair => {
+            warn(
+                spec,
+                &mut warnings,
+                format!(
+                    "iocage create failed on stale state '{}', repairing it and retrying",
+                    state
+                ),
+            )?;
+            repair_stale_state(&pool, create_name)?;
+            create()?
+        }
+        // The jail is created before its packages are installed, so it can still be provisioned
+        // by installing them afterwards, where a failure is retried as with --retries
+        Err(Error::IocageCreatePkgInstall(err))
+            if create_name.is_some() && !create_time_pkgs.is_empty() && jail_exists(name)? =>
+        {
+            warn(
+                spec,
+                &mut warnings,
+                format!(
+                    "iocage create failed to install packages ({}), installing them after creation",
+                    err
+                ),
+            )?;
+            deferred_pkgs.splice(0..0, create_time_pkgs.iter().copied());
+            name.to_string()
+        }
+        result => result?,
+    };
+    step.finish();
+    let name = name.as_str();
+    if spec.uuid_name {
+        info!("Jail named '{}' by iocage", name);
+        audit.rename(name);
+    }
+
+    let dataset = jail_dataset(&pool, name);
+    let uuid = jail_uuid(name)?;
+    let root = jail_root(&dataset)?;
+    info!("Created jail with uuid '{}' at '{}'", uuid, root.display());
+    // Changes to the properties from here on are recorded for review
+    let created = iocage_properties(name)?;
+    let mut report = ProvisionReport {
+        name: name.to_string(),
+        uuid,
+        dataset: dataset.clone(),
+        disk_quota: spec.disk_quota.clone(),
+        disk_reserve: spec.disk_reserve.clone(),
+        snapshot_schedule: spec.snapshot_schedule,
+        monitoring_endpoint: None,
+        dns_name: None,
+        expires_at: None,
+        root: root.clone(),
+        ip: spec.ip,
+        release,
+        user: None,
+        tags: spec.tags.clone(),
+        iocage_version,
+        skipped: false,
+        checkpoint,
+        ssh_host_keys: Vec::new(),
+        property_changes: Vec::new(),
+        warnings: Vec::new(),
+        stopped_after: None,
+        next_steps: NextSteps::new(name, spec.ip.addr(), None),
+        pkglist,
+    };
+    let zfs_props = zfs_properties(spec);
+    if !zfs_props.is_empty() {
+        info!("Setting ZFS properties on '{}'", dataset);
+        run_zfs_set(&dataset, &zfs_props)?;
+    }
+    if report.snapshot_schedule.is_some() && Path::new(SANOID_CONF).exists() {
+        info!("Adding '{}' to sanoid configuration", dataset);
+        register_sanoid(&report)?;
+    }
+
+    for (path, size) in &tmpfs {
+        info!("Mounting tmpfs at '{}'", path);
+        run_iocage_fstab_add_tmpfs(name, path, size.as_deref())?;
+    }
+
+    if spec.network_timeout > 0 {
+        let step = progress::Step::start("Waiting for jail network");
+        exec_network_wait(name, spec.network_timeout, !spec.offline)?;
+        step.finish();
+    }
+
+    if spec.minimal {
+        info!("Stripping jail to a minimal base system");
+        exec_minimal(name, spec.ports)?;
+    }
+
+    if report.stop_after(spec, Phase::Create, &mut warnings, &created)? {
+        return Ok(report);
+    }
+
+    // The certificates are trusted before pkg or fetch connects to a mirror over HTTPS
+    if !ca_certs.is_empty() {
+        info!("Installing CA certificates");
+        exec_ca_certs(name, &ca_certs)?;
+    }
+
+    if let Some(ref repo_dir) = spec.pkg_repo_dir {
+        info!("Mounting local package repository '{}'", repo_dir.display());
+        run_iocage_fstab_add(name, repo_dir, JAIL_PKG_REPO_DIR, false)?;
+
+        info!("Configuring local package repository");
+        exec_pkg_repo(name, spec.offline)?;
+    }
+
+    if spec.dns_check && !spec.offline {
+        info!("Checking that the jail can reach its pkg mirror");
+        exec_network_check(name)?;
+    }
+
+    if spec.defer_packages {
+        let step = progress::Step::start("Bootstrapping pkg");
+        with_retries(spec, &mut warnings, "pkg bootstrap", || {
+            exec_pkg_bootstrap(name)
+        })?;
+        step.finish();
+    }
+
+    if !deferred_pkgs.is_empty() {
+        let step = progress::Step::start("Installing packages");
+        with_retries(spec, &mut warnings, "package installation", || {
+            exec_pkg_install(name, &deferred_pkgs)
+        })?;
+        step.finish();
+    }
+
+    if make_conf.is_some() || src_conf.is_some() {
+        info!("Installing build configuration");
+        exec_make_conf(name, make_conf.as_deref(), src_conf.as_deref())?;
+    }
+
+    if spec.ports {
+        info!("Mounting ports tree '{}'", HOST_PORTS_DIR);
+        run_iocage_fstab_add(name, Path::new(HOST_PORTS_DIR), JAIL_PORTS_DIR, false)?;
+        if let Some(ref dir) = spec.ports_distfiles_dir {
+            info!("Mounting ports distfiles directory '{}'", dir.display());
+            run_iocage_fstab_add(name, dir, JAIL_PORTS_DISTFILES_DIR, true)?;
+        }
+        if let Some(ref dir) = spec.ports_packages_dir {
+            info!("Mounting ports packages directory '{}'", dir.display());
+            run_iocage_fstab_add(name, dir, JAIL_PORTS_PACKAGES_DIR, true)?;
+        }
+
+        info!("Configuring ports build directories");
+        exec_ports_config(name)?;
+    }
+
+    if report.stop_after(spec, Phase::Network, &mut warnings, &created)? {
+        return Ok(report);
+    }
+
+    // The user which can log in over SSH, preferring the --user to the automation user
+    let mut ssh_user = None;
+    if let Some(user) = user {
+        let group = find_group(user.primary_group_id())?;
+
+        info!("Preparing sudo config");
+        exec_sudo_config(name)?;
+
+        let group_name = match (jail_group_name(name, group.gid())?, spec.id_conflict) {
+            (None, _) => {
+                info!("Creating group '{}'", group.name().to_string_lossy());
+                exec_create_group(name, &group, Some(group.gid()))?;
+                group.name().to_string_lossy().into_owned()
+            }
+            (Some(existing), IdConflictPolicy::Fail) => {
+                return Err(Error::GidConflict(group.gid(), existing));
+            }
+            (Some(existing), IdConflictPolicy::Reuse) => {
+                info!("Reusing group '{}' with gid {}", existing, group.gid());
+                existing
+            }
+            (Some(existing), IdConflictPolicy::NextFree) => {
+                info!(
+                    "Creating group '{}' with a free gid as {} is used by '{}'",
+                    group.name().to_string_lossy(),
+                    group.gid(),
+                    existing
+                );
+                exec_create_group(name, &group, None)?;
+                group.name().to_string_lossy().into_owned()
+            }
+        };
+
+        let groups = if spec.copy_groups {
+            let groups = supplementary_groups(&user);
+            if !groups.is_empty() {
+                info!(
+                    "Creating supplementary groups '{}'",
+                    groups
+                        .iter()
+                        .map(|g| g.name().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+                exec_copy_groups(name, &groups)?;
+            }
+            groups
+        } else {
+            Vec::new()
+        };
+
+        let mut home = UserHome {
+            dir: spec.home_dir.clone(),
+            mode: spec.home_mode,
+            create: spec.create_home,
+        };
+        if spec.home_dataset {
+            let home_dir = home
+                .dir
+                .get_or_insert_with(|| format!("/home/{}", user.name().to_string_lossy()));
+            let home_dataset = format!("{}/home/{}", dataset, user.name().to_string_lossy());
+            info!("Creating ZFS dataset '{}' for home directory", home_dataset);
+            let mountpoint = jail_home_path(&root, home_dir);
+            // `pw` populates and takes ownership of the existing mount point directory
+            run_zfs_create(&home_dataset, &mountpoint)?;
+        }
+
+        let (user_name, created) = match (jail_user_name(name, user.uid())?, spec.id_conflict) {
+            (None, _) => {
+                info!("Creating user '{}'", user.name().to_string_lossy());
+                exec_create_user(name, &user, &group_name, &groups, Some(user.uid()), &home)?;
+                (user.name().to_string_lossy().into_owned(), true)
+            }
+            (Some(existing), IdConflictPolicy::Fail) => {
+                return Err(Error::UidConflict(user.uid(), existing));
+            }
+            (Some(existing), IdConflictPolicy::Reuse) => {
+                info!("Reusing user '{}' with uid {}", existing, user.uid());
+                exec_reuse_user(name, &existing, &groups)?;
+                (existing, false)
+            }
+            (Some(existing), IdConflictPolicy::NextFree) => {
+                info!(
+                    "Creating user '{}' with a free uid as {} is used by '{}'",
+                    user.name().to_string_lossy(),
+                    user.uid(),
+                    existing
+                );
+                exec_create_user(name, &user, &group_name, &groups, None, &home)?;
+                (user.name().to_string_lossy().into_owned(), true)
+            }
+        };
+
+        // Only a shell which is in the base system or installed as a package is available
+        if created && !jail_has_executable(name, user.shell())? {
+            warn(
+                spec,
+                &mut warnings,
+                format!(
+                    "login shell '{}' of user '{}' is not installed in the jail",
+                    user.shell().display(),
+                    user_name
+                ),
+            )?;
+        }
+
+        if spec.login_class.is_some() || spec.lang.is_some() {
+            info!("Configuring login class and locale of '{}'", user_name);
+            exec_user_login(
+                name,
+                &user_name,
+                spec.login_class.as_deref(),
+                spec.lang.as_deref(),
+            )?;
+        }
+        report.user = Some(user_name.clone());
+        ssh_user = Some(user_name);
+    }
+
+    if let Some(ref automation_user) = spec.automation_user {
+        info!("Creating automation user '{}'", automation_user);
+        exec_automation_user(
+            name,
+            automation_user,
+            automation_key.as_deref(),
+            &spec.automation_commands,
+        )?;
+        if ssh_user.is_none() && automation_key.is_some() {
+            ssh_user = Some(automation_user.clone());
+        }
+    }
+
+    if report.stop_after(spec, Phase::Users, &mut warnings, &created)? {
+        return Ok(report);
+    }
+
+    if spec.vpn_ready {
+        if spec.vnet_off {
+            warn(
+                spec,
+                &mut warnings,
+                format!(
+                    "IP forwarding is not enabled in '{}' as a shared-IP jail uses the host's \
+                     network stack",
+                    name
+                ),
+            )?;
+        } else {
+            info!("Enabling IP forwarding");
+            iocage_exec(name, script::ip_forwarding()).map_err(Error::ExecIpForwarding)?;
+        }
+    }
+
+    if spec.ntp_service {
+        info!("Enabling NTP service");
+        exec_ntp_service(name)?;
+    }
+
+    if let Some(ref remote) = spec.syslog_remote {
+        info!("Configuring syslog forwarding to '{}'", remote);
+        exec_syslog_remote(name, remote)?;
+    }
+
+    if let Some(ref relay) = spec.smtp_relay {
+        info!("Configuring mail relay through '{}'", relay);
+        exec_mail_relay(name, relay)?;
+    }
+
+    if let Some(agent) = spec.monitoring {
+        info!("Enabling monitoring agent '{}'", agent.as_str());
+        iocage_exec(
+            name,
+            script::monitoring(agent, spec.monitoring_server.as_deref()),
+        )
+        .map_err(Error::ExecMonitoring)?;
+        report.monitoring_endpoint = Some(agent.endpoint(spec.ip.addr()));
+    }
+
+    for recipe in &spec.recipes {
+        info!("Applying recipe '{}'", recipe.as_str());
+        recipe::apply(name, &dataset, &root, *recipe)?;
+    }
+
+    if spec.periodic_log {
+        info!("Configuring periodic output logging");
+        exec_periodic_log(name)?;
+    }
+
+    if !spec.cron_entries.is_empty() {
+        info!("Installing cron entries");
+        exec_cron_entries(name, &spec.cron_entries)?;
+    }
+
+    let mut ssh_host_keys = Vec::new();
+    if spec.ssh_service {
+        info!("Enabling SSH service");
+        // A shared-IP jail's sshd is bound to its own address rather than the wildcard address
+        let listen_addr = if spec.vnet_off {
+            Some(spec.ip.addr())
+        } else {
+            None
+        };
+        exec_ssh_service(
+            name,
+            &spec.ssh_host_key_types,
+            &ssh_host_key_files,
+            listen_addr,
+        )?;
+        ssh_host_keys = read_ssh_host_keys(name)?;
+        for key in &ssh_host_keys {
+            output!("{} {}", key.fingerprint, key.key_type);
+        }
+        if let Some(ref known_hosts) = spec.known_hosts {
+            info!("Adding SSH host keys to '{}'", known_hosts.display());
+            append_known_hosts(known_hosts, name, spec.ip.addr(), &ssh_host_keys)?;
+        }
+    }
+
+    if let Some(ref user_data) = user_data {
+        info!("Applying user-data");
+        exec_user_data(name, user_data)?;
+    }
+
+    if !firstboot.is_empty() {
+        info!("Installing first boot scripts");
+        exec_firstboot(name, &firstboot)?;
+    }
+
+    if spec.hosts_entry {
+        info!("Adding '{}' to '{}'", name, hosts::HOSTS_FILE);
+        hosts::seed_jail(name)?;
+        hosts::add_host_entry(name, spec.ip.addr())?;
+    }
+
+    if let Some(ref registration) = spec.register_dns {
+        let fqdn = registration.fqdn(name);
+        info!("Registering '{}' with '{}'", fqdn, registration.server);
+        dns::register(registration, name, spec.ip.addr())?;
+        report.dns_name = Some(fqdn);
+    }
+
+    report.record_property_changes(&created)?;
+
+    let expires = ttl.map(expiry).transpose()?;
+    report.expires_at = expires.map(audit::timestamp);
+
+    #[cfg(feature = "manifest")]
+    {
+        if record {
+            info!("Recording '{}' in state file '{}'", name, STATE_FILE);
+            state::record(name, spec, expires)?;
+        }
+    }
+    #[cfg(not(feature = "manifest"))]
+    let _ = (expires, record);
+
+    section!("Instance '{}' provisioned successfully", name);
+
+    if spec.ssh_service {
+        report.next_steps = NextSteps::new(name, spec.ip.addr(), ssh_user.as_deref());
+    }
+    if let Some(ref ssh) = report.next_steps.ssh {
+        output!("Connect:  {}", ssh);
+    }
+    output!("Console:  {}", report.next_steps.console);
+    output!("Dataset:  {}", report.dataset);
+    if let Some(ref quota) = report.disk_quota {
+        output!("Quota:    {}", quota);
+    }
+    if let Some(ref reserve) = report.disk_reserve {
+        output!("Reserve:  {}", reserve);
+    }
+    if let Some(ref schedule) = report.snapshot_schedule {
+        output!("Snapshots: {}", schedule);
+    }
+    if let Some(ref endpoint) = report.monitoring_endpoint {
+        output!("Metrics:  {}", endpoint);
+    }
+    if let Some(ref dns_name) = report.dns_name {
+        output!("DNS:      {}", dns_name);
+    }
+    if let Some(ref expires_at) = report.expires_at {
+        output!("Expires:  {}", expires_at);
+    }
+    output!("Stop:     {}", report.next_steps.stop);
+    output!("Destroy:  {}", report.next_steps.destroy);
+
+    report.ssh_host_keys = ssh_host_keys;
+    report.warnings = warnings;
+
+    Ok(report)
+}
+
+/// Returns the time at which a jail expires when it expires `ttl` seconds from now.
+///
+/// # Errors
+///
+/// Returns an `Err` if the time is too far in the future to be represented.
+fn expiry(ttl: u64) -> Result<SystemTime> {
+    SystemTime::now()
+        .checked_add(Duration::from_secs(ttl))
+        .ok_or(Error::InvalidExpiry(ttl))
+}
+
+/// Starts an interactive login shell in a jail as root or the given user.
+///
+/// Unlike the provisioning commands, the shell inherits the terminal of the current process and
+/// this function returns once the shell exits. Interrupts from the terminal are left to the shell,
+/// so `SIGINT` is ignored by the current process until then. The exit code of the shell is not
+/// considered an error.
+///
+/// # Errors
+///
+/// Returns an `Err` if the shell could not be started.
+pub fn login_shell(jail_name: &str, user: Option<&str>) -> Result<()> {
+    let mut cmd = iocage_command();
+    cmd.arg("exec")
+        .arg(jail_name)
+        .arg("login")
+        .arg("-f")
+        .arg(user.unwrap_or("root"))
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+
+    trace!("running; cmd={}", redact(&format!("{:?}", cmd)));
+    let _audit = audit::JailScope::enter(Some(jail_name));
+    let started = SystemTime::now();
+    let status = process::run_interactive(&mut cmd)
+        .map_err(|err| Error::IocageLogin(CmdError::Spawn(cmd_get_program(&cmd), err)))?;
+    record_command(&cmd, started, &status, "");
+
+    Ok(())
+}
+
+/// Returns summary information about all existing jails, including their descriptions.
+///
+/// # Errors
+///
+/// Returns an `Err` if the jails or their properties could not be read.
+pub fn list_jails() -> Result<Vec<JailInfo>> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("list").arg("-h");
+    let stdout = cmd_stdout(cmd).map_err(Error::IocageList)?;
+
+    parse::list(&stdout)
+        .into_iter()
+        .map(|jail| {
+            Ok(JailInfo {
+                description: jail_description(&jail.name)?,
+                ..jail
+            })
+        })
+        .collect()
+}
+
+/// Returns summary information about an existing jail, or `None` if no jail has the given name.
+///
+/// The description of the jail is not read and so is always `None`.
+///
+/// # Errors
+///
+/// Returns an `Err` if the jails could not be listed.
+pub fn find_jail(jail_name: &str) -> Result<Option<JailInfo>> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("list").arg("-h");
+    let stdout = cmd_stdout(cmd).map_err(Error::IocageList)?;
+
+    Ok(parse::list(&stdout)
+        .into_iter()
+        .find(|jail| jail.name == jail_name))
+}
+
+/// Returns whether a jail with the given name exists.
+///
+/// # Errors
+///
+/// Returns an `Err` if the jails could not be listed.
+pub fn jail_exists(jail_name: &str) -> Result<bool> {
+    find_jail(jail_name).map(|jail| jail.is_some())
+}
+
+/// Returns the iocage properties of an existing jail which are suitable for creating another jail
+/// like it.
+///
+/// Properties which identify the jail (such as its hostname, UUID, addresses, MAC addresses, and
+/// notes) or which describe its runtime state are omitted. The jail's release is included under
+/// the `"release"` key so that it can be used as the release of the new jail.
+///
+/// # Errors
+///
+/// Returns an `Err` if the properties of the jail could not be read.
+pub fn same_as_properties(jail_name: &str) -> Result<BTreeMap<String, String>> {
+    let props = iocage_properties(jail_name)?
+        .into_iter()
+        .filter(|(key, _)| key == "release" || !is_identity_property(key))
+        .collect();
+
+    Ok(props)
+}
+
+/// Returns all iocage properties of a jail, with any secret values masked.
+///
+/// # Errors
+///
+/// Returns an `Err` if the properties of the jail could not be read.
+fn iocage_properties(jail_name: &str) -> Result<BTreeMap<String, String>> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("get").arg("all").arg(jail_name);
+    let stdout = cmd_stdout(cmd).map_err(|err| Error::IocageGet(jail_name.to_string(), err))?;
+
+    Ok(parse::properties(&stdout)
+        .into_iter()
+        .map(|(key, value)| (key, redact(&value)))
+        .collect())
+}
+
+/// Sets iocage properties, each given as `KEY=VALUE`, of an existing jail.
+///
+/// Most properties take effect the next time the jail is started.
+///
+/// # Errors
+///
+/// Returns an `Err` if the properties were not successfully set.
+pub fn set_properties(jail_name: &str, props: &[String]) -> Result<()> {
+    run_iocage_set(jail_name, props)
+}
+
+/// Sets whether progress output is written to the standard error stream rather than standard
+/// output.
+///
+/// This is useful when standard output is reserved for machine readable output.
+pub fn set_progress_to_stderr(enabled: bool) {
+    PROGRESS_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether progress output is written to the standard error stream rather than standard
+/// output.
+pub(crate) fn progress_to_stderr() -> bool {
+    PROGRESS_TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Sets whether long-running steps, such as creating a jail and installing packages, show a
+/// progress bar in place of their streamed output.
+///
+/// Progress bars redraw a line of the progress output stream and so should only be enabled when
+/// that stream is a terminal. They are never shown when logging more verbosely than the default.
+pub fn set_progress_bars(enabled: bool) {
+    PROGRESS_BARS.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether long-running steps show a progress bar in place of their streamed output.
+pub fn progress_bars() -> bool {
+    PROGRESS_BARS.load(Ordering::Relaxed)
+}
+
+/// How much detail is output while provisioning, from only errors to every script run in a jail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors are output.
+    Silent,
+    /// Only warnings and errors are output, without steps or command output.
+    Quiet,
+    /// Steps and the output of the commands they run are output.
+    Normal,
+    /// The details of each step, such as the values it determined, are also logged.
+    Detail,
+    /// Each command which is run is also logged, with its arguments.
+    Commands,
+    /// Each script which is run in a jail is also logged, with its exported environment.
+    Scripts,
+}
+
+impl Verbosity {
+    /// Returns the verbosity for a number of `-v` (verbose) and `-q` (quiet) flags.
+    pub fn from_flags(verbose: usize, quiet: usize) -> Self {
+        match (verbose, quiet) {
+            (0, 0) => Self::Normal,
+            (0, 1) => Self::Quiet,
+            (0, _) => Self::Silent,
+            (1, _) => Self::Detail,
+            (2, _) => Self::Commands,
+            _ => Self::Scripts,
+        }
+    }
+
+    /// Returns the most detailed level of log records which are output at this verbosity.
+    pub fn level_filter(self) -> log::LevelFilter {
+        match self {
+            Self::Silent => log::LevelFilter::Error,
+            Self::Quiet => log::LevelFilter::Warn,
+            Self::Normal => log::LevelFilter::Info,
+            Self::Detail => log::LevelFilter::Debug,
+            Self::Commands | Self::Scripts => log::LevelFilter::Trace,
+        }
+    }
+}
+
+/// Sets how much detail is output while provisioning.
+///
+/// The maximum log level should be set to the verbosity's [`Verbosity::level_filter`], as the
+/// output of steps and commands follows the log level, while this additionally determines whether
+/// the scripts run in a jail are logged.
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Returns how much detail is output while provisioning.
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Silent,
+        1 => Verbosity::Quiet,
+        2 => Verbosity::Normal,
+        3 => Verbosity::Detail,
+        4 => Verbosity::Commands,
+        _ => Verbosity::Scripts,
+    }
+}
+
+/// Sets the interval, in seconds, after which a silent long-running step prints a line saying that
+/// it is still running along with its elapsed time. An interval of 0 disables these lines.
+///
+/// Heartbeat lines keep systems which stop jobs without output, such as CI, from stopping a step
+/// like creating a thick jail. They are not printed while a progress bar is shown.
+pub fn set_heartbeat_interval(secs: u64) {
+    HEARTBEAT_SECS.store(secs, Ordering::Relaxed);
+}
+
+/// Returns the interval after which a silent long-running step prints a heartbeat line, if any.
+pub fn heartbeat_interval() -> Option<Duration> {
+    match HEARTBEAT_SECS.load(Ordering::Relaxed) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// Sets whether each line of progress output, including the output of commands, is prefixed with
+/// an RFC 3339 timestamp in UTC.
+///
+/// Progress bars are not shown while timestamps are enabled, so that every line is timestamped.
+pub fn set_timestamps(enabled: bool) {
+    TIMESTAMPS.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether each line of progress output is prefixed with a timestamp.
+pub fn timestamps() -> bool {
+    TIMESTAMPS.load(Ordering::Relaxed)
+}
+
+/// Returns a line of progress output, prefixed with the current time if timestamps are enabled.
+fn stamped(line: String) -> String {
+    if timestamps() {
+        format!("{} {}", audit::timestamp(SystemTime::now()), line)
+    } else {
+        line
+    }
+}
+
+/// Sets whether iocage's own debug flag is passed to the iocage invocations whose output is
+/// streamed, such as creating a jail or running a script in it. Invocations whose output is
+/// parsed, such as listing jails, are never given the flag.
+///
+/// This is useful when debugging a failure inside of iocage itself.
+pub fn set_iocage_debug(enabled: bool) {
+    IOCAGE_DEBUG.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether iocage's own debug flag is passed to the iocage invocations whose output is
+/// streamed.
+pub fn iocage_debug() -> bool {
+    IOCAGE_DEBUG.load(Ordering::Relaxed)
+}
+
+/// Determines and returns a default gateway IP address by querying the `netstat` command.
+///
+/// # Errors
+///
+/// Returns an `Err` if:
+///
+/// * The `netstat` command cannot be found
+/// * The output of the command cannot be parsed as UTF-8
+/// * No line of output starting with `"default"` can be found
+/// * The default line cannot be successfully split
+/// * The IP address string cannot be parsed as an IP address
+pub fn netstat_gateway_addr() -> result::Result<IpAddr, GatewayError> {
+    netstat_default_route()?
+        .nth(1)
+        .ok_or(GatewayError::NetstatParse(
+            "second column not found on default line",
+        ))?
+        .parse()
+        .map_err(GatewayError::IpAddr)
+}
+
+/// Determines and returns the network interface of the host's default route by querying the
+/// `netstat` command.
+///
+/// # Errors
+///
+/// Returns an `Err` if:
+///
+/// * The `netstat` command cannot be found
+/// * The output of the command cannot be parsed as UTF-8
+/// * No line of output starting with `"default"` can be found
+/// * The default line has no interface column
+pub fn netstat_gateway_interface() -> result::Result<String, GatewayError> {
+    netstat_default_route()?
+        .nth(3)
+        .ok_or(GatewayError::NetstatParse(
+            "interface column not found on default line",
+        ))
+}
+
+/// Returns the whitespace separated columns of the host's default IPv4 route, as printed by the
+/// `netstat` command.
+fn netstat_default_route() -> result::Result<std::vec::IntoIter<String>, GatewayError> {
+    Ok(str::from_utf8(
+        Command::new("netstat")
+            .args(&["-r", "-n", "-f", "inet"])
+            .output()
+            .map_err(GatewayError::Cmd)?
+            .stdout
+            .as_ref(),
+    )
+    .map_err(GatewayError::Utf8)?
+    .lines()
+    .find(|line| line.starts_with("default"))
+    .ok_or(GatewayError::NetstatParse("default line not found"))?
+    .split_ascii_whitespace()
+    .map(str::to_string)
+    .collect::<Vec<_>>()
+    .into_iter())
+}
+
+/// Returns a default release value based on the current host.
+pub fn default_release() -> String {
+    host::kernel_release()
+        .split('-')
+        .map(|s| if s == "STABLE" { "RELEASE" } else { s })
+        .take(2)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Returns a `User` for a given name, if one exists.
+///
+/// If `None` is provided as an argument, then `Ok(None)` will be returned.
+///
+/// # Errors
+///
+/// Returns an `Err` if an associated system user cannot be found for the given user name.
+fn find_user(user_str: Option<&str>) -> Result<Option<User>> {
+    match user_str {
+        Some(user_str) => match host::user_by_name(user_str) {
+            Some(user_info) => Ok(Some(user_info)),
+            None => Err(Error::NoUser(user_str.to_string())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns a `Group` for a given group ID (i.e. `gid`).
+///
+/// # Errors
+///
+/// Returns an `Err` if an associated system group cannot be found for the given group ID.
+fn find_group(gid: u32) -> Result<Group> {
+    host::group_by_gid(gid).ok_or(Error::NoGid(gid))
+}
+
+/// Returns the supplementary groups of a user on the host, excluding their primary group and the
+/// `wheel` group which is always granted.
+fn supplementary_groups(user: &User) -> Vec<Group> {
+    host::user_groups(user)
+        .into_iter()
+        .filter(|group| group.gid() != user.primary_group_id() && group.name() != "wheel")
+        .collect()
+}
+
+/// Reads the CA certificates to install in the jail from the host and returns their file names
+/// and contents.
+///
+/// # Errors
+///
+/// Returns an `Err` if a certificate file or the host's certificate directory could not be read.
+fn read_ca_certs(spec: &JailSpec) -> Result<Vec<(String, String)>> {
+    let mut paths = Vec::new();
+
+    if spec.copy_ca_certs {
+        let dir = Path::new(HOST_CA_CERTS_DIR);
+        if dir.is_dir() {
+            let entries =
+                fs::read_dir(dir).map_err(|err| Error::ReadCaCerts(dir.to_path_buf(), err))?;
+            for entry in entries {
+                let path = entry
+                    .map_err(|err| Error::ReadCaCerts(dir.to_path_buf(), err))?
+                    .path();
+                match path.extension().and_then(OsStr::to_str) {
+                    Some("pem") | Some("crt") => paths.push(path),
+                    _ => {}
+                }
+            }
+            paths.sort();
+        }
+    }
+    paths.extend(spec.ca_certs.iter().cloned());
+
+    let mut certs: Vec<(String, String)> = Vec::new();
+    for path in paths {
+        let stem = path
+            .file_stem()
+            .unwrap_or_else(|| OsStr::new("ca"))
+            .to_string_lossy()
+            .into_owned();
+        // Certificates with the same file stem, such as a host certificate also given with
+        // --ca-cert, are given distinct names so that none is overwritten
+        let mut file_name = format!("{}.pem", stem);
+        let mut n = 0;
+        while certs.iter().any(|(name, _)| name == &file_name) {
+            n += 1;
+            file_name = format!("{}-{}.pem", stem, n);
+        }
+        let contents =
+            fs::read_to_string(&path).map_err(|err| Error::ReadCaCerts(path.clone(), err))?;
+        certs.push((file_name, contents));
+    }
+
+    Ok(certs)
+}
+
+/// Reads existing SSH host keys from a directory on the host and returns their file names and
+/// contents.
+///
+/// # Errors
+///
+/// Returns an `Err` if the directory or a key file could not be read.
+fn read_ssh_host_key_files(dir: &Path) -> Result<Vec<(String, String)>> {
+    let entries =
+        fs::read_dir(dir).map_err(|err| Error::ReadSshHostKeys(dir.to_path_buf(), err))?;
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|err| Error::ReadSshHostKeys(dir.to_path_buf(), err))?
+            .path();
+        let is_host_key = path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .map(|name| {
+                name.starts_with("ssh_host_")
+                    && (name.ends_with("_key") || name.ends_with("_key.pub"))
+            })
+            .unwrap_or(false);
+        if is_host_key {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    paths
+        .into_iter()
+        .map(|path| {
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let contents = fs::read_to_string(&path)
+                .map_err(|err| Error::ReadSshHostKeys(path.clone(), err))?;
+            if !file_name.ends_with(".pub") {
+                register_secret(&contents);
+            }
+            Ok((file_name, contents))
+        })
+        .collect()
+}
+
+/// Ensures that all of the given jails exist.
+///
+/// # Errors
+///
+/// Returns an `Err` if the existing jails could not be listed or if any jail does not exist.
+fn ensure_jails_exist(names: &[String]) -> Result<()> {
+    let existing = list_jail_names()?;
+
+    match names.iter().find(|name| !existing.contains(name)) {
+        Some(name) => Err(Error::DependencyNotFound(name.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Ensures that the given routing table (FIB) number is available on the host.
+///
+/// # Errors
+///
+/// Returns an `Err` if the number of routing tables could not be determined or if the FIB number
+/// is out of range.
+fn ensure_fib_available(fib: u32) -> Result<()> {
+    let fibs = sysctl_u32("net.fibs")?;
+
+    if fib < fibs {
+        Ok(())
+    } else {
+        Err(Error::FibUnavailable(fib, fibs))
+    }
+}
+
+/// Returns the host network interface of a jail's network, which is either the given interface or
+/// that of the host's default route.
+///
+/// # Errors
+///
+/// Returns an `Err` if no interface is given and the interface of the default route could not be
+/// determined.
+fn host_interface(spec: &JailSpec) -> Result<String> {
+    match spec.interface {
+        Some(ref interface) => Ok(interface.clone()),
+        None => netstat_gateway_interface().map_err(Error::DefaultInterface),
+    }
+}
+
+/// Creates the host bridge which VNET jails are attached to, if it is missing, and adds the given
+/// uplink interface to it, unless it is already a member.
+///
+/// The bridge and its members are also added to the host's `/etc/rc.conf` so that they persist
+/// across a reboot.
+///
+/// # Errors
+///
+/// Returns an `Err` if the uplink interface doesn't exist or if the bridge could not be
+/// inspected, created, or configured.
+fn setup_bridge(uplink: &str) -> Result<()> {
+    let mut cmd = Command::new("ifconfig");
+    cmd.arg("-l");
+    let interfaces = cmd_stdout(cmd).map_err(|err| Error::SetupBridge(BRIDGE, err))?;
+    let interfaces = interfaces.split_whitespace().collect::<Vec<_>>();
+    if !interfaces.contains(&uplink) {
+        return Err(Error::InterfaceNotFound(uplink.to_string()));
+    }
+
+    let mut cmds = Vec::new();
+    if interfaces.contains(&BRIDGE) {
+        let mut cmd = Command::new("ifconfig");
+        cmd.arg(BRIDGE);
+        let stdout = cmd_stdout(cmd).map_err(|err| Error::SetupBridge(BRIDGE, err))?;
+        if parse::bridge_members(&stdout).iter().any(|m| m == uplink) {
+            debug!(
+                "host bridge is set up; bridge={}, uplink={}",
+                BRIDGE, uplink
+            );
+            return Ok(());
+        }
+        info!("Adding '{}' to host bridge '{}'", uplink, BRIDGE);
+    } else {
+        info!("Creating host bridge '{}' over '{}'", BRIDGE, uplink);
+        let mut cmd = Command::new("ifconfig");
+        cmd.arg(BRIDGE).arg("create");
+        cmds.push(cmd);
+        let mut cmd = Command::new("sysrc");
+        cmd.arg(format!("cloned_interfaces+={}", BRIDGE));
+        cmds.push(cmd);
+    }
+    let mut cmd = Command::new("ifconfig");
+    cmd.arg(BRIDGE).arg("addm").arg(uplink).arg("up");
+    cmds.push(cmd);
+    let mut cmd = Command::new("sysrc");
+    cmd.arg(format!("ifconfig_{}+=addm {} up", BRIDGE, uplink));
+    cmds.push(cmd);
+
+    for cmd in cmds {
+        run_and_indent(cmd).map_err(|err| Error::SetupBridge(BRIDGE, err))?;
+    }
+
+    Ok(())
+}
+
+/// Ensures that the given CPU list is valid and names only CPUs which are available on the host.
+///
+/// # Errors
+///
+/// Returns an `Err` if the number of CPUs could not be determined or if the CPU list is invalid or
+/// names a CPU which is out of range.
+fn ensure_cpuset_available(cpuset: &str) -> Result<()> {
+    let ncpu = sysctl_u32("hw.ncpu")?;
+
+    match parse::cpu_list(cpuset) {
+        Some(cpus) if cpus.iter().all(|cpu| *cpu < ncpu) => Ok(()),
+        _ => Err(Error::CpusetUnavailable(cpuset.to_string(), ncpu)),
+    }
+}
+
+/// Ensures that the host has the free memory and load average required by the spec before any
+/// heavy operation, such as creating the jail, starts.
+///
+/// A busy host is waited on for up to the spec's `wait_for_capacity`, or reported as a warning
+/// when it is unset.
+///
+/// # Errors
+///
+/// Returns an `Err` if the host's resources could not be read, or if it is still busy once the
+/// wait is over.
+fn ensure_capacity(spec: &JailSpec, warnings: &mut Vec<String>) -> Result<()> {
+    if spec.min_free_memory.is_none() && spec.max_load.is_none() {
+        return Ok(());
+    }
+    // A wait too long to be represented has no deadline
+    let deadline =
+        Instant::now().checked_add(Duration::from_secs(spec.wait_for_capacity.unwrap_or(0)));
+    loop {
+        let shortage = match capacity_shortage(spec)? {
+            Some(shortage) => shortage,
+            None => return Ok(()),
+        };
+        match spec.wait_for_capacity {
+            None => {
+                return warn(
+                    spec,
+                    warnings,
+                    format!(
+                        "host is low on resources ({}), provisioning anyway",
+                        shortage
+                    ),
+                );
+            }
+            Some(wait) if deadline.map_or(false, |deadline| Instant::now() >= deadline) => {
+                return Err(Error::HostBusy(shortage, wait));
+            }
+            Some(_) => {
+                info!(
+                    "Host is low on resources ({}), waiting for capacity",
+                    shortage
+                );
+                let remaining = deadline.map_or(CAPACITY_POLL_INTERVAL, |deadline| {
+                    deadline.saturating_duration_since(Instant::now())
+                });
+                thread::sleep(CAPACITY_POLL_INTERVAL.min(remaining));
+            }
+        }
+    }
+}
+
+/// Returns a description of how the host's resources fall short of the spec's thresholds, if
+/// they do.
+///
+/// # Errors
+///
+/// Returns an `Err` if the host's free memory or load average could not be read.
+fn capacity_shortage(spec: &JailSpec) -> Result<Option<String>> {
+    let mut shortages = Vec::new();
+    if let Some(min) = spec.min_free_memory {
+        // Inactive pages are reclaimed as soon as they are needed, so they count as free
+        let pages = u64::from(sysctl_u32("vm.stats.vm.v_free_count")?)
+            + u64::from(sysctl_u32("vm.stats.vm.v_inactive_count")?);
+        let free = pages * u64::from(sysctl_u32("hw.pagesize")?) / (1024 * 1024);
+        if free < min {
+            shortages.push(format!("{} MiB of memory free, below {} MiB", free, min));
+        }
+    }
+    if let Some(max) = spec.max_load {
+        let value = sysctl("vm.loadavg")?;
+        let load = parse::loadavg(&value).ok_or(Error::SysctlParse("vm.loadavg", value))?;
+        if load > max {
+            shortages.push(format!("load average of {:.2}, above {}", load, max));
+        }
+    }
+
+    Ok(if shortages.is_empty() {
+        None
+    } else {
+        Some(shortages.join(", "))
+    })
+}
+
+/// Returns the value of a numeric sysctl on the host.
+///
+/// # Errors
+///
+/// Returns an `Err` if the `sysctl` program fails or if its output is not a number.
+fn sysctl_u32(name: &'static str) -> Result<u32> {
+    let value = sysctl(name)?;
+
+    value.parse().map_err(|_| Error::SysctlParse(name, value))
+}
+
+/// Returns the value of a sysctl on the host.
+///
+/// # Errors
+///
+/// Returns an `Err` if the `sysctl` program fails.
+fn sysctl(name: &'static str) -> Result<String> {
+    let mut cmd = Command::new("sysctl");
+    cmd.arg("-n").arg(name);
+
+    Ok(cmd_stdout(cmd)
+        .map_err(|err| Error::Sysctl(name, err))?
+        .trim()
+        .to_string())
+}
+
+/// Returns the names of all existing jails.
+///
+/// # Errors
+///
+/// Returns an `Err` if the `iocage` program was not found or exits with a code that is not zero.
+fn list_jail_names() -> Result<Vec<String>> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("list").arg("-h");
+    let stdout = cmd_stdout(cmd).map_err(Error::IocageList)?;
+
+    Ok(parse::list(&stdout)
+        .into_iter()
+        .map(|jail| jail.name)
+        .collect())
+}
+
+/// Returns the additional iocage properties to set when the jail is created.
+fn jail_properties(spec: &JailSpec) -> Vec<String> {
+    let mut props = Vec::new();
+
+    if let Some(ref description) = spec.description {
+        props.push(format!("notes={}", description));
+    }
+    if let Some(priority) = spec.priority {
+        props.push(format!("priority={}", priority));
+    }
+    if !spec.depends.is_empty() {
+        props.push(format!("depends={}", spec.depends.join(" ")));
+    }
+    if let Some(fib) = spec.fib {
+        props.push(format!("exec_fib={}", fib));
+    }
+    if let Some(ref cpuset) = spec.cpuset {
+        props.push(format!("cpuset={}", cpuset));
+    }
+    if let Some(securelevel) = spec.securelevel {
+        props.push(format!("securelevel={}", securelevel));
+    }
+    if let Some(sysvipc) = spec.sysvipc {
+        for key in &["sysvmsg", "sysvsem", "sysvshm"] {
+            props.push(format!("{}={}", key, sysvipc.as_str()));
+        }
+    }
+    if spec.vpn_ready {
+        props.push("allow_tun=1".to_string());
+        props.push("allow_raw_sockets=1".to_string());
+    }
+    for recipe in &spec.recipes {
+        for prop in recipe.properties() {
+            let key = prop.split('=').next();
+            if !props
+                .iter()
+                .any(|explicit| explicit.split('=').next() == key)
+            {
+                props.push(prop.to_string());
+            }
+        }
+    }
+    for (key, cmds) in &[
+        ("exec_prestart", &spec.exec_prestart),
+        ("exec_poststart", &spec.exec_poststart),
+        ("exec_prestop", &spec.exec_prestop),
+        ("exec_poststop", &spec.exec_poststop),
+    ] {
+        // Each property holds a single command line which is run by a shell, so multiple commands
+        // are chained to run in order and stop at the first failure. As the property is passed to
+        // iocage as a single program argument, no further quoting is required.
+        if !cmds.is_empty() {
+            props.push(format!("{}={}", key, cmds.join(" && ")));
+        }
+    }
+
+    if spec.base_props.is_empty() {
+        props
+    } else {
+        let explicit = props
+            .iter()
+            .filter_map(|prop| prop.split('=').next())
+            .collect::<Vec<_>>();
+        let mut base = spec
+            .base_props
+            .iter()
+            .filter(|(key, _)| {
+                !explicit.contains(&key.as_str())
+                    && !CREATE_PROPERTIES.contains(&key.as_str())
+                    && !is_identity_property(key)
+            })
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>();
+        base.extend(props);
+        base
+    }
+}
+
+/// Returns the description of a jail, stored in its iocage `notes` property.
+///
+/// # Errors
+///
+/// Returns an `Err` if the property could not be read.
+fn jail_description(jail_name: &str) -> Result<Option<String>> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("get").arg("notes").arg(jail_name);
+    let notes = cmd_stdout(cmd).map_err(|err| Error::IocageGet(jail_name.to_string(), err))?;
+
+    Ok(parse::property(&notes))
+}
+
+/// Records a non-fatal issue as a warning, unless the spec requires warnings to be treated as
+/// errors.
+///
+/// # Errors
+///
+/// Returns an `Err` with the warning if the spec is strict.
+fn warn(spec: &JailSpec, warnings: &mut Vec<String>, warning: String) -> Result<()> {
+    if spec.strict {
+        return Err(Error::StrictWarning(warning));
+    }
+    log::warn!("{}", warning);
+    warnings.push(warning);
+
+    Ok(())
+}
+
+/// Runs a step, retrying it with exponential backoff after transient failures as allowed by the
+/// spec.
+///
+/// A step which only succeeds after being retried is recorded as a warning.
+///
+/// # Errors
+///
+/// Returns an `Err` if the step fails with a non-transient error, fails on its last attempt, or
+/// needed a retry when the spec is strict.
+fn with_retries<T, F>(
+    spec: &JailSpec,
+    warnings: &mut Vec<String>,
+    step_name: &str,
+    mut step: F,
+) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let base_delay = Duration::from_secs(spec.retry_delay);
+    let mut attempt = 0;
+
+    loop {
+        match step() {
+            Err(err) if attempt < spec.retries && err.is_transient() && !cancelled() => {
+                let delay = base_delay
+                    .checked_mul(2u32.saturating_pow(attempt))
+                    .unwrap_or(base_delay);
+                attempt += 1;
+                eoutput!(
+                    "{}; retrying in {}s (retry {} of {})",
+                    err,
+                    delay.as_secs(),
+                    attempt,
+                    spec.retries
+                );
+                thread::sleep(delay);
+            }
+            Ok(value) if attempt > 0 => {
+                let warning = format!("{} succeeded after {} retries", step_name, attempt);
+                warn(spec, warnings, warning)?;
+                return Ok(value);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Returns whether or not an iocage property identifies a jail or describes its state, and so is
+/// never copied from or shared with another jail.
+pub fn is_identity_property(key: &str) -> bool {
+    IDENTITY_PROPERTIES.contains(&key) || (key.starts_with("vnet") && key.ends_with("_mac"))
+}
+
+/// Returns the ZFS properties to set on the jail's dataset.
+fn zfs_properties(spec: &JailSpec) -> Vec<String> {
+    let mut props = spec.zfs_props.clone();
+    if let Some(ref quota) = spec.disk_quota {
+        props.push(format!("quota={}", quota));
+    }
+    if let Some(ref reserve) = spec.disk_reserve {
+        props.push(format!("refreservation={}", reserve));
+    }
+    if let Some(ref schedule) = spec.snapshot_schedule {
+        props.push("com.sun:auto-snapshot=true".to_string());
+        // zfs-auto-snapshot also takes frequent snapshots every 15 minutes unless disabled
+        props.push("com.sun:auto-snapshot:frequent=false".to_string());
+        for (interval, count) in schedule.intervals() {
+            props.push(format!("com.sun:auto-snapshot:{}={}", interval, count > 0));
+        }
+    }
+
+    props
+}
+
+/// Returns the name of the active iocage pool.
+///
+/// # Errors
+///
+/// Returns an `Err` if the active iocage pool could not be determined.
+fn iocage_pool() -> Result<String> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("get").arg("--pool");
+
+    Ok(cmd_stdout(cmd)
+        .map_err(Error::IocagePool)?
+        .trim()
+        .to_string())
+}
+
+/// Returns the version of the installed iocage.
+///
+/// # Errors
+///
+/// Returns an `Err` if iocage could not be run or its version could not be parsed.
+pub fn iocage_version() -> Result<IocageVersion> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("--version");
+
+    cmd_stdout(cmd).map_err(Error::IocageVersion)?.parse()
+}
+
+/// A program required on the host which is not installed, with the package which provides it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Prereq {
+    /// Name of the missing program.
+    pub program: &'static str,
+    /// Name of the package which provides the program.
+    pub package: String,
+}
+
+/// Returns the programs required on the host which are not installed and can be installed from
+/// packages, along with the package providing each.
+///
+/// Only iocage is considered, as the other required programs (such as `zfs` and `netstat`) are
+/// part of the base system. The iocage package for the newest available Python flavor is chosen.
+///
+/// # Errors
+///
+/// Returns an `Err` if the package repository could not be queried, or if no package provides a
+/// missing program.
+pub fn missing_prereqs() -> Result<Vec<Prereq>> {
+    let mut missing = Vec::new();
+    if !host_has_program("iocage") {
+        let mut cmd = Command::new("pkg");
+        cmd.arg("rquery")
+            .arg("-x")
+            .arg("%n")
+            .arg("^py3[0-9]+-iocage$");
+        let stdout = cmd_stdout(cmd).map_err(Error::PrereqQuery)?;
+        let package = parse::iocage_package(&stdout).ok_or(Error::PrereqUnavailable("iocage"))?;
+        missing.push(Prereq {
+            program: "iocage",
+            package,
+        });
+    }
+
+    Ok(missing)
+}
+
+/// Installs the packages which provide the given missing host programs.
+///
+/// # Errors
+///
+/// Returns an `Err` if the packages could not be installed.
+pub fn install_prereqs(prereqs: &[Prereq]) -> Result<()> {
+    let mut cmd = Command::new("pkg");
+    cmd.env("ASSUME_ALWAYS_YES", "yes")
+        .arg("install")
+        .args(prereqs.iter().map(|prereq| &prereq.package));
+
+    run_and_indent(cmd).map_err(Error::InstallPrereqs)
+}
+
+/// Returns whether or not an executable program with the given name is in the host's `PATH`.
+fn host_has_program(program: &str) -> bool {
+    let path = match std::env::var_os("PATH") {
+        Some(path) => path,
+        None => return false,
+    };
+    std::env::split_paths(&path).any(|dir| {
+        let meta = match fs::metadata(dir.join(program)) {
+            Ok(meta) => meta,
+            Err(_) => return false,
+        };
+        #[cfg(unix)]
+        let executable = std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) & 0o111 != 0;
+        #[cfg(not(unix))]
+        let executable = true;
+        meta.is_file() && executable
+    })
+}
+
+/// Returns the version of the installed iocage, ensuring that it is supported.
+///
+/// # Errors
+///
+/// Returns an `Err` if the version could not be determined or is not supported.
+fn ensure_iocage_supported() -> Result<IocageVersion> {
+    let version = iocage_version()?;
+    debug!("detected iocage version; version={}", version);
+    if version.is_supported() {
+        Ok(version)
+    } else {
+        Err(Error::UnsupportedIocageVersion(version))
+    }
+}
+
+/// Returns the UUID of an existing jail.
+///
+/// # Errors
+///
+/// Returns an `Err` if the property could not be read.
+fn jail_uuid(jail_name: &str) -> Result<String> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("get").arg("host_hostuuid").arg(jail_name);
+    let stdout = cmd_stdout(cmd).map_err(|err| Error::IocageUuid(jail_name.to_string(), err))?;
+
+    Ok(parse::property(&stdout).unwrap_or_else(|| String::from("-")))
+}
+
+/// Runs the recovery steps for stale iocage state which makes `iocage create` fail.
+///
+/// An earlier jail with the same name is force stopped (ignoring any failure) and waited on until
+/// it is no longer dying, the VNET interfaces of jails which no longer exist are destroyed, and
+/// the jail's dataset is destroyed if it has no iocage configuration. The steps which concern the
+/// jail are skipped when iocage will name it.
+///
+/// # Errors
+///
+/// Returns an `Err` if a recovery step fails.
+fn repair_stale_state(pool: &str, jail_name: Option<&str>) -> Result<()> {
+    if let Some(jail_name) = jail_name {
+        info!("Stopping earlier jail '{}'", jail_name);
+        let mut cmd = iocage_command();
+        cmd.arg("stop").arg("--force").arg(jail_name);
+        if let Err(err) = run_and_indent(cmd) {
+            debug!("stopping earlier jail '{}' failed: {}", jail_name, err);
+        }
+        wait_for_dying_jail(jail_name)?;
+    }
+
+    let mut cmd = Command::new("jls");
+    cmd.arg("-d").arg("jid");
+    let jids = cmd_stdout(cmd).map_err(|err| Error::RepairStaleState("jls", err))?;
+    let mut cmd = Command::new("ifconfig");
+    cmd.arg("-l");
+    let interfaces = cmd_stdout(cmd).map_err(|err| Error::RepairStaleState("ifconfig", err))?;
+    for interface in parse::stale_vnet_interfaces(&interfaces, &jids) {
+        info!("Destroying stale interface '{}'", interface);
+        let mut cmd = Command::new("ifconfig");
+        cmd.arg(&interface).arg("destroy");
+        run_and_indent(cmd).map_err(|err| Error::RepairStaleState("ifconfig destroy", err))?;
+    }
+
+    if let Some(jail_name) = jail_name {
+        let dataset = jail_dataset(pool, jail_name);
+        let mut cmd = Command::new("zfs");
+        cmd.arg("list")
+            .arg("-H")
+            .arg("-o")
+            .arg("name")
+            .arg(&dataset);
+        if cmd_stdout(cmd).is_ok() {
+            let mountpoint = zfs_mountpoint(&dataset)?;
+            if !Path::new(&mountpoint).join("config.json").exists() {
+                info!("Destroying dataset '{}' without a configuration", dataset);
+                let mut cmd = Command::new("zfs");
+                cmd.arg("destroy").arg("-r").arg(&dataset);
+                run_and_indent(cmd).map_err(|err| Error::RepairStaleState("zfs destroy", err))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Waits for a stopped jail to no longer be dying in the kernel.
+///
+/// # Errors
+///
+/// Returns an `Err` if the jail is still dying after [`DYING_JAIL_TIMEOUT`].
+fn wait_for_dying_jail(jail_name: &str) -> Result<()> {
+    // iocage names its jails `ioc-<NAME>`, with any dots replaced
+    let jail = format!("ioc-{}", jail_name.replace('.', "_"));
+    let deadline = Instant::now() + DYING_JAIL_TIMEOUT;
+    loop {
+        let mut cmd = Command::new("jls");
+        cmd.arg("-d").arg("-j").arg(&jail).arg("jid");
+        // `jls -j` exits with a code that is not zero once the jail is gone
+        if cmd_stdout(cmd).is_err() {
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            return Err(Error::JailStillDying(
+                jail_name.to_string(),
+                DYING_JAIL_TIMEOUT.as_secs(),
+            ));
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
+
+/// Adds a stanza for a jail's dataset, with its snapshot schedule, to the host's sanoid
+/// configuration unless the configuration already has one for the dataset.
+///
+/// # Errors
+///
+/// Returns an `Err` if the sanoid configuration could not be read or written.
+fn register_sanoid(report: &ProvisionReport) -> Result<()> {
+    let path = Path::new(SANOID_CONF);
+    let conf =
+        fs::read_to_string(path).map_err(|err| Error::WriteSanoidConf(path.to_path_buf(), err))?;
+    let header = format!("[{}]", report.dataset);
+    if conf.lines().any(|line| line.trim() == header) {
+        info!("Keeping existing sanoid stanza for '{}'", report.dataset);
+        return Ok(());
+    }
+
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(path)
+        .map_err(|err| Error::WriteSanoidConf(path.to_path_buf(), err))?;
+    let separator = if conf.is_empty() || conf.ends_with("\n\n") {
+        ""
+    } else if conf.ends_with('\n') {
+        "\n"
+    } else {
+        "\n\n"
+    };
+    file.write_all(format!("{}{}", separator, emitters::sanoid(report)).as_bytes())
+        .map_err(|err| Error::WriteSanoidConf(path.to_path_buf(), err))
+}
+
+/// Returns the name of the ZFS dataset for the given jail.
+fn jail_dataset(pool: &str, jail_name: &str) -> String {
+    format!("{}/iocage/jails/{}", pool, jail_name)
+}
+
+/// Ensures that a requested parent dataset matches where iocage will create the jail.
+///
+/// # Errors
+///
+/// Returns an `Err` if the requested parent dataset is not the active iocage pool or one of its
+/// iocage datasets.
+fn ensure_dataset_parent(parent: &str, pool: &str) -> Result<()> {
+    let parent = parent.trim_end_matches('/');
+    let iocroot = format!("{}/iocage", pool);
+    let jails = format!("{}/iocage/jails", pool);
+
+    if parent == pool || parent == iocroot || parent == jails {
+        Ok(())
+    } else {
+        Err(Error::DatasetParentUnsupported(
+            parent.to_string(),
+            pool.to_string(),
+        ))
+    }
+}
+
+/// Reads the contents of a file on the host.
+///
+/// # Errors
+///
+/// Returns an `Err` if the file could not be read.
+fn read_host_file(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|err| Error::ReadHostFile(path.to_path_buf(), err))
+}
+
+/// Reads and validates a package list JSON file on the host, returning its contents and package
+/// names.
+///
+/// # Errors
+///
+/// Returns an `Err` if the file could not be read or is not a valid package list.
+fn read_pkglist(path: &Path) -> Result<(String, Vec<String>)> {
+    let contents = read_host_file(path)?;
+    let pkgs = parse::pkglist(&contents)
+        .map_err(|problem| Error::InvalidPkglist(path.to_path_buf(), problem))?;
+
+    Ok((contents, pkgs))
+}
+
+/// Reads and parses a cloud-config user-data file on the host.
+///
+/// # Errors
+///
+/// Returns an `Err` if the file could not be read or is not supported user-data.
+fn read_user_data(path: &Path) -> Result<UserData> {
+    UserData::parse(&read_host_file(path)?)
+        .map_err(|err| Error::ParseUserData(path.to_path_buf(), err))
+}
+
+/// Returns the list of packages to be installed in the jail when it is created.
+fn packages<'a>(
+    spec: &'a JailSpec,
+    user: Option<&User>,
+    user_data: Option<&'a UserData>,
+) -> Vec<&'a str> {
+    let mut pkgs = Vec::new();
+
+    if user.is_some()
+        || (spec.automation_user.is_some() && !spec.automation_commands.is_empty())
+        || user_data
+            .iter()
+            .any(|data| data.users.iter().any(|user| !user.sudo.is_empty()))
+    {
+        pkgs.push("sudo");
+    }
+    if let Some(user) = user {
+        let shell = user
+            .shell()
+            .file_name()
+            .unwrap_or_else(|| OsStr::new(""))
+            .to_string_lossy();
+        if shell == "bash" {
+            pkgs.push("bash");
+        }
+    }
+    if spec.ports {
+        pkgs.push("portmaster");
+    }
+    if spec.copy_ca_certs || !spec.ca_certs.is_empty() {
+        pkgs.push("ca_root_nss");
+    }
+    if let Some(agent) = spec.monitoring {
+        pkgs.push(agent.package());
+    }
+    if spec.vpn_ready {
+        pkgs.push("wireguard-tools");
+    }
+    for recipe in &spec.recipes {
+        for pkg in recipe.packages() {
+            if !pkgs.contains(pkg) {
+                pkgs.push(pkg);
+            }
+        }
+    }
+    let extra = spec
+        .packages
+        .iter()
+        .chain(user_data.iter().flat_map(|data| data.packages.iter()));
+    for pkg in extra {
+        if !pkgs.contains(&pkg.as_str()) {
+            pkgs.push(pkg);
+        }
+    }
+
+    pkgs
+}
+
+/// Renders the package list JSON for the `iocage create` subcommand from the given packages and
+/// any extra keys.
+///
+/// The packages are always the `pkgs` key, replacing a `pkgs` key in `extra`.
+pub fn pkglist_json(pkgs: &[&str], extra: &BTreeMap<String, serde_json::Value>) -> String {
+    let mut object = extra.clone();
+    object.insert("pkgs".to_string(), serde_json::json!(pkgs));
+
+    serde_json::to_string(&object).expect("json values always serialize")
+}
+
+/// Creates a package list JSON file for the `iocage create` subcommand and returns the file path.
+///
+/// # Errors
+///
+/// Returns an `Err` if the JSON file could not be successfully created and written.
+fn create_pkglist_json(json_str: &str) -> io::Result<NamedTempFile> {
+    let json = tempfile::Builder::new()
+        .prefix("pkglist")
+        .suffix(".json")
+        .rand_bytes(5)
+        .tempfile()?;
+    fs::write(json.path(), json_str.as_bytes())?;
+
+    Ok(json)
+}
+
+/// Configures pkg(8) in the given jail to use the mounted local package repository.
+///
+/// If `offline` is `true`, then the default remote `FreeBSD` repository is also disabled.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_pkg_repo(jail_name: &str, offline: bool) -> Result<()> {
+    let mut src = String::from("mkdir -p /usr/local/etc/pkg/repos\n");
+    src.push_str(&heredoc_to_file(
+        "/usr/local/etc/pkg/repos/iocage-provision-local.conf",
+        &format!(
+            concat!(
+                "local: {{\n",
+                "  url: \"file://{dir}\",\n",
+                "  signature_type: \"none\",\n",
+                "  priority: 100,\n",
+                "  enabled: yes\n",
+                "}}\n",
+            ),
+            dir = JAIL_PKG_REPO_DIR,
+        ),
+    ));
+    if offline {
+        src.push_str(&heredoc_to_file(
+            "/usr/local/etc/pkg/repos/FreeBSD.conf",
+            "FreeBSD: { enabled: no }\n",
+        ));
+    }
+
+    iocage_exec(jail_name, src).map_err(Error::ExecPkgRepo)
+}
+
+/// Bootstraps pkg(8) in the given jail unless it is already installed.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_pkg_bootstrap(jail_name: &str) -> Result<()> {
+    iocage_exec(
+        jail_name,
+        "if ! pkg -N >/dev/null 2>&1; then\n  env ASSUME_ALWAYS_YES=yes pkg bootstrap\nfi\n",
+    )
+    .map_err(Error::ExecPkgBootstrap)
+}
+
+/// Installs packages in the given jail, bootstrapping pkg(8) if required.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_pkg_install(jail_name: &str, pkgs: &[&str]) -> Result<()> {
+    iocage_exec(
+        jail_name,
+        format!(
+            "env ASSUME_ALWAYS_YES=yes pkg install {}\n",
+            pkgs.iter()
+                .map(|pkg| quote(pkg))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    )
+    .map_err(Error::ExecPkgInstall)
+}
+
+/// Checks that the given jail can resolve its pkg mirror and connect to its HTTPS port.
+///
+/// # Errors
+///
+/// Returns an `Err` if the check could not be executed in the jail, or if the mirror could not be
+/// resolved or connected to.
+fn exec_network_check(jail_name: &str) -> Result<()> {
+    let stdout =
+        iocage_exec_stdout(jail_name, script::network_check()).map_err(Error::ExecNetworkCheck)?;
+
+    let mut words = stdout.lines().last().unwrap_or_default().split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("resolve"), Some(host)) => Err(Error::MirrorUnresolvable(host.to_string())),
+        (Some("connect"), Some(host)) => Err(Error::MirrorUnreachable(host.to_string())),
+        (_, host) => {
+            debug!("jail reached its pkg mirror; host={}", host.unwrap_or("-"));
+            Ok(())
+        }
+    }
+}
+
+/// Waits for up to the given number of seconds until the given jail has a default route and, if
+/// `dns` is set, can resolve its pkg mirror.
+///
+/// # Errors
+///
+/// Returns an `Err` if the wait could not be executed in the jail, or if the network was not
+/// ready before the timeout.
+fn exec_network_wait(jail_name: &str, timeout: u64, dns: bool) -> Result<()> {
+    let stdout = iocage_exec_stdout(jail_name, script::network_wait(timeout, dns))
+        .map_err(Error::ExecNetworkWait)?;
+
+    let mut words = stdout.lines().last().unwrap_or_default().split_whitespace();
+    match (words.next(), words.next()) {
+        (Some("route"), _) => Err(Error::NetworkNotReady(
+            String::from("default route"),
+            timeout,
+        )),
+        (Some("resolve"), Some(host)) => Err(Error::NetworkNotReady(
+            format!("resolving {}", host),
+            timeout,
+        )),
+        (_, host) => {
+            debug!("jail network is ready; host={}", host.unwrap_or("-"));
+            Ok(())
+        }
+    }
+}
+
+/// Installs scripts in the given jail which run once on its next start.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_firstboot(jail_name: &str, scripts: &[String]) -> Result<()> {
+    iocage_exec(jail_name, script::firstboot(scripts)).map_err(Error::ExecFirstboot)
+}
+
+/// Applies cloud-config user-data to the given jail, other than its packages.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_user_data(jail_name: &str, user_data: &UserData) -> Result<()> {
+    iocage_exec(jail_name, script::user_data(user_data)).map_err(Error::ExecUserData)
+}
+
+/// Strips the given jail's base system down to a minimal one, keeping the toolchain if asked.
+///
+/// # Errors
+///
+/// Returns an `Err` if the jail's version could not be read, if there is no removal list for its
+/// major version, or if the removals were not successfully executed in the jail.
+fn exec_minimal(jail_name: &str, keep_toolchain: bool) -> Result<()> {
+    let version = iocage_exec_stdout(jail_name, "freebsd-version -u")
+        .map_err(Error::ExecMinimal)?
+        .trim()
+        .to_string();
+    let src = version
+        .split('.')
+        .next()
+        .and_then(|major| major.parse::<u32>().ok())
+        .and_then(|major| script::minimal_base(major, keep_toolchain))
+        .ok_or(Error::MinimalUnsupported(version))?;
+
+    iocage_exec(jail_name, src).map_err(Error::ExecMinimal)
+}
+
+/// Installs make.conf(5) and/or src.conf(5) files in the given jail.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_make_conf(jail_name: &str, make_conf: Option<&str>, src_conf: Option<&str>) -> Result<()> {
+    let mut src = String::new();
+    if let Some(contents) = make_conf {
+        src.push_str(&heredoc_to_file("/etc/make.conf", contents));
+    }
+    if let Some(contents) = src_conf {
+        src.push_str(&heredoc_to_file("/etc/src.conf", contents));
+    }
+
+    iocage_exec(jail_name, src).map_err(Error::ExecMakeConf)
+}
+
+/// Configures the ports build directories in the given jail.
+///
+/// As the ports tree is mounted read-only, all writable build directories are relocated under
+/// `/var/ports`.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_ports_config(jail_name: &str) -> Result<()> {
+    let mut src = format!(
+        "mkdir -p {wrk} {dist} {pkgs}\n",
+        dist = JAIL_PORTS_DISTFILES_DIR,
+        pkgs = JAIL_PORTS_PACKAGES_DIR,
+        wrk = JAIL_PORTS_WORK_DIR,
+    );
+    src.push_str(&heredoc(
+        "/etc/make.conf",
+        &format!(
+            "WRKDIRPREFIX?=\t{wrk}\nDISTDIR?=\t{dist}\nPACKAGES?=\t{pkgs}\n",
+            dist = JAIL_PORTS_DISTFILES_DIR,
+            pkgs = JAIL_PORTS_PACKAGES_DIR,
+            wrk = JAIL_PORTS_WORK_DIR,
+        ),
+        true,
+    ));
+
+    iocage_exec(jail_name, src).map_err(Error::ExecPortsConfig)
+}
+
+/// Installs CA certificates into the trust store of the given jail.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_ca_certs(jail_name: &str, certs: &[(String, String)]) -> Result<()> {
+    let mut src = format!("mkdir -p {}\n", JAIL_CA_CERTS_DIR);
+    for (file_name, contents) in certs {
+        src.push_str(&heredoc_to_file(
+            &format!("{}/{}", JAIL_CA_CERTS_DIR, file_name),
+            contents,
+        ));
+    }
+    src.push_str("certctl rehash\n");
+
+    iocage_exec(jail_name, src).map_err(Error::ExecCaCerts)
+}
+
+/// Prepares the sudo config in the given jail.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_sudo_config(jail_name: &str) -> Result<()> {
+    iocage_exec(jail_name, script::sudo_config()).map_err(Error::ExecSudoConfig)
+}
+
+/// Creates a system group in the given jail.
+///
+/// If no `gid` is given, the next free `gid` in the jail is used.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_create_group(jail_name: &str, group: &Group, gid: Option<u32>) -> Result<()> {
+    iocage_exec(
+        jail_name,
+        script::create_group(&group.name().to_string_lossy(), gid),
+    )
+    .map_err(Error::ExecCreateGroup)
+}
+
+/// Creates the given supplementary groups in the given jail if they don't already exist.
+///
+/// Each group is created with the same `gid` as on the host unless that `gid` is already taken in
+/// the jail, in which case the next free `gid` is used.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_copy_groups(jail_name: &str, groups: &[Group]) -> Result<()> {
+    let groups = groups
+        .iter()
+        .map(|group| (group.name().to_string_lossy().into_owned(), group.gid()))
+        .collect::<Vec<_>>();
+
+    iocage_exec(jail_name, script::copy_groups(&groups)).map_err(Error::ExecCopyGroups)
+}
+
+/// Creates a system user in the given jail.
+///
+/// If no `uid` is given, the next free `uid` in the jail is used.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_create_user(
+    jail_name: &str,
+    user: &User,
+    group_name: &str,
+    groups: &[Group],
+    uid: Option<u32>,
+    home: &UserHome,
+) -> Result<()> {
+    iocage_exec(
+        jail_name,
+        script::create_user(
+            &user.name().to_string_lossy(),
+            &user.shell().to_string_lossy(),
+            group_name,
+            &group_names(groups),
+            uid,
+            home,
+        ),
+    )
+    .map_err(Error::ExecCreateUser)
+}
+
+/// Adds an existing user in the given jail to the `wheel` group and the given supplementary
+/// groups so that it can be used in place of a new user.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_reuse_user(jail_name: &str, user_name: &str, groups: &[Group]) -> Result<()> {
+    iocage_exec(
+        jail_name,
+        script::reuse_user(user_name, &group_names(groups)),
+    )
+    .map_err(Error::ExecCreateUser)
+}
+
+/// Sets the login class and the locale of a user in the given jail.
+///
+/// The locale is set in the user's `~/.login_conf` file, if the user has a home directory, so that
+/// it applies to all login sessions.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_user_login(
+    jail_name: &str,
+    user_name: &str,
+    login_class: Option<&str>,
+    lang: Option<&str>,
+) -> Result<()> {
+    iocage_exec(jail_name, script::user_login(user_name, login_class, lang))
+        .map_err(Error::ExecUserLogin)
+}
+
+/// Returns the names of the given groups.
+fn group_names(groups: &[Group]) -> Vec<String> {
+    groups
+        .iter()
+        .map(|group| group.name().to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Returns the name of the group in the given jail which has the given `gid`, if any.
+///
+/// # Errors
+///
+/// Returns an `Err` if the group database of the jail could not be queried.
+fn jail_group_name(jail_name: &str, gid: u32) -> Result<Option<String>> {
+    jail_id_lookup(jail_name, "groupshow -g", gid)
+}
+
+/// Returns the name of the user in the given jail which has the given `uid`, if any.
+///
+/// # Errors
+///
+/// Returns an `Err` if the user database of the jail could not be queried.
+fn jail_user_name(jail_name: &str, uid: u32) -> Result<Option<String>> {
+    jail_id_lookup(jail_name, "usershow -u", uid)
+}
+
+/// Returns whether an executable file exists at the given path in the given jail.
+///
+/// # Errors
+///
+/// Returns an `Err` if the check could not be executed in the jail.
+fn jail_has_executable(jail_name: &str, path: &Path) -> Result<bool> {
+    let stdout = iocage_exec_stdout(
+        jail_name,
+        format!(
+            "if [ -x {} ]; then echo yes; fi",
+            quote(&path.to_string_lossy())
+        ),
+    )
+    .map_err(Error::ExecFileCheck)?;
+
+    Ok(stdout.trim() == "yes")
+}
+
+/// Runs a `pw` lookup of a numeric id in the given jail and returns the name of the matching
+/// entry, if any.
+///
+/// # Errors
+///
+/// Returns an `Err` if the lookup could not be executed in the jail.
+fn jail_id_lookup(jail_name: &str, pw_cmd: &str, id: u32) -> Result<Option<String>> {
+    // `pw` prints a colon separated database entry starting with the name and fails if there is no
+    // entry with the id
+    let stdout = iocage_exec_stdout(
+        jail_name,
+        format!("pw {} {} 2>/dev/null || true", pw_cmd, id),
+    )
+    .map_err(Error::ExecIdLookup)?;
+
+    Ok(stdout
+        .lines()
+        .next()
+        .and_then(|line| line.split(':').next())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string))
+}
+
+/// Creates a non-interactive automation user in the given jail.
+///
+/// The user's password is disabled so that the only way to log in is with the given SSH public
+/// key. When commands are given, a sudoers entry allows the user to run only those commands as
+/// root without a password.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_automation_user(
+    jail_name: &str,
+    user: &str,
+    key: Option<&str>,
+    commands: &[String],
+) -> Result<()> {
+    iocage_exec(jail_name, script::automation_user(user, key, commands))
+        .map_err(Error::ExecAutomationUser)
+}
+
+/// Configures and starts an SSH service in the given jail.
+///
+/// Any given host key files are installed before the service first starts. When host key types
+/// are given, only those types are generated and used by the service. When a listen address is
+/// given, the service only listens on that address.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_ssh_service(
+    jail_name: &str,
+    key_types: &[SshHostKeyType],
+    key_files: &[(String, String)],
+    listen_addr: Option<IpAddr>,
+) -> Result<()> {
+    iocage_exec(
+        jail_name,
+        script::ssh_service(key_types, key_files, listen_addr),
+    )
+    .map_err(Error::ExecSshService)
+}
+
+/// Reads the public SSH host keys of the given jail along with their SHA256 fingerprints.
+///
+/// # Errors
+///
+/// Returns an `Err` if the keys could not be read from the jail.
+fn read_ssh_host_keys(jail_name: &str) -> Result<Vec<SshHostKey>> {
+    // Each line contains the fingerprint line from `ssh-keygen` followed by the public key line,
+    // separated by a tab
+    let stdout = iocage_exec_stdout(
+        jail_name,
+        r#"for f in /etc/ssh/ssh_host_*_key.pub; do
+    printf '%s\t%s\n' "$(ssh-keygen -l -E sha256 -f "$f")" "$(cat "$f")"
+done"#,
+    )
+    .map_err(Error::ExecSshHostKeys)?;
+
+    Ok(stdout
+        .lines()
+        .filter_map(|line| {
+            let mut cols = line.splitn(2, '\t');
+            let fingerprint = cols.next()?.split_ascii_whitespace().nth(1)?;
+            let mut key = cols.next()?.split_ascii_whitespace();
+            Some(SshHostKey {
+                key_type: key.next()?.to_string(),
+                public_key: key.next()?.to_string(),
+                fingerprint: fingerprint.to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Appends a `known_hosts` line for each SSH host key of a jail to a file, creating it if needed.
+///
+/// # Errors
+///
+/// Returns an `Err` if the file could not be opened or written to.
+fn append_known_hosts(path: &Path, jail_name: &str, ip: IpAddr, keys: &[SshHostKey]) -> Result<()> {
+    let contents = keys
+        .iter()
+        .map(|key| format!("{},{} {} {}\n", jail_name, ip, key.key_type, key.public_key))
+        .collect::<String>();
+
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|err| Error::WriteKnownHosts(path.to_path_buf(), err))
+}
+
+/// Configures and starts an NTP service in the given jail.
+///
+/// The service is set to step the clock on start so that a jail which has drifted badly is
+/// corrected immediately rather than slewed over a long period.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_ntp_service(jail_name: &str) -> Result<()> {
+    iocage_exec(jail_name, script::ntp_service()).map_err(Error::ExecNtpService)
+}
+
+/// Configures the syslog service in the given jail to forward all messages to a remote log host.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_syslog_remote(jail_name: &str, remote: &str) -> Result<()> {
+    iocage_exec(
+        jail_name,
+        format!(
+            concat!(
+                "mkdir -p /etc/syslog.d\n",
+                "echo {rule} >/etc/syslog.d/iocage-provision-remote.conf\n",
+                // Secure mode (`-s`) stops syslogd from listening on the network while still
+                // allowing messages to be forwarded, unlike `-ss`
+                "sysrc -f /etc/rc.conf syslogd_enable=\"YES\" syslogd_flags=\"-s\"\n",
+                "service syslogd restart\n",
+            ),
+            rule = quote(&format!("*.*\t@{}", remote)),
+        ),
+    )
+    .map_err(Error::ExecSyslogRemote)
+}
+
+/// Configures the mailer in the given jail to relay all mail through a smarthost.
+///
+/// The DragonFly Mail Agent (`dma`) from the base system is used for submission and the sendmail
+/// daemons are disabled so that the jail does not listen for incoming mail.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_mail_relay(jail_name: &str, relay: &str) -> Result<()> {
+    let (host, port) = parse::host_port(relay);
+
+    let mut src = String::new();
+    src.push_str("sed -i '' -e '/^SMARTHOST /d' -e '/^PORT /d' /etc/dma/dma.conf\n");
+    src.push_str(&format!(
+        "echo {} >>/etc/dma/dma.conf\n",
+        quote(&format!("SMARTHOST {}", host))
+    ));
+    if let Some(port) = port {
+        src.push_str(&format!("echo 'PORT {}' >>/etc/dma/dma.conf\n", port));
+    }
+    src.push_str(&heredoc_to_file(
+        "/etc/mail/mailer.conf",
+        concat!(
+            "sendmail\t/usr/libexec/dma\n",
+            "send-mail\t/usr/libexec/dma\n",
+            "mailq\t\t/usr/libexec/dma\n",
+            "newaliases\t/usr/libexec/dma\n",
+        ),
+    ));
+    src.push_str(concat!(
+        "sysrc -f /etc/rc.conf sendmail_enable=\"NONE\"\n",
+        "service sendmail onestop >/dev/null 2>&1 || true\n",
+    ));
+
+    iocage_exec(jail_name, src).map_err(Error::ExecMailRelay)
+}
+
+/// Configures periodic(8) in the given jail to write its reports to log files.
+///
+/// By default the daily, weekly, monthly, and security reports are mailed to root which, in a
+/// jail without a mail relay, accumulates in an unread mailbox. Writing the reports to files under
+/// `/var/log` keeps them readable and subject to log rotation.
+///
+/// # Errors
+///
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_periodic_log(jail_name: &str) -> Result<()> {
+    iocage_exec(
+        jail_name,
+        concat!(
+            "sysrc -f /etc/periodic.conf",
+            " daily_output=\"/var/log/daily.log\"",
+            " weekly_output=\"/var/log/weekly.log\"",
+            " monthly_output=\"/var/log/monthly.log\"",
+            " daily_status_security_enable=\"YES\"",
+            " daily_status_security_output=\"/var/log/security.log\"\n",
+        ),
+    )
+    .map_err(Error::ExecPeriodicLog)
+}
+
+/// Appends entries to the root user's crontab in the given jail.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if the current effective `uid` is any value other than `0`.
-pub fn ensure_root() -> Result<()> {
-    if users::get_effective_uid() != 0 {
-        Err(Error::NotRoot)
-    } else {
-        Ok(())
+/// Returns an `Err` if the commands were not successfully executed in the jail.
+fn exec_cron_entries(jail_name: &str, entries: &[String]) -> Result<()> {
+    let mut src = String::from("{\n  crontab -l 2>/dev/null || true\n");
+    for entry in entries {
+        src.push_str(&format!("  echo {}\n", quote(entry)));
     }
+    src.push_str("} | crontab -\n");
+
+    iocage_exec(jail_name, src).map_err(Error::ExecCronEntries)
 }
 
-/// Creates, starts, and sets up a new FreeBSD jail via the `iocage` program.
+/// The network stack of a jail to be created.
+#[derive(Debug)]
+enum JailNetwork {
+    /// A VNET network stack of its own, with the given default gateway.
+    Vnet(IpAddr),
+    /// The host's network stack, with the jail's address added to the given host interface.
+    Shared(String),
+}
+
+/// Creates a new jail with the given configuration and returns its name.
+///
+/// When no name is given, iocage names the jail with a short UUID which is read from its output.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if a jail could not be completely provisioned successfully. Note that a
-/// failure from this function may leave behind a jail in an inconsistent state that needs to be
-/// cleaned up out of band.
-pub fn provision_jail(
-    name: &str,
+/// Returns an `Err` if the jail was not successfully created or the name of a jail named by
+/// iocage could not be determined. A failure while installing the packages of the package list is
+/// reported as [`Error::IocageCreatePkgInstall`], as the jail itself may have been created.
+fn run_iocage_create(
+    jail_name: Option<&str>,
     ip: &IpNet,
-    gateway: &IpAddr,
+    network: &JailNetwork,
     release: &str,
     thick_jail: bool,
-    user: Option<&str>,
-    ssh_service: bool,
-) -> Result<()> {
-    let user = find_user(user)?;
-    let json = create_pkglist_json(user.as_ref()).map_err(Error::CreatePkglistJson)?;
-
-    section!("Provisioning a jail named '{}'", name);
-
-    info!("Creating '{}' via iocage", name);
-    run_iocage_create(name, ip, gateway, release, thick_jail, json.path())?;
-
-    if let Some(user) = user {
-        let group = find_group(user.primary_group_id())?;
-
-        info!("Preparing sudo config");
-        exec_sudo_config(name)?;
+    pkglist: Option<&Path>,
+    props: &[String],
+) -> Result<String> {
+    let mut cmd = iocage_command();
+    cmd.arg("--force").arg("create");
+    match jail_name {
+        Some(jail_name) => cmd.arg("--name").arg(jail_name),
+        None => cmd.arg("--short"),
+    };
+    cmd.arg("--release").arg(release);
+    if let Some(pkglist) = pkglist {
+        cmd.arg("--pkglist").arg(pkglist);
+    }
+    if thick_jail {
+        cmd.arg("--thickjail");
+    }
+    match network {
+        JailNetwork::Vnet(gateway) => cmd
+            .arg("vnet=on")
+            .arg(format!("ip4_addr=vnet0|{}", ip))
+            .arg(format!("defaultrouter={}", gateway)),
+        JailNetwork::Shared(interface) => cmd
+            .arg("vnet=off")
+            .arg(format!("ip4_addr={}|{}", interface, ip))
+            .arg("defaultrouter=none"),
+    };
+    cmd.arg("resolver=none").arg("boot=on").args(props);
 
-        info!("Creating group '{}'", group.name().to_string_lossy());
-        exec_create_group(name, &group)?;
+    let (status, stdout, stderr) = spawn_and_indent_capture(cmd).map_err(Error::IocageCreate)?;
 
-        info!("Creating user '{}'", user.name().to_string_lossy());
-        exec_create_user(name, &user, &group)?;
+    if !status.success() {
+        let output = format!("{}{}", stdout, stderr);
+        if let Some(state) = parse::stale_state(&output) {
+            return Err(Error::StaleState(state));
+        }
+        let err = CmdError::Failed(status.code().unwrap_or(-1));
+        if parse::create_pkg_failure(&output) {
+            return Err(Error::IocageCreatePkgInstall(err));
+        }
+        return Err(Error::IocageCreate(err));
     }
-
-    if ssh_service {
-        info!("Enabling SSH service");
-        exec_ssh_service(name)?;
+    match jail_name {
+        Some(jail_name) => Ok(jail_name.to_string()),
+        None => parse::created_name(&stdout).ok_or(Error::IocageCreatedName),
     }
+}
 
-    section!("Instance '{}' provisioned successfully", name);
+/// Sets iocage properties of the given jail.
+///
+/// # Errors
+///
+/// Returns an `Err` if the properties were not successfully set.
+fn run_iocage_set(jail_name: &str, props: &[String]) -> Result<()> {
+    let _audit = audit::JailScope::enter(Some(jail_name));
+    let mut cmd = iocage_command();
+    cmd.arg("set").args(props).arg(jail_name);
 
-    Ok(())
+    run_and_indent(cmd).map_err(|err| Error::IocageSet(jail_name.to_string(), err))
 }
 
-/// Determines and returns a default gateway IP address by querying the `netstat` command.
+/// Starts the given jail.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if:
-///
-/// * The `netstat` command cannot be found
-/// * The output of the command cannot be parsed as UTF-8
-/// * No line of output starting with `"default"` can be found
-/// * The default line cannot be successfully split
-/// * The IP address string cannot be parsed as an IP address
-pub fn netstat_gateway_addr() -> result::Result<IpAddr, GatewayError> {
-    str::from_utf8(
-        Command::new("netstat")
-            .args(&["-r", "-n", "-f", "inet"])
-            .output()
-            .map_err(GatewayError::Cmd)?
-            .stdout
-            .as_ref(),
-    )
-    .map_err(GatewayError::Utf8)?
-    .lines()
-    .find(|line| line.starts_with("default"))
-    .map(|line| line.split_ascii_whitespace())
-    .ok_or(GatewayError::NetstatParse("default line not found"))?
-    .nth(1)
-    .ok_or(GatewayError::NetstatParse(
-        "second column not found on default line",
-    ))?
-    .parse()
-    .map_err(GatewayError::IpAddr)
-}
+/// Returns an `Err` if the jail was not successfully started.
+fn run_iocage_start(jail_name: &str) -> Result<()> {
+    let _audit = audit::JailScope::enter(Some(jail_name));
+    let mut cmd = iocage_command();
+    cmd.arg("start").arg(jail_name);
 
-/// Returns a default release value based on the current host.
-pub fn default_release() -> String {
-    utsname::uname()
-        .release()
-        .split('-')
-        .map(|s| if s == "STABLE" { "RELEASE" } else { s })
-        .take(2)
-        .collect::<Vec<_>>()
-        .join("-")
+    run_and_indent(cmd).map_err(|err| Error::IocageStart(jail_name.to_string(), err))
 }
 
-/// Returns a `User` for a given name, if one exists.
-///
-/// If `None` is provided as an argument, then `Ok(None)` will be returned.
+/// Stops the given jail.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if an associated system user cannot be found for the given user name.
-fn find_user(user_str: Option<&str>) -> Result<Option<User>> {
-    match user_str {
-        Some(user_str) => match users::get_user_by_name(user_str) {
-            Some(user_info) => Ok(Some(user_info)),
-            None => Err(Error::NoUser(user_str.to_string())),
-        },
-        None => Ok(None),
-    }
+/// Returns an `Err` if the jail was not successfully stopped.
+fn run_iocage_stop(jail_name: &str) -> Result<()> {
+    let _audit = audit::JailScope::enter(Some(jail_name));
+    let mut cmd = iocage_command();
+    cmd.arg("stop").arg(jail_name);
+
+    run_and_indent(cmd).map_err(|err| Error::IocageStop(jail_name.to_string(), err))
 }
 
-/// Returns a `Group` for a given group ID (i.e. `gid`).
+/// Destroys the given jail and all of its datasets, and removes its entry from the host's hosts
+/// file if it has one.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if an associated system group cannot be found for the given group ID.
-fn find_group(gid: u32) -> Result<Group> {
-    users::get_group_by_gid(gid).ok_or(Error::NoGid(gid))
+/// Returns an `Err` if the jail was not successfully destroyed or the hosts file could not be
+/// updated.
+fn run_iocage_destroy(jail_name: &str) -> Result<()> {
+    let _audit = audit::JailScope::enter(Some(jail_name));
+    let mut cmd = iocage_command();
+    cmd.arg("destroy").arg("--force").arg(jail_name);
+
+    run_and_indent(cmd).map_err(|err| Error::IocageDestroy(jail_name.to_string(), err))?;
+
+    hosts::remove_host_entry(jail_name)
 }
 
-/// Creates a package list JSON file for the `iocage create` subcommand and returns the file path.
+/// Adds a nullfs mount of a host directory into the given jail.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if the JSON file could not be successfully created and written.
-fn create_pkglist_json(user: Option<&User>) -> io::Result<NamedTempFile> {
-    let json_str = match user {
-        Some(user) => {
-            let shell = user
-                .shell()
-                .file_name()
-                .unwrap_or_else(|| OsStr::new(""))
-                .to_string_lossy();
+/// Returns an `Err` if the fstab entry was not successfully added.
+fn run_iocage_fstab_add(jail_name: &str, src: &Path, dst: &str, read_write: bool) -> Result<()> {
+    run_iocage_fstab_add_entry(
+        jail_name,
+        &format!(
+            "{} {} nullfs {} 0 0",
+            src.display(),
+            dst,
+            if read_write { "rw" } else { "ro" }
+        ),
+    )
+}
 
-            match shell.as_ref() {
-                "bash" => r#"{"pkgs":["sudo","bash"]}"#,
-                _ => r#"{"pkgs":["sudo"]}"#,
-            }
-        }
-        None => r#"{"pkgs":[]}"#,
+/// Adds a tmpfs mount, optionally limited in size, into the given jail.
+///
+/// A mount of `/tmp` or `/var/tmp` is world writable with the sticky bit set, as those
+/// directories are in the base system.
+///
+/// # Errors
+///
+/// Returns an `Err` if the fstab entry was not successfully added.
+fn run_iocage_fstab_add_tmpfs(jail_name: &str, dst: &str, size: Option<&str>) -> Result<()> {
+    let mode = if matches!(dst, "/tmp" | "/var/tmp") {
+        "1777"
+    } else {
+        "0755"
     };
+    let mut options = format!("rw,mode={}", mode);
+    if let Some(size) = size {
+        options.push_str(&format!(",size={}", size));
+    }
 
-    let json = tempfile::Builder::new()
-        .prefix("pkglist")
-        .suffix(".json")
-        .rand_bytes(5)
-        .tempfile()?;
-    fs::write(json.path(), json_str.as_bytes())?;
-
-    Ok(json)
+    run_iocage_fstab_add_entry(jail_name, &format!("tmpfs {} tmpfs {} 0 0", dst, options))
 }
 
-/// Prepares the sudo config in the given jail.
+/// Adds an fstab entry, with its destination relative to the root of the jail, into the given
+/// jail.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if the commands were not successfully executed in the jail.
-fn exec_sudo_config(jail_name: &str) -> Result<()> {
-    iocage_exec(
-        jail_name,
-        "echo '%wheel ALL=(ALL) NOPASSWD: ALL' >/usr/local/etc/sudoers.d/wheel",
-    )
-    .map_err(Error::ExecSudoConfig)
+/// Returns an `Err` if the fstab entry was not successfully added.
+fn run_iocage_fstab_add_entry(jail_name: &str, entry: &str) -> Result<()> {
+    let mut cmd = iocage_command();
+    cmd.arg("fstab").arg("--add").arg(jail_name).arg(entry);
+
+    run_and_indent(cmd).map_err(Error::IocageFstab)
 }
 
-/// Creates a system group in the given jail.
+/// Sets properties on a ZFS dataset.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if the commands were not successfully executed in the jail.
-fn exec_create_group(jail_name: &str, group: &Group) -> Result<()> {
-    iocage_exec(
-        jail_name,
-        format!(
-            "pw groupadd -n '{grp}' -g '{gid}'",
-            gid = group.gid(),
-            grp = group.name().to_string_lossy(),
-        ),
-    )
-    .map_err(Error::ExecCreateGroup)
+/// Returns an `Err` if the properties were not successfully set.
+fn run_zfs_set(dataset: &str, props: &[String]) -> Result<()> {
+    let mut cmd = Command::new("zfs");
+    cmd.arg("set").args(props).arg(dataset);
+
+    run_and_indent(cmd).map_err(|err| Error::ZfsSet(dataset.to_string(), err))
 }
 
-/// Creates a system user in the given jail.
+/// Creates a ZFS dataset with the given mount point.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if the commands were not successfully executed in the jail.
-fn exec_create_user(jail_name: &str, user: &User, group: &Group) -> Result<()> {
-    iocage_exec(
-        jail_name,
-        format!(
-            "pw useradd -n '{usr}' -u '{uid}' -g '{grp}' -G wheel -m -s '{shl}'",
-            grp = group.name().to_string_lossy(),
-            shl = user.shell().display(),
-            uid = user.uid(),
-            usr = user.name().to_string_lossy(),
-        ),
-    )
-    .map_err(Error::ExecCreateUser)
+/// Returns an `Err` if the dataset was not successfully created.
+fn run_zfs_create(dataset: &str, mountpoint: &str) -> Result<()> {
+    let mut cmd = Command::new("zfs");
+    cmd.arg("create")
+        .arg("-p")
+        .arg("-o")
+        .arg(format!("mountpoint={}", mountpoint))
+        .arg(dataset);
+
+    run_and_indent(cmd).map_err(|err| Error::ZfsCreate(dataset.to_string(), err))
 }
 
-/// Configures and starts an SSH service in the given jail.
+/// Returns the path on the host of a home directory in a jail.
+///
+/// Before FreeBSD 14, `/home` is a symlink to `usr/home`, which is resolved within the jail's root
+/// so that a dataset is never mounted through it.
+fn jail_home_path(root: &Path, home_dir: &str) -> String {
+    let home_dir = home_dir.trim_start_matches('/');
+    let path = match (
+        home_dir.strip_prefix("home/"),
+        fs::read_link(root.join("home")),
+    ) {
+        (Some(name), Ok(target)) => root
+            .join(target.strip_prefix("/").unwrap_or(&target))
+            .join(name),
+        _ => root.join(home_dir),
+    };
+
+    path.display().to_string()
+}
+
+/// Returns the path of a jail's root directory on the host, given the jail's dataset.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if the commands were not successfully executed in the jail.
-fn exec_ssh_service(jail_name: &str) -> Result<()> {
-    iocage_exec(
-        jail_name,
-        r#"sysrc -f /etc/rc.conf sshd_enable="YES" && service sshd start"#,
-    )
-    .map_err(Error::ExecSshService)
+/// Returns an `Err` if the mount point of the root dataset could not be read.
+fn jail_root(dataset: &str) -> Result<PathBuf> {
+    zfs_mountpoint(&format!("{}/root", dataset)).map(PathBuf::from)
 }
 
-/// Creates a new jail with the given configuration.
+/// Returns the mount point of a ZFS dataset.
 ///
 /// # Errors
 ///
-/// Returns an `Err` if the jail was not successfully created.
-fn run_iocage_create(
-    jail_name: &str,
-    ip: &IpNet,
-    gateway: &IpAddr,
-    release: &str,
-    thick_jail: bool,
-    pkglist: &Path,
-) -> Result<()> {
-    let mut cmd = Command::new("iocage");
-    cmd.arg("--force")
-        .arg("create")
-        .arg("--name")
-        .arg(jail_name)
-        .arg("--release")
-        .arg(release)
-        .arg("--pkglist")
-        .arg(pkglist);
-    if thick_jail {
-        cmd.arg("--thickjail");
+/// Returns an `Err` if the `zfs` program fails.
+fn zfs_mountpoint(dataset: &str) -> Result<String> {
+    let mut cmd = Command::new("zfs");
+    cmd.arg("get")
+        .arg("-H")
+        .arg("-o")
+        .arg("value")
+        .arg("mountpoint")
+        .arg(dataset);
+
+    Ok(cmd_stdout(cmd)
+        .map_err(|err| Error::ZfsGet(dataset.to_string(), err))?
+        .trim()
+        .to_string())
+}
+
+/// The environment variables exported in every script run in a jail while it is in scope.
+struct ExecEnvScope {
+    previous: String,
+}
+
+impl ExecEnvScope {
+    /// Exports the given environment variables in every script run in a jail until the returned
+    /// scope is dropped.
+    fn enter(env: &BTreeMap<String, String>) -> Self {
+        let previous = std::mem::replace(&mut *exec_env(), script::exec_env(env));
+        Self { previous }
+    }
+}
+
+impl Drop for ExecEnvScope {
+    fn drop(&mut self) {
+        *exec_env() = std::mem::take(&mut self.previous);
     }
-    cmd.arg("vnet=on")
-        .arg(format!("ip4_addr=vnet0|{}", ip))
-        .arg(format!("defaultrouter={}", gateway))
-        .arg("resolver=none")
-        .arg("boot=on")
-        .env("PYTHONUNBUFFERED", "true");
+}
 
-    let status = spawn_and_indent(cmd).map_err(Error::IocageCreate)?;
+fn exec_env() -> std::sync::MutexGuard<'static, String> {
+    EXEC_ENV
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
 
-    if status.success() {
-        Ok(())
-    } else {
-        Err(Error::IocageCreate(CmdError::Failed(
-            status.code().unwrap_or(-1),
-        )))
+/// Returns the preamble of every script run in a jail: strict error handling followed by the
+/// exported environment variables, if any.
+fn exec_preamble() -> String {
+    format!("set -eu\n\n{}", exec_env())
+}
+
+/// Logs a script which is run in the given jail, preceded by its preamble, when scripts are
+/// logged.
+fn trace_exec_script(jail_name: &str, src: &str) {
+    if verbosity() >= Verbosity::Scripts {
+        trace!(
+            "jail script; jail={}, src=\n{}",
+            jail_name,
+            redact(&format!("{}{}", exec_preamble(), src))
+        );
     }
 }
 
+/// Writes a script, preceded by its preamble, to the standard input of `sh` in a jail and closes
+/// the stream so that `sh` runs to the end of the script.
+fn write_exec_script(mut stdin: ChildStdin, src: &str) -> result::Result<(), CmdError> {
+    stdin
+        .write_all(exec_preamble().as_bytes())
+        .map_err(CmdError::StdinWrite)?;
+    stdin
+        .write_all(src.as_bytes())
+        .map_err(CmdError::StdinWrite)
+}
+
 /// Executes a command or script of commands in the given jail.
 ///
 /// # Errors
@@ -395,31 +3871,72 @@ fn run_iocage_create(
 /// * The `iocage` program was not found
 /// * The `iocage` exits with a code that is not zero
 fn iocage_exec<S: AsRef<str>>(jail_name: &str, src: S) -> result::Result<(), IocageExecError> {
-    let mut cmd = Command::new("iocage");
+    let mut cmd = iocage_command();
+    cmd.arg("exec").arg(jail_name).arg("sh");
+
+    trace_exec_script(jail_name, src.as_ref());
+    let (status, _, _) =
+        spawn_and_indent_with_stdin(cmd, |stdin| write_exec_script(stdin, src.as_ref()))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(CmdError::Failed(status.code().unwrap_or(-1)).into())
+    }
+}
+
+/// Executes a shell script in the given jail and returns its standard output.
+///
+/// The standard error stream of the script is not captured and is passed through to the caller.
+///
+/// # Errors
+///
+/// Returns an `Err` if the script could not be run or exits with a code that is not zero.
+fn iocage_exec_stdout<S: AsRef<str>>(
+    jail_name: &str,
+    src: S,
+) -> result::Result<String, IocageExecError> {
+    let mut cmd = iocage_command();
     cmd.arg("exec")
         .arg(jail_name)
         .arg("sh")
-        // `iocage` is a Python program and will therefore buffer output when executed in a
-        // non-interactive mode. Setting a value for the `PYTHONUNBUFFERED` environment variable
-        // ensures that the output streams don't needlessly buffer.
-        //
-        // See: https://docs.python.org/2/using/cmdline.html#envvar-PYTHONUNBUFFERED
-        .env("PYTHONUNBUFFERED", "true");
-
-    let status = spawn_and_indent_with_stdin(cmd, |mut stdin| {
-        stdin
-            .write_all(b"set -eu\n\n")
-            .map_err(CmdError::StdinWrite)?;
-        stdin
-            .write_all(src.as_ref().as_bytes())
-            .map_err(CmdError::StdinWrite)?;
-        Ok(())
-    })?;
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+
+    trace!("running; cmd={}", redact(&format!("{:?}", cmd)));
+    trace_exec_script(jail_name, src.as_ref());
+    let started = SystemTime::now();
+    let (mut child, _group) = process::spawn(&mut cmd)?;
+    let stdin = child.stdin.take().ok_or(CmdError::StreamCapture("stdin"))?;
+    write_exec_script(stdin, src.as_ref())?;
+
+    let output = child.wait_with_output().map_err(CmdError::ChildWait)?;
+    record_command(
+        &cmd,
+        started,
+        &output.status,
+        &String::from_utf8_lossy(&output.stdout),
+    );
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(CmdError::Failed(output.status.code().unwrap_or(-1)).into())
+    }
+}
+
+/// Spawns a `Command`, indents the output stream contents, and checks that it succeeded.
+///
+/// # Errors
+///
+/// Returns an `Err` if the command could not be run or exits with a code that is not zero.
+fn run_and_indent(cmd: Command) -> result::Result<(), CmdError> {
+    let status = spawn_and_indent(cmd)?;
 
     if status.success() {
         Ok(())
     } else {
-        Err(CmdError::Failed(status.code().unwrap_or(-1)).into())
+        Err(CmdError::Failed(status.code().unwrap_or(-1)))
     }
 }
 
@@ -434,11 +3951,29 @@ fn iocage_exec<S: AsRef<str>>(jail_name: &str, src: S) -> result::Result<(), Ioc
 /// * One of the output-reading threads panics
 /// * The command wasn't running
 fn spawn_and_indent(cmd: Command) -> result::Result<ExitStatus, CmdError> {
+    spawn_and_indent_with_stdin(cmd, |_| Ok(())).map(|(status, _, _)| status)
+}
+
+/// Spawns a `Command`, indents the output stream contents, and returns its `ExitStatus` along
+/// with the contents of its standard output and standard error streams.
+///
+/// # Errors
+///
+/// Returns an `Err` if:
+///
+/// * The command failed to spawn
+/// * One of the I/O streams failed to be properly captured
+/// * One of the output-reading threads panics
+/// * The command wasn't running
+fn spawn_and_indent_capture(
+    cmd: Command,
+) -> result::Result<(ExitStatus, String, String), CmdError> {
     spawn_and_indent_with_stdin(cmd, |_| Ok(()))
 }
 
 /// Spawns a `Command` with data for the standard input stream, indents the output stream contents,
-/// and returns its `ExitStatus`.
+/// and returns its `ExitStatus` along with the contents of its standard output and standard error
+/// streams.
 ///
 /// # Errors
 ///
@@ -451,7 +3986,7 @@ fn spawn_and_indent(cmd: Command) -> result::Result<ExitStatus, CmdError> {
 fn spawn_and_indent_with_stdin<F>(
     mut cmd: Command,
     stdin_func: F,
-) -> result::Result<ExitStatus, CmdError>
+) -> result::Result<(ExitStatus, String, String), CmdError>
 where
     F: FnOnce(ChildStdin) -> result::Result<(), CmdError>,
 {
@@ -459,10 +3994,9 @@ where
         .stdout(Stdio::piped())
         .stderr(Stdio::piped());
 
-    debug!("running; cmd={:?}", &cmd);
-    let mut child = cmd
-        .spawn()
-        .map_err(|err| CmdError::Spawn(cmd_get_program(&cmd), err))?;
+    trace!("running; cmd={}", redact(&format!("{:?}", cmd)));
+    let started = SystemTime::now();
+    let (mut child, _group) = process::spawn(&mut cmd)?;
 
     {
         let stdin = child.stdin.take().ok_or(CmdError::StreamCapture("stdin"))?;
@@ -476,10 +4010,15 @@ where
             .ok_or(CmdError::StreamCapture("stdout"))?,
     );
     let stdout_handle = thread::spawn(move || {
+        let mut captured = capture::RingBuffer::new(capture::CAPTURE_LIMIT);
         for line in stdout.lines() {
             // This error happens in a thread, so we will panic here on error
-            output!("{}", line.expect("failed to read line from stdout"));
+            let line = line.expect("failed to read line from stdout");
+            progress::touch();
+            output!("{}", line);
+            captured.push_line(&line);
         }
+        captured.into_string()
     });
 
     let stderr = BufReader::new(
@@ -489,28 +4028,136 @@ where
             .ok_or(CmdError::StreamCapture("stderr"))?,
     );
     let stderr_handle = thread::spawn(move || {
+        let mut captured = capture::RingBuffer::new(capture::CAPTURE_LIMIT);
         for line in stderr.lines() {
             // This error happens in a thread, so we will panic here on error
-            eoutput!("{}", line.expect("failed to read line from stderr"));
+            let line = line.expect("failed to read line from stderr");
+            progress::touch();
+            eoutput!("{}", line);
+            captured.push_line(&line);
         }
+        captured.into_string()
     });
 
     let status = child.wait();
 
-    stdout_handle
+    let stdout = stdout_handle
         .join()
         .map_err(|_| CmdError::Thread("stdout"))?;
-    stderr_handle
+    let stderr = stderr_handle
         .join()
         .map_err(|_| CmdError::Thread("stderr"))?;
 
-    status.map_err(CmdError::ChildWait)
+    let status = status.map_err(CmdError::ChildWait)?;
+    record_command(&cmd, started, &status, &format!("{}{}", stdout, stderr));
+
+    Ok((status, stdout, stderr))
+}
+
+/// Runs a `Command` to completion and returns its standard output stream contents.
+///
+/// The standard error stream is not captured.
+///
+/// # Errors
+///
+/// Returns an `Err` if the command failed to spawn or exits with a code that is not zero.
+fn cmd_stdout(mut cmd: Command) -> result::Result<String, CmdError> {
+    cmd.stdin(Stdio::null()).stderr(Stdio::inherit());
+
+    trace!("running; cmd={}", redact(&format!("{:?}", cmd)));
+    let started = SystemTime::now();
+    let (child, _group) = process::spawn(cmd.stdout(Stdio::piped()))?;
+    let output = child.wait_with_output().map_err(CmdError::ChildWait)?;
+    record_command(
+        &cmd,
+        started,
+        &output.status,
+        &String::from_utf8_lossy(&output.stdout),
+    );
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(CmdError::Failed(output.status.code().unwrap_or(-1)))
+    }
+}
+
+/// Records a command which has run, with its captured output, in the run log if a run is being
+/// recorded, and appends an audit record of it if the audit log is enabled.
+fn record_command(cmd: &Command, started: SystemTime, status: &ExitStatus, output: &str) {
+    run_log::record(cmd, started, Some(status), output);
+    audit::record(cmd, Some(status));
+}
+
+/// Prints a line of progress output to the stream selected with `set_progress_to_stderr`.
+fn print_progress(args: fmt::Arguments<'_>) {
+    let line = redact(&args.to_string());
+    if progress::capture(&line) {
+        return;
+    }
+    let line = stamped(line);
+    if progress_to_stderr() {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Returns a new `iocage` command whose output is streamed, with iocage's debug flag if it is
+/// enabled.
+fn iocage_command() -> Command {
+    let mut cmd = Command::new("iocage");
+    if iocage_debug() {
+        cmd.arg("--debug");
+    }
+    cmd
+}
+
+/// Returns a new `iocage` command whose output is parsed, which is never given iocage's debug
+/// flag as its debug messages would be mixed into the output.
+fn iocage_query_command() -> Command {
+    Command::new("iocage")
 }
 
 fn cmd_get_program(cmd: &Command) -> String {
-    shell_words::split(&format!("{:?}", cmd))
-        .ok()
-        .map(|args| args.into_iter().next())
-        .flatten()
-        .unwrap_or_else(|| "<unknown>".to_string())
+    cmd_argv(cmd).into_iter().next().unwrap_or_default()
+}
+
+/// Returns the program and arguments of a `Command`, parsed from its `Debug` form.
+///
+/// Newer Rust releases lead the `Debug` form with any working directory and environment variables
+/// set on the command, which are skipped.
+pub(crate) fn cmd_argv(cmd: &Command) -> Vec<String> {
+    let mut words = shell_words::split(&format!("{:?}", cmd))
+        .unwrap_or_default()
+        .into_iter()
+        .peekable();
+    loop {
+        match words.peek().map(String::as_str) {
+            // `cd DIR &&`
+            Some("cd") => {
+                words.nth(2);
+            }
+            // `env -i` or `env -u KEY`
+            Some("env") | Some("-i") => {
+                words.next();
+            }
+            Some("-u") => {
+                words.nth(1);
+            }
+            Some(word) if is_env_assignment(word) => {
+                words.next();
+            }
+            _ => break,
+        }
+    }
+
+    words.collect()
+}
+
+fn is_env_assignment(word: &str) -> bool {
+    match word.find('=') {
+        Some(idx) => script::is_env_name(&word[..idx]),
+        None => false,
+    }
 }
@@ -0,0 +1,544 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Manifests which declare several jails in a single TOML file.
+//!
+//! A manifest contains one `[[jail]]` table per jail whose keys are the fields of a `JailSpec`.
+//! Only the `name` and `ip` keys are required. When a jail doesn't set a `gateway` or `release`,
+//! the computed defaults given when loading the manifest are used.
+//!
+//! String values may contain `{{ variable }}` references which are replaced with the jail's
+//! `name`, `ip`, `ip.host` (the address without its prefix length), `ip.prefix`, `gateway`, and
+//! `release`, or with an environment variable as `env.VARIABLE`. The `name`, `ip`, `gateway`, and
+//! `release` values themselves may only refer to environment variables.
+//!
+//! A jail may set `extends` to the name of a `[profile.NAME]` table, or of another jail, to
+//! inherit its settings. Profiles hold the same keys as a jail and may themselves extend another
+//! profile or jail. Inherited settings are merged as follows:
+//!
+//! * Arrays (such as `cron_entries`, `ca_certs`, and `zfs_props`) are concatenated, inherited
+//!   values first
+//! * Tables (such as `base_props`) are merged key by key, with the extending jail's values winning
+//! * Any other value set by the extending jail replaces the inherited one
+//! * The `name` and `ip` of an extended jail are never inherited
+//!
+//! Templates in inherited values are rendered with the variables of the extending jail.
+//!
+//! ```toml
+//! [profile.base-web]
+//! ssh_service = true
+//! cron_entries = ["@daily /usr/local/bin/rotate-logs"]
+//! base_props = { allow_raw_sockets = "1" }
+//!
+//! [[jail]]
+//! name = "db"
+//! ip = "10.0.0.20/24"
+//! sysvipc = "new"
+//!
+//! [[jail]]
+//! name = "web"
+//! ip = "10.0.0.21/24"
+//! extends = "base-web"
+//! cron_entries = ["@hourly /usr/local/bin/warm-cache {{ ip.host }}"]
+//! depends = ["db"]
+//! description = "Web server for {{ env.SITE }} at {{ ip.host }}"
+//! ```
+
+use super::{template, JailSpec};
+use ipnet::IpNet;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::result;
+
+/// Keys of a jail which identify it and which are provided as template variables.
+const IDENTITY_KEYS: &[&str] = &["name", "ip", "gateway", "release"];
+
+/// Keys of a jail which are not inherited by a jail extending it.
+const UNIQUE_KEYS: &[&str] = &["name", "ip"];
+
+/// A JSON Schema describing the manifest format.
+pub const MANIFEST_SCHEMA: &str = include_str!("../schema/manifest.schema.json");
+
+/// Error when reading or parsing a manifest.
+#[derive(Debug, thiserror::Error)]
+pub enum ManifestError {
+    /// More than one jail in a manifest has the same name.
+    #[error("duplicate jail name in manifest; name={name}, line={line}")]
+    DuplicateName { name: String, line: usize },
+    /// A manifest value is missing, unknown, or of the wrong type.
+    #[error("invalid manifest value; key={key}, line={line}: {message}{}", hint(.suggestion))]
+    Invalid {
+        key: String,
+        line: usize,
+        message: String,
+        suggestion: Option<String>,
+    },
+    #[error("could not read manifest file; path={0}")]
+    Read(PathBuf, #[source] io::Error),
+    /// A manifest is not valid TOML.
+    #[error("invalid manifest syntax; line={line}, column={column}")]
+    Syntax {
+        line: usize,
+        column: usize,
+        #[source]
+        source: toml::de::Error,
+    },
+    /// A manifest value refers to an unknown template variable or is not a valid template.
+    #[error("invalid manifest template; key={key}, line={line}: {message}")]
+    Template {
+        key: String,
+        line: usize,
+        message: String,
+    },
+}
+
+/// The jails declared in a manifest.
+#[derive(Debug)]
+pub struct Manifest {
+    /// Specs of the declared jails, in the order they are declared.
+    pub jails: Vec<JailSpec>,
+}
+
+impl Manifest {
+    /// Reads and parses a manifest file.
+    ///
+    /// The `gateway` and `release` functions are only called, at most once each, if a jail
+    /// doesn't set the corresponding value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Err` if the file could not be read or if the manifest is invalid.
+    pub fn load<G, R>(path: &Path, gateway: G, release: R) -> result::Result<Self, ManifestError>
+    where
+        G: FnOnce() -> IpAddr,
+        R: FnOnce() -> String,
+    {
+        let src =
+            fs::read_to_string(path).map_err(|err| ManifestError::Read(path.to_path_buf(), err))?;
+
+        Self::from_toml(&src, gateway, release)
+    }
+
+    /// Parses a manifest from a TOML string.
+    ///
+    /// The `gateway` and `release` functions are only called, at most once each, if a jail
+    /// doesn't set the corresponding value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Err` if the manifest is not valid TOML, if a value is missing, unknown, or of
+    /// the wrong type, if a jail extends an unknown or circular definition, or if more than one
+    /// jail has the same name.
+    pub fn from_toml<G, R>(src: &str, gateway: G, release: R) -> result::Result<Self, ManifestError>
+    where
+        G: FnOnce() -> IpAddr,
+        R: FnOnce() -> String,
+    {
+        let (mut gateway, mut release) = (Some(gateway), Some(release));
+        let (mut gateway_value, mut release_value) = (None, None);
+
+        let doc: toml::Value = toml::from_str(src).map_err(|err| {
+            let (line, column) = err.line_col().unwrap_or((0, 0));
+            ManifestError::Syntax {
+                line: line + 1,
+                column: column + 1,
+                source: err,
+            }
+        })?;
+        let doc: Document = serde_path_to_error::deserialize(doc).map_err(|err| {
+            invalid(
+                src,
+                Section::Top,
+                &err.path().to_string(),
+                &err.inner().to_string(),
+            )
+        })?;
+
+        let mut names = BTreeSet::new();
+        let mut jails = Vec::with_capacity(doc.jail.len());
+        for (idx, table) in doc.jail.iter().enumerate() {
+            let mut chain = Vec::new();
+            if let Some(name) = jail_name(table) {
+                chain.push(name.to_string());
+            }
+            let mut table = resolve_extends(
+                src,
+                Section::Jail(idx),
+                table.clone(),
+                &doc.profile,
+                &doc.jail,
+                &mut chain,
+            )?;
+
+            if !table.contains_key("gateway") {
+                let value = gateway_value
+                    .get_or_insert_with(|| (gateway.take().expect("called once"))().to_string());
+                table.insert("gateway".to_string(), toml::Value::String(value.clone()));
+            }
+            if !table.contains_key("release") {
+                let value =
+                    release_value.get_or_insert_with(|| (release.take().expect("called once"))());
+                table.insert("release".to_string(), toml::Value::String(value.clone()));
+            }
+
+            render_jail(src, idx, &mut table)?;
+
+            let spec: JailSpec = serde_path_to_error::deserialize(toml::Value::Table(table))
+                .map_err(|err| {
+                    invalid(
+                        src,
+                        Section::Jail(idx),
+                        &err.path().to_string(),
+                        &err.inner().to_string(),
+                    )
+                })?;
+            if !names.insert(spec.name.clone()) {
+                return Err(ManifestError::DuplicateName {
+                    line: key_line(src, Section::Jail(idx), "name"),
+                    name: spec.name,
+                });
+            }
+            jails.push(spec);
+        }
+
+        Ok(Self { jails })
+    }
+}
+
+/// The top level structure of a manifest, before its jails are parsed.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Document {
+    #[serde(default)]
+    jail: Vec<toml::value::Table>,
+    #[serde(default)]
+    profile: BTreeMap<String, toml::value::Table>,
+}
+
+/// A table in a manifest.
+#[derive(Clone, Copy, Debug)]
+enum Section<'a> {
+    /// The top level table.
+    Top,
+    /// The `[[jail]]` table at an index.
+    Jail(usize),
+    /// The `[profile.NAME]` table with a name.
+    Profile(&'a str),
+}
+
+impl Section<'_> {
+    /// Returns the key of a path within the table, as used in error messages.
+    fn key(self, path: &str) -> String {
+        let table = match self {
+            Section::Top => return path.to_string(),
+            Section::Jail(idx) => format!("jail[{}]", idx),
+            Section::Profile(name) => format!("profile.{}", name),
+        };
+        if path.is_empty() {
+            table
+        } else {
+            format!("{}.{}", table, path)
+        }
+    }
+
+    /// Returns whether a line is the header of the table, given the number of `[[jail]]` headers
+    /// preceding it.
+    fn is_header(self, line: &str, jails: usize) -> bool {
+        match self {
+            Section::Top => false,
+            Section::Jail(idx) => line.starts_with("[[jail]]") && idx == jails,
+            Section::Profile(name) => {
+                let header = line.split(']').next().unwrap_or("");
+                let header = header.trim_start_matches('[').trim();
+                header == format!("profile.{}", name) || header == format!("profile.\"{}\"", name)
+            }
+        }
+    }
+}
+
+/// Merges the settings which a jail or profile table extends into it, following any chain of
+/// `extends` keys.
+///
+/// The `chain` holds the names of the definitions extended so far, used to detect cycles.
+///
+/// # Errors
+///
+/// Returns an `Err` if `extends` is not a string, names an unknown profile or jail, or leads back
+/// to a definition already in the chain.
+fn resolve_extends<'a>(
+    src: &str,
+    section: Section<'_>,
+    mut table: toml::value::Table,
+    profiles: &'a BTreeMap<String, toml::value::Table>,
+    jails: &'a [toml::value::Table],
+    chain: &mut Vec<String>,
+) -> Result<toml::value::Table, ManifestError> {
+    let base_name = match table.remove("extends") {
+        None => return Ok(table),
+        Some(toml::Value::String(name)) => name,
+        Some(other) => {
+            return Err(invalid(
+                src,
+                section,
+                "extends",
+                &format!("invalid type: {}, expected a string", other.type_str()),
+            ))
+        }
+    };
+    if chain.contains(&base_name) {
+        chain.push(base_name);
+        return Err(invalid(
+            src,
+            section,
+            "extends",
+            &format!("circular extends: {}", chain.join(" -> ")),
+        ));
+    }
+
+    let (base_section, base) = if let Some((name, base)) = profiles.get_key_value(&base_name) {
+        (Section::Profile(name), base)
+    } else if let Some(idx) = jails
+        .iter()
+        .position(|jail| jail_name(jail) == Some(&base_name))
+    {
+        (Section::Jail(idx), &jails[idx])
+    } else {
+        let candidates = profiles
+            .keys()
+            .map(String::as_str)
+            .chain(jails.iter().filter_map(jail_name));
+        let mut err = invalid(
+            src,
+            section,
+            "extends",
+            &format!("unknown profile or jail `{}`", base_name),
+        );
+        if let ManifestError::Invalid {
+            ref mut suggestion, ..
+        } = err
+        {
+            *suggestion = closest(&base_name, candidates);
+        }
+        return Err(err);
+    };
+
+    chain.push(base_name);
+    let mut base = resolve_extends(src, base_section, base.clone(), profiles, jails, chain)?;
+    if let Section::Jail(_) = base_section {
+        for key in UNIQUE_KEYS {
+            base.remove(*key);
+        }
+    }
+
+    Ok(merge(base, table))
+}
+
+/// Returns the name set in a jail's table.
+fn jail_name(jail: &toml::value::Table) -> Option<&str> {
+    jail.get("name").and_then(toml::Value::as_str)
+}
+
+/// Merges a table's values over the values of the table it extends.
+fn merge(mut base: toml::value::Table, table: toml::value::Table) -> toml::value::Table {
+    for (key, value) in table {
+        let merged = match (base.remove(&key), value) {
+            (Some(toml::Value::Array(mut values)), toml::Value::Array(more)) => {
+                values.extend(more);
+                toml::Value::Array(values)
+            }
+            (Some(toml::Value::Table(mut values)), toml::Value::Table(more)) => {
+                for (key, value) in more {
+                    values.insert(key, value);
+                }
+                toml::Value::Table(values)
+            }
+            (_, value) => value,
+        };
+        base.insert(key, merged);
+    }
+
+    base
+}
+
+/// Renders the template variables in all string values of a jail's table.
+///
+/// # Errors
+///
+/// Returns an `Err` if a value refers to an unknown variable or is not a valid template.
+fn render_jail(src: &str, idx: usize, table: &mut toml::value::Table) -> Result<(), ManifestError> {
+    let template_err = |key: &str, message| ManifestError::Template {
+        key: format!("jail[{}].{}", idx, key),
+        line: key_line(src, Section::Jail(idx), key),
+        message,
+    };
+
+    // The identity values are rendered first as the other variables are derived from them
+    for key in IDENTITY_KEYS {
+        if let Some(value) = table.get_mut(*key) {
+            render_value(value, &env_var).map_err(|message| template_err(key, message))?;
+        }
+    }
+
+    let mut vars = BTreeMap::new();
+    for key in IDENTITY_KEYS {
+        if let Some(toml::Value::String(value)) = table.get(*key) {
+            vars.insert(key.to_string(), value.clone());
+        }
+    }
+    if let Some(ip) = vars.get("ip").and_then(|ip| ip.parse::<IpNet>().ok()) {
+        vars.insert("ip.host".to_string(), ip.addr().to_string());
+        vars.insert("ip.prefix".to_string(), ip.prefix_len().to_string());
+    }
+    let lookup = |var: &str| vars.get(var).cloned().or_else(|| env_var(var));
+
+    for (key, value) in table.iter_mut() {
+        if !IDENTITY_KEYS.contains(&key.as_str()) {
+            render_value(value, &lookup).map_err(|message| template_err(key, message))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Renders the template variables in a value and in all values nested within it.
+fn render_value(
+    value: &mut toml::Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    match value {
+        toml::Value::String(s) => *s = template::render(s, lookup)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                render_value(value, lookup)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                render_value(value, lookup)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Looks up an `env.VARIABLE` template variable in the environment.
+fn env_var(var: &str) -> Option<String> {
+    var.strip_prefix("env.")
+        .and_then(|name| env::var(name).ok())
+}
+
+/// Builds an error for an invalid value at a path in a table of a manifest.
+fn invalid(src: &str, section: Section<'_>, path: &str, message: &str) -> ManifestError {
+    // A path of "." refers to the table itself, such as when a required key is missing
+    let path = if path == "." { "" } else { path };
+    let key = path.split(&['.', '['][..]).next().unwrap_or("");
+    let suggestion = suggestion(message);
+
+    ManifestError::Invalid {
+        key: section.key(path),
+        line: key_line(src, section, key),
+        // The list of every expected value is noise when the closest one is suggested
+        message: match suggestion {
+            Some(_) => message.split(", expected").next().unwrap_or(message),
+            None => message,
+        }
+        .to_string(),
+        suggestion,
+    }
+}
+
+/// Returns the line number of a key in a table of a manifest.
+///
+/// If the key can't be found, the line number of the table header is returned, or `1` for the top
+/// level table.
+fn key_line(src: &str, section: Section<'_>, key: &str) -> usize {
+    let mut jails = 0;
+    let mut header_line = 1;
+    let mut in_table = matches!(section, Section::Top);
+
+    for (num, line) in src.lines().enumerate() {
+        let line = line.trim_start();
+        if line.starts_with('[') {
+            if in_table {
+                break;
+            }
+            if section.is_header(line, jails) {
+                in_table = true;
+                header_line = num + 1;
+            }
+            if line.starts_with("[[jail]]") {
+                jails += 1;
+            }
+        } else if in_table && !key.is_empty() && line.starts_with(key) {
+            let rest = line[key.len()..].trim_start();
+            if rest.starts_with('=') {
+                return num + 1;
+            }
+        }
+    }
+
+    header_line
+}
+
+/// Returns the closest expected value named in an "unknown field" or "unknown variant" message.
+fn suggestion(message: &str) -> Option<String> {
+    if !message.starts_with("unknown ") {
+        return None;
+    }
+
+    // Messages quote the unknown value first, followed by each of the expected values
+    let mut quoted = message.split('`').skip(1).step_by(2);
+    let unknown = quoted.next()?;
+    closest(unknown, quoted)
+}
+
+/// Returns the candidate closest to an unknown value, if any is close enough to be a likely typo.
+fn closest<'a, I>(unknown: &str, candidates: I) -> Option<String>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(unknown, candidate), candidate))
+        .filter(|(distance, candidate)| *distance <= (candidate.len() / 3).max(2))
+        .min()
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+/// Returns the Levenshtein edit distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(cur).min(row[j])
+            };
+            prev = cur;
+        }
+    }
+
+    row[b.len()]
+}
+
+/// Formats an optional suggestion for an error message.
+fn hint(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!(" (did you mean `{}`?)", suggestion),
+        None => String::new(),
+    }
+}
@@ -0,0 +1,402 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Parsers for the output of `iocage list` and `iocage get`.
+//!
+//! The parsers are pure functions of a command's standard output so that they can be tested
+//! against output captured from different iocage versions.
+
+use super::{JailInfo, StaleState};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+/// Longest duration accepted by [`duration`], of 100 years, in seconds.
+pub const MAX_DURATION: u64 = 100 * 365 * 24 * 60 * 60;
+
+/// Parses the scripted (`-h`) output of `iocage list` or `iocage list -l` into jail summaries.
+///
+/// The short format has tab separated columns of: JID, name, state, release, and IPv4 address.
+/// The long format has columns of: JID, name, boot, state, type, release, IPv4 address, IPv6
+/// address, template, and (in newer versions) basejail. Lines with too few columns are skipped.
+///
+/// The description of each jail is not part of the list output and so is always `None`.
+pub fn list(stdout: &str) -> Vec<JailInfo> {
+    stdout
+        .lines()
+        .map(|line| line.split('\t').collect::<Vec<_>>())
+        .filter_map(|cols| {
+            let (name, state, release, ip4) = match cols.len() {
+                5..=8 => (cols[1], cols[2], cols[3], cols[4]),
+                n if n >= 9 => (cols[1], cols[3], cols[5], cols[6]),
+                _ => return None,
+            };
+            Some(JailInfo {
+                jid: cols[0].parse().ok(),
+                name: name.to_string(),
+                state: state.to_string(),
+                release: release.to_string(),
+                ip4: ip4.to_string(),
+                description: None,
+            })
+        })
+        .collect()
+}
+
+/// Returns the interface of the first address of a jail's `ip4_addr` or `ip6_addr` property
+/// value, such as `vnet0` of `vnet0|10.0.0.5/24`, or `None` if it has no interface (for example
+/// when the jail uses DHCP).
+pub fn address_interface(addrs: &str) -> Option<&str> {
+    addrs
+        .split(',')
+        .next()
+        .and_then(|addr| addr.find('|').map(|idx| addr[..idx].trim()))
+        .filter(|interface| !interface.is_empty())
+}
+
+/// Parses the output of `iocage get all` into a map of property names to values.
+///
+/// Each line is a `KEY:VALUE` pair; lines without a colon are skipped. Values may themselves
+/// contain colons (such as IPv6 addresses), so only the first colon separates the key.
+pub fn properties(stdout: &str) -> BTreeMap<String, String> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let idx = line.find(':')?;
+            Some((
+                line[..idx].trim().to_string(),
+                line[idx + 1..].trim().to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// Parses the output of `iocage get PROPERTY` into the property's value, or `None` if the
+/// property is unset.
+///
+/// iocage reports an unset property as `none`.
+pub fn property(stdout: &str) -> Option<String> {
+    match stdout.trim() {
+        "" | "none" => None,
+        value => Some(value.to_string()),
+    }
+}
+
+/// Parses the output of `iocage get PROPERTY JAIL` for a boolean property into whether it is on.
+///
+/// Depending on its version, iocage reports a boolean property as `1` or `0`, or as `on` or
+/// `off`.
+pub fn enabled(stdout: &str) -> bool {
+    matches!(stdout.trim(), "1" | "on" | "yes" | "true")
+}
+
+/// Parses the value of the `vm.loadavg` sysctl, such as `{ 0.52 0.41 0.38 }`, into the one minute
+/// load average.
+pub fn loadavg(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .trim_start_matches('{')
+        .split_whitespace()
+        .next()
+        .and_then(|load| load.parse().ok())
+}
+
+/// Parses the output of `iocage create` into the name of the created jail.
+///
+/// iocage reports the created jail with a `NAME successfully created!` line, which is the only
+/// way to learn the name it generated for a jail created without one.
+pub fn created_name(stdout: &str) -> Option<String> {
+    stdout
+        .lines()
+        .filter_map(|line| line.trim().strip_suffix(" successfully created!"))
+        .filter_map(|name| name.split_whitespace().next_back())
+        .next_back()
+        .map(str::to_string)
+}
+
+/// Parses the output of `ifconfig BRIDGE` into the names of the bridge's member interfaces.
+pub fn bridge_members(stdout: &str) -> Vec<String> {
+    stdout
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("member:"))
+        .filter_map(|member| member.split_whitespace().next())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses the output of `ifconfig INTERFACE` into the interface's IPv4 and IPv6 addresses.
+///
+/// The zone of a link-local IPv6 address (i.e. `%em0`) is removed.
+pub fn interface_addrs(stdout: &str) -> Vec<IpAddr> {
+    stdout
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("inet") | Some("inet6") => words.next(),
+                _ => None,
+            }
+        })
+        .filter_map(|addr| addr.split('%').next()?.parse().ok())
+        .collect()
+}
+
+/// Parses a cpuset(1) CPU list, such as `0-3` or `0,2,4-7`, into its CPU numbers in ascending
+/// order.
+///
+/// Returns `None` if the list is empty, has an empty or reversed range, or has a value which is
+/// not a number.
+pub fn cpu_list(list: &str) -> Option<Vec<u32>> {
+    let mut cpus = Vec::new();
+    for item in list.split(',') {
+        let mut parts = item.splitn(2, '-');
+        let (first, last): (u32, u32) = match (parts.next(), parts.next()) {
+            (Some(first), Some(last)) => (first.trim().parse().ok()?, last.trim().parse().ok()?),
+            _ => {
+                let cpu = item.trim().parse().ok()?;
+                (cpu, cpu)
+            }
+        };
+        if first > last {
+            return None;
+        }
+        cpus.extend(first..=last);
+    }
+    cpus.sort_unstable();
+    cpus.dedup();
+
+    Some(cpus)
+}
+
+/// Parses the package names matched by `pkg rquery` into the iocage package for the newest
+/// Python flavor (i.e. `py311-iocage` rather than `py39-iocage`), if any.
+pub fn iocage_package(stdout: &str) -> Option<String> {
+    stdout
+        .lines()
+        .map(str::trim)
+        .filter_map(|name| {
+            let flavor = name.strip_prefix("py")?.strip_suffix("-iocage")?;
+            flavor.parse::<u32>().ok().map(|flavor| (flavor, name))
+        })
+        .max_by_key(|(flavor, _)| *flavor)
+        .map(|(_, name)| name.to_string())
+}
+
+/// Parses and validates the contents of a package list JSON file for `iocage create --pkglist`
+/// into its package names.
+///
+/// The file must hold an object with a `pkgs` array of package names; any other keys are left to
+/// iocage. Returns an `Err` describing the problem and the offending key (i.e. `pkgs[2]`) if the
+/// contents are not valid.
+pub fn pkglist(src: &str) -> Result<Vec<String>, String> {
+    let value: serde_json::Value = serde_json::from_str(src).map_err(|err| {
+        format!(
+            "invalid JSON at line {}, column {}",
+            err.line(),
+            err.column()
+        )
+    })?;
+    let object = value
+        .as_object()
+        .ok_or_else(|| String::from("top level must be an object"))?;
+    let pkgs = object
+        .get("pkgs")
+        .ok_or_else(|| String::from("key 'pkgs' is missing"))?
+        .as_array()
+        .ok_or_else(|| String::from("key 'pkgs' must be an array"))?;
+
+    pkgs.iter()
+        .enumerate()
+        .map(|(idx, pkg)| match pkg.as_str() {
+            Some(pkg) if !pkg.trim().is_empty() => Ok(pkg.to_string()),
+            _ => Err(format!("key 'pkgs[{}]' must be a package name", idx)),
+        })
+        .collect()
+}
+
+/// Parses the output of a failed `iocage create` into the stale state which made it fail, if it
+/// matches one of the known failure signatures.
+pub fn stale_state(output: &str) -> Option<StaleState> {
+    if output.contains("is dying")
+        || (output.contains("jail_set") && output.contains("Resource temporarily unavailable"))
+    {
+        Some(StaleState::DyingJail)
+    } else if output.contains("missing its configuration")
+        || output.contains("missing it's configuration")
+        || output.contains("dataset already exists")
+    {
+        Some(StaleState::MissingConfig)
+    } else if output.contains("SIOCIFCREATE2: File exists")
+        || output.contains("SIOCSIFNAME: File exists")
+    {
+        Some(StaleState::StaleInterface)
+    } else {
+        None
+    }
+}
+
+/// Returns whether the output of a failed `iocage create` shows that it failed while installing
+/// the packages of its package list, after the jail itself was created.
+///
+/// iocage announces the checks of the pkg mirror and the bootstrapping of pkg(8) which start its
+/// package installation, so a failure after either of these is a failure to install packages.
+pub fn create_pkg_failure(output: &str) -> bool {
+    output.lines().map(str::trim).any(|line| {
+        line.starts_with("Installing pkg...")
+            || line.contains("DNS response to ")
+            || line.contains("SRV response to ")
+            || line.contains("DNSSEC response to ")
+            || line.starts_with("pkg error")
+    })
+}
+
+/// Parses the output of `ifconfig -l` into the VNET interfaces (named `vnet<N>.<JID>` by iocage)
+/// of jails which are not in the output of `jls -d jid`.
+pub fn stale_vnet_interfaces(ifconfig: &str, jids: &str) -> Vec<String> {
+    let jids: Vec<&str> = jids.lines().map(str::trim).collect();
+
+    ifconfig
+        .split_whitespace()
+        .filter(|name| {
+            let mut parts = match name.strip_prefix("vnet") {
+                Some(rest) => rest.splitn(2, '.'),
+                None => return false,
+            };
+            match (parts.next(), parts.next()) {
+                (Some(idx), Some(jid)) => {
+                    idx.parse::<u32>().is_ok() && jid.parse::<u32>().is_ok() && !jids.contains(&jid)
+                }
+                _ => false,
+            }
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses and validates a tmpfs mount of the form `PATH[=SIZE]` into its absolute path in the jail
+/// and its optional size limit, such as `512m`.
+///
+/// A size is a whole number with an optional `k`, `m`, `g`, or `t` suffix. Returns an `Err`
+/// describing the problem if the mount is not valid.
+pub fn tmpfs(mount: &str) -> Result<(String, Option<String>), String> {
+    let mut parts = mount.splitn(2, '=');
+    let (path, size) = (parts.next().unwrap_or(mount), parts.next());
+    if !path.starts_with('/') || path.contains(char::is_whitespace) {
+        return Err(format!(
+            "tmpfs path must be absolute without whitespace; path={}",
+            path
+        ));
+    }
+    if let Some(size) = size {
+        let digits = size.trim_end_matches(&['k', 'm', 'g', 't', 'K', 'M', 'G', 'T'][..]);
+        if digits.is_empty()
+            || !digits.bytes().all(|b| b.is_ascii_digit())
+            || size.len() - digits.len() > 1
+        {
+            return Err(format!(
+                "tmpfs size must be a number with an optional k, m, g, or t suffix; size={}",
+                size
+            ));
+        }
+    }
+
+    Ok((
+        path.trim_end_matches('/').to_string(),
+        size.map(str::to_string),
+    ))
+}
+
+/// Returns whether or not a value is a ZFS size, such as `20G` or `1.5T`: a number with an optional
+/// fractional part and an optional `K`, `M`, `G`, `T`, or `P` suffix (optionally followed by `B`).
+pub fn is_zfs_size(size: &str) -> bool {
+    let number = size
+        .strip_suffix(&['B', 'b'][..])
+        .filter(|number| number.ends_with(|c: char| c.is_ascii_alphabetic()))
+        .unwrap_or(size);
+    let number = number
+        .strip_suffix(&['K', 'M', 'G', 'T', 'P', 'k', 'm', 'g', 't', 'p'][..])
+        .unwrap_or(number);
+
+    !number.is_empty()
+        && !number.starts_with('.')
+        && matches!(number.parse::<f64>(), Ok(n) if n >= 0.0)
+        && number.bytes().all(|b| b.is_ascii_digit() || b == b'.')
+}
+
+/// Parses a duration, such as `72h` or `2w`, into a number of seconds.
+///
+/// A duration is a whole number directly followed by a unit of `s` (seconds), `m` (minutes), `h`
+/// (hours), `d` (days), or `w` (weeks), and is at most [`MAX_DURATION`] long.
+pub fn duration(duration: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid duration; duration={}", duration);
+    let idx = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(invalid)?;
+    let (number, unit) = duration.split_at(idx);
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid()),
+    };
+
+    let secs = number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(scale))
+        .filter(|secs| *secs > 0)
+        .ok_or_else(invalid)?;
+    if secs > MAX_DURATION {
+        return Err(format!(
+            "duration must be at most 100 years; duration={}",
+            duration
+        ));
+    }
+
+    Ok(secs)
+}
+
+/// Splits a `HOST[:PORT]` string into its host and optional port parts.
+///
+/// An IPv6 address is only given a port when it is enclosed in brackets (i.e. `[2001:db8::1]:587`),
+/// as the last group of an unbracketed address can't be told apart from a port. The brackets are
+/// removed from the host.
+pub fn host_port(s: &str) -> (&str, Option<&str>) {
+    let is_port = |port: &str| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit());
+    if s.starts_with('[') {
+        if let Some(end) = s.find(']') {
+            let host = &s[1..end];
+            return match &s[end + 1..] {
+                "" => (host, None),
+                rest if rest.starts_with(':') && is_port(&rest[1..]) => (host, Some(&rest[1..])),
+                _ => (s, None),
+            };
+        }
+    }
+    let mut parts = s.rsplitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(port), Some(host)) if !host.contains(':') && is_port(port) => (host, Some(port)),
+        _ => (s, None),
+    }
+}
+
+/// Parses a line of progress output, such as that of `iocage fetch` or `pkg`, into the latest
+/// whole percentage it reports, if any.
+///
+/// A percentage is a number, possibly with a fractional part, directly followed by `%`. Numbers
+/// greater than 100 are not considered percentages.
+pub fn percentage(line: &str) -> Option<u8> {
+    line.match_indices('%')
+        .filter_map(|(idx, _)| {
+            let number = &line[..idx];
+            let start = number
+                .rfind(|c: char| !c.is_ascii_digit() && c != '.')
+                .map_or(0, |i| i + 1);
+            number[start..].parse::<f64>().ok()
+        })
+        .filter(|percent| (0.0..=100.0).contains(percent))
+        .map(|percent| percent as u8)
+        .next_back()
+}
@@ -0,0 +1,350 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Tracking of the child processes spawned while provisioning a jail.
+//!
+//! On Unix hosts each child is started as the leader of its own process group and its group is
+//! recorded in a registry until the child has been waited on. Terminating a registered group
+//! reaches every process the child started in turn, such as the `pkg` processes which `iocage`
+//! spawns, rather than only the immediate child.
+//!
+//! As children no longer share the terminal's foreground process group, an interrupt from the
+//! terminal isn't delivered to them directly. A program should call [`terminate_on_signal`] so
+//! that an interrupt is forwarded to every registered group.
+//!
+//! The registry is a fixed set of atomic slots so that it can be read from a signal handler.
+//!
+//! Every child is also given the environment variables set with [`set_child_env`], along with
+//! the defaults in [`DEFAULT_CHILD_ENV`] unless they are disabled, and is run at the scheduling
+//! priority set with [`set_child_priority`] so that provisioning can give way to the other work
+//! on a busy host.
+
+use super::{audit, cmd_get_program, run_log, CmdError};
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Environment variables which are set for every child unless disabled with [`set_child_env`].
+///
+/// `iocage` is a Python program and will therefore buffer output when executed in a
+/// non-interactive mode. Setting a value for the `PYTHONUNBUFFERED` environment variable ensures
+/// that the output streams don't needlessly buffer.
+///
+/// See: <https://docs.python.org/2/using/cmdline.html#envvar-PYTHONUNBUFFERED>
+pub const DEFAULT_CHILD_ENV: &[(&str, &str)] = &[("PYTHONUNBUFFERED", "true")];
+
+/// Maximum number of process groups which are tracked at once.
+const MAX_GROUPS: usize = 32;
+
+// Only used to spell out the initial slots of the registry
+#[allow(clippy::declare_interior_mutable_const)]
+const EMPTY: AtomicI32 = AtomicI32::new(0);
+
+static GROUPS: [AtomicI32; MAX_GROUPS] = [
+    EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+    EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+    EMPTY, EMPTY, EMPTY, EMPTY, EMPTY, EMPTY,
+];
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+static DEFAULT_ENV: AtomicBool = AtomicBool::new(true);
+
+lazy_static::lazy_static! {
+    static ref CHILD_ENV: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+
+    static ref CHILD_PRIORITY: Mutex<ChildPriority> = Mutex::new(ChildPriority::default());
+}
+
+/// The scheduling priority at which every child is run.
+///
+/// A priority is inherited by the processes which a child starts in turn, such as the `pkg` and
+/// `tar` processes which `iocage` spawns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChildPriority {
+    /// Nice value, from -20 (most favorable) to 20 (least favorable), as with `nice(1)`.
+    pub nice: Option<i32>,
+    /// Idle priority, from 0 (highest) to 31 (lowest), as with `idprio(1)`.
+    ///
+    /// A child with an idle priority only runs when no other process is ready to. Idle priorities
+    /// are only supported on FreeBSD, and a child fails to spawn elsewhere.
+    pub idprio: Option<u16>,
+}
+
+/// A registered process group, which is removed from the registry when dropped.
+#[derive(Debug)]
+pub(crate) struct ProcessGroup {
+    slot: Option<usize>,
+}
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            GROUPS[slot].store(0, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Sets the environment variables given to every child, and whether the defaults in
+/// [`DEFAULT_CHILD_ENV`] are also given.
+///
+/// A variable set here overrides a default variable of the same name.
+pub fn set_child_env(env: Vec<(String, String)>, defaults: bool) {
+    DEFAULT_ENV.store(defaults, Ordering::Relaxed);
+    *CHILD_ENV
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = env;
+}
+
+/// Sets the environment variables given to every child on a `Command`.
+fn apply_child_env(cmd: &mut Command) {
+    if DEFAULT_ENV.load(Ordering::Relaxed) {
+        cmd.envs(DEFAULT_CHILD_ENV.iter().copied());
+    }
+    cmd.envs(
+        CHILD_ENV
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .iter()
+            .map(|(key, value)| (key, value)),
+    );
+}
+
+/// Sets the scheduling priority at which every child is run.
+///
+/// Until a priority is set, children run at the priority of this program.
+pub fn set_child_priority(priority: ChildPriority) {
+    *CHILD_PRIORITY
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = priority;
+}
+
+#[cfg(unix)]
+fn child_priority() -> ChildPriority {
+    *CHILD_PRIORITY
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Spawns a `Command` in its own process group and registers the group.
+///
+/// The returned `ProcessGroup` should be held until the child has been waited on.
+///
+/// # Errors
+///
+/// Returns an `Err` if the running children have been terminated or the command failed to spawn.
+pub(crate) fn spawn(cmd: &mut Command) -> Result<(Child, ProcessGroup), CmdError> {
+    if cancelled() {
+        return Err(CmdError::Cancelled(cmd_get_program(cmd)));
+    }
+
+    apply_child_env(cmd);
+    #[cfg(unix)]
+    {
+        // Safety: the closure only makes the `setpgid` system call, which is async-signal-safe
+        unsafe {
+            std::os::unix::process::CommandExt::pre_exec(cmd, imp::set_process_group);
+        }
+        let priority = child_priority();
+        if priority != ChildPriority::default() {
+            // Safety: the closure only makes the `setpriority` and `rtprio` system calls, which
+            // are async-signal-safe
+            unsafe {
+                std::os::unix::process::CommandExt::pre_exec(cmd, move || {
+                    imp::set_priority(priority)
+                });
+            }
+        }
+    }
+
+    let child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            run_log::record(cmd, SystemTime::now(), None, "");
+            audit::record(cmd, None);
+            return Err(CmdError::Spawn(cmd_get_program(cmd), err));
+        }
+    };
+    let group = register(child.id());
+
+    Ok((child, group))
+}
+
+/// Returns whether or not the running children have been terminated with [`terminate_children`].
+///
+/// Once cancelled, no further commands are spawned.
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Terminates every registered process group and prevents further commands from being spawned.
+///
+/// Each group is sent a `SIGTERM` signal. This function is safe to call from a signal handler.
+pub fn terminate_children() {
+    CANCELLED.store(true, Ordering::SeqCst);
+
+    for group in GROUPS.iter() {
+        let pgid = group.load(Ordering::SeqCst);
+        if pgid > 0 {
+            imp::kill_group(pgid);
+        }
+    }
+}
+
+/// Installs handlers for the `SIGINT`, `SIGTERM`, and `SIGHUP` signals which call
+/// [`terminate_children`].
+///
+/// Each handler is reset once it has run so that a second interrupt terminates the program
+/// immediately.
+///
+/// # Errors
+///
+/// Returns an `Err` if a signal handler could not be installed.
+pub fn terminate_on_signal() -> std::io::Result<()> {
+    imp::install_handlers()
+}
+
+/// Runs an interactive `Command`, such as a login shell, to completion in the terminal's
+/// foreground process group and returns its exit status.
+///
+/// The child handles interrupts from the terminal itself, so `SIGINT` is ignored by this program
+/// until the child exits rather than terminating the children. The child is started with the
+/// default action for `SIGINT`.
+///
+/// # Errors
+///
+/// Returns an `Err` if the command failed to spawn or could not be waited on.
+pub(crate) fn run_interactive(cmd: &mut Command) -> std::io::Result<ExitStatus> {
+    apply_child_env(cmd);
+    imp::run_interactive(cmd)
+}
+
+fn register(pid: u32) -> ProcessGroup {
+    let pgid = pid as i32;
+    let slot = GROUPS.iter().position(|group| {
+        group
+            .compare_exchange(0, pgid, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    });
+    if slot.is_none() {
+        log::debug!(
+            "process group registry is full, not tracking; pgid={}",
+            pgid
+        );
+    }
+
+    ProcessGroup { slot }
+}
+
+#[cfg(unix)]
+mod imp {
+    use nix::libc;
+    use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+    use nix::unistd::{self, Pid};
+    use std::io;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, ExitStatus};
+
+    pub(super) fn kill_group(pgid: i32) {
+        // A group which has already exited is not an error worth reporting here
+        let _ = signal::killpg(Pid::from_raw(pgid), Signal::SIGTERM);
+    }
+
+    pub(super) fn install_handlers() -> std::io::Result<()> {
+        let action = SigAction::new(
+            SigHandler::Handler(handle_signal),
+            SaFlags::SA_RESETHAND | SaFlags::SA_RESTART,
+            SigSet::empty(),
+        );
+        for &sig in &[Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+            // Safety: the handler only performs atomic operations and calls `killpg`, all of
+            // which are async-signal-safe
+            unsafe { signal::sigaction(sig, &action) }.map_err(|_| io::Error::last_os_error())?;
+        }
+
+        Ok(())
+    }
+
+    extern "C" fn handle_signal(_: nix::libc::c_int) {
+        super::terminate_children();
+    }
+
+    pub(super) fn run_interactive(cmd: &mut Command) -> io::Result<ExitStatus> {
+        let ignore = SigAction::new(SigHandler::SigIgn, SaFlags::empty(), SigSet::empty());
+        // Safety: ignoring a signal doesn't install a handler
+        let previous = unsafe { signal::sigaction(Signal::SIGINT, &ignore) }
+            .map_err(|_| io::Error::last_os_error())?;
+        // Safety: the closure only calls `sigaction`, which is async-signal-safe
+        unsafe {
+            cmd.pre_exec(|| {
+                let default = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
+                signal::sigaction(Signal::SIGINT, &default)
+                    .map(|_| ())
+                    .map_err(|_| io::Error::last_os_error())
+            });
+        }
+        let status = cmd.status();
+        // Safety: the previous action was installed by this program or is the default
+        let _ = unsafe { signal::sigaction(Signal::SIGINT, &previous) };
+
+        status
+    }
+
+    /// Makes the calling process, which is a child about to exec, the leader of a new process group.
+    pub(super) fn set_process_group() -> io::Result<()> {
+        unistd::setpgid(Pid::from_raw(0), Pid::from_raw(0)).map_err(|_| io::Error::last_os_error())
+    }
+
+    /// Sets the scheduling priority of the calling process, which is a child about to exec.
+    pub(super) fn set_priority(priority: super::ChildPriority) -> io::Result<()> {
+        if let Some(nice) = priority.nice {
+            // Safety: `setpriority` has no memory safety requirements
+            if unsafe { libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        if let Some(prio) = priority.idprio {
+            set_idprio(prio)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(target_os = "freebsd")]
+    fn set_idprio(prio: u16) -> io::Result<()> {
+        let mut rtp = libc::rtprio {
+            type_: libc::RTP_PRIO_IDLE,
+            prio,
+        };
+        // Safety: `rtp` is a valid `rtprio` struct for the duration of the call
+        if unsafe { libc::rtprio(libc::RTP_SET, 0, &mut rtp) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Idle priorities are only supported on FreeBSD. Only the error number of a failure before
+    /// exec reaches the parent, so none of the error's message would be reported.
+    #[cfg(not(target_os = "freebsd"))]
+    fn set_idprio(_prio: u16) -> io::Result<()> {
+        Err(io::Error::from_raw_os_error(libc::ENOTSUP))
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    pub(super) fn kill_group(_pgid: i32) {}
+
+    pub(super) fn install_handlers() -> std::io::Result<()> {
+        Ok(())
+    }
+
+    pub(super) fn run_interactive(
+        cmd: &mut std::process::Command,
+    ) -> std::io::Result<std::process::ExitStatus> {
+        cmd.status()
+    }
+}
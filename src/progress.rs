@@ -0,0 +1,270 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Progress bars for long-running provisioning steps.
+//!
+//! While a [`Step`] is running with progress bars enabled, the lines of command output which
+//! would otherwise be streamed are captured instead and a single status line is redrawn in their
+//! place, showing a spinner, the elapsed time, and either a bar of the latest percentage found in
+//! the output or the latest line of output. When a step fails, its captured output is printed so
+//! that the cause of the failure isn't lost.
+//!
+//! Otherwise, while a step's output is streamed, a heartbeat line saying that the step is still
+//! running is logged whenever it has been silent for the [`heartbeat_interval`].
+
+use super::capture::{RingBuffer, CAPTURE_LIMIT};
+use super::{heartbeat_interval, parse, progress_bars, progress_to_stderr, redact, timestamps};
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Frames of the spinner shown while a step is running.
+const SPINNER: &[char] = &['|', '/', '-', '\\'];
+
+/// Width of the bar, in characters, when a percentage is known.
+const BAR_WIDTH: usize = 24;
+
+/// Maximum number of characters of the latest line of output shown in the status line.
+const MAX_LINE_WIDTH: usize = 48;
+
+/// How often the status line is redrawn.
+const TICK: Duration = Duration::from_millis(100);
+
+lazy_static::lazy_static! {
+    static ref ACTIVE: Mutex<Option<State>> = Mutex::new(None);
+
+    static ref HEARTBEAT: Mutex<Option<Heartbeat>> = Mutex::new(None);
+}
+
+/// The state of the running step whose output is streamed.
+#[derive(Debug)]
+struct Heartbeat {
+    message: String,
+    started: Instant,
+    last_output: Instant,
+}
+
+/// The state of the running step.
+#[derive(Debug)]
+struct State {
+    message: String,
+    started: Instant,
+    frame: usize,
+    percent: Option<u8>,
+    latest: String,
+    captured: RingBuffer,
+}
+
+impl State {
+    fn render(&self) -> String {
+        let elapsed = self.started.elapsed().as_secs();
+        let mut line = format!(
+            "  - {} {} [{:02}:{:02}]",
+            self.message,
+            SPINNER[self.frame % SPINNER.len()],
+            elapsed / 60,
+            elapsed % 60
+        );
+        match self.percent {
+            Some(percent) => {
+                let filled = BAR_WIDTH * usize::from(percent) / 100;
+                line.push_str(&format!(
+                    " [{}{}] {:>3}%",
+                    "#".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled),
+                    percent
+                ));
+            }
+            None if !self.latest.is_empty() => {
+                line.push(' ');
+                line.extend(self.latest.chars().take(MAX_LINE_WIDTH));
+            }
+            None => {}
+        }
+        line
+    }
+}
+
+/// A long-running step which shows a progress bar while it runs, if progress bars are enabled.
+///
+/// When progress bars aren't enabled, output is timestamped, or the program is logging more
+/// verbosely than the default, the step's message is logged and command output is streamed as it
+/// otherwise would be, with a heartbeat line logged whenever the output has been silent for the
+/// heartbeat interval.
+#[derive(Debug)]
+pub(crate) struct Step {
+    ticker: Option<JoinHandle<()>>,
+}
+
+impl Step {
+    /// Starts a step with the given message.
+    pub(crate) fn start(message: &str) -> Self {
+        if !progress_bars() || timestamps() || log::max_level() != log::LevelFilter::Info {
+            log::info!("{}", message);
+            return Self {
+                ticker: heartbeat_interval().map(|interval| start_heartbeat(message, interval)),
+            };
+        }
+
+        *lock() = Some(State {
+            message: message.to_string(),
+            started: Instant::now(),
+            frame: 0,
+            percent: None,
+            latest: String::new(),
+            captured: RingBuffer::new(CAPTURE_LIMIT),
+        });
+        let ticker = thread::spawn(|| loop {
+            {
+                let mut active = lock();
+                match active.as_mut() {
+                    Some(state) => {
+                        state.frame += 1;
+                        draw(&state.render());
+                    }
+                    None => return,
+                }
+            }
+            thread::sleep(TICK);
+        });
+
+        Self {
+            ticker: Some(ticker),
+        }
+    }
+
+    /// Finishes the step successfully, replacing the status line with the elapsed time.
+    pub(crate) fn finish(mut self) {
+        self.end(true);
+    }
+
+    fn end(&mut self, success: bool) {
+        let ticker = match self.ticker.take() {
+            Some(ticker) => ticker,
+            None => return,
+        };
+        if lock_heartbeat().take().is_some() {
+            // The heartbeat only panics if logging panics, in which case there is nothing to do
+            let _ = ticker.join();
+            return;
+        }
+        let state = lock().take();
+        // The ticker only panics if drawing panics, in which case there is nothing left to draw
+        let _ = ticker.join();
+
+        if let Some(state) = state {
+            let elapsed = state.started.elapsed().as_secs();
+            let outcome = if success { "done" } else { "failed" };
+            let mut summary = format!("  - {} ({} in {}s)\n", state.message, outcome, elapsed);
+            if !success {
+                summary.push_str(&state.captured.into_string());
+            }
+            draw(&summary);
+        }
+    }
+}
+
+impl Drop for Step {
+    fn drop(&mut self) {
+        // A step which wasn't finished has returned early with an error
+        self.end(false);
+    }
+}
+
+/// Captures a line of command output for the running step, returning `false` if no step is
+/// showing a progress bar and so the line should be printed as usual.
+pub(crate) fn capture(line: &str) -> bool {
+    let mut active = lock();
+    let state = match active.as_mut() {
+        Some(state) => state,
+        None => return false,
+    };
+
+    if let Some(percent) = parse::percentage(line) {
+        state.percent = Some(percent);
+    }
+    let latest = line.trim();
+    if !latest.is_empty() {
+        state.latest = latest.to_string();
+    }
+    state.captured.push_line(line);
+    draw(&state.render());
+
+    true
+}
+
+/// Notes that a line of command output was read, resetting the running step's heartbeat.
+pub(crate) fn touch() {
+    if let Some(heartbeat) = lock_heartbeat().as_mut() {
+        heartbeat.last_output = Instant::now();
+    }
+}
+
+/// Starts logging a heartbeat line for a step whenever it has been silent for the interval.
+fn start_heartbeat(message: &str, interval: Duration) -> JoinHandle<()> {
+    let now = Instant::now();
+    *lock_heartbeat() = Some(Heartbeat {
+        message: message.to_string(),
+        started: now,
+        last_output: now,
+    });
+
+    thread::spawn(move || loop {
+        {
+            let mut active = lock_heartbeat();
+            match active.as_mut() {
+                Some(heartbeat) => {
+                    if heartbeat.last_output.elapsed() >= interval {
+                        log::info!(
+                            "Still running step '{}' ({} elapsed)",
+                            heartbeat.message,
+                            elapsed(heartbeat.started.elapsed())
+                        );
+                        heartbeat.last_output = Instant::now();
+                    }
+                }
+                None => return,
+            }
+        }
+        thread::sleep(TICK);
+    })
+}
+
+/// Formats an elapsed time in whole units, such as `45s`, `3m20s`, or `1h05m00s`.
+fn elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, s) => format!("{}h{:02}m{:02}s", h, m, s),
+    }
+}
+
+/// Redraws the status line on the progress output stream.
+fn draw(line: &str) {
+    // Clear the current line before drawing over it
+    let line = format!("\r\x1b[2K{}", redact(line));
+    if progress_to_stderr() {
+        let _ = io::stderr().write_all(line.as_bytes());
+    } else {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(line.as_bytes());
+        let _ = stdout.flush();
+    }
+}
+
+/// Locks the running step's state, even if a thread panicked while holding the lock.
+fn lock() -> std::sync::MutexGuard<'static, Option<State>> {
+    ACTIVE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+/// Locks the running step's heartbeat, even if a thread panicked while holding the lock.
+fn lock_heartbeat() -> std::sync::MutexGuard<'static, Option<Heartbeat>> {
+    HEARTBEAT
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
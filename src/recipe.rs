@@ -0,0 +1,139 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Recipes, which are presets for a common kind of jail.
+//!
+//! A recipe adds to what a jail's spec declares rather than replacing it:
+//!
+//! * iocage properties, unless the spec sets the same properties itself
+//! * packages, installed with the jail's other packages
+//! * a dataset for the service's data, created beneath the jail's dataset with properties tuned
+//!   for the service and kept along with the jail's other data datasets
+//! * a script, run in the jail once it's provisioned, which initializes and starts the service
+//!
+//! Recipes which only install packages, such as `ci-runner`, have no dataset or script.
+
+use super::{iocage_exec, run_zfs_create, run_zfs_set, script, Error, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::result;
+use std::str;
+
+/// Recipes which can be applied to a jail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Recipe {
+    /// A disposable build environment for CI jobs, with common build toolchains.
+    CiRunner,
+    /// A PostgreSQL database server.
+    Postgres,
+}
+
+/// A dataset for a recipe's service data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RecipeDataset {
+    /// Name of the dataset relative to the jail's dataset.
+    pub name: &'static str,
+    /// Mount point of the dataset in the jail.
+    pub mountpoint: &'static str,
+    /// ZFS properties set on the dataset.
+    pub props: &'static [&'static str],
+}
+
+impl Recipe {
+    /// Returns the name of this recipe.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::CiRunner => "ci-runner",
+            Self::Postgres => "postgres",
+        }
+    }
+
+    /// Returns the iocage properties set by this recipe.
+    pub fn properties(&self) -> &'static [&'static str] {
+        match self {
+            Self::CiRunner => &[],
+            // The server allocates shared memory and semaphores with System V IPC
+            Self::Postgres => &["sysvshm=new", "sysvsem=new"],
+        }
+    }
+
+    /// Returns the packages installed by this recipe.
+    pub fn packages(&self) -> &'static [&'static str] {
+        match self {
+            Self::CiRunner => &[
+                "autoconf", "automake", "cmake", "git", "gmake", "go", "libtool", "ninja",
+                "pkgconf", "python3", "rust",
+            ],
+            Self::Postgres => &["postgresql16-server"],
+        }
+    }
+
+    /// Returns the dataset created for this recipe's service data, if it has one.
+    pub fn dataset(&self) -> Option<RecipeDataset> {
+        match self {
+            Self::CiRunner => None,
+            Self::Postgres => Some(RecipeDataset {
+                name: "data/postgres",
+                mountpoint: "/var/db/postgres",
+                // A record size of two database pages suits both table scans and compression
+                props: &[
+                    "recordsize=16K",
+                    "compression=lz4",
+                    "atime=off",
+                    "logbias=throughput",
+                ],
+            }),
+        }
+    }
+}
+
+impl str::FromStr for Recipe {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "ci-runner" => Ok(Self::CiRunner),
+            "postgres" => Ok(Self::Postgres),
+            _ => Err(format!("invalid recipe; recipe={}", s)),
+        }
+    }
+}
+
+/// Applies a recipe to a provisioned jail, creating its dataset and running its script, if it
+/// has them.
+///
+/// # Errors
+///
+/// Returns an `Err` if the dataset could not be created or the script was not successfully
+/// executed in the jail.
+pub(crate) fn apply(jail_name: &str, dataset: &str, root: &Path, recipe: Recipe) -> Result<()> {
+    if let Some(data) = recipe.dataset() {
+        let data_dataset = format!("{}/{}", dataset, data.name);
+        info!(
+            "Creating ZFS dataset '{}' for {}",
+            data_dataset, data.mountpoint
+        );
+        run_zfs_create(
+            &data_dataset,
+            &format!("{}{}", root.display(), data.mountpoint),
+        )?;
+        run_zfs_set(
+            &data_dataset,
+            &data
+                .props
+                .iter()
+                .map(|prop| prop.to_string())
+                .collect::<Vec<_>>(),
+        )?;
+    }
+
+    match script::recipe(recipe) {
+        Some(src) => {
+            iocage_exec(jail_name, src).map_err(|err| Error::ExecRecipe(recipe.as_str(), err))
+        }
+        None => Ok(()),
+    }
+}
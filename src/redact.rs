@@ -0,0 +1,60 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Masking of secret values in logs, errors, and other output.
+//!
+//! Secrets are registered once when they become known (for example the secrets of a spec or the
+//! contents of private key files) and every registered value is then replaced by [`REDACTED`] in
+//! text passed through [`redact`].
+
+use serde::Serializer;
+use std::sync::RwLock;
+
+/// The text which replaces a secret value.
+pub const REDACTED: &str = "<redacted>";
+
+/// Secrets shorter than this are not registered as masking them would mangle unrelated text.
+const MIN_SECRET_LEN: usize = 4;
+
+lazy_static::lazy_static! {
+    /// All registered secret values.
+    static ref SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+}
+
+/// Registers a secret value so that it is masked by [`redact`].
+///
+/// Each line of a multi-line value (such as a private key) is also registered so that it is
+/// masked when the value is output line by line. The escaped form of each value is registered as
+/// well, so that it is masked in `Debug` formatted text (such as a logged command) and in JSON,
+/// where characters such as `"` and `\` are escaped.
+pub fn register_secret(secret: &str) {
+    let mut secrets = SECRETS.write().expect("secrets lock poisoned");
+    let lines = secret.lines().map(str::trim);
+    for value in std::iter::once(secret.trim()).chain(lines) {
+        let escaped = value.escape_debug().to_string();
+        for value in std::iter::once(value).chain(Some(escaped.as_str())) {
+            if value.len() >= MIN_SECRET_LEN && !secrets.iter().any(|s| s == value) {
+                secrets.push(value.to_string());
+            }
+        }
+    }
+    // Longer secrets are replaced first so that a secret containing another is fully masked
+    secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+}
+
+/// Returns the text with every registered secret value masked.
+pub fn redact(text: &str) -> String {
+    let secrets = SECRETS.read().expect("secrets lock poisoned");
+    secrets.iter().fold(text.to_string(), |text, secret| {
+        text.replace(secret, REDACTED)
+    })
+}
+
+/// Serializes a list of secrets with each value masked.
+pub(crate) fn serialize_masked<S>(secrets: &[String], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.collect_seq(secrets.iter().map(|_| REDACTED))
+}
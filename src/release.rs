@@ -0,0 +1,254 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Imports of locally built userlands as iocage releases.
+//!
+//! A release given as `path:PATH` names a userland on the host rather than a release which
+//! iocage fetches. The userland is either a directory, such as the `DESTDIR` of a
+//! `make installworld`, or a tarball of one. It is imported once as an iocage release named after
+//! the directory or tarball, and jails are then created from that release as from any other.
+//!
+//! In the experimental pkgbase mode, a userland is instead built by installing FreeBSD base
+//! system packages from the official pkgbase repository into a new release with `pkg --rootdir`,
+//! so that only the selected parts of the base system are installed.
+
+use super::{
+    cmd_stdout, iocage_query_command, quote, run_and_indent, sysctl, zfs_mountpoint, Error, Result,
+};
+use log::info;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Prefix of a release which names a local userland.
+const PATH_PREFIX: &str = "path:";
+
+/// Base system packages which are installed in a pkgbase userland when none are given.
+pub const PKGBASE_DEFAULT_PACKAGES: &[&str] = &[
+    "FreeBSD-pkg-bootstrap",
+    "FreeBSD-rc",
+    "FreeBSD-runtime",
+    "FreeBSD-utilities",
+];
+
+/// Name of the repository of base system packages.
+const PKGBASE_REPO: &str = "FreeBSD-base";
+
+/// Suffixes of tarball file names, which are removed to name the imported release.
+const TARBALL_SUFFIXES: &[&str] = &[".tar.gz", ".tar.xz", ".tar", ".tgz", ".txz"];
+
+/// Returns the path of the local userland which a release names, if it names one.
+pub fn local_path(release: &str) -> Option<&Path> {
+    release.strip_prefix(PATH_PREFIX).map(Path::new)
+}
+
+/// Returns the name of the release which a local userland is imported as, or `None` if the path
+/// has no file name.
+///
+/// The name is the file name of the directory or tarball, without any tarball suffix.
+pub fn local_name(path: &Path) -> Option<String> {
+    let file_name = path.file_name()?.to_string_lossy();
+    let name = TARBALL_SUFFIXES
+        .iter()
+        .find_map(|suffix| file_name.strip_suffix(suffix))
+        .unwrap_or(&file_name);
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Returns the major version of a release and the branch of the pkgbase repository which holds
+/// its packages, or `None` if the release is not a FreeBSD version.
+///
+/// A `RELEASE` version is installed from its minor version's release branch (i.e.
+/// `base_release_2` for `13.2-RELEASE`) and any other version, such as a `STABLE` one, from the
+/// latest packages of its major version (i.e. `base_latest`).
+pub fn pkgbase_branch(release: &str) -> Option<(u32, String)> {
+    let mut parts = release.splitn(2, '-');
+    let version = parts.next()?;
+    let kind = parts.next().unwrap_or("");
+    let mut parts = version.splitn(2, '.');
+    let (major, minor) = (parts.next()?, parts.next()?);
+    let major = major.parse::<u32>().ok()?;
+    let minor = minor.parse::<u32>().ok()?;
+
+    let branch = if kind == "RELEASE" {
+        format!("base_release_{}", minor)
+    } else {
+        String::from("base_latest")
+    };
+    Some((major, branch))
+}
+
+/// Returns the name of the release which a pkgbase userland of the given packages is built as.
+///
+/// A userland of other than the default packages is named with a hash of its packages, so that
+/// userlands with different packages are kept apart.
+pub fn pkgbase_name(release: &str, packages: &[String]) -> String {
+    if packages.is_empty() {
+        return format!("{}-pkgbase", release);
+    }
+
+    // FNV-1a, which is stable across builds unlike the standard library's hasher
+    let mut packages = packages.to_vec();
+    packages.sort();
+    let hash = packages.join(",").bytes().fold(0x811c_9dc5_u32, |hash, b| {
+        (hash ^ u32::from(b)).wrapping_mul(0x0100_0193)
+    });
+    format!("{}-pkgbase-{:08x}", release, hash)
+}
+
+/// Builds a userland from pkgbase packages for the given release as a release in the given pool,
+/// unless it has been built already, and returns the name of the release.
+///
+/// When no packages are given, the [`PKGBASE_DEFAULT_PACKAGES`] are installed.
+///
+/// # Errors
+///
+/// Returns an `Err` if:
+///
+/// * The release is not a FreeBSD version
+/// * The existing releases could not be listed
+/// * The release dataset could not be created
+/// * The packages could not be installed
+pub(crate) fn build_pkgbase(pool: &str, release: &str, packages: &[String]) -> Result<String> {
+    let (major, branch) =
+        pkgbase_branch(release).ok_or_else(|| Error::PkgbaseRelease(release.to_string()))?;
+    let name = pkgbase_name(release, packages);
+
+    if list_releases()?.iter().any(|existing| existing == &name) {
+        info!("Using pkgbase release '{}'", name);
+        return Ok(name);
+    }
+
+    info!("Building pkgbase release '{}'", name);
+    let root = create_release_dataset(pool, &name)?;
+    let repo_conf_dir = tempfile::tempdir().map_err(Error::PkgbaseRepoConf)?;
+    fs::write(
+        repo_conf_dir.path().join(format!("{}.conf", PKGBASE_REPO)),
+        format!(
+            "{repo}: {{\n  url: \"pkg+https://pkg.FreeBSD.org/${{ABI}}/{branch}\",\n  \
+            mirror_type: \"srv\",\n  signature_type: \"fingerprints\",\n  \
+            fingerprints: \"/usr/share/keys/pkg\",\n  enabled: yes\n}}\n",
+            branch = branch,
+            repo = PKGBASE_REPO,
+        ),
+    )
+    .map_err(Error::PkgbaseRepoConf)?;
+
+    let mut cmd = Command::new("pkg");
+    cmd.arg("--rootdir")
+        .arg(&root)
+        .arg("--repo-conf-dir")
+        .arg(repo_conf_dir.path())
+        .arg("-o")
+        .arg(format!(
+            "ABI=FreeBSD:{}:{}",
+            major,
+            sysctl("hw.machine_arch")?
+        ))
+        .arg("-o")
+        .arg("IGNORE_OSVERSION=yes")
+        .arg("-o")
+        .arg("ASSUME_ALWAYS_YES=yes")
+        .arg("install")
+        .arg("--repository")
+        .arg(PKGBASE_REPO);
+    if packages.is_empty() {
+        cmd.args(PKGBASE_DEFAULT_PACKAGES);
+    } else {
+        cmd.args(packages);
+    }
+    run_and_indent(cmd).map_err(Error::PkgbaseInstall)?;
+
+    Ok(name)
+}
+
+/// Imports a local userland as a release in the given pool, unless a release of the same name
+/// already exists, and returns the name of the release.
+///
+/// # Errors
+///
+/// Returns an `Err` if:
+///
+/// * The userland doesn't exist or its path has no file name
+/// * The existing releases could not be listed
+/// * The release dataset could not be created
+/// * The userland could not be copied into the release
+pub(crate) fn import(pool: &str, path: &Path) -> Result<String> {
+    let name = local_name(path).ok_or_else(|| Error::LocalReleaseNotFound(path.to_path_buf()))?;
+    if !path.exists() {
+        return Err(Error::LocalReleaseNotFound(path.to_path_buf()));
+    }
+
+    if list_releases()?.iter().any(|release| release == &name) {
+        info!(
+            "Using release '{}' imported from '{}'",
+            name,
+            path.display()
+        );
+        return Ok(name);
+    }
+
+    info!("Importing release '{}' from '{}'", name, path.display());
+    let root = create_release_dataset(pool, &name)?;
+    let src = if path.is_dir() {
+        format!(
+            "tar -cf - -C {} . | tar -xpf - -C {}",
+            quote(&path.to_string_lossy()),
+            quote(&root)
+        )
+    } else {
+        format!(
+            "tar -xpf {} -C {}",
+            quote(&path.to_string_lossy()),
+            quote(&root)
+        )
+    };
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(src);
+    run_and_indent(cmd).map_err(|err| Error::ImportRelease(path.to_path_buf(), err))?;
+
+    Ok(name)
+}
+
+/// Creates the root dataset of a new release in the given pool and returns its mount point.
+///
+/// # Errors
+///
+/// Returns an `Err` if the dataset could not be created or its mount point could not be read.
+fn create_release_dataset(pool: &str, name: &str) -> Result<String> {
+    let dataset = format!("{}/iocage/releases/{}/root", pool, name);
+    let mut cmd = Command::new("zfs");
+    cmd.arg("create").arg("-p").arg(&dataset);
+    run_and_indent(cmd).map_err(|err| Error::ZfsCreate(dataset.clone(), err))?;
+
+    zfs_mountpoint(&dataset)
+}
+
+/// Returns the names of the releases which have been fetched, imported, or built.
+///
+/// # Errors
+///
+/// Returns an `Err` if the `iocage` program fails.
+fn list_releases() -> Result<Vec<String>> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("list").arg("--release").arg("-h");
+
+    Ok(cmd_stdout(cmd)
+        .map_err(Error::IocageList)?
+        .lines()
+        .map(|line| {
+            line.split('\t')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        })
+        .filter(|release| !release.is_empty())
+        .collect())
+}
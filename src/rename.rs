@@ -0,0 +1,86 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Renaming of a jail which keeps what iocage-provision tracks about it consistent.
+//!
+//! A running jail is stopped while iocage renames it and started again afterwards. A managed
+//! jail's spec (including its tags) and expiry move to the new name in the state file, and its
+//! hosts file entry and DNS registration are replaced with ones for the new name.
+
+#[cfg(feature = "manifest")]
+use super::{dns, hosts, state};
+use super::{find_jail, iocage_command, jail_uuid, run_and_indent, run_iocage_set};
+use super::{run_iocage_start, run_iocage_stop, Error, Result};
+use log::info;
+
+/// Renames a jail, optionally also setting its hostname to the new name.
+///
+/// # Errors
+///
+/// Returns an `Err` if:
+///
+/// * The jail does not exist, or a jail with the new name already exists
+/// * The jail could not be stopped, renamed, or started again
+/// * The hostname could not be set
+/// * The state file, hosts file, or DNS registration could not be updated
+pub fn rename_jail(old: &str, new: &str, set_hostname: bool) -> Result<()> {
+    let jail = find_jail(old)?.ok_or_else(|| Error::JailNotFound(old.to_string()))?;
+    if let Some(existing) = find_jail(new)? {
+        return Err(Error::JailExists(
+            new.to_string(),
+            jail_uuid(new)?,
+            existing.state,
+        ));
+    }
+
+    section!("Renaming jail '{}' to '{}'", old, new);
+
+    let running = jail.state == "up";
+    if running {
+        info!("Stopping jail '{}'", old);
+        run_iocage_stop(old)?;
+    }
+
+    info!("Renaming jail '{}' to '{}'", old, new);
+    let mut cmd = iocage_command();
+    cmd.arg("rename").arg(old).arg(new);
+    run_and_indent(cmd).map_err(|err| Error::IocageRename(old.to_string(), err))?;
+
+    if set_hostname {
+        info!("Setting hostname of '{}'", new);
+        run_iocage_set(new, &[format!("host_hostname={}", new)])?;
+    }
+
+    #[cfg(feature = "manifest")]
+    if let Some(spec) = state::rename(old, new)? {
+        if spec.hosts_entry {
+            info!(
+                "Replacing '{}' with '{}' in '{}'",
+                old,
+                new,
+                hosts::HOSTS_FILE
+            );
+            hosts::remove_host_entry(old)?;
+            hosts::add_host_entry(new, spec.ip.addr())?;
+        }
+        if let Some(ref registration) = spec.register_dns {
+            info!(
+                "Replacing '{}' with '{}' in DNS",
+                registration.fqdn(old),
+                registration.fqdn(new)
+            );
+            dns::deregister(registration, old)?;
+            dns::register(registration, new, spec.ip.addr())?;
+        }
+    }
+
+    if running {
+        info!("Starting jail '{}'", new);
+        run_iocage_start(new)?;
+    }
+
+    section!("Jail '{}' renamed to '{}'", old, new);
+
+    Ok(())
+}
@@ -0,0 +1,185 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A structured record of a provisioning run, for later auditing or attaching to a ticket.
+//!
+//! Once started with [`start_run_log`], every command which is run is recorded in memory, and
+//! [`write_run_log`] writes the whole run as a single JSON object with the fields:
+//!
+//! * `version`: the version of this crate
+//! * `started` and `finished`: the times the run started and finished, in RFC 3339 format in UTC
+//! * `spec`: the jail's spec, including all resolved defaults
+//! * `commands`: each command with its `started` and `finished` times, `duration_ms`, `jail`,
+//!   `argv`, `result` (as in the audit log), `exit_code`, and `output` (the end of its captured
+//!   output, truncated to [`OUTPUT_LIMIT`] bytes)
+//! * `result`: `status` (`success` or `failure`) with either the `report` of the jail, or the
+//!   `error`, its `causes`, and its `kind`
+//!
+//! Unlike the audit log, commands which only read state are recorded too. All values are masked
+//! with [`redact`].
+
+use super::{audit, cmd_argv, redact, Error, JailSpec, ProvisionReport};
+use serde::Serialize;
+use std::error::Error as _;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// Maximum number of bytes of a command's output which are recorded, keeping its end.
+const OUTPUT_LIMIT: usize = 4096;
+
+lazy_static::lazy_static! {
+    static ref RUN_LOG: Mutex<Option<Run>> = Mutex::new(None);
+}
+
+struct Run {
+    started: SystemTime,
+    commands: Vec<CommandRecord>,
+}
+
+#[derive(Serialize)]
+struct CommandRecord {
+    started: String,
+    finished: String,
+    duration_ms: u128,
+    jail: Option<String>,
+    argv: Vec<String>,
+    result: String,
+    exit_code: Option<i32>,
+    output: String,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    version: &'static str,
+    started: String,
+    finished: String,
+    spec: &'a JailSpec,
+    commands: &'a [CommandRecord],
+    result: RunResult<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+enum RunResult<'a> {
+    Success {
+        report: &'a ProvisionReport,
+    },
+    Failure {
+        error: String,
+        causes: Vec<String>,
+        kind: &'static str,
+    },
+}
+
+/// Starts recording a run, discarding any commands recorded by a previous run.
+pub fn start_run_log() {
+    *lock() = Some(Run {
+        started: SystemTime::now(),
+        commands: Vec::new(),
+    });
+}
+
+/// Records a command which has run (or failed to spawn, when `status` is `None`) along with its
+/// captured output, if a run is being recorded.
+pub(crate) fn record(
+    cmd: &Command,
+    started: SystemTime,
+    status: Option<&ExitStatus>,
+    output: &str,
+) {
+    let mut run = lock();
+    let run = match run.as_mut() {
+        Some(run) => run,
+        None => return,
+    };
+    let finished = SystemTime::now();
+
+    run.commands.push(CommandRecord {
+        started: audit::timestamp(started),
+        finished: audit::timestamp(finished),
+        duration_ms: finished
+            .duration_since(started)
+            .unwrap_or(Duration::from_secs(0))
+            .as_millis(),
+        jail: audit::jail(),
+        argv: cmd_argv(cmd).iter().map(|arg| redact(arg)).collect(),
+        result: audit::result(status),
+        exit_code: status.and_then(ExitStatus::code),
+        output: redact(truncate(output)),
+    });
+}
+
+/// Writes the recorded run, with the jail's spec and the result of provisioning it, to a file.
+///
+/// The file is replaced if it exists, and its parent directories are created as needed.
+///
+/// # Errors
+///
+/// Returns an `Err` if the file could not be written.
+pub fn write_run_log(
+    path: &Path,
+    spec: &JailSpec,
+    result: Result<&ProvisionReport, &Error>,
+) -> io::Result<()> {
+    let run = lock();
+    let (started, commands) = match run.as_ref() {
+        Some(run) => (run.started, run.commands.as_slice()),
+        None => (SystemTime::now(), &[][..]),
+    };
+    let result = match result {
+        Ok(report) => RunResult::Success { report },
+        Err(err) => RunResult::Failure {
+            error: err.to_string(),
+            causes: std::iter::successors(err.source(), |err| (*err).source())
+                .map(ToString::to_string)
+                .collect(),
+            kind: err.kind().as_str(),
+        },
+    };
+    let record = Record {
+        version: env!("CARGO_PKG_VERSION"),
+        started: audit::timestamp(started),
+        finished: audit::timestamp(SystemTime::now()),
+        spec,
+        commands,
+        result,
+    };
+    let mut json = serde_json::to_string_pretty(&record).map_err(io::Error::from)?;
+    json.push('\n');
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.create(true).write(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(redact(&json).as_bytes())
+}
+
+/// Returns the end of a command's output, at most [`OUTPUT_LIMIT`] bytes long.
+fn truncate(output: &str) -> &str {
+    if output.len() <= OUTPUT_LIMIT {
+        return output;
+    }
+    let mut start = output.len() - OUTPUT_LIMIT;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    &output[start..]
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<Run>> {
+    RUN_LOG
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
@@ -0,0 +1,726 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Generation of the `sh` scripts which are executed in a jail while it is provisioned.
+//!
+//! Each function returns the source of a script without running it, so that the generated
+//! scripts can be inspected and tested without a jail. Every value interpolated into a script is
+//! quoted with [`quote`] so that a value containing spaces, quotes, or other characters which are
+//! special to `sh` is always passed as a single literal word.
+
+use super::hosts;
+use super::recipe::Recipe;
+use super::user_data::UserData;
+use super::{MonitoringAgent, SshHostKeyType};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+
+/// Delimiter used for here-documents embedded in jail scripts.
+const HEREDOC_DELIMITER: &str = "_IOCAGE_PROVISION_EOF_";
+
+/// Home directory settings for a user created in a jail.
+#[derive(Clone, Debug, Default)]
+pub struct UserHome {
+    /// Home directory path, if not the `pw` default.
+    pub dir: Option<String>,
+    /// Permissions mode of the home directory, if not the `pw` default.
+    pub mode: Option<u32>,
+    /// Whether or not the home directory is created and populated.
+    pub create: bool,
+}
+
+/// Returns the script which allows members of the `wheel` group to run any command as root
+/// without a password.
+pub fn sudo_config() -> String {
+    String::from("echo '%wheel ALL=(ALL) NOPASSWD: ALL' >/usr/local/etc/sudoers.d/wheel")
+}
+
+/// Returns the script which creates a system group.
+///
+/// If no `gid` is given, the next free `gid` in the jail is used.
+pub fn create_group(group_name: &str, gid: Option<u32>) -> String {
+    let mut src = format!("pw groupadd -n {}", quote(group_name));
+    if let Some(gid) = gid {
+        src.push_str(&format!(" -g {}", gid));
+    }
+    src
+}
+
+/// Returns the script which creates the given supplementary groups, each a name and `gid`, if
+/// they don't already exist.
+///
+/// Each group is created with the given `gid` unless that `gid` is already taken in the jail, in
+/// which case the next free `gid` is used.
+pub fn copy_groups(groups: &[(String, u32)]) -> String {
+    let mut src = String::new();
+    for (group_name, gid) in groups {
+        // Use the host's gid unless it is already taken in the jail, otherwise let `pw` pick one
+        src.push_str(&format!(
+            "pw groupshow -n {grp} >/dev/null 2>&1 || \
+            if pw groupshow -g {gid} >/dev/null 2>&1; then pw groupadd -n {grp}; \
+            else pw groupadd -n {grp} -g {gid}; fi\n",
+            gid = gid,
+            grp = quote(group_name),
+        ));
+    }
+    src
+}
+
+/// Returns the script which creates a system user with the given primary group, supplementary
+/// groups, and login shell.
+///
+/// If no `uid` is given, the next free `uid` in the jail is used.
+pub fn create_user(
+    user_name: &str,
+    shell: &str,
+    group_name: &str,
+    groups: &[String],
+    uid: Option<u32>,
+    home: &UserHome,
+) -> String {
+    let mut src = format!(
+        "pw useradd -n {usr} -g {grp} -G {grps} -s {shl}",
+        grp = quote(group_name),
+        grps = quote(&group_list(groups)),
+        shl = quote(shell),
+        usr = quote(user_name),
+    );
+    if let Some(uid) = uid {
+        src.push_str(&format!(" -u {}", uid));
+    }
+    if let Some(ref dir) = home.dir {
+        src.push_str(&format!(" -d {}", quote(dir)));
+    }
+    if home.create {
+        src.push_str(" -m");
+        if let Some(mode) = home.mode {
+            src.push_str(&format!(" -M {:o}", mode));
+        }
+    }
+    src
+}
+
+/// Returns the script which adds an existing user to the `wheel` group and the given
+/// supplementary groups so that it can be used in place of a new user.
+pub fn reuse_user(user_name: &str, groups: &[String]) -> String {
+    format!(
+        "pw usermod -n {usr} -G {grps}",
+        grps = quote(&group_list(groups)),
+        usr = quote(user_name),
+    )
+}
+
+/// Returns the script which sets the login class and the locale of a user.
+///
+/// The locale is set in the user's `~/.login_conf` file, if the user has a home directory, so that
+/// it applies to all login sessions.
+pub fn user_login(user_name: &str, login_class: Option<&str>, lang: Option<&str>) -> String {
+    let mut src = String::new();
+    if let Some(login_class) = login_class {
+        src.push_str(&format!(
+            "pw usermod -n {usr} -L {cls}\n",
+            cls = quote(login_class),
+            usr = quote(user_name),
+        ));
+    }
+    if let Some(lang) = lang {
+        let login_conf = match lang.find('.') {
+            Some(idx) => format!(
+                "me:\\\n\t:charset={}:\\\n\t:lang={}:",
+                &lang[idx + 1..],
+                lang
+            ),
+            None => format!("me:\\\n\t:lang={}:", lang),
+        };
+        src.push_str(&format!(
+            "home=\"$(pw usershow -n {usr} | cut -d: -f9)\"\n\
+            if [ -d \"$home\" ]; then\n\
+            cd \"$home\"\n",
+            usr = quote(user_name),
+        ));
+        src.push_str(&heredoc_to_file(".login_conf", &login_conf));
+        src.push_str(&format!(
+            "chown {} .login_conf\nfi\n",
+            quote(&format!("{}:", user_name))
+        ));
+    }
+    src
+}
+
+/// Returns the script which creates a non-interactive automation user.
+///
+/// The user's password is disabled so that the only way to log in is with the given SSH public
+/// key. When commands are given, a sudoers entry allows the user to run only those commands as
+/// root without a password.
+pub fn automation_user(user_name: &str, key: Option<&str>, commands: &[String]) -> String {
+    let mut src = format!(
+        "pw useradd -n {usr} -c 'Automation user' -m -s /bin/sh -h -\n",
+        usr = quote(user_name)
+    );
+    if let Some(key) = key {
+        src.push_str(&authorized_keys(user_name, key));
+    }
+    if !commands.is_empty() {
+        let commands = commands
+            .iter()
+            .map(|command| sudoers_escape(command))
+            .collect::<Vec<_>>()
+            .join(", ");
+        src.push_str(&sudoers_rules(
+            user_name,
+            &format!("{} ALL=(root) NOPASSWD: {}", user_name, commands),
+        ));
+    }
+    src
+}
+
+/// Returns the script which configures and starts an SSH service.
+///
+/// Any given host key files, each a file name and contents, are installed before the service
+/// first starts. When host key types are given, only those types are generated and used by the
+/// service. When a listen address is given, such as the address of a jail which shares the host's
+/// network stack, the service only listens on that address.
+pub fn ssh_service(
+    key_types: &[SshHostKeyType],
+    key_files: &[(String, String)],
+    listen_addr: Option<IpAddr>,
+) -> String {
+    let mut src = String::new();
+    for (file_name, contents) in key_files {
+        let path = format!("/etc/ssh/{}", file_name);
+        src.push_str(&heredoc_to_file(&path, contents));
+        let mode = if file_name.ends_with(".pub") {
+            "0644"
+        } else {
+            "0600"
+        };
+        src.push_str(&format!("chmod {} {}\n", mode, quote(&path)));
+    }
+    if !key_types.is_empty() {
+        // The sshd rc script only generates the enabled key types on first start and sshd only
+        // loads the listed host keys
+        let enables = SshHostKeyType::ALL
+            .iter()
+            .map(|key_type| {
+                let enable = if key_types.contains(key_type) {
+                    "YES"
+                } else {
+                    "NO"
+                };
+                format!("sshd_{}_enable=\"{}\"", key_type.as_str(), enable)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        src.push_str(&format!("sysrc -f /etc/rc.conf {}\n", enables));
+        for key_type in key_types {
+            src.push_str(&format!(
+                "echo 'HostKey /etc/ssh/ssh_host_{}_key' >>/etc/ssh/sshd_config\n",
+                key_type.as_str()
+            ));
+        }
+    }
+    if let Some(listen_addr) = listen_addr {
+        src.push_str(&format!(
+            "echo 'ListenAddress {}' >>/etc/ssh/sshd_config\n",
+            listen_addr
+        ));
+    }
+    src.push_str(r#"sysrc -f /etc/rc.conf sshd_enable="YES" && service sshd start"#);
+    src
+}
+
+/// Returns the script which enables and starts the NTP service.
+///
+/// The service is set to step the clock on start so that a jail which has drifted badly is
+/// corrected immediately rather than slewed over a long period.
+pub fn ntp_service() -> String {
+    String::from(
+        r#"sysrc -f /etc/rc.conf ntpd_enable="YES" ntpd_sync_on_start="YES" && service ntpd start"#,
+    )
+}
+
+/// Returns the script which enables IPv4 and IPv6 forwarding in a VNET jail, now and at boot.
+pub fn ip_forwarding() -> String {
+    String::from(concat!(
+        "sysrc -f /etc/rc.conf gateway_enable=\"YES\" ipv6_gateway_enable=\"YES\"\n",
+        "sysctl net.inet.ip.forwarding=1 net.inet6.ip6.forwarding=1\n",
+    ))
+}
+
+/// Returns the script which initializes, enables, and starts a recipe's service, whose package is
+/// already installed and whose data dataset is already mounted, or `None` if the recipe has no
+/// service.
+///
+/// The PostgreSQL data directory is only initialized when the dataset is empty, so existing data
+/// is never overwritten.
+pub fn recipe(recipe: Recipe) -> Option<String> {
+    match recipe {
+        Recipe::CiRunner => None,
+        Recipe::Postgres => Some(String::from(concat!(
+            "chown postgres:postgres /var/db/postgres\n",
+            "sysrc -f /etc/rc.conf postgresql_enable=\"YES\"\n",
+            "if [ -z \"$(ls -A /var/db/postgres)\" ]; then\n",
+            "  service postgresql initdb\n",
+            "fi\n",
+            "service postgresql start\n",
+        ))),
+    }
+}
+
+/// Returns the script which configures, enables, and starts a monitoring agent, whose package is
+/// already installed.
+///
+/// The node exporter listens on all addresses with its defaults. The Zabbix agent's configuration
+/// is replaced with one which answers passive checks from, and sends active checks to, the given
+/// server and names the jail by its hostname.
+pub fn monitoring(agent: MonitoringAgent, server: Option<&str>) -> String {
+    match agent {
+        MonitoringAgent::NodeExporter => String::from(concat!(
+            "sysrc -f /etc/rc.conf node_exporter_enable=\"YES\"\n",
+            "service node_exporter start\n",
+        )),
+        MonitoringAgent::ZabbixAgent => {
+            let server = server.unwrap_or("127.0.0.1");
+            let mut src = String::from("mkdir -p /var/log/zabbix /var/run/zabbix\n");
+            src.push_str("chown zabbix:zabbix /var/log/zabbix /var/run/zabbix\n");
+            src.push_str(&heredoc_to_file(
+                "/usr/local/etc/zabbix6/zabbix_agentd.conf",
+                &format!(
+                    concat!(
+                        "PidFile=/var/run/zabbix/zabbix_agentd.pid\n",
+                        "LogFile=/var/log/zabbix/zabbix_agentd.log\n",
+                        "Server={server}\n",
+                        "ServerActive={server}\n",
+                        "HostnameItem=system.hostname\n",
+                    ),
+                    server = server.replace('\n', "")
+                ),
+            ));
+            src.push_str("sysrc -f /etc/rc.conf zabbix_agentd_enable=\"YES\"\n");
+            src.push_str("service zabbix_agentd start\n");
+            src
+        }
+    }
+}
+
+/// Returns the script which replaces the marked lines of the jail's hosts file with the given
+/// entries, which are already marked.
+pub fn hosts_entries(entries: &str) -> String {
+    let mut src = format!(
+        "sed -i '' {} {}\n",
+        quote(&format!("/{}/d", hosts::MARKER)),
+        hosts::HOSTS_FILE
+    );
+    if !entries.is_empty() {
+        src.push_str(&heredoc(hosts::HOSTS_FILE, entries, true));
+    }
+    src
+}
+
+/// Returns the script which checks that the jail can resolve its pkg mirror and connect to the
+/// mirror's HTTPS port.
+///
+/// The mirror's host is read from the jail's base repository configuration. The script prints a
+/// line of the result (`ok`, `resolve`, or `connect`, naming the failed step) and the host, as
+/// the exit status of a script isn't reliably passed through `iocage exec`.
+pub fn network_check() -> String {
+    let mut src = String::from(PKG_MIRROR);
+    src.push_str(concat!(
+        "if ! host \"$mirror\" >/dev/null 2>&1; then\n",
+        "  echo \"resolve $mirror\"\n",
+        "elif ! nc -z -w 10 \"$mirror\" 443 >/dev/null 2>&1; then\n",
+        "  echo \"connect $mirror\"\n",
+        "else\n",
+        "  echo \"ok $mirror\"\n",
+        "fi\n",
+    ));
+    src
+}
+
+/// Returns the script which waits, for up to the given number of seconds, until the jail has a
+/// default route and (if `dns` is set) can resolve its pkg mirror.
+///
+/// A jail which was just started may still be configuring its network when it is first used, so
+/// both are polled once a second. As with [`network_check`], the script prints a line of the
+/// result (`ok`, or `route` or `resolve` naming what was still being waited for) and the mirror's
+/// host.
+pub fn network_wait(timeout: u64, dns: bool) -> String {
+    let mut src = String::from(PKG_MIRROR);
+    src.push_str(&format!("deadline=$(($(date +%s) + {}))\n", timeout));
+    src.push_str(concat!(
+        "while :; do\n",
+        "  if ! route -n get default >/dev/null 2>&1; then\n",
+        "    waiting=route\n",
+    ));
+    if dns {
+        src.push_str(concat!(
+            "  elif ! host \"$mirror\" >/dev/null 2>&1; then\n",
+            "    waiting=resolve\n",
+        ));
+    }
+    src.push_str(concat!(
+        "  else\n",
+        "    echo \"ok $mirror\"\n",
+        "    break\n",
+        "  fi\n",
+        "  if [ \"$(date +%s)\" -ge \"$deadline\" ]; then\n",
+        "    echo \"$waiting $mirror\"\n",
+        "    break\n",
+        "  fi\n",
+        "  sleep 1\n",
+        "done\n",
+    ));
+    src
+}
+
+/// Script snippet which sets `mirror` to the host of the jail's pkg mirror, as read from its base
+/// repository configuration.
+const PKG_MIRROR: &str = concat!(
+    "mirror=\"$(sed -n 's|^[[:space:]]*url:[[:space:]]*\"[a-z+]*://\\([^/:\"]*\\).*|\\1|p' ",
+    "/etc/pkg/FreeBSD.conf | head -n 1)\"\n",
+    "mirror=\"${mirror:-pkg.FreeBSD.org}\"\n",
+);
+
+/// Returns the script which installs the given scripts to run once, in order, on the jail's next
+/// start.
+///
+/// The scripts are run by an rc.d service with the `firstboot` keyword, which rc(8) only starts
+/// while the `/firstboot` sentinel file exists and which removes the sentinel once the jail has
+/// started. Scripts which don't start with a `#!` line are run by `sh`.
+pub fn firstboot(scripts: &[String]) -> String {
+    let mut src = format!("mkdir -p {}\n", FIRSTBOOT_DIR);
+    for (idx, contents) in scripts.iter().enumerate() {
+        let path = format!("{}/{:02}", FIRSTBOOT_DIR, idx + 1);
+        src.push_str(&heredoc_to_file(&path, contents));
+        src.push_str(&format!("chmod 0700 {}\n", quote(&path)));
+    }
+    src.push_str(&heredoc_to_file(
+        "/usr/local/etc/rc.d/iocage_provision_firstboot",
+        concat!(
+            "#!/bin/sh\n",
+            "\n",
+            "# PROVIDE: iocage_provision_firstboot\n",
+            "# REQUIRE: LOGIN\n",
+            "# KEYWORD: firstboot\n",
+            "\n",
+            ". /etc/rc.subr\n",
+            "\n",
+            "name=\"iocage_provision_firstboot\"\n",
+            "rcvar=\"iocage_provision_firstboot_enable\"\n",
+            "start_cmd=\"${name}_start\"\n",
+            "stop_cmd=\":\"\n",
+            "\n",
+            "iocage_provision_firstboot_start()\n",
+            "{\n",
+            "\tfor script in /usr/local/etc/iocage-provision/firstboot.d/*; do\n",
+            "\t\t[ -f \"$script\" ] || continue\n",
+            "\t\techo \"Running first boot script $script\"\n",
+            "\t\tif head -c 2 \"$script\" | grep -q '^#!'; then\n",
+            "\t\t\t\"$script\"\n",
+            "\t\telse\n",
+            "\t\t\tsh \"$script\"\n",
+            "\t\tfi\n",
+            "\tdone\n",
+            "}\n",
+            "\n",
+            "load_rc_config $name\n",
+            "run_rc_command \"$1\"\n",
+        ),
+    ));
+    src.push_str("chmod 0555 /usr/local/etc/rc.d/iocage_provision_firstboot\n");
+    src.push_str("sysrc -f /etc/rc.conf iocage_provision_firstboot_enable=\"YES\"\n");
+    src.push_str("touch /firstboot\n");
+    src
+}
+
+/// Directory in a jail which holds the scripts run on its first boot, in order of their names.
+const FIRSTBOOT_DIR: &str = "/usr/local/etc/iocage-provision/firstboot.d";
+
+/// Returns the script which applies cloud-config user-data, other than its packages.
+///
+/// Users are created first with their passwords disabled, then files are written (so that they
+/// may be owned by those users), and finally the commands are run by `sh -e` in order. Packages
+/// are installed with the jail's other packages.
+pub fn user_data(data: &UserData) -> String {
+    let mut src = String::new();
+    for user in &data.users {
+        src.push_str(&format!("pw useradd -n {} -m", quote(&user.name)));
+        if let Some(ref gecos) = user.gecos {
+            src.push_str(&format!(" -c {}", quote(gecos)));
+        }
+        if let Some(ref shell) = user.shell {
+            src.push_str(&format!(" -s {}", quote(shell)));
+        }
+        if !user.groups.is_empty() {
+            src.push_str(&format!(" -G {}", quote(&user.groups.join(","))));
+        }
+        src.push_str(" -h -\n");
+        if !user.ssh_authorized_keys.is_empty() {
+            src.push_str(&authorized_keys(
+                &user.name,
+                &user.ssh_authorized_keys.join("\n"),
+            ));
+        }
+        if !user.sudo.is_empty() {
+            let rules = user
+                .sudo
+                .iter()
+                .map(|rule| format!("{} {}", user.name, rule))
+                .collect::<Vec<_>>()
+                .join("\n");
+            src.push_str(&sudoers_rules(&user.name, &rules));
+        }
+    }
+    for file in &data.write_files {
+        if let Some(dir) = file
+            .path
+            .rfind('/')
+            .map(|idx| &file.path[..idx])
+            .filter(|dir| !dir.is_empty())
+        {
+            src.push_str(&format!("mkdir -p {}\n", quote(dir)));
+        }
+        src.push_str(&heredoc(&file.path, &file.content, file.append));
+        if let Some(mode) = file.permissions {
+            src.push_str(&format!("chmod {:04o} {}\n", mode, quote(&file.path)));
+        }
+        if let Some(ref owner) = file.owner {
+            src.push_str(&format!("chown {} {}\n", quote(owner), quote(&file.path)));
+        }
+    }
+    if !data.runcmd.is_empty() {
+        src.push_str(&heredoc_to_file(USER_DATA_RUNCMD, &data.runcmd.join("\n")));
+        src.push_str(&format!(
+            "sh -e {script}\nrm -f {script}\n",
+            script = USER_DATA_RUNCMD
+        ));
+    }
+    src
+}
+
+/// Path in a jail of the script which runs the commands of its user-data.
+const USER_DATA_RUNCMD: &str = "/var/tmp/iocage-provision-runcmd";
+
+/// Returns the script which strips a jail's base system down to a minimal one, or `None` if there
+/// is no curated removal list for the given major version of FreeBSD.
+///
+/// The test suite, 32-bit compatibility libraries, debug symbols, documentation, examples, and
+/// (unless `keep_toolchain` is set, such as for building ports) the compiler toolchain are removed,
+/// and sendmail is disabled. The toolchain's files differ between major versions, so each supported
+/// version has its own removal list.
+pub fn minimal_base(major: u32, keep_toolchain: bool) -> Option<String> {
+    let (mut paths, toolchain): (Vec<&str>, &[&str]) = match major {
+        12 => (
+            MINIMAL_COMMON.to_vec(),
+            &[
+                "/usr/bin/c++",
+                "/usr/bin/cc",
+                "/usr/bin/clang",
+                "/usr/bin/clang++",
+                "/usr/bin/clang-cpp",
+                "/usr/bin/cpp",
+                "/usr/bin/ld.bfd",
+                "/usr/bin/ld.lld",
+                "/usr/bin/lldb",
+                "/usr/lib/clang",
+            ],
+        ),
+        13 | 14 => (
+            MINIMAL_COMMON.to_vec(),
+            &[
+                "/usr/bin/c++",
+                "/usr/bin/cc",
+                "/usr/bin/clang",
+                "/usr/bin/clang++",
+                "/usr/bin/clang-cpp",
+                "/usr/bin/cpp",
+                "/usr/bin/ld.lld",
+                "/usr/bin/lldb",
+                "/usr/bin/llvm-addr2line",
+                "/usr/bin/llvm-ar",
+                "/usr/bin/llvm-nm",
+                "/usr/bin/llvm-objdump",
+                "/usr/lib/clang",
+            ],
+        ),
+        _ => return None,
+    };
+    if !keep_toolchain {
+        paths.extend(toolchain);
+    }
+
+    let mut src = String::from("rm -rf");
+    for path in paths {
+        src.push_str(" \\\n  ");
+        src.push_str(&quote(path));
+    }
+    src.push('\n');
+    src.push_str("sysrc -f /etc/rc.conf sendmail_enable=\"NONE\"\n");
+    src.push_str("service sendmail onestop >/dev/null 2>&1 || true\n");
+    Some(src)
+}
+
+/// Paths removed from a minimal base system of every supported version.
+const MINIMAL_COMMON: &[&str] = &[
+    "/libexec/ld-elf32.so.1",
+    "/usr/lib/debug",
+    "/usr/lib32",
+    "/usr/libexec/ld-elf32.so.1",
+    "/usr/share/doc",
+    "/usr/share/examples",
+    "/usr/tests",
+];
+
+/// Returns a shell snippet which installs authorized SSH public keys for a user in the `.ssh`
+/// directory of the home directory `pw` has recorded for the user.
+fn authorized_keys(user_name: &str, keys: &str) -> String {
+    let mut src = format!(
+        "ssh_dir=\"$(pw usershow -n {usr} | cut -d: -f9)/.ssh\"\n\
+        mkdir -p \"$ssh_dir\"\n\
+        cd \"$ssh_dir\"\n",
+        usr = quote(user_name)
+    );
+    src.push_str(&heredoc_to_file("authorized_keys", keys));
+    src.push_str(&format!(
+        "chmod 0700 .\nchmod 0600 authorized_keys\nchown -R {} .\ncd /\n",
+        quote(&format!("{}:", user_name))
+    ));
+    src
+}
+
+/// Returns a shell snippet which installs sudoers rules for a user.
+///
+/// The rules are written to a file which `sudo` ignores and checked with `visudo` before they are
+/// moved into place, so that a bad rule can never break `sudo` for the whole jail.
+fn sudoers_rules(user_name: &str, rules: &str) -> String {
+    let sudoers = format!("/usr/local/etc/sudoers.d/{}", sudoers_file_name(user_name));
+    let tmp = format!("{}.tmp", sudoers);
+    let mut src = heredoc_to_file(&tmp, rules);
+    src.push_str(&format!(
+        "chmod 0440 {tmp}\n\
+        if visudo -cf {tmp} >/dev/null; then mv {tmp} {dst}; \
+        else rm -f {tmp}; exit 1; fi\n",
+        dst = quote(&sudoers),
+        tmp = quote(&tmp),
+    ));
+    src
+}
+
+/// Returns the name of the file in `sudoers.d` for a user.
+///
+/// `sudo` skips files whose names contain a `.` or end in `~`, so every character other than
+/// letters, digits, `-`, and `_` is replaced with an `_`.
+fn sudoers_file_name(user_name: &str) -> String {
+    user_name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Returns a shell snippet which writes the given contents to a file via a here-document.
+pub(crate) fn heredoc_to_file(path: &str, contents: &str) -> String {
+    heredoc(path, contents, false)
+}
+
+/// Returns a shell snippet which writes, or appends, the given contents to a file via a
+/// here-document.
+///
+/// The here-document's delimiter is chosen so that it never matches a line of the contents.
+pub(crate) fn heredoc(path: &str, contents: &str, append: bool) -> String {
+    let delimiter = heredoc_delimiter(contents);
+    let mut src = format!(
+        "cat <<'{}' {}{}\n",
+        delimiter,
+        if append { ">>" } else { ">" },
+        quote(path)
+    );
+    src.push_str(contents);
+    if !contents.ends_with('\n') {
+        src.push('\n');
+    }
+    src.push_str(&delimiter);
+    src.push('\n');
+    src
+}
+
+/// Returns a here-document delimiter which doesn't match any line of the given contents.
+fn heredoc_delimiter(contents: &str) -> String {
+    let mut delimiter = String::from(HEREDOC_DELIMITER);
+    let mut n = 0;
+    while contents.lines().any(|line| line == delimiter) {
+        n += 1;
+        delimiter = format!("{}{}", HEREDOC_DELIMITER, n);
+    }
+    delimiter
+}
+
+/// Returns the `export` statements for environment variables which are set at the top of every
+/// script run in a jail, or an empty string when there are none.
+pub fn exec_env(env: &BTreeMap<String, String>) -> String {
+    let mut src = String::new();
+    for (key, value) in env {
+        src.push_str(&format!("export {}={}\n", key, quote(value)));
+    }
+    if !src.is_empty() {
+        src.push('\n');
+    }
+    src
+}
+
+/// Returns whether or not a name is valid for an environment variable set by `sh`, that is it
+/// consists of letters, digits, and underscores and doesn't start with a digit.
+pub fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns whether or not a name is valid for a user created by `pw`, that is it is at most 32
+/// characters long, doesn't start with a `-`, and contains only printable ASCII characters other
+/// than those `pw` rejects. A trailing `$` is allowed for machine accounts.
+pub fn is_user_name(name: &str) -> bool {
+    const BAD_CHARS: &str = " ,\t:+&#%$^()!@~*?<>=|\\/\"'`;[]{}";
+
+    let body = name.strip_suffix('$').unwrap_or(name);
+    !body.is_empty()
+        && name.len() <= 32
+        && !name.starts_with('-')
+        && body
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !BAD_CHARS.contains(c))
+}
+
+/// Quotes a value so that `sh` reads it as a single literal word.
+///
+/// Values which contain only characters that are never special to `sh` are returned unchanged.
+pub fn quote(value: &str) -> Cow<'_, str> {
+    shell_words::quote(value)
+}
+
+/// Returns the comma separated supplementary group list for a user, always including `wheel`.
+fn group_list(groups: &[String]) -> String {
+    std::iter::once("wheel")
+        .chain(groups.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Escapes the characters which are special in a sudoers command specification.
+fn sudoers_escape(command: &str) -> String {
+    let mut escaped = String::with_capacity(command.len());
+    for c in command.chars() {
+        if matches!(c, '\\' | ',' | ':' | '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,168 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Retrieval of secrets, such as passwords, keys, and tokens, which a spec references by name.
+//!
+//! A spec names the secrets it needs (see [`JailSpec::exec_secrets`](crate::JailSpec)) rather
+//! than holding their values, so that they are kept out of manifests. Each secret is resolved
+//! from the [`SecretSource`] set with [`set_secret_source`] when the jail is provisioned, and its
+//! value is masked in all output. The built in sources are:
+//!
+//! * [`EnvSource`]: the value of the environment variable with the secret's name (the default)
+//! * [`FileSource`]: the contents of the file with the secret's name in a directory
+//! * [`ExecSource`]: the output of a command which is given the secret's name as its argument
+//!
+//! Programs which embed this crate can implement [`SecretSource`] to retrieve secrets from a
+//! store such as Vault.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+    static ref SOURCE: Mutex<Option<Box<dyn SecretSource>>> = Mutex::new(None);
+}
+
+/// A store from which secrets are retrieved by name.
+pub trait SecretSource: Send {
+    /// Returns the value of the named secret, or `None` if the source has no such secret.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Err` if the source could not be read.
+    fn secret(&self, name: &str) -> io::Result<Option<String>>;
+}
+
+/// A source which reads each secret from the environment variable with the secret's name.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EnvSource;
+
+impl SecretSource for EnvSource {
+    fn secret(&self, name: &str) -> io::Result<Option<String>> {
+        Ok(env::var(name).ok())
+    }
+}
+
+/// A source which reads each secret from the file with the secret's name in a directory.
+///
+/// A single trailing newline is removed from the contents of the file.
+#[derive(Clone, Debug)]
+pub struct FileSource {
+    dir: PathBuf,
+}
+
+impl FileSource {
+    /// Returns a source which reads secrets from files in the given directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl SecretSource for FileSource {
+    fn secret(&self, name: &str) -> io::Result<Option<String>> {
+        // A name must not reach outside of the directory
+        if name.is_empty() || name.contains('/') || name.starts_with('.') {
+            return Ok(None);
+        }
+        match fs::read_to_string(self.dir.join(name)) {
+            Ok(contents) => Ok(Some(trim_newline(contents))),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// A source which runs a command with each secret's name as its only argument and reads the
+/// secret from its standard output.
+///
+/// A single trailing newline is removed from the output. A command which exits with a code that
+/// is not zero is considered to have no such secret. The command's output is never logged.
+#[derive(Clone, Debug)]
+pub struct ExecSource {
+    program: PathBuf,
+}
+
+impl ExecSource {
+    /// Returns a source which runs the given program to retrieve secrets.
+    pub fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+        }
+    }
+}
+
+impl SecretSource for ExecSource {
+    fn secret(&self, name: &str) -> io::Result<Option<String>> {
+        let output = Command::new(&self.program)
+            .arg(name)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let stdout = String::from_utf8(output.stdout)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+        Ok(Some(trim_newline(stdout)))
+    }
+}
+
+/// Parses a built in source, given as `env`, `file:DIR`, or `exec:PROGRAM`.
+///
+/// # Errors
+///
+/// Returns an `Err` if the source is not one of the built in sources.
+pub fn parse_source(s: &str) -> Result<Box<dyn SecretSource>, String> {
+    let mut parts = s.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("env"), None) => Ok(Box::new(EnvSource)),
+        (Some("file"), Some(dir)) if !dir.is_empty() => Ok(Box::new(FileSource::new(dir))),
+        (Some("exec"), Some(program)) if !program.is_empty() => {
+            Ok(Box::new(ExecSource::new(program)))
+        }
+        _ => Err(format!(
+            "secret source must be env, file:DIR, or exec:PROGRAM; source={}",
+            s
+        )),
+    }
+}
+
+/// Sets the source from which the secrets named by a spec are retrieved.
+///
+/// Until a source is set, secrets are read from environment variables with [`EnvSource`].
+pub fn set_secret_source(source: Box<dyn SecretSource>) {
+    *SOURCE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(source);
+}
+
+/// Retrieves the named secret from the source set with [`set_secret_source`].
+///
+/// # Errors
+///
+/// Returns an `Err` if the source could not be read.
+pub(crate) fn resolve(name: &str) -> io::Result<Option<String>> {
+    match SOURCE
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .as_deref()
+    {
+        Some(source) => source.secret(name),
+        None => EnvSource.secret(name),
+    }
+}
+
+fn trim_newline(mut value: String) -> String {
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    value
+}
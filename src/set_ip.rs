@@ -0,0 +1,73 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Changing the address of an existing jail.
+//!
+//! The jail's address (and, for a VNET jail, its default route) is changed with iocage on the
+//! same interface as before, and a running jail is restarted so that its network is configured
+//! again. A managed jail's spec is updated in the state file, and its hosts file entry and DNS
+//! registration, if it has them, are replaced with ones for the new address.
+
+#[cfg(feature = "manifest")]
+use super::{dns, hosts, state};
+use super::{find_jail, parse, run_iocage_set, run_iocage_start, run_iocage_stop, Error, Result};
+use ipnet::IpNet;
+use log::info;
+use std::net::IpAddr;
+
+/// Changes the address of a jail, and optionally its default route.
+///
+/// # Errors
+///
+/// Returns an `Err` if:
+///
+/// * The jail does not exist
+/// * The interface of the jail's current address could not be determined
+/// * The jail's properties could not be set, or it could not be restarted
+/// * The state file, hosts file, or DNS registration could not be updated
+pub fn set_jail_ip(jail_name: &str, ip: IpNet, gateway: Option<IpAddr>) -> Result<()> {
+    let jail = find_jail(jail_name)?.ok_or_else(|| Error::JailNotFound(jail_name.to_string()))?;
+    let interface = parse::address_interface(&jail.ip4)
+        .ok_or_else(|| Error::AddressInterfaceUnknown(jail_name.to_string()))?;
+
+    section!("Changing address of jail '{}' to {}", jail_name, ip);
+
+    let mut props = vec![format!(
+        "{}={}|{}",
+        if ip.addr().is_ipv4() {
+            "ip4_addr"
+        } else {
+            "ip6_addr"
+        },
+        interface,
+        ip
+    )];
+    if let Some(gateway) = gateway {
+        props.push(format!("defaultrouter={}", gateway));
+    }
+    info!("Setting {}", props.join(" "));
+    run_iocage_set(jail_name, &props)?;
+
+    if jail.state == "up" {
+        info!("Restarting jail '{}'", jail_name);
+        run_iocage_stop(jail_name)?;
+        run_iocage_start(jail_name)?;
+    }
+
+    #[cfg(feature = "manifest")]
+    if let Some(spec) = state::set_ip(jail_name, ip, gateway)? {
+        if spec.hosts_entry {
+            info!("Updating '{}' in '{}'", jail_name, hosts::HOSTS_FILE);
+            hosts::add_host_entry(jail_name, ip.addr())?;
+        }
+        if let Some(ref registration) = spec.register_dns {
+            info!("Updating '{}' in DNS", registration.fqdn(jail_name));
+            dns::register(registration, jail_name, ip.addr())?;
+        }
+    }
+
+    section!("Jail '{}' now has address {}", jail_name, ip);
+
+    Ok(())
+}
@@ -0,0 +1,1102 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The model of a jail: its spec, the report of it once provisioned, and the values they hold.
+//!
+//! These types, along with the [`parse`](crate::parse) functions and the
+//! [`emitters`](crate::emitters), only describe jails and never run anything on the host, so they
+//! can be reused by tooling on any platform, such as a dashboard or a CI job which lints
+//! manifests. Everything which runs on the host, such as [`provision_jail`](crate::provision_jail),
+//! is FreeBSD-only. All of these types are also exported from the crate root.
+
+use super::script::quote;
+use super::{dns, recipe, redact, Error};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::result;
+use std::str;
+
+/// iocage properties which describe a jail's runtime state.
+const RUNTIME_PROPERTIES: &[&str] = &["jid", "last_started", "state"];
+
+/// The desired configuration of a jail to be provisioned.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct JailSpec {
+    /// Name for the jail instance.
+    pub name: String,
+    /// IP address & subnet mask for the jail instance.
+    pub ip: IpNet,
+    /// IP address of the default gateway route for a VNET.
+    pub gateway: IpAddr,
+    /// Whether or not to create a jail which shares the host's network stack rather than one with
+    /// its own VNET network stack.
+    ///
+    /// A shared-IP jail's address is added to a host interface and the jail has no default route
+    /// of its own, so `gateway` is not used.
+    #[serde(default)]
+    pub vnet_off: bool,
+    /// Host network interface (i.e. `"em0"`) to which a shared-IP jail's address is added, or
+    /// which is the uplink of the bridge set up with `setup_bridge`, or `None` for the interface
+    /// of the host's default route.
+    ///
+    /// Only used when `vnet_off` or `setup_bridge` is set.
+    pub interface: Option<String>,
+    /// Whether or not to create the host bridge which VNET jails are attached to, if it is
+    /// missing, and add the uplink `interface` to it, persisting the configuration in the host's
+    /// `/etc/rc.conf`.
+    #[serde(default)]
+    pub setup_bridge: bool,
+    /// Whether or not to load the rules suggested by the host firewall check into pf, as
+    /// described in [`firewall`](crate::firewall).
+    #[serde(default)]
+    pub fix_firewall: bool,
+    /// FreeBSD release to use for the jail instance.
+    ///
+    /// A release of `path:PATH` names a locally built userland directory or tarball, which is
+    /// imported as a release named after its file name, as described in [`release`](crate::release).
+    pub release: String,
+    /// Whether or not to create a thick jail rather than a clone.
+    #[serde(default)]
+    pub thick_jail: bool,
+    /// Whether or not to build the jail's userland from FreeBSD pkgbase packages rather than use a
+    /// release fetched by iocage. This mode is experimental.
+    ///
+    /// The userland is built once per release and set of packages, as described in [`release`](crate::release).
+    #[serde(default)]
+    pub pkgbase: bool,
+    /// Base system packages to install when `pkgbase` is set, or empty to install the
+    /// [`release::PKGBASE_DEFAULT_PACKAGES`](crate::release::PKGBASE_DEFAULT_PACKAGES).
+    #[serde(default)]
+    pub pkgbase_packages: Vec<String>,
+    /// Whether or not to strip the jail's base system down to a minimal one after it is created.
+    ///
+    /// The removals are described in [`script::minimal_base`](crate::script::minimal_base). The toolchain is kept when `ports`
+    /// is set.
+    #[serde(default)]
+    pub minimal: bool,
+    /// Name of a host system user to copy into the jail instance.
+    pub user: Option<String>,
+    /// Home directory of the user in the jail, if not the `pw` default.
+    pub home_dir: Option<String>,
+    /// Permissions mode of the user's home directory, if not the `pw` default.
+    pub home_mode: Option<u32>,
+    /// Whether or not to create the user's home directory.
+    #[serde(default = "default_true")]
+    pub create_home: bool,
+    /// Whether or not to create a dedicated ZFS dataset for the user's home directory.
+    ///
+    /// The dataset is created beneath the jail's dataset and is mounted at the home directory in
+    /// the jail, which is the `pw` default of `/home/NAME` when `home_dir` is not set.
+    #[serde(default)]
+    pub home_dataset: bool,
+    /// Login class of the user in the jail.
+    pub login_class: Option<String>,
+    /// Locale of the user in the jail (i.e. `"en_US.UTF-8"`).
+    pub lang: Option<String>,
+    /// How to handle the uid or gid of the user already being used in the jail.
+    #[serde(default)]
+    pub id_conflict: IdConflictPolicy,
+    /// Whether or not to copy the host user's supplementary group memberships into the jail.
+    #[serde(default)]
+    pub copy_groups: bool,
+    /// Name of a non-interactive automation user to create in the jail instance.
+    ///
+    /// The user has no password and can only log in with the `automation_key` SSH public key.
+    pub automation_user: Option<String>,
+    /// SSH public key file on the host which is the only authorized key of the automation user.
+    pub automation_key: Option<PathBuf>,
+    /// Commands (with absolute paths) which the automation user may run as root through sudo.
+    ///
+    /// When empty, no sudo access is granted to the automation user.
+    #[serde(default)]
+    pub automation_commands: Vec<String>,
+    /// Whether or not to install and set up an SSH service.
+    #[serde(default)]
+    pub ssh_service: bool,
+    /// Types of SSH host keys to generate for the SSH service.
+    ///
+    /// When empty, the SSH service generates its default set of host key types.
+    #[serde(default)]
+    pub ssh_host_key_types: Vec<SshHostKeyType>,
+    /// Directory on the host containing existing SSH host keys to install in the jail.
+    ///
+    /// Files named `ssh_host_*_key` and `ssh_host_*_key.pub` are installed before the SSH service
+    /// first starts so that a rebuilt jail keeps the host keys of the jail it replaces.
+    pub ssh_host_keys_dir: Option<PathBuf>,
+    /// Whether or not to enable and start an NTP service.
+    #[serde(default)]
+    pub ntp_service: bool,
+    /// Monitoring agent to install, enable, and start in the jail, whose endpoint is included in
+    /// the report of the provisioned jail.
+    pub monitoring: Option<MonitoringAgent>,
+    /// Server (or comma separated servers) which the monitoring agent answers, required by the
+    /// Zabbix agent.
+    pub monitoring_server: Option<String>,
+    /// Remote log host (with an optional port) to which the jail's syslogd forwards all messages.
+    pub syslog_remote: Option<String>,
+    /// Entries (i.e. `"SCHEDULE COMMAND"`) to add to the root user's crontab in the jail.
+    #[serde(default)]
+    pub cron_entries: Vec<String>,
+    /// Whether or not to send periodic(8) reports to log files rather than by mail.
+    #[serde(default)]
+    pub periodic_log: bool,
+    /// Smarthost (with an optional port) through which the jail's mailer relays all mail.
+    pub smtp_relay: Option<String>,
+    /// Whether or not to copy the host's locally trusted CA certificates into the jail.
+    #[serde(default)]
+    pub copy_ca_certs: bool,
+    /// Paths to additional PEM encoded CA certificates on the host to trust in the jail.
+    #[serde(default)]
+    pub ca_certs: Vec<PathBuf>,
+    /// Additional packages to install in the jail, along with those required by other settings.
+    #[serde(default)]
+    pub packages: Vec<String>,
+    /// Additional keys of the package list JSON given to `iocage create`, as rendered by
+    /// [`pkglist_json`](crate::pkglist_json).
+    ///
+    /// The `pkgs` key is always the list of packages and can't be overridden.
+    #[serde(default)]
+    pub pkglist_extra: BTreeMap<String, serde_json::Value>,
+    /// Path to an existing package list JSON file on the host to give to `iocage create` as is,
+    /// in place of a generated one.
+    ///
+    /// Any packages required by other settings which are not in the file are installed after the
+    /// jail has been created, and `pkglist_extra` is not used.
+    pub pkglist: Option<PathBuf>,
+    /// Path to a local package repository on the host to mount into and use in the jail.
+    pub pkg_repo_dir: Option<PathBuf>,
+    /// Whether or not to disable all remote package repositories in the jail.
+    ///
+    /// This is intended to be used along with `pkg_repo_dir` so that jails can be provisioned on
+    /// networks with no Internet access.
+    #[serde(default)]
+    pub offline: bool,
+    /// Whether or not to verify that the jail can resolve and connect to its pkg mirror before
+    /// any packages are installed.
+    ///
+    /// Packages are installed after the jail has been created when this is set, so that a
+    /// misconfigured resolver or VNET fails fast with a networking error. The check is skipped
+    /// for an `offline` jail.
+    #[serde(default)]
+    pub dns_check: bool,
+    /// Whether or not to create the jail without a package list and install every package after
+    /// the jail has been created.
+    ///
+    /// pkg(8) is bootstrapped and the packages are installed by scripts run in the jail, which
+    /// export `exec_env` (such as proxy settings), use the `pkg_repo_dir` repository, and are
+    /// retried as `retries`, so that packages are handled the same way as in later steps. The
+    /// packages of a `pkglist` file are installed along with the others.
+    #[serde(default)]
+    pub defer_packages: bool,
+    /// Seconds to wait after the jail is created until it has a default route and can resolve
+    /// its pkg mirror, before anything is run in it.
+    ///
+    /// A jail which was just started may still be configuring its network, so this avoids racing
+    /// its startup. Resolving is not waited for by an `offline` jail, and `0` disables the wait.
+    #[serde(default = "default_network_timeout")]
+    pub network_timeout: u64,
+    /// Zone in which the jail's name is registered with its address by a dynamic DNS update, as
+    /// described in [`dns`].
+    pub register_dns: Option<dns::DnsRegistration>,
+    /// Whether or not to mount the host's ports tree read-only into the jail.
+    #[serde(default)]
+    pub ports: bool,
+    /// Path to a ports distfiles directory on the host to mount read-write into the jail.
+    pub ports_distfiles_dir: Option<PathBuf>,
+    /// Path to a ports packages directory on the host to mount read-write into the jail.
+    pub ports_packages_dir: Option<PathBuf>,
+    /// Path to a make.conf(5) file on the host to install as `/etc/make.conf` in the jail.
+    pub make_conf: Option<PathBuf>,
+    /// Path to a src.conf(5) file on the host to install as `/etc/src.conf` in the jail.
+    pub src_conf: Option<PathBuf>,
+    /// Paths to scripts on the host to run once, in order, on the jail's next start, as described
+    /// in [`script::firstboot`](crate::script::firstboot).
+    #[serde(default)]
+    pub firstboot: Vec<PathBuf>,
+    /// Path to a cloud-config user-data file on the host to apply to the jail, as described in
+    /// [`user_data`](crate::user_data).
+    pub user_data: Option<PathBuf>,
+    /// Environment variables exported at the top of every script run in the jail while
+    /// provisioning it, such as proxy settings or install tokens.
+    #[serde(default)]
+    pub exec_env: BTreeMap<String, String>,
+    /// Environment variables exported like `exec_env`, whose values are the names of secrets
+    /// which are resolved when the jail is provisioned, as described in
+    /// [`secret`](crate::secret).
+    ///
+    /// Keeps passwords, keys, and tokens out of manifests. The resolved values are masked in all
+    /// output.
+    #[serde(default)]
+    pub exec_secrets: BTreeMap<String, String>,
+    /// Start priority of the jail at host boot, where lower values start earlier.
+    pub priority: Option<u32>,
+    /// Names of existing jails which must be started before this jail.
+    #[serde(default)]
+    pub depends: Vec<String>,
+    /// Commands run on the host before the jail starts.
+    #[serde(default)]
+    pub exec_prestart: Vec<String>,
+    /// Commands run on the host after the jail starts.
+    #[serde(default)]
+    pub exec_poststart: Vec<String>,
+    /// Commands run on the host before the jail stops.
+    #[serde(default)]
+    pub exec_prestop: Vec<String>,
+    /// Commands run on the host after the jail stops.
+    #[serde(default)]
+    pub exec_poststop: Vec<String>,
+    /// Routing table (FIB) used by processes in the jail.
+    pub fib: Option<u32>,
+    /// CPUs of the host to which the jail's processes are restricted, as a cpuset(1) CPU list
+    /// (i.e. `"0-3"` or `"0,2"`).
+    pub cpuset: Option<String>,
+    /// Kernel security level of the jail, from `-1` (insecure) to `3` (network secure).
+    pub securelevel: Option<i32>,
+    /// System V IPC access mode of the jail.
+    pub sysvipc: Option<SysvIpc>,
+    /// Presets for a common kind of jail, such as a database server, which are applied as
+    /// described in [`recipe`].
+    #[serde(default)]
+    pub recipes: Vec<recipe::Recipe>,
+    /// Whether or not to prepare the jail to run a VPN such as WireGuard.
+    ///
+    /// The jail may use tun devices (through iocage's devfs ruleset) and raw sockets, the
+    /// `wireguard-tools` package is installed, and IP forwarding is enabled in a VNET jail.
+    #[serde(default)]
+    pub vpn_ready: bool,
+    /// Maximum space (i.e. `"20G"`) which the jail's dataset and its descendants may use, set as
+    /// the dataset's ZFS `quota`.
+    pub disk_quota: Option<String>,
+    /// Space (i.e. `"5G"`) guaranteed to the jail's dataset, set as the dataset's ZFS
+    /// `refreservation`.
+    pub disk_reserve: Option<String>,
+    /// Snapshots of the jail's dataset to keep, so that it is backed up from the start.
+    ///
+    /// The zfs-auto-snapshot properties (`com.sun:auto-snapshot` and one per interval) are set on
+    /// the jail's dataset, and if the host has a sanoid configuration, a stanza for the dataset
+    /// with the schedule is added to it unless it already has one.
+    pub snapshot_schedule: Option<SnapshotSchedule>,
+    /// ZFS properties (i.e. `"compression=zstd"`) to set on the jail's dataset.
+    #[serde(default)]
+    pub zfs_props: Vec<String>,
+    /// Size limited tmpfs mounts (i.e. `"/tmp=512m"`) of the form `PATH[=SIZE]` in the jail.
+    ///
+    /// Each mount is added to the jail's fstab right after the jail is created, so that temporary
+    /// files are kept in memory rather than written to the pool. A mount of `/tmp` or `/var/tmp`
+    /// is world writable with the sticky bit set.
+    #[serde(default)]
+    pub tmpfs: Vec<String>,
+    /// Expected parent dataset (or pool) beneath which the jail's dataset is created.
+    ///
+    /// iocage always creates jails beneath the `iocage` dataset of its activated pool, so this
+    /// value is checked against the activated pool before the jail is created rather than being
+    /// used to relocate the jail. A pool name, `POOL/iocage`, or `POOL/iocage/jails` are accepted.
+    pub dataset_parent: Option<String>,
+    /// Whether or not to checkpoint the iocage datasets before making any changes.
+    ///
+    /// A checkpoint can be rolled back with [`rollback_checkpoint`](crate::rollback_checkpoint).
+    #[serde(default)]
+    pub checkpoint: bool,
+    /// Whether or not the jail is disposable, such as a CI build environment, and expires
+    /// [`EPHEMERAL_TTL`](crate::EPHEMERAL_TTL) seconds after it is provisioned unless `expires_in` is set.
+    #[serde(default)]
+    pub ephemeral: bool,
+    /// Time, in seconds, after which the jail expires once it is provisioned.
+    ///
+    /// The expiry is recorded in the state file, and expired jails are destroyed with
+    /// [`expired_plan`](crate::expired_plan) and [`apply`](crate::apply).
+    pub expires_in: Option<u64>,
+    /// How to handle a jail with the same name already existing.
+    #[serde(default)]
+    pub if_exists: IfExistsPolicy,
+    /// Whether iocage names the jail with a short UUID rather than `name` being used.
+    ///
+    /// The generated name is returned in the report. This mode isn't available in manifests as
+    /// they identify jails by name.
+    #[serde(skip)]
+    pub uuid_name: bool,
+    /// Number of times a network dependent step (such as installing packages) is retried after a
+    /// transient failure.
+    ///
+    /// Failures which can't succeed on a later attempt, such as invalid input or a jail name
+    /// which is already used, are never retried.
+    #[serde(default)]
+    pub retries: u32,
+    /// Delay in seconds before the first retry of a failed step, doubling before each further
+    /// retry.
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+    /// Free memory of the host, in MiB, below which the host is considered too busy to start
+    /// provisioning the jail.
+    pub min_free_memory: Option<u64>,
+    /// One minute load average of the host above which the host is considered too busy to start
+    /// provisioning the jail.
+    pub max_load: Option<f64>,
+    /// Seconds to wait for a busy host to have capacity before the jail is created.
+    ///
+    /// When the host is too busy according to `min_free_memory` or `max_load`, provisioning is
+    /// deferred until it has capacity and fails once this time has passed. When unset, a busy
+    /// host is reported as a warning and provisioning goes ahead.
+    pub wait_for_capacity: Option<u64>,
+    /// Whether or not non-fatal issues (warnings) fail the provisioning rather than being
+    /// reported.
+    #[serde(default)]
+    pub strict: bool,
+    /// Whether or not to repair stale iocage state which made `iocage create` fail and then retry
+    /// it once.
+    ///
+    /// The recovery steps stop an earlier jail with the same name and wait for it to be removed,
+    /// destroy the VNET interfaces of jails which no longer exist, and destroy the jail's dataset
+    /// if it has no iocage configuration.
+    #[serde(default)]
+    pub repair: bool,
+    /// Phase after which provisioning stops, leaving the jail in that phase's intermediate state.
+    ///
+    /// A jail which is stopped before its last phase is not recorded in the state file.
+    pub stop_after: Option<Phase>,
+    /// iocage properties to use as a base for the jail, typically copied from another jail with
+    /// [`same_as_properties`](crate::same_as_properties).
+    ///
+    /// Any property which is set explicitly by another field takes precedence over a base
+    /// property, and the identity of the jail (such as its name, addresses, and release) is never
+    /// taken from the base properties.
+    #[serde(default)]
+    pub base_props: BTreeMap<String, String>,
+    /// Human readable description of the jail, stored in the iocage `notes` property.
+    pub description: Option<String>,
+    /// Labels describing the jail, such as its environment or role, which are included in the
+    /// report of the provisioned jail.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Whether or not to add the jail's name and address to the host's `/etc/hosts` and to seed
+    /// the jail's `/etc/hosts` with the host and the other jails added this way, as described in
+    /// [`hosts`](crate::hosts).
+    #[serde(default)]
+    pub hosts_entry: bool,
+    /// A file to which `known_hosts` lines for the jail's SSH host keys are appended.
+    ///
+    /// Only used when `ssh_service` is set.
+    pub known_hosts: Option<PathBuf>,
+    /// Secret values, such as passwords or tokens used in other settings, which are masked
+    /// wherever they would appear in logs, errors, and JSON output.
+    ///
+    /// The values themselves are always serialized masked.
+    #[serde(default, serialize_with = "redact::serialize_masked")]
+    pub secrets: Vec<String>,
+}
+
+impl JailSpec {
+    /// Creates a new `JailSpec` with the required values and all optional features disabled.
+    pub fn new(
+        name: impl Into<String>,
+        ip: IpNet,
+        gateway: IpAddr,
+        release: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            ip,
+            gateway,
+            vnet_off: false,
+            interface: None,
+            setup_bridge: false,
+            fix_firewall: false,
+            release: release.into(),
+            thick_jail: false,
+            pkgbase: false,
+            pkgbase_packages: Vec::new(),
+            minimal: false,
+            user: None,
+            home_dir: None,
+            home_mode: None,
+            create_home: true,
+            home_dataset: false,
+            login_class: None,
+            lang: None,
+            id_conflict: IdConflictPolicy::Fail,
+            copy_groups: false,
+            automation_user: None,
+            automation_key: None,
+            automation_commands: Vec::new(),
+            ssh_service: false,
+            ssh_host_key_types: Vec::new(),
+            ssh_host_keys_dir: None,
+            ntp_service: false,
+            monitoring: None,
+            monitoring_server: None,
+            syslog_remote: None,
+            cron_entries: Vec::new(),
+            periodic_log: false,
+            smtp_relay: None,
+            copy_ca_certs: false,
+            ca_certs: Vec::new(),
+            packages: Vec::new(),
+            pkglist_extra: BTreeMap::new(),
+            pkglist: None,
+            pkg_repo_dir: None,
+            offline: false,
+            dns_check: false,
+            defer_packages: false,
+            network_timeout: default_network_timeout(),
+            register_dns: None,
+            ports: false,
+            ports_distfiles_dir: None,
+            ports_packages_dir: None,
+            make_conf: None,
+            src_conf: None,
+            firstboot: Vec::new(),
+            user_data: None,
+            exec_env: BTreeMap::new(),
+            exec_secrets: BTreeMap::new(),
+            priority: None,
+            depends: Vec::new(),
+            exec_prestart: Vec::new(),
+            exec_poststart: Vec::new(),
+            exec_prestop: Vec::new(),
+            exec_poststop: Vec::new(),
+            fib: None,
+            cpuset: None,
+            securelevel: None,
+            sysvipc: None,
+            recipes: Vec::new(),
+            vpn_ready: false,
+            disk_quota: None,
+            disk_reserve: None,
+            snapshot_schedule: None,
+            zfs_props: Vec::new(),
+            tmpfs: Vec::new(),
+            dataset_parent: None,
+            checkpoint: false,
+            ephemeral: false,
+            expires_in: None,
+            if_exists: IfExistsPolicy::Fail,
+            uuid_name: false,
+            retries: 0,
+            retry_delay: default_retry_delay(),
+            min_free_memory: None,
+            max_load: None,
+            wait_for_capacity: None,
+            strict: false,
+            repair: false,
+            stop_after: None,
+            base_props: BTreeMap::new(),
+            description: None,
+            tags: BTreeMap::new(),
+            hosts_entry: false,
+            known_hosts: None,
+            secrets: Vec::new(),
+        }
+    }
+}
+
+/// A report of a successfully provisioned jail.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ProvisionReport {
+    /// Name of the jail.
+    pub name: String,
+    /// UUID of the jail (i.e. its `host_hostuuid` property).
+    pub uuid: String,
+    /// ZFS dataset of the jail.
+    pub dataset: String,
+    /// ZFS quota set on the jail's dataset, if any.
+    pub disk_quota: Option<String>,
+    /// ZFS refreservation set on the jail's dataset, if any.
+    pub disk_reserve: Option<String>,
+    /// Snapshots of the jail's dataset to keep, if a schedule was set.
+    pub snapshot_schedule: Option<SnapshotSchedule>,
+    /// Endpoint at which the jail's monitoring agent is reached, if one was installed.
+    pub monitoring_endpoint: Option<String>,
+    /// Fully qualified name under which the jail was registered in DNS, if it was.
+    pub dns_name: Option<String>,
+    /// Time at which the jail expires as an RFC 3339 timestamp, if it does.
+    pub expires_at: Option<String>,
+    /// Path of the jail's root directory on the host.
+    pub root: PathBuf,
+    /// IP address and network of the jail.
+    pub ip: IpNet,
+    /// FreeBSD release of the jail.
+    pub release: String,
+    /// Name of the user set up in the jail, if any, which is the name of the existing user when
+    /// one was reused.
+    pub user: Option<String>,
+    /// Labels describing the jail.
+    pub tags: BTreeMap<String, String>,
+    /// Version of iocage which created the jail.
+    pub iocage_version: IocageVersion,
+    /// Whether the jail was left untouched as it already existed.
+    pub skipped: bool,
+    /// Name of the checkpoint taken before the jail was created, if one was requested.
+    pub checkpoint: Option<String>,
+    /// SSH host keys of the jail, if an SSH service was enabled.
+    pub ssh_host_keys: Vec<SshHostKey>,
+    /// Changes to the jail's iocage properties made after it was created, which are also
+    /// recorded in the audit log.
+    pub property_changes: Vec<PropertyChange>,
+    /// Non-fatal issues found while provisioning the jail.
+    pub warnings: Vec<String>,
+    /// Phase after which provisioning was stopped, if it was stopped early.
+    pub stopped_after: Option<Phase>,
+    /// Commands to connect to and manage the jail.
+    pub next_steps: NextSteps,
+    /// Package list JSON which was given to `iocage create`, as rendered by [`pkglist_json`](crate::pkglist_json).
+    ///
+    /// Empty when the jail was created without a package list.
+    pub pkglist: String,
+}
+
+/// A change to one of a jail's iocage properties.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PropertyChange {
+    /// Name of the property.
+    pub property: String,
+    /// Value of the property before the change, if it was set.
+    pub before: Option<String>,
+    /// Value of the property after the change, if it is set.
+    pub after: Option<String>,
+}
+
+impl fmt::Display for PropertyChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.property,
+            self.before.as_deref().unwrap_or("(unset)"),
+            self.after.as_deref().unwrap_or("(unset)")
+        )
+    }
+}
+
+/// Returns the changes between two sets of a jail's iocage properties, in order of property name.
+///
+/// Properties which describe the jail's runtime state, such as `jid` and `last_started`, change
+/// whenever the jail is started and are ignored.
+pub fn property_changes(
+    before: &BTreeMap<String, String>,
+    after: &BTreeMap<String, String>,
+) -> Vec<PropertyChange> {
+    before
+        .keys()
+        .chain(after.keys())
+        .filter(|key| !RUNTIME_PROPERTIES.contains(&key.as_str()))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(|key| PropertyChange {
+            property: key.clone(),
+            before: before.get(key).cloned(),
+            after: after.get(key).cloned(),
+        })
+        .collect()
+}
+
+/// Commands to connect to and manage a provisioned jail.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct NextSteps {
+    /// Command to log in to the jail over SSH, if an SSH service and a user were set up.
+    pub ssh: Option<String>,
+    /// Command to attach to a root console in the jail.
+    pub console: String,
+    /// Command to stop the jail.
+    pub stop: String,
+    /// Command to destroy the jail and its datasets.
+    pub destroy: String,
+}
+
+impl NextSteps {
+    /// Builds the commands for the given jail, logging in over SSH as the given user if any.
+    pub fn new(jail_name: &str, addr: IpAddr, ssh_user: Option<&str>) -> Self {
+        Self {
+            ssh: ssh_user.map(|user| format!("ssh {}@{}", quote(user), addr)),
+            console: format!("iocage console {}", quote(jail_name)),
+            stop: format!("iocage stop {}", quote(jail_name)),
+            destroy: format!("iocage destroy -f {}", quote(jail_name)),
+        }
+    }
+}
+
+/// A public SSH host key of a jail.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct SshHostKey {
+    /// Key type (i.e. `"ssh-ed25519"`).
+    pub key_type: String,
+    /// Base64 encoded public key.
+    pub public_key: String,
+    /// SHA256 fingerprint of the key (i.e. `"SHA256:..."`).
+    pub fingerprint: String,
+}
+
+/// Summary information about an existing jail.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JailInfo {
+    /// Jail ID of the jail, if it is running.
+    pub jid: Option<u32>,
+    /// Name of the jail.
+    pub name: String,
+    /// Running state of the jail (i.e. `"up"` or `"down"`).
+    pub state: String,
+    /// FreeBSD release of the jail.
+    pub release: String,
+    /// IPv4 address configuration of the jail.
+    pub ip4: String,
+    /// Human readable description of the jail, if one was set.
+    pub description: Option<String>,
+}
+
+/// A version of iocage, as reported by `iocage --version`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IocageVersion {
+    /// Major version number.
+    pub major: u32,
+    /// Minor version number.
+    pub minor: u32,
+}
+
+impl IocageVersion {
+    /// The oldest supported version of iocage.
+    ///
+    /// All versions from this one up to, but not including, the next major version accept the
+    /// `iocage` flags and properties which are used to create and provision jails. Versions
+    /// outside this range are rejected rather than being given flags which may not work.
+    pub const MIN_SUPPORTED: Self = Self { major: 1, minor: 2 };
+
+    /// Returns whether this version of iocage is supported.
+    pub fn is_supported(&self) -> bool {
+        *self >= Self::MIN_SUPPORTED && self.major == Self::MIN_SUPPORTED.major
+    }
+
+    /// Returns whether `iocage create` of this version accepts the `--thickjail` flag, which was
+    /// added in 1.2.
+    pub fn has_thickjail(&self) -> bool {
+        *self >= Self { major: 1, minor: 2 }
+    }
+}
+
+impl fmt::Display for IocageVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl str::FromStr for IocageVersion {
+    type Err = Error;
+
+    /// Parses the output of `iocage --version` (i.e. `"1.2 RELEASE"`), ignoring any text around
+    /// the first version number.
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let parse_err = || Error::ParseIocageVersion(s.trim().to_string());
+        let version = s
+            .split_whitespace()
+            .find(|word| word.starts_with(|c: char| c.is_ascii_digit()))
+            .ok_or_else(parse_err)?;
+        let mut parts = version.split('.').map(str::parse::<u32>);
+        match (parts.next(), parts.next()) {
+            (Some(Ok(major)), Some(Ok(minor))) => Ok(Self { major, minor }),
+            (Some(Ok(major)), None) => Ok(Self { major, minor: 0 }),
+            _ => Err(parse_err()),
+        }
+    }
+}
+
+impl Serialize for IocageVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Monitoring agents which can be installed in a jail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MonitoringAgent {
+    /// The Prometheus node exporter, which is scraped over HTTP.
+    NodeExporter,
+    /// The Zabbix agent, which answers passive checks from a Zabbix server.
+    ZabbixAgent,
+}
+
+impl MonitoringAgent {
+    /// Returns the name of this agent.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::NodeExporter => "node-exporter",
+            Self::ZabbixAgent => "zabbix-agent",
+        }
+    }
+
+    /// Returns the package which provides this agent.
+    pub fn package(&self) -> &'static str {
+        match self {
+            Self::NodeExporter => "node_exporter",
+            Self::ZabbixAgent => "zabbix6-agent",
+        }
+    }
+
+    /// Returns the endpoint at which this agent is reached on the given address.
+    pub fn endpoint(&self, addr: IpAddr) -> String {
+        let addr = SocketAddr::new(
+            addr,
+            match self {
+                Self::NodeExporter => 9100,
+                Self::ZabbixAgent => 10050,
+            },
+        );
+        match self {
+            Self::NodeExporter => format!("http://{}/metrics", addr),
+            Self::ZabbixAgent => addr.to_string(),
+        }
+    }
+}
+
+impl str::FromStr for MonitoringAgent {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "node-exporter" => Ok(Self::NodeExporter),
+            "zabbix-agent" => Ok(Self::ZabbixAgent),
+            _ => Err(format!("invalid monitoring agent; agent={}", s)),
+        }
+    }
+}
+
+/// System V IPC (message queues, semaphores, and shared memory) access modes for a jail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SysvIpc {
+    /// The jail has its own separate set of IPC objects.
+    New,
+    /// The jail shares the IPC objects of the host.
+    Inherit,
+    /// The jail has no access to IPC objects.
+    Disable,
+}
+
+impl SysvIpc {
+    /// Returns the iocage property value for this mode.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::New => "new",
+            Self::Inherit => "inherit",
+            Self::Disable => "disable",
+        }
+    }
+}
+
+impl str::FromStr for SysvIpc {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "new" => Ok(Self::New),
+            "inherit" => Ok(Self::Inherit),
+            "disable" => Ok(Self::Disable),
+            _ => Err(format!("invalid sysvipc mode; mode={}", s)),
+        }
+    }
+}
+
+/// Policies for handling a uid or gid which is already used in a jail when copying a user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdConflictPolicy {
+    /// Fail with an error naming the existing user or group.
+    Fail,
+    /// Use the existing user or group in place of a new one.
+    Reuse,
+    /// Create the user or group with the next free id in the jail.
+    NextFree,
+}
+
+impl Default for IdConflictPolicy {
+    fn default() -> Self {
+        Self::Fail
+    }
+}
+
+impl IdConflictPolicy {
+    /// Returns the name of this policy.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fail => "fail",
+            Self::Reuse => "reuse",
+            Self::NextFree => "next-free",
+        }
+    }
+}
+
+impl str::FromStr for IdConflictPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "reuse" => Ok(Self::Reuse),
+            "next-free" => Ok(Self::NextFree),
+            _ => Err(format!("invalid id conflict policy; policy={}", s)),
+        }
+    }
+}
+
+/// Policies for handling a jail with the requested name already existing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IfExistsPolicy {
+    /// Fail with an error naming the existing jail.
+    Fail,
+    /// Leave the existing jail untouched and report it as skipped.
+    Skip,
+    /// Destroy the existing jail and provision a new one in its place.
+    Replace,
+}
+
+impl Default for IfExistsPolicy {
+    fn default() -> Self {
+        Self::Fail
+    }
+}
+
+impl IfExistsPolicy {
+    /// Returns the name of this policy.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Fail => "fail",
+            Self::Skip => "skip",
+            Self::Replace => "replace",
+        }
+    }
+}
+
+impl str::FromStr for IfExistsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "skip" => Ok(Self::Skip),
+            "replace" => Ok(Self::Replace),
+            _ => Err(format!("invalid if exists policy; policy={}", s)),
+        }
+    }
+}
+
+/// State left behind by an earlier jail or an interrupted run which makes `iocage create` fail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StaleState {
+    /// An earlier jail with the same name is still dying in the kernel.
+    DyingJail,
+    /// A dataset for the jail exists without an iocage configuration, such as after an
+    /// interrupted `iocage create`.
+    MissingConfig,
+    /// A VNET interface of a jail which no longer exists is left on the host.
+    StaleInterface,
+}
+
+impl StaleState {
+    /// Returns the name of this state.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DyingJail => "dying-jail",
+            Self::MissingConfig => "missing-config",
+            Self::StaleInterface => "stale-interface",
+        }
+    }
+
+    /// Describes the recovery steps which `--repair` runs for this state.
+    pub fn repair_description(&self) -> &'static str {
+        match self {
+            Self::DyingJail => "stop the earlier jail and wait for it to be removed",
+            Self::MissingConfig => "destroy the jail's dataset which has no iocage configuration",
+            Self::StaleInterface => "destroy the VNET interfaces of jails which no longer exist",
+        }
+    }
+}
+
+impl fmt::Display for StaleState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Phases of provisioning a jail, in the order they are run.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// Creating the jail, setting its ZFS properties, and stripping its base system.
+    Create,
+    /// Waiting for and checking the jail's network, and installing its packages, build
+    /// configuration, ports tree, and CA certificates.
+    Network,
+    /// Creating the jail's user and automation user.
+    Users,
+    /// Configuring the jail's services, SSH, user-data, and first boot scripts.
+    Services,
+}
+
+impl Phase {
+    /// Returns the name of this phase.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Create => "create",
+            Self::Network => "network",
+            Self::Users => "users",
+            Self::Services => "services",
+        }
+    }
+}
+
+impl str::FromStr for Phase {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "create" => Ok(Self::Create),
+            "network" => Ok(Self::Network),
+            "users" => Ok(Self::Users),
+            "services" => Ok(Self::Services),
+            _ => Err(format!("invalid phase; phase={}", s)),
+        }
+    }
+}
+
+/// Number of snapshots of each interval to keep of a jail's dataset, for snapshot tools such as
+/// zfs-auto-snapshot and sanoid.
+///
+/// A schedule is written as a comma separated list of `INTERVAL:COUNT` pairs, such as
+/// `daily:7,weekly:4`, where an interval which isn't listed keeps no snapshots.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SnapshotSchedule {
+    /// Number of hourly snapshots to keep.
+    pub hourly: u32,
+    /// Number of daily snapshots to keep.
+    pub daily: u32,
+    /// Number of weekly snapshots to keep.
+    pub weekly: u32,
+    /// Number of monthly snapshots to keep.
+    pub monthly: u32,
+}
+
+impl SnapshotSchedule {
+    /// Returns each interval with its number of snapshots to keep, from the shortest interval.
+    pub fn intervals(&self) -> [(&'static str, u32); 4] {
+        [
+            ("hourly", self.hourly),
+            ("daily", self.daily),
+            ("weekly", self.weekly),
+            ("monthly", self.monthly),
+        ]
+    }
+}
+
+impl fmt::Display for SnapshotSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let intervals = self
+            .intervals()
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(interval, count)| format!("{}:{}", interval, count))
+            .collect::<Vec<_>>();
+        f.write_str(&intervals.join(","))
+    }
+}
+
+impl str::FromStr for SnapshotSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        let mut schedule = Self::default();
+        let mut seen = Vec::new();
+        for pair in s.split(',').map(str::trim) {
+            let mut parts = pair.splitn(2, ':');
+            let (interval, count) = match (parts.next(), parts.next()) {
+                (Some(interval), Some(count)) => (interval, count),
+                _ => {
+                    return Err(format!(
+                        "schedule must be INTERVAL:COUNT pairs; value={}",
+                        pair
+                    ))
+                }
+            };
+            let count = count
+                .parse::<u32>()
+                .map_err(|_| format!("invalid snapshot count; value={}", pair))?;
+            let slot = match interval {
+                "hourly" => &mut schedule.hourly,
+                "daily" => &mut schedule.daily,
+                "weekly" => &mut schedule.weekly,
+                "monthly" => &mut schedule.monthly,
+                _ => return Err(format!("invalid snapshot interval; interval={}", interval)),
+            };
+            if seen.contains(&interval) {
+                return Err(format!(
+                    "duplicate snapshot interval; interval={}",
+                    interval
+                ));
+            }
+            seen.push(interval);
+            *slot = count;
+        }
+
+        Ok(schedule)
+    }
+}
+
+/// Types of SSH host keys which can be generated for a jail.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SshHostKeyType {
+    /// An RSA key.
+    Rsa,
+    /// An ECDSA key.
+    Ecdsa,
+    /// An Ed25519 key.
+    Ed25519,
+}
+
+impl SshHostKeyType {
+    /// All supported key types.
+    pub(crate) const ALL: [Self; 3] = [Self::Rsa, Self::Ecdsa, Self::Ed25519];
+
+    /// Returns the name of this key type as used by `ssh-keygen` and the sshd rc script.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Rsa => "rsa",
+            Self::Ecdsa => "ecdsa",
+            Self::Ed25519 => "ed25519",
+        }
+    }
+}
+
+impl str::FromStr for SshHostKeyType {
+    type Err = String;
+
+    fn from_str(s: &str) -> result::Result<Self, Self::Err> {
+        match s {
+            "rsa" => Ok(Self::Rsa),
+            "ecdsa" => Ok(Self::Ecdsa),
+            "ed25519" => Ok(Self::Ed25519),
+            _ => Err(format!("invalid ssh host key type; type={}", s)),
+        }
+    }
+}
+
+/// Returns the default number of seconds to wait for a jail's network to be ready.
+fn default_network_timeout() -> u64 {
+    60
+}
+
+/// Returns the default delay in seconds before the first retry of a failed step.
+fn default_retry_delay() -> u64 {
+    5
+}
+
+/// Returns `true`, used as the default value of boolean settings which are enabled by default.
+fn default_true() -> bool {
+    true
+}
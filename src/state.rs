@@ -0,0 +1,562 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! The state file which records the jails managed by iocage-provision.
+//!
+//! Once enabled with [`set_record_state`], every successfully provisioned jail is recorded in the
+//! state file along with the spec it was provisioned from. Jails provisioned by [`apply`] are
+//! always recorded. A plan compares the jails declared in a manifest against the managed jails
+//! and determines which jails need to be created, modified, or destroyed to match the manifest.
+//! Jails which were not provisioned by iocage-provision are never modified or destroyed.
+//!
+//! As a spec may hold values such as install tokens in its `exec_env`, the state file is only
+//! readable by its owner.
+//!
+//! An ephemeral jail is also recorded with the time at which it expires, and the managed jails
+//! which have expired can be destroyed regardless of any manifest.
+//!
+//! A jail is modified by destroying it and provisioning it again from its new spec, so any data
+//! in a modified jail is lost. When a managed jail is destroyed its data datasets (such as a home
+//! directory dataset) can instead be kept, in which case they are moved beneath the
+//! `iocage-provision/kept/NAME` dataset of the active pool.
+
+use super::{dns, host, iocage_pool, jail_dataset, list_jail_names, provision, run_and_indent};
+use super::{run_iocage_destroy, run_iocage_stop, run_zfs_set, Error, JailSpec, ProvisionReport};
+use super::{Result, STATE_FILE};
+use ipnet::IpNet;
+use log::info;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static RECORD_STATE: AtomicBool = AtomicBool::new(false);
+
+/// The contents of the state file.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct State {
+    /// Specs of the managed jails, keyed by jail name.
+    #[serde(default)]
+    jail: BTreeMap<String, JailSpec>,
+    /// Times at which managed jails expire, in seconds since the Unix epoch, keyed by jail name.
+    #[serde(default)]
+    expires: BTreeMap<String, u64>,
+}
+
+impl State {
+    /// Reads the state file, returning an empty state if it doesn't exist yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Err` if the state file could not be read or parsed.
+    fn load() -> Result<Self> {
+        let path = Path::new(STATE_FILE);
+        let src = match fs::read_to_string(path) {
+            Ok(src) => src,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(Error::ReadState(path.to_path_buf(), err)),
+        };
+
+        toml::from_str(&src).map_err(|err| Error::ParseState(path.to_path_buf(), err))
+    }
+
+    /// Writes the state file, replacing its previous contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Err` if the state could not be serialized or the state file could not be
+    /// written.
+    fn save(&self) -> Result<()> {
+        let path = Path::new(STATE_FILE);
+        // Tables must follow all other values in TOML, which serializing via a `Value` ensures
+        let src = toml::Value::try_from(self)
+            .and_then(|value| toml::to_string(&value))
+            .map_err(Error::SerializeState)?;
+
+        // The new contents are renamed over the old so that an interrupted write can't corrupt
+        // the state file
+        let tmp = path.with_extension("toml.tmp");
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|err| Error::WriteState(path.to_path_buf(), err))?;
+        }
+        write_private(&tmp, &src)
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|err| Error::WriteState(path.to_path_buf(), err))
+    }
+}
+
+/// An action which brings the host's jails closer to those declared in a manifest.
+#[derive(Debug)]
+pub enum Action {
+    /// Provision a declared jail which doesn't exist.
+    Create(JailSpec),
+    /// Destroy a managed jail and provision it again as its declared spec has changed.
+    Modify {
+        /// The declared spec of the jail.
+        spec: JailSpec,
+        /// Keys of the spec whose values differ from the managed jail's spec.
+        changes: Vec<String>,
+    },
+    /// Destroy a managed jail which is no longer declared.
+    Destroy(String),
+}
+
+impl Action {
+    /// Returns the name of the jail this action applies to.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Create(spec) | Self::Modify { spec, .. } => &spec.name,
+            Self::Destroy(name) => name,
+        }
+    }
+
+    /// Returns the symbol which marks the kind of this action in a plan.
+    pub fn symbol(&self) -> char {
+        match self {
+            Self::Create(_) => '+',
+            Self::Modify { .. } => '~',
+            Self::Destroy(_) => '-',
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Create(spec) => write!(f, "create {}", spec.name),
+            Self::Modify { spec, changes } => write!(
+                f,
+                "modify {} (destroy and create); changed={}",
+                spec.name,
+                changes.join(",")
+            ),
+            Self::Destroy(name) => write!(f, "destroy {}", name),
+        }
+    }
+}
+
+/// The actions needed to bring the host's jails in line with a manifest.
+#[derive(Debug)]
+pub struct Plan {
+    /// Actions in the order they are applied: all destroys first, followed by creates and
+    /// modifies in the order the jails are declared.
+    pub actions: Vec<Action>,
+}
+
+impl Plan {
+    /// Returns whether the plan has no actions.
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No changes, the jails match the manifest.");
+        }
+
+        let count =
+            |matches: fn(&Action) -> bool| self.actions.iter().filter(|a| matches(a)).count();
+        for action in &self.actions {
+            writeln!(f, "{} {}", action.symbol(), action)?;
+        }
+        writeln!(
+            f,
+            "\nPlan: {} to create, {} to modify, {} to destroy.",
+            count(|a| matches!(a, Action::Create(_))),
+            count(|a| matches!(a, Action::Modify { .. })),
+            count(|a| matches!(a, Action::Destroy(_))),
+        )
+    }
+}
+
+/// Compares declared jails against the managed jails in the state file and returns the actions
+/// needed to make them match.
+///
+/// # Errors
+///
+/// Returns an `Err` if:
+///
+/// * The state file could not be read
+/// * The existing jails could not be listed
+/// * A declared jail exists but is not managed by iocage-provision
+pub fn plan(jails: Vec<JailSpec>) -> Result<Plan> {
+    let state = State::load()?;
+    let existing = list_jail_names()?.into_iter().collect::<BTreeSet<_>>();
+
+    let mut actions = undeclared(&state, &existing, &jails);
+    for spec in jails {
+        let managed = state.jail.get(&spec.name);
+        let action = match (existing.contains(&spec.name), managed) {
+            (false, _) => Action::Create(spec),
+            (true, None) => return Err(Error::JailNotManaged(spec.name)),
+            (true, Some(managed)) => {
+                let changes = changed_keys(managed, &spec)?;
+                if changes.is_empty() {
+                    continue;
+                }
+                Action::Modify { spec, changes }
+            }
+        };
+        actions.push(action);
+    }
+
+    Ok(Plan { actions })
+}
+
+/// Returns the actions to destroy the managed jails which exist but are not declared.
+///
+/// # Errors
+///
+/// Returns an `Err` if the state file could not be read or the existing jails could not be
+/// listed.
+pub fn prune_plan(jails: &[JailSpec]) -> Result<Plan> {
+    let state = State::load()?;
+    let existing = list_jail_names()?.into_iter().collect::<BTreeSet<_>>();
+
+    Ok(Plan {
+        actions: undeclared(&state, &existing, jails),
+    })
+}
+
+/// Returns the names of the managed jails which exist.
+///
+/// # Errors
+///
+/// Returns an `Err` if the state file could not be read or the existing jails could not be
+/// listed.
+pub fn managed_jails() -> Result<Vec<String>> {
+    Ok(managed_specs()?.into_iter().map(|(name, _)| name).collect())
+}
+
+/// Returns the names and recorded specs of the managed jails which exist.
+///
+/// # Errors
+///
+/// Returns an `Err` if the state file could not be read or the existing jails could not be
+/// listed.
+pub(crate) fn managed_specs() -> Result<Vec<(String, JailSpec)>> {
+    let state = State::load()?;
+    let existing = list_jail_names()?.into_iter().collect::<BTreeSet<_>>();
+
+    Ok(state
+        .jail
+        .into_iter()
+        .filter(|(name, _)| existing.contains(name))
+        .collect())
+}
+
+/// Returns the actions to destroy the managed jails which exist and expired at or before the
+/// given time.
+///
+/// # Errors
+///
+/// Returns an `Err` if the state file could not be read or the existing jails could not be
+/// listed.
+pub fn expired_plan(now: SystemTime) -> Result<Plan> {
+    let state = State::load()?;
+    let existing = list_jail_names()?.into_iter().collect::<BTreeSet<_>>();
+    let now = unix_secs(now);
+
+    Ok(Plan {
+        actions: state
+            .expires
+            .iter()
+            .filter(|(name, expires)| {
+                **expires <= now && existing.contains(*name) && state.jail.contains_key(*name)
+            })
+            .map(|(name, _)| Action::Destroy(name.clone()))
+            .collect(),
+    })
+}
+
+/// Applies the actions of a plan, returning the reports of the provisioned jails.
+///
+/// When `keep_data` is set, the data datasets of jails destroyed by `Destroy` actions are kept.
+/// Managed jails which no longer exist (for example if they were destroyed out of band) are
+/// removed from the state file.
+///
+/// # Errors
+///
+/// Returns an `Err` if any action fails. Actions before the failed one have been applied and
+/// those after it have not.
+pub fn apply(plan: Plan, keep_data: bool) -> Result<Vec<ProvisionReport>> {
+    let existing = list_jail_names()?.into_iter().collect::<BTreeSet<_>>();
+    let mut state = State::load()?;
+    let before = state.jail.len();
+    state.jail = state
+        .jail
+        .into_iter()
+        .filter(|(name, _)| existing.contains(name))
+        .collect();
+    let jails = &state.jail;
+    state.expires = state
+        .expires
+        .into_iter()
+        .filter(|(name, _)| jails.contains_key(name))
+        .collect();
+    if state.jail.len() != before {
+        state.save()?;
+    }
+
+    let mut reports = Vec::new();
+    for action in plan.actions {
+        section!("Applying: {}", action);
+        match action {
+            Action::Create(spec) => reports.push(provision(&spec, true)?),
+            Action::Modify { spec, .. } => {
+                destroy_jail(&spec.name, false)?;
+                reports.push(provision(&spec, true)?);
+            }
+            Action::Destroy(name) => destroy_jail(&name, keep_data)?,
+        }
+    }
+
+    Ok(reports)
+}
+
+/// Sets whether or not the jails provisioned with [`provision_jail`](crate::provision_jail) are
+/// recorded as managed in the state file.
+///
+/// Jails are not recorded until this is enabled, so that provisioning a jail has no side effect
+/// on the state file unless asked for. Jails provisioned by [`apply`] are always recorded, as its
+/// plans depend on them.
+pub fn set_record_state(record: bool) {
+    RECORD_STATE.store(record, Ordering::Relaxed);
+}
+
+/// Returns whether or not provisioned jails are recorded in the state file.
+pub(crate) fn record_state() -> bool {
+    RECORD_STATE.load(Ordering::Relaxed)
+}
+
+/// Checks that the state file can be written when provisioned jails are recorded, creating its
+/// directory as needed.
+///
+/// # Errors
+///
+/// Returns an `Err` if the directory which holds the state file is not writable.
+pub(crate) fn check_state_file() -> Result<()> {
+    let path = Path::new(STATE_FILE);
+    match path.parent() {
+        Some(parent) if record_state() => {
+            fs::create_dir_all(parent).map_err(|err| Error::WriteState(path.to_path_buf(), err))?;
+            if host::is_writable(parent) {
+                Ok(())
+            } else {
+                Err(Error::WriteState(
+                    path.to_path_buf(),
+                    io::Error::from(io::ErrorKind::PermissionDenied),
+                ))
+            }
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Records a provisioned jail as managed in the state file, along with the time at which it
+/// expires, if it does.
+///
+/// The jail's name is given separately as it differs from the spec's name when the jail was named
+/// by iocage.
+///
+/// # Errors
+///
+/// Returns an `Err` if the state file could not be read or written.
+pub(crate) fn record(name: &str, spec: &JailSpec, expires: Option<SystemTime>) -> Result<()> {
+    let mut state = State::load()?;
+    let mut spec = spec.clone();
+    spec.name = name.to_string();
+    spec.uuid_name = false;
+    match expires {
+        Some(expires) => state.expires.insert(spec.name.clone(), unix_secs(expires)),
+        None => state.expires.remove(&spec.name),
+    };
+    state.jail.insert(spec.name.clone(), spec);
+    state.save()
+}
+
+/// Moves a managed jail's spec and expiry to a new name in the state file, returning the renamed
+/// spec, or `None` if the jail is not managed.
+///
+/// # Errors
+///
+/// Returns an `Err` if the state file could not be read or written.
+pub(crate) fn rename(old: &str, new: &str) -> Result<Option<JailSpec>> {
+    let mut state = State::load()?;
+    let mut spec = match state.jail.remove(old) {
+        Some(spec) => spec,
+        None => return Ok(None),
+    };
+    spec.name = new.to_string();
+    if let Some(expires) = state.expires.remove(old) {
+        state.expires.insert(new.to_string(), expires);
+    }
+    state.jail.insert(new.to_string(), spec.clone());
+    state.save()?;
+
+    Ok(Some(spec))
+}
+
+/// Updates the address, and optionally the default route, of a managed jail's spec in the state
+/// file, returning the updated spec, or `None` if the jail is not managed.
+///
+/// # Errors
+///
+/// Returns an `Err` if the state file could not be read or written.
+pub(crate) fn set_ip(name: &str, ip: IpNet, gateway: Option<IpAddr>) -> Result<Option<JailSpec>> {
+    let mut state = State::load()?;
+    let spec = match state.jail.get_mut(name) {
+        Some(spec) => spec,
+        None => return Ok(None),
+    };
+    spec.ip = ip;
+    if let Some(gateway) = gateway {
+        spec.gateway = gateway;
+    }
+    let spec = spec.clone();
+    state.save()?;
+
+    Ok(Some(spec))
+}
+
+/// Returns the number of whole seconds since the Unix epoch of a time.
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Returns the actions to destroy the managed jails which exist but are not declared.
+fn undeclared(state: &State, existing: &BTreeSet<String>, jails: &[JailSpec]) -> Vec<Action> {
+    let declared = jails
+        .iter()
+        .map(|spec| spec.name.as_str())
+        .collect::<BTreeSet<_>>();
+
+    state
+        .jail
+        .keys()
+        .filter(|name| existing.contains(*name) && !declared.contains(name.as_str()))
+        .map(|name| Action::Destroy(name.clone()))
+        .collect()
+}
+
+/// Destroys a managed jail and removes it from the state file, optionally keeping its data
+/// datasets.
+///
+/// # Errors
+///
+/// Returns an `Err` if the jail could not be destroyed, its data datasets could not be kept, or
+/// the state file could not be updated.
+fn destroy_jail(name: &str, keep_data: bool) -> Result<()> {
+    let mut state = State::load()?;
+    let registration = state
+        .jail
+        .get(name)
+        .and_then(|spec| spec.register_dns.clone());
+    let datasets = match state.jail.get(name) {
+        Some(spec) if keep_data => data_datasets(spec)?,
+        _ => Vec::new(),
+    };
+    if !datasets.is_empty() {
+        info!("Stopping jail '{}'", name);
+        run_iocage_stop(name)?;
+
+        let kept = format!("{}/iocage-provision/kept/{}", iocage_pool()?, name);
+        for (dataset, suffix) in datasets {
+            let target = format!("{}/{}", kept, suffix);
+            info!("Keeping dataset '{}' as '{}'", dataset, target);
+            // The dataset is unmounted as its mount point is beneath the jail's root
+            run_zfs_set(&dataset, &["mountpoint=none".to_string()])?;
+            let mut cmd = Command::new("zfs");
+            cmd.arg("rename").arg("-p").arg(&dataset).arg(&target);
+            run_and_indent(cmd).map_err(|err| Error::ZfsRename(dataset, err))?;
+        }
+    }
+
+    info!("Destroying jail '{}'", name);
+    run_iocage_destroy(name)?;
+    if let Some(registration) = registration {
+        info!("Removing '{}' from DNS", registration.fqdn(name));
+        dns::deregister(&registration, name)?;
+    }
+
+    state.jail.remove(name);
+    state.expires.remove(name);
+    state.save()
+}
+
+/// Returns the data datasets of a managed jail, each with its name relative to the jail's kept
+/// dataset.
+///
+/// # Errors
+///
+/// Returns an `Err` if the active iocage pool could not be determined.
+fn data_datasets(spec: &JailSpec) -> Result<Vec<(String, String)>> {
+    let mut names = Vec::new();
+    if let (true, Some(user)) = (spec.home_dataset, spec.user.as_deref()) {
+        names.push(format!("home/{}", user));
+    }
+    names.extend(
+        spec.recipes
+            .iter()
+            .filter_map(|recipe| recipe.dataset())
+            .map(|data| data.name.to_string()),
+    );
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let dataset = jail_dataset(&iocage_pool()?, &spec.name);
+    let datasets = names
+        .into_iter()
+        .map(|name| (format!("{}/{}", dataset, name), name))
+        .collect();
+
+    Ok(datasets)
+}
+
+/// Returns the keys whose values differ between two specs.
+///
+/// # Errors
+///
+/// Returns an `Err` if either spec could not be serialized.
+fn changed_keys(old: &JailSpec, new: &JailSpec) -> Result<Vec<String>> {
+    let table = |spec| match toml::Value::try_from(spec).map_err(Error::SerializeState)? {
+        toml::Value::Table(table) => Ok(table),
+        _ => unreachable!("a spec serializes to a table"),
+    };
+    let (old, new) = (table(old)?, table(new)?);
+
+    Ok(old
+        .keys()
+        .chain(new.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect())
+}
+
+/// Writes a new file which only its owner can read, replacing any leftover file of the same name.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(contents.as_bytes())
+}
@@ -0,0 +1,35 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Simple `{{ variable }}` interpolation for manifest values.
+
+/// Renders a template string by replacing each `{{ variable }}` with its value.
+///
+/// Whitespace around a variable name is ignored.
+///
+/// # Errors
+///
+/// Returns an `Err` with a message if a variable is unknown or a `{{` is not closed.
+pub(crate) fn render<F>(template: &str, lookup: F) -> Result<String, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| format!("unclosed `{{{{` in template `{}`", template))?;
+        let var = after[..end].trim();
+        let value = lookup(var).ok_or_else(|| format!("unknown template variable `{}`", var))?;
+        rendered.push_str(&value);
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
@@ -0,0 +1,639 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! A constrained subset of cloud-init's cloud-config user-data.
+//!
+//! User-data written for cloud-init on virtual machines can be reused for a jail, as long as it
+//! only uses the following top-level keys:
+//!
+//! * `packages`: package names to install
+//! * `users`: users to create, each with a `name` and optionally a `gecos`, `groups`, `shell`,
+//!   `ssh_authorized_keys`, and `sudo` rule (the `default` user is ignored)
+//! * `write_files`: files to write, each with a `path`, `content`, and optionally `permissions`,
+//!   `owner`, and `append`
+//! * `runcmd`: commands to run, each a shell command line or a list of program and arguments
+//! * `package_update`: accepted and ignored, as the package catalog is always updated
+//!
+//! Any other key is an error rather than being silently ignored. The packages are installed with
+//! the jail's other packages, and then the users are created, the files are written, and the
+//! commands are run, in that order, stopping at the first failure.
+//!
+//! The document is parsed as a subset of YAML: block mappings and sequences, plain and quoted
+//! scalars, flow sequences (i.e. `[ls, -l]`), and literal (`|`) and folded (`>`) block scalars.
+//! Anchors, tags, flow mappings, and multiple documents are not supported.
+
+use std::fmt;
+
+use crate::script;
+
+/// User-data of a jail.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UserData {
+    /// Packages to install.
+    pub packages: Vec<String>,
+    /// Users to create.
+    pub users: Vec<UserDataUser>,
+    /// Files to write.
+    pub write_files: Vec<WriteFile>,
+    /// Shell command lines to run.
+    pub runcmd: Vec<String>,
+}
+
+/// A user to create in a jail.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UserDataUser {
+    /// Name of the user.
+    pub name: String,
+    /// Full name or comment of the user.
+    pub gecos: Option<String>,
+    /// Existing groups to add the user to.
+    pub groups: Vec<String>,
+    /// Login shell of the user, if not the `pw` default.
+    pub shell: Option<String>,
+    /// SSH public keys which may log in as the user.
+    pub ssh_authorized_keys: Vec<String>,
+    /// sudoers rules for the user, without the user name (i.e. `ALL=(ALL) NOPASSWD:ALL`).
+    pub sudo: Vec<String>,
+}
+
+/// A file to write in a jail.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteFile {
+    /// Absolute path of the file.
+    pub path: String,
+    /// Contents of the file.
+    pub content: String,
+    /// Permissions mode of the file, if not the default.
+    pub permissions: Option<u32>,
+    /// Owner of the file as `USER` or `USER:GROUP`, if not root.
+    pub owner: Option<String>,
+    /// Whether or not to append to the file rather than replace it.
+    pub append: bool,
+}
+
+/// Error when user-data could not be parsed.
+#[derive(Debug, thiserror::Error)]
+#[error("{msg}; line={line}")]
+pub struct UserDataError {
+    line: usize,
+    msg: String,
+}
+
+impl UserDataError {
+    fn new(line: usize, msg: impl fmt::Display) -> Self {
+        Self {
+            line,
+            msg: msg.to_string(),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, UserDataError>;
+
+impl UserData {
+    /// Parses cloud-config user-data.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `Err` if the document is not valid in the supported subset of YAML, or if it
+    /// uses a key or value which is not supported.
+    pub fn parse(src: &str) -> Result<Self> {
+        let mut data = Self::default();
+        let root = match Parser::new(src).parse()? {
+            Some(root) => root,
+            None => return Ok(data),
+        };
+
+        for (key, value) in root.into_map("user-data")? {
+            match key.as_str() {
+                "packages" => {
+                    for pkg in value.into_seq("packages")? {
+                        data.packages.push(pkg.into_str("package")?);
+                    }
+                }
+                "users" => {
+                    for user in value.into_seq("users")? {
+                        // The image's default user doesn't exist in a jail
+                        if matches!(user, Node::Str(ref name, _) if name == "default") {
+                            continue;
+                        }
+                        data.users.push(user_from(user)?);
+                    }
+                }
+                "write_files" => {
+                    for file in value.into_seq("write_files")? {
+                        data.write_files.push(write_file_from(file)?);
+                    }
+                }
+                "runcmd" => {
+                    for cmd in value.into_seq("runcmd")? {
+                        data.runcmd.push(command_from(cmd)?);
+                    }
+                }
+                "package_update" => {}
+                _ => {
+                    return Err(UserDataError::new(
+                        value.line(),
+                        format!("unsupported cloud-config key '{}'", key),
+                    ))
+                }
+            }
+        }
+
+        Ok(data)
+    }
+}
+
+fn user_from(node: Node) -> Result<UserDataUser> {
+    let line = node.line();
+    let mut user = UserDataUser::default();
+    for (key, value) in node.into_map("user")? {
+        match key.as_str() {
+            "name" => user.name = value.into_str("name")?,
+            "gecos" => user.gecos = Some(value.into_str("gecos")?),
+            "groups" => user.groups = value.into_list("groups")?,
+            "shell" => user.shell = Some(value.into_str("shell")?),
+            "ssh_authorized_keys" => {
+                for key in value.into_seq("ssh_authorized_keys")? {
+                    user.ssh_authorized_keys.push(key.into_str("key")?);
+                }
+            }
+            "sudo" => match value {
+                Node::Str(ref rule, _) if rule == "false" => {}
+                Node::Seq(..) => {
+                    for rule in value.into_seq("sudo")? {
+                        user.sudo.push(rule.into_str("sudo rule")?);
+                    }
+                }
+                value => user.sudo.push(value.into_str("sudo")?),
+            },
+            _ => {
+                return Err(UserDataError::new(
+                    value.line(),
+                    format!("unsupported user key '{}'", key),
+                ))
+            }
+        }
+    }
+    if user.name.is_empty() {
+        return Err(UserDataError::new(line, "user must have a name"));
+    }
+    if !script::is_user_name(&user.name) {
+        return Err(UserDataError::new(
+            line,
+            format!("invalid user name; name={}", user.name),
+        ));
+    }
+
+    Ok(user)
+}
+
+fn write_file_from(node: Node) -> Result<WriteFile> {
+    let line = node.line();
+    let mut file = WriteFile::default();
+    for (key, value) in node.into_map("file")? {
+        match key.as_str() {
+            "path" => file.path = value.into_str("path")?,
+            "content" => file.content = value.into_str("content")?,
+            "permissions" => {
+                let line = value.line();
+                let mode = value.into_str("permissions")?;
+                file.permissions = Some(u32::from_str_radix(&mode, 8).map_err(|_| {
+                    UserDataError::new(line, format!("permissions must be octal; value={}", mode))
+                })?);
+            }
+            "owner" => file.owner = Some(value.into_str("owner")?),
+            "append" => file.append = value.into_bool("append")?,
+            "encoding" => {
+                let line = value.line();
+                let encoding = value.into_str("encoding")?;
+                if encoding != "text/plain" {
+                    return Err(UserDataError::new(
+                        line,
+                        format!("unsupported file encoding '{}'", encoding),
+                    ));
+                }
+            }
+            _ => {
+                return Err(UserDataError::new(
+                    value.line(),
+                    format!("unsupported write_files key '{}'", key),
+                ))
+            }
+        }
+    }
+    if !file.path.starts_with('/') {
+        return Err(UserDataError::new(line, "file must have an absolute path"));
+    }
+
+    Ok(file)
+}
+
+fn command_from(node: Node) -> Result<String> {
+    match node {
+        Node::Seq(..) => Ok(node
+            .into_list("command")?
+            .iter()
+            .map(|word| crate::script::quote(word).into_owned())
+            .collect::<Vec<_>>()
+            .join(" ")),
+        node => node.into_str("command"),
+    }
+}
+
+/// A parsed YAML node, with the line it starts on.
+#[derive(Debug)]
+enum Node {
+    Str(String, usize),
+    Seq(Vec<Node>, usize),
+    Map(Vec<(String, Node)>, usize),
+}
+
+impl Node {
+    fn line(&self) -> usize {
+        match self {
+            Self::Str(_, line) | Self::Seq(_, line) | Self::Map(_, line) => *line,
+        }
+    }
+
+    fn into_str(self, what: &str) -> Result<String> {
+        match self {
+            Self::Str(s, _) => Ok(s),
+            node => Err(UserDataError::new(
+                node.line(),
+                format!("{} must be a string", what),
+            )),
+        }
+    }
+
+    fn into_bool(self, what: &str) -> Result<bool> {
+        let line = self.line();
+        match self.into_str(what)?.as_str() {
+            "true" | "True" | "yes" => Ok(true),
+            "false" | "False" | "no" => Ok(false),
+            _ => Err(UserDataError::new(
+                line,
+                format!("{} must be true or false", what),
+            )),
+        }
+    }
+
+    fn into_seq(self, what: &str) -> Result<Vec<Node>> {
+        match self {
+            Self::Seq(items, _) => Ok(items),
+            node => Err(UserDataError::new(
+                node.line(),
+                format!("{} must be a list", what),
+            )),
+        }
+    }
+
+    fn into_map(self, what: &str) -> Result<Vec<(String, Node)>> {
+        match self {
+            Self::Map(entries, _) => Ok(entries),
+            node => Err(UserDataError::new(
+                node.line(),
+                format!("{} must be a mapping", what),
+            )),
+        }
+    }
+
+    /// Returns a list of strings, given either as a list or as a comma separated string.
+    fn into_list(self, what: &str) -> Result<Vec<String>> {
+        match self {
+            Self::Str(s, _) => Ok(s
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect()),
+            node => node
+                .into_seq(what)?
+                .into_iter()
+                .map(|item| item.into_str(what))
+                .collect(),
+        }
+    }
+}
+
+/// A line of the document: its number, indentation, and content after the indentation.
+#[derive(Clone, Debug)]
+struct Line<'a> {
+    number: usize,
+    indent: usize,
+    text: &'a str,
+}
+
+/// A parser of the supported subset of YAML, which works a line at a time by indentation.
+struct Parser<'a> {
+    raw: Vec<&'a str>,
+    lines: Vec<Line<'a>>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        let raw = src.lines().collect::<Vec<_>>();
+        let lines = raw
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, line)| {
+                let text = line.trim_start_matches(' ');
+                if text.trim().is_empty() || text.starts_with('#') || text == "---" {
+                    None
+                } else {
+                    Some(Line {
+                        number: idx + 1,
+                        indent: line.len() - text.len(),
+                        text: text.trim_end(),
+                    })
+                }
+            })
+            .collect();
+
+        Self { raw, lines, pos: 0 }
+    }
+
+    fn parse(mut self) -> Result<Option<Node>> {
+        let first = match self.lines.first() {
+            Some(first) => first.clone(),
+            None => return Ok(None),
+        };
+        let node = self.block(first.indent)?;
+        if let Some(line) = self.lines.get(self.pos) {
+            return Err(UserDataError::new(line.number, "unexpected indentation"));
+        }
+
+        Ok(Some(node))
+    }
+
+    fn peek(&self) -> Option<&Line<'a>> {
+        self.lines.get(self.pos)
+    }
+
+    /// Parses a block sequence or mapping at the given indentation.
+    fn block(&mut self, indent: usize) -> Result<Node> {
+        let line = self.peek().cloned().expect("a block starts on a line");
+        if line.text.starts_with('\t') {
+            return Err(UserDataError::new(
+                line.number,
+                "tabs are not allowed in indentation",
+            ));
+        }
+        if is_seq_item(line.text) {
+            self.seq(indent)
+        } else {
+            self.map(indent)
+        }
+    }
+
+    fn seq(&mut self, indent: usize) -> Result<Node> {
+        let start = self.peek().map_or(0, |line| line.number);
+        let mut items = Vec::new();
+        while let Some(line) = self.peek().cloned() {
+            if line.indent != indent || !is_seq_item(line.text) {
+                break;
+            }
+            let rest = line.text[1..].trim_start();
+            if rest.is_empty() {
+                self.pos += 1;
+                items.push(self.nested(indent, line.number)?);
+            } else if split_key(rest).is_some() {
+                // A mapping which starts on the item's line continues at the indentation of its
+                // first key
+                let key_indent = line.indent + (line.text.len() - rest.len());
+                self.lines[self.pos] = Line {
+                    number: line.number,
+                    indent: key_indent,
+                    text: rest,
+                };
+                items.push(self.map(key_indent)?);
+            } else {
+                self.pos += 1;
+                items.push(self.value(rest, indent, line.number)?);
+            }
+        }
+
+        Ok(Node::Seq(items, start))
+    }
+
+    fn map(&mut self, indent: usize) -> Result<Node> {
+        let start = self.peek().map_or(0, |line| line.number);
+        let mut entries = Vec::new();
+        while let Some(line) = self.peek().cloned() {
+            if line.indent != indent || is_seq_item(line.text) {
+                if line.indent > indent {
+                    return Err(UserDataError::new(line.number, "unexpected indentation"));
+                }
+                break;
+            }
+            let (key, rest) = split_key(line.text)
+                .ok_or_else(|| UserDataError::new(line.number, "expected a 'key: value' line"))?;
+            let key = unquote(key, line.number)?;
+            self.pos += 1;
+            let value = if rest.is_empty() {
+                match self.peek() {
+                    // A sequence may be indented at the same level as the key which holds it
+                    Some(next) if next.indent == indent && is_seq_item(next.text) => {
+                        self.seq(indent)?
+                    }
+                    _ => self.nested(indent, line.number)?,
+                }
+            } else {
+                self.value(rest, indent, line.number)?
+            };
+            entries.push((key, value));
+        }
+
+        Ok(Node::Map(entries, start))
+    }
+
+    /// Parses the block nested beneath a line, or an empty string if there is none.
+    fn nested(&mut self, indent: usize, number: usize) -> Result<Node> {
+        match self.peek() {
+            Some(next) if next.indent > indent => {
+                let next_indent = next.indent;
+                self.block(next_indent)
+            }
+            _ => Ok(Node::Str(String::new(), number)),
+        }
+    }
+
+    /// Parses the value which follows a key or sequence item marker on a line.
+    fn value(&mut self, text: &str, indent: usize, number: usize) -> Result<Node> {
+        let text = strip_comment(text);
+        match text.chars().next() {
+            Some('|') | Some('>') => {
+                Ok(Node::Str(self.block_scalar(text, indent, number)?, number))
+            }
+            Some('[') => flow_seq(text, number),
+            Some('{') | Some('&') | Some('*') | Some('!') => Err(UserDataError::new(
+                number,
+                "flow mappings, anchors, aliases, and tags are not supported",
+            )),
+            _ => Ok(Node::Str(unquote(text, number)?, number)),
+        }
+    }
+
+    /// Parses a literal (`|`) or folded (`>`) block scalar from the raw lines which follow its
+    /// header line.
+    fn block_scalar(&mut self, header: &str, indent: usize, number: usize) -> Result<String> {
+        let folded = header.starts_with('>');
+        let chomp = &header[1..];
+        if !matches!(chomp, "" | "-" | "+") {
+            return Err(UserDataError::new(
+                number,
+                format!("unsupported block scalar header '{}'", header),
+            ));
+        }
+
+        // Every raw line after the header which is blank or indented beyond its key is content
+        let mut content = Vec::new();
+        let mut block_indent = None;
+        let mut idx = number;
+        while let Some(raw) = self.raw.get(idx) {
+            let text = raw.trim_start_matches(' ');
+            let line_indent = raw.len() - text.len();
+            if text.is_empty() {
+                content.push("");
+            } else if line_indent > indent {
+                let block_indent = *block_indent.get_or_insert(line_indent);
+                if line_indent < block_indent {
+                    break;
+                }
+                content.push(&raw[block_indent..]);
+            } else {
+                break;
+            }
+            idx += 1;
+        }
+        // Skip the structural lines which were part of the block scalar
+        while matches!(self.peek(), Some(line) if line.number <= idx) {
+            self.pos += 1;
+        }
+
+        while content.last() == Some(&"") {
+            content.pop();
+        }
+        let mut s = if folded {
+            content
+                .split(|line| line.is_empty())
+                .map(|para| para.join(" "))
+                .collect::<Vec<_>>()
+                .join("\n")
+        } else {
+            content.join("\n")
+        };
+        if chomp != "-" && !s.is_empty() {
+            s.push('\n');
+        }
+
+        Ok(s)
+    }
+}
+
+/// Returns whether or not a line is an item of a block sequence.
+fn is_seq_item(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// Splits a `key: value` line into its key and value, unless the colon is within quotes.
+fn split_key(text: &str) -> Option<(&str, &str)> {
+    let mut quote = None;
+    for (idx, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') if idx == 0 => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, ':') => {
+                let rest = &text[idx + 1..];
+                if rest.is_empty() || rest.starts_with(' ') {
+                    return Some((text[..idx].trim_end(), rest.trim()));
+                }
+            }
+            (None, '#') if idx > 0 && text[..idx].ends_with(' ') => return None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Removes a trailing comment from a value, unless the value is quoted.
+fn strip_comment(text: &str) -> &str {
+    if text.starts_with('"') || text.starts_with('\'') {
+        return text;
+    }
+    match text.find(" #") {
+        Some(idx) => text[..idx].trim_end(),
+        None => text,
+    }
+}
+
+/// Returns the value of a plain, single quoted, or double quoted scalar.
+fn unquote(text: &str, number: usize) -> Result<String> {
+    let unterminated = || UserDataError::new(number, "unterminated quoted string");
+    if let Some(inner) = text.strip_prefix('\'') {
+        let inner = inner.strip_suffix('\'').ok_or_else(unterminated)?;
+        Ok(inner.replace("''", "'"))
+    } else if let Some(inner) = text.strip_prefix('"') {
+        let inner = inner.strip_suffix('"').ok_or_else(unterminated)?;
+        let mut s = String::with_capacity(inner.len());
+        let mut chars = inner.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                s.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => s.push('\n'),
+                Some('t') => s.push('\t'),
+                Some(c @ '"') | Some(c @ '\\') | Some(c @ '/') => s.push(c),
+                _ => return Err(UserDataError::new(number, "unsupported escape sequence")),
+            }
+        }
+        Ok(s)
+    } else {
+        Ok(text.to_string())
+    }
+}
+
+/// Parses a flow sequence of scalars, such as `[ls, -l, "/usr/local"]`, on a single line.
+fn flow_seq(text: &str, number: usize) -> Result<Node> {
+    let inner = text
+        .strip_prefix('[')
+        .and_then(|text| text.strip_suffix(']'))
+        .ok_or_else(|| UserDataError::new(number, "flow sequence must end on the same line"))?;
+
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut quote = None;
+    for c in inner.chars() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') if item.trim().is_empty() => {
+                quote = Some(c);
+                item.push(c);
+            }
+            (Some(q), c) if c == q => {
+                quote = None;
+                item.push(c);
+            }
+            (None, ',') => {
+                items.push(Node::Str(unquote(item.trim(), number)?, number));
+                item.clear();
+            }
+            (None, '[') | (None, '{') => {
+                return Err(UserDataError::new(
+                    number,
+                    "nested flow collections are not supported",
+                ))
+            }
+            _ => item.push(c),
+        }
+    }
+    if !item.trim().is_empty() {
+        items.push(Node::Str(unquote(item.trim(), number)?, number));
+    }
+
+    Ok(Node::Seq(items, number))
+}
@@ -0,0 +1,162 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Checking that the managed jails came back up after the host rebooted.
+//!
+//! Every managed jail whose `boot` property is on should have been started by iocage while the
+//! host booted. Each such jail is checked to be running, to answer a ping at its address and,
+//! when it has an SSH service, to accept connections on the SSH port. Since iocage starts the
+//! jails one after another, and their services take a moment to start, failed checks are retried
+//! until a timeout runs out.
+
+use super::{cmd_stdout, iocage_query_command, parse, state, Error, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Port on which the SSH service of a jail listens.
+const SSH_PORT: u16 = 22;
+
+/// Time to wait for a ping reply or an SSH connection, in seconds.
+const PROBE_TIMEOUT: u64 = 2;
+
+/// Time to wait between rounds of checks.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The result of checking that a managed jail came up after the host booted.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct BootCheck {
+    /// Name of the jail.
+    pub name: String,
+    /// Checks which the jail failed, which is empty when it passed them all.
+    pub failures: Vec<BootFailure>,
+}
+
+impl BootCheck {
+    /// Returns whether the jail passed all of its checks.
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// A check which a managed jail failed.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootFailure {
+    /// The jail is not running.
+    NotRunning,
+    /// The jail did not answer a ping at the given address.
+    Unreachable(IpAddr),
+    /// The jail's SSH service did not accept a connection at the given address.
+    SshUnavailable(SocketAddr),
+}
+
+impl fmt::Display for BootFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotRunning => f.write_str("not running"),
+            Self::Unreachable(addr) => write!(f, "no ping reply from {}", addr),
+            Self::SshUnavailable(addr) => write!(f, "no SSH service at {}", addr),
+        }
+    }
+}
+
+/// Checks that the managed jails which start at boot are running and reachable, retrying failed
+/// checks until the timeout runs out.
+///
+/// The result of the last round of checks is returned for each jail, in order of name.
+///
+/// # Errors
+///
+/// Returns an `Err` if the state file could not be read or the jails or their properties could
+/// not be listed.
+pub fn verify_boot(timeout: Duration) -> Result<Vec<BootCheck>> {
+    // A timeout too long to be represented has no deadline
+    let deadline = Instant::now().checked_add(timeout);
+    loop {
+        let checks = check_jails()?;
+        if checks.iter().all(BootCheck::passed)
+            || deadline.map_or(false, |deadline| Instant::now() >= deadline)
+        {
+            return Ok(checks);
+        }
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+/// Checks each of the managed jails which start at boot once.
+fn check_jails() -> Result<Vec<BootCheck>> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("list").arg("-h");
+    let stdout = cmd_stdout(cmd).map_err(Error::IocageList)?;
+    let states = parse::list(&stdout)
+        .into_iter()
+        .map(|jail| (jail.name, jail.state))
+        .collect::<BTreeMap<_, _>>();
+
+    let mut checks = Vec::new();
+    for (name, spec) in state::managed_specs()? {
+        if !starts_at_boot(&name)? {
+            continue;
+        }
+
+        let mut failures = Vec::new();
+        if states.get(&name).map(String::as_str) != Some("up") {
+            failures.push(BootFailure::NotRunning);
+        } else {
+            let addr = spec.ip.addr();
+            if !ping(addr) {
+                failures.push(BootFailure::Unreachable(addr));
+            }
+            let ssh_addr = SocketAddr::new(addr, SSH_PORT);
+            if spec.ssh_service
+                && TcpStream::connect_timeout(&ssh_addr, Duration::from_secs(PROBE_TIMEOUT))
+                    .is_err()
+            {
+                failures.push(BootFailure::SshUnavailable(ssh_addr));
+            }
+        }
+        checks.push(BootCheck { name, failures });
+    }
+
+    Ok(checks)
+}
+
+/// Returns whether a jail's `boot` property is on.
+fn starts_at_boot(jail_name: &str) -> Result<bool> {
+    let mut cmd = iocage_query_command();
+    cmd.arg("get").arg("boot").arg(jail_name);
+    let stdout = cmd_stdout(cmd).map_err(|err| Error::IocageGet(jail_name.to_string(), err))?;
+
+    Ok(parse::enabled(&stdout))
+}
+
+/// Returns whether an address answers a single ping.
+fn ping(addr: IpAddr) -> bool {
+    // FreeBSD's `ping6` takes its overall timeout with `-X` rather than `-t`
+    let mut cmd = match addr {
+        IpAddr::V4(_) => {
+            let mut cmd = Command::new("ping");
+            cmd.arg("-t");
+            cmd
+        }
+        IpAddr::V6(_) => {
+            let mut cmd = Command::new("ping6");
+            cmd.arg("-X");
+            cmd
+        }
+    };
+    cmd.arg(PROBE_TIMEOUT.to_string())
+        .arg("-c")
+        .arg("1")
+        .arg(addr.to_string())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    cmd.status().map(|status| status.success()).unwrap_or(false)
+}
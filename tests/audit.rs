@@ -0,0 +1,59 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{fake_program, prepend_path};
+use std::fs;
+
+// The program search path is process-wide, so this is the only test in this file
+#[test]
+fn test_only_mutating_commands_are_recorded() {
+    let dir = tempfile::tempdir().unwrap();
+    fake_program(dir.path(), "iocage", "echo zroot\n");
+    fake_program(
+        dir.path(),
+        "zfs",
+        r#"case "$*" in
+  *"-t filesystem"*) printf 'zroot/iocage\nzroot/iocage/new\n';;
+  *"-t snapshot"*) echo 'zroot/iocage@cp';;
+esac
+"#,
+    );
+    prepend_path(dir.path());
+    let audit_log = dir.path().join("log/audit.log");
+    iocage_provision::set_audit_log(Some(audit_log.clone()));
+
+    iocage_provision::rollback_checkpoint("cp").expect("rollback should succeed");
+
+    let records = fs::read_to_string(&audit_log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let subcommands = records
+        .iter()
+        .map(|record| record["argv"][1].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(vec!["destroy", "rollback", "destroy"], subcommands);
+    for record in &records {
+        assert_eq!("zfs", record["argv"][0]);
+        assert_eq!("success", record["result"]);
+        assert!(record["jail"].is_null());
+        assert!(record["timestamp"].as_str().unwrap().ends_with('Z'));
+    }
+    assert_eq!(
+        vec!["zfs", "rollback", "-r", "zroot/iocage@cp"],
+        records[1]["argv"].as_array().unwrap().to_vec()
+    );
+
+    // An audit log which can't be written is found up front, and doesn't fail a command which ran
+    let unwritable = audit_log.join("audit.log");
+    iocage_provision::set_audit_log(Some(unwritable.clone()));
+    assert!(iocage_provision::check_audit_log().is_err());
+    iocage_provision::rollback_checkpoint("cp").expect("rollback should still succeed");
+    assert!(!unwritable.exists());
+}
@@ -0,0 +1,47 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{fake_program, prepend_path};
+use iocage_provision::{Error, ErrorKind};
+use std::fs;
+
+// The program search path is process-wide, so this is the only test in this file
+#[test]
+fn test_rollback_refuses_later_snapshots_with_clones() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("zfs.log");
+    fake_program(dir.path(), "iocage", "echo zroot\n");
+    fake_program(
+        dir.path(),
+        "zfs",
+        &format!(
+            r#"echo "$*" >>{log}
+case "$*" in
+  *"-t filesystem"*) printf 'zroot/iocage\nzroot/iocage/releases\nzroot/iocage/new\n';;
+  *"name,clones"*) printf 'zroot/iocage@cp\t\nzroot/iocage/releases@cp\t\nzroot/iocage/new@base\tzroot/iocage/new/child\nzroot/iocage/releases@later\tzroot/other/clone\n';;
+  *"-t snapshot"*) printf 'zroot/iocage@cp\nzroot/iocage/releases@cp\n';;
+esac
+"#,
+            log = log.display()
+        ),
+    );
+    prepend_path(dir.path());
+
+    let err = iocage_provision::rollback_checkpoint("cp").unwrap_err();
+    match err {
+        Error::CheckpointClones(ref snapshot, ref clones) => {
+            assert_eq!("zroot/iocage/releases@later", snapshot);
+            assert_eq!("zroot/other/clone", clones);
+        }
+        ref err => panic!("unexpected error: {}", err),
+    }
+    assert_eq!(ErrorKind::Conflict, err.kind());
+    let calls = fs::read_to_string(&log).unwrap();
+    assert!(!calls.contains("destroy"), "calls={}", calls);
+    assert!(!calls.contains("rollback"), "calls={}", calls);
+}
@@ -0,0 +1,54 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{fake_program, prepend_path};
+use std::env;
+use std::fs;
+
+// The program search path and child environment are process-wide, so this is the only test in
+// this file
+#[test]
+fn test_child_env_is_given_to_commands() {
+    let dir = tempfile::tempdir().unwrap();
+    fake_program(
+        dir.path(),
+        "iocage",
+        "printf 'unbuffered:%s\\nlocale:%s\\n' \"${PYTHONUNBUFFERED-unset}\" \"${LC_ALL-unset}\"\n",
+    );
+    prepend_path(dir.path());
+    env::remove_var("PYTHONUNBUFFERED");
+    env::remove_var("LC_ALL");
+
+    let props = iocage_provision::same_as_properties("ferris").unwrap();
+    assert_eq!("true", props["unbuffered"]);
+    assert_eq!("unset", props["locale"]);
+
+    iocage_provision::set_child_env(
+        vec![
+            (String::from("LC_ALL"), String::from("C.UTF-8")),
+            (String::from("PYTHONUNBUFFERED"), String::from("1")),
+        ],
+        true,
+    );
+    let props = iocage_provision::same_as_properties("ferris").unwrap();
+    assert_eq!("1", props["unbuffered"]);
+    assert_eq!("C.UTF-8", props["locale"]);
+
+    iocage_provision::set_child_env(vec![(String::from("LC_ALL"), String::from("C"))], false);
+    let props = iocage_provision::same_as_properties("ferris").unwrap();
+    assert_eq!("unset", props["unbuffered"]);
+    assert_eq!("C", props["locale"]);
+
+    // A command which could not be run is named by its program rather than its environment
+    fs::remove_file(dir.path().join("iocage")).unwrap();
+    let err = iocage_provision::same_as_properties("ferris").unwrap_err();
+    assert_eq!(
+        Some("check that iocage is installed and in PATH"),
+        err.remediation().as_deref()
+    );
+}
@@ -0,0 +1,35 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{fake_program, prepend_path};
+use iocage_provision::ChildPriority;
+
+// The program search path and child priority are process-wide, so this is the only test in this
+// file
+#[test]
+fn test_child_priority_is_given_to_commands() {
+    let dir = tempfile::tempdir().unwrap();
+    fake_program(
+        dir.path(),
+        "iocage",
+        "printf 'nice:%s\\n' \"$(ps -o nice= -p $$ | tr -d ' ')\"\n",
+    );
+    prepend_path(dir.path());
+
+    let props = iocage_provision::same_as_properties("ferris").unwrap();
+    let base: i32 = props["nice"].parse().unwrap();
+
+    // Lowering a priority is allowed without privileges
+    let nice = (base + 5).min(20);
+    iocage_provision::set_child_priority(ChildPriority {
+        nice: Some(nice),
+        idprio: None,
+    });
+    let props = iocage_provision::same_as_properties("ferris").unwrap();
+    assert_eq!(nice.to_string(), props["nice"]);
+}
@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+//! Helpers shared by the tests which run fake host programs.
+
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+/// Writes an executable `sh` script with the given name and body into a directory.
+pub fn fake_program(dir: &Path, name: &str, src: &str) {
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{}", src)).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Puts a directory at the front of the program search path, so that its fake programs are run in
+/// place of the host's.
+///
+/// The search path is process-wide, so a test file which calls this should hold only one test.
+pub fn prepend_path(dir: &Path) {
+    env::set_var(
+        "PATH",
+        format!("{}:{}", dir.display(), env::var("PATH").unwrap()),
+    );
+}
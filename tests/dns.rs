@@ -0,0 +1,82 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::dns::{self, DnsRegistration};
+use std::path::PathBuf;
+
+fn registration() -> DnsRegistration {
+    "jails.example.com:ns1.example.com:/etc/jails.key"
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn test_from_str() {
+    assert_eq!(
+        DnsRegistration {
+            zone: String::from("jails.example.com"),
+            server: String::from("ns1.example.com"),
+            key: PathBuf::from("/etc/jails.key"),
+        },
+        registration()
+    );
+}
+
+#[test]
+fn test_from_str_ipv6_server() {
+    let registration: DnsRegistration = "jails.example.com:[2001:db8::53]:/etc/jails.key"
+        .parse()
+        .unwrap();
+
+    assert_eq!("2001:db8::53", registration.server);
+    assert_eq!(PathBuf::from("/etc/jails.key"), registration.key);
+}
+
+#[test]
+fn test_from_str_invalid() {
+    assert!("jails.example.com".parse::<DnsRegistration>().is_err());
+    assert!("jails.example.com:ns1".parse::<DnsRegistration>().is_err());
+    assert!("jails.example.com::/etc/jails.key"
+        .parse::<DnsRegistration>()
+        .is_err());
+    assert!(":ns1:/etc/jails.key".parse::<DnsRegistration>().is_err());
+}
+
+#[test]
+fn test_fqdn() {
+    assert_eq!("web.jails.example.com.", registration().fqdn("web"));
+}
+
+#[test]
+fn test_update_add() {
+    assert_eq!(
+        "server ns1.example.com\n\
+         zone jails.example.com\n\
+         update delete web.jails.example.com. A\n\
+         update delete web.jails.example.com. AAAA\n\
+         update add web.jails.example.com. 300 A 10.0.0.5\n\
+         send\n",
+        dns::update(&registration(), "web", Some("10.0.0.5".parse().unwrap()))
+    );
+}
+
+#[test]
+fn test_update_add_ipv6() {
+    assert!(
+        dns::update(&registration(), "web", Some("2001:db8::5".parse().unwrap()))
+            .contains("update add web.jails.example.com. 300 AAAA 2001:db8::5\n")
+    );
+}
+
+#[test]
+fn test_update_delete() {
+    assert_eq!(
+        "server ns1.example.com\n\
+         zone jails.example.com\n\
+         update delete web.jails.example.com. A\n\
+         update delete web.jails.example.com. AAAA\n\
+         send\n",
+        dns::update(&registration(), "web", None)
+    );
+}
@@ -0,0 +1,104 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::{emitters, IocageVersion, NextSteps, ProvisionReport, SnapshotSchedule};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+fn report() -> ProvisionReport {
+    let mut tags = BTreeMap::new();
+    tags.insert("env".to_string(), "prod".to_string());
+    tags.insert("role".to_string(), "db \"primary\"".to_string());
+
+    ProvisionReport {
+        name: "db".to_string(),
+        uuid: "db".to_string(),
+        dataset: "zroot/iocage/jails/db".to_string(),
+        disk_quota: Some("20G".to_string()),
+        disk_reserve: None,
+        snapshot_schedule: Some(SnapshotSchedule {
+            daily: 7,
+            weekly: 4,
+            ..SnapshotSchedule::default()
+        }),
+        root: PathBuf::from("/zroot/iocage/jails/db/root"),
+        ip: "10.0.0.5/24".parse().unwrap(),
+        release: "13.0-RELEASE".to_string(),
+        user: None,
+        tags,
+        iocage_version: IocageVersion { major: 1, minor: 2 },
+        skipped: false,
+        checkpoint: None,
+        ssh_host_keys: Vec::new(),
+        property_changes: Vec::new(),
+        warnings: Vec::new(),
+        stopped_after: None,
+        monitoring_endpoint: None,
+        dns_name: None,
+        expires_at: None,
+        next_steps: NextSteps::new("ferris", "10.0.0.5".parse().unwrap(), None),
+        pkglist: r#"{"pkgs":[]}"#.to_string(),
+    }
+}
+
+#[test]
+fn test_terraform_is_an_object_of_strings() {
+    let json: serde_json::Value =
+        serde_json::from_str(&emitters::terraform(&report())).expect("output should be JSON");
+    let object = json.as_object().expect("output should be an object");
+
+    assert!(object.values().all(serde_json::Value::is_string));
+    assert_eq!("db", object["name"]);
+    assert_eq!("10.0.0.5/24", object["ip"]);
+    assert_eq!("10.0.0.5", object["address"]);
+    assert_eq!("13.0-RELEASE", object["release"]);
+
+    let tags: BTreeMap<String, String> =
+        serde_json::from_str(object["tags"].as_str().unwrap()).expect("tags should be JSON");
+    assert_eq!(report().tags, tags);
+}
+
+#[test]
+fn test_next_steps() {
+    let steps = NextSteps::new("ferris", "10.0.0.25".parse().unwrap(), Some("jdoe"));
+
+    assert_eq!(Some("ssh jdoe@10.0.0.25"), steps.ssh.as_deref());
+    assert_eq!("iocage console ferris", steps.console);
+    assert_eq!("iocage stop ferris", steps.stop);
+    assert_eq!("iocage destroy -f ferris", steps.destroy);
+    assert_eq!(
+        None,
+        NextSteps::new("ferris", "10.0.0.25".parse().unwrap(), None).ssh
+    );
+}
+
+#[test]
+fn test_sanoid() {
+    assert_eq!(
+        "[zroot/iocage/jails/db]\n\
+         \tfrequently = 0\n\
+         \thourly = 0\n\
+         \tdaily = 7\n\
+         \tweekly = 4\n\
+         \tmonthly = 0\n\
+         \tyearly = 0\n\
+         \tautosnap = yes\n\
+         \tautoprune = yes\n\
+         \trecursive = yes\n",
+        emitters::sanoid(&report())
+    );
+}
+
+#[test]
+fn test_snapshot_schedule() {
+    let schedule: SnapshotSchedule = "daily:7, weekly:4".parse().unwrap();
+    assert_eq!(report().snapshot_schedule, Some(schedule));
+    assert_eq!("daily:7,weekly:4", schedule.to_string());
+
+    assert!("".parse::<SnapshotSchedule>().is_err());
+    assert!("daily".parse::<SnapshotSchedule>().is_err());
+    assert!("daily:x".parse::<SnapshotSchedule>().is_err());
+    assert!("yearly:1".parse::<SnapshotSchedule>().is_err());
+    assert!("daily:7,daily:3".parse::<SnapshotSchedule>().is_err());
+}
@@ -0,0 +1,62 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::{CmdError, Error, ErrorKind, IocageExecError};
+use std::io;
+
+#[test]
+fn test_kinds() {
+    assert_eq!(ErrorKind::Permission, Error::NotRoot.kind());
+    assert_eq!(
+        ErrorKind::Conflict,
+        Error::JailExists("db".into(), "db".into(), "up".into()).kind()
+    );
+    assert_eq!(
+        ErrorKind::Network,
+        Error::MirrorUnresolvable("pkg.FreeBSD.org".into()).kind()
+    );
+    assert_eq!(ErrorKind::NotFound, Error::JailNotFound("db".into()).kind());
+    assert_eq!(
+        ErrorKind::NotFound,
+        Error::SecretNotFound("api-token".into()).kind()
+    );
+    assert_eq!(
+        ErrorKind::Command,
+        Error::IocageCreate(CmdError::Failed(1)).kind()
+    );
+}
+
+#[test]
+fn test_remediation() {
+    assert!(Error::NotRoot.remediation().unwrap().contains("--sudo"));
+    assert!(Error::IocageCreate(CmdError::Failed(1))
+        .remediation()
+        .unwrap()
+        .contains("iocage fetch"));
+    assert!(Error::DependencyNotFound("db".into())
+        .remediation()
+        .unwrap()
+        .contains("'db'"));
+    assert_eq!(None, Error::IocageCreatedName.remediation());
+}
+
+#[test]
+fn test_is_transient() {
+    assert!(Error::IocageCreatePkgInstall(CmdError::Failed(1)).is_transient());
+    assert!(Error::ExecPkgBootstrap(IocageExecError::from(CmdError::Failed(1))).is_transient());
+    assert!(!Error::IocageCreate(CmdError::Failed(1)).is_transient());
+}
+
+#[test]
+fn test_remediation_of_program_which_could_not_run() {
+    let err = Error::ExecPkgInstall(IocageExecError::from(CmdError::Spawn(
+        "iocage".into(),
+        io::Error::from(io::ErrorKind::NotFound),
+    )));
+
+    assert_eq!(
+        Some("check that iocage is installed and in PATH"),
+        err.remediation().as_deref()
+    );
+}
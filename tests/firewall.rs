@@ -0,0 +1,138 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::firewall::{self, Firewall, JailNet};
+
+fn vnet(ip: &str) -> JailNet {
+    JailNet {
+        ip: ip.parse().unwrap(),
+        vnet: true,
+        uplink: "em0".to_string(),
+        uplink_addrs: vec!["192.168.1.10".parse().unwrap()],
+    }
+}
+
+fn shared(ip: &str) -> JailNet {
+    JailNet {
+        vnet: false,
+        ..vnet(ip)
+    }
+}
+
+const PF_DEFAULT_BLOCK: &str = "\
+block drop all
+pass quick on lo0 all flags S/SA keep state
+pass out on em0 all flags S/SA keep state
+pass in on em0 proto tcp from any to any port = ssh flags S/SA keep state
+";
+
+#[test]
+fn test_check_pf_default_block() {
+    let findings = firewall::check_pf(&vnet("192.168.1.50/24"), PF_DEFAULT_BLOCK, "");
+
+    assert_eq!(1, findings.len());
+    assert_eq!(Firewall::Pf, findings[0].firewall);
+    assert_eq!("pass quick on epair all", findings[0].rule);
+
+    let findings = firewall::check_pf(&shared("192.168.1.50/24"), PF_DEFAULT_BLOCK, "");
+
+    // The host's own outbound rule on its uplink also passes a shared-IP jail's traffic
+    assert!(findings.is_empty());
+}
+
+#[test]
+fn test_check_pf_passed() {
+    let rules = format!(
+        "{}pass quick on epair all flags S/SA keep state\n",
+        PF_DEFAULT_BLOCK
+    );
+    assert!(firewall::check_pf(&vnet("192.168.1.50/24"), &rules, "").is_empty());
+
+    let rules = format!(
+        "{}pass in quick inet from 192.168.1.0/24 to any flags S/SA keep state\n",
+        PF_DEFAULT_BLOCK
+    );
+    assert!(firewall::check_pf(&vnet("192.168.1.50/24"), &rules, "").is_empty());
+
+    // Without a default block, nothing needs to pass the jail's traffic
+    let rules = "pass in on em0 proto tcp from any to any port = ssh flags S/SA keep state\n";
+    assert!(firewall::check_pf(&vnet("192.168.1.50/24"), rules, "").is_empty());
+}
+
+#[test]
+fn test_check_pf_nat() {
+    let findings = firewall::check_pf(&vnet("10.200.0.50/24"), "", "");
+
+    assert_eq!(1, findings.len());
+    assert_eq!(
+        "nat on em0 inet from 10.200.0.0/24 to any -> (em0)",
+        findings[0].rule
+    );
+    assert_eq!(
+        "pf does not translate the jail's private subnet 10.200.0.0/24 to the host's address; \
+        suggested rule: nat on em0 inet from 10.200.0.0/24 to any -> (em0)",
+        findings[0].to_string()
+    );
+
+    let nat = "nat on em0 inet from 10.200.0.0/24 to any -> (em0) round-robin\n";
+    assert!(firewall::check_pf(&vnet("10.200.0.50/24"), "", nat).is_empty());
+
+    // A jail on the uplink's subnet, or with a public address, needs no translation
+    assert!(firewall::check_pf(&vnet("192.168.1.50/24"), "", "").is_empty());
+    assert!(firewall::check_pf(&vnet("203.0.113.5/24"), "", "").is_empty());
+}
+
+const IPFW_DEFAULT_DENY: &str = "\
+00100 allow ip from any to any via lo0
+00200 allow tcp from any to me 22 in via em0
+00300 allow ip from me to any out via em0 keep-state
+65535 deny ip from any to any
+";
+
+#[test]
+fn test_check_ipfw() {
+    let findings = firewall::check_ipfw(&vnet("192.168.1.50/24"), IPFW_DEFAULT_DENY);
+
+    assert_eq!(1, findings.len());
+    assert_eq!(Firewall::Ipfw, findings[0].firewall);
+    assert_eq!("allow ip from any to any via epair*", findings[0].rule);
+
+    // The host's own outbound rule on its uplink also allows a shared-IP jail's traffic
+    assert!(firewall::check_ipfw(&shared("192.168.1.50/24"), IPFW_DEFAULT_DENY).is_empty());
+
+    let rules = "00100 allow ip from any to any via lo0\n65535 deny ip from any to any\n";
+    let findings = firewall::check_ipfw(&shared("192.168.1.50/24"), rules);
+
+    assert_eq!(
+        "allow ip from 192.168.1.50 to any keep-state",
+        findings[0].rule
+    );
+
+    let rules = format!(
+        "00150 allow ip from any to any via epair*\n{}",
+        IPFW_DEFAULT_DENY
+    );
+    assert!(firewall::check_ipfw(&vnet("192.168.1.50/24"), &rules).is_empty());
+
+    let rules = "00100 allow ip from any to any via lo0\n65535 allow ip from any to any\n";
+    assert!(firewall::check_ipfw(&vnet("192.168.1.50/24"), rules).is_empty());
+}
+
+#[test]
+fn test_pf_anchor_rules() {
+    let existing = "pass quick on epair all\n";
+    let rules = [
+        "nat on em0 inet from 10.200.0.0/24 to any -> (em0)",
+        "pass quick on epair all",
+    ];
+
+    assert_eq!(
+        "nat on em0 inet from 10.200.0.0/24 to any -> (em0)\npass quick on epair all\n",
+        firewall::pf_anchor_rules(existing, &rules)
+    );
+    assert_eq!(
+        "pass quick on epair all\n",
+        firewall::pf_anchor_rules("", &rules[1..])
+    );
+}
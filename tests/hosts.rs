@@ -0,0 +1,76 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::hosts;
+
+const HOSTS: &str = "::1\t\t\tlocalhost localhost.my.domain\n\
+                     127.0.0.1\t\tlocalhost localhost.my.domain\n\
+                     192.0.2.1\t\thost.example.com host # the host\n\
+                     192.0.2.50\t\tprinter\n\
+                     10.0.0.6\tdb\t# iocage-provision jail=db\n";
+
+#[test]
+fn test_entry() {
+    assert_eq!(
+        "10.0.0.5\tweb\t# iocage-provision jail=web",
+        hosts::entry("web", "10.0.0.5".parse().unwrap())
+    );
+}
+
+#[test]
+fn test_with_entry_appends() {
+    assert_eq!(
+        format!("{}10.0.0.5\tweb\t# iocage-provision jail=web\n", HOSTS),
+        hosts::with_entry(HOSTS, "web", "10.0.0.5".parse().unwrap())
+    );
+}
+
+#[test]
+fn test_with_entry_replaces() {
+    let contents = hosts::with_entry(HOSTS, "db", "10.0.0.7".parse().unwrap());
+
+    assert!(!contents.contains("10.0.0.6"));
+    assert!(contents.ends_with("10.0.0.7\tdb\t# iocage-provision jail=db\n"));
+}
+
+#[test]
+fn test_without_entry() {
+    let contents = hosts::with_entry(HOSTS, "web", "10.0.0.5".parse().unwrap());
+
+    assert_eq!(HOSTS, hosts::without_entry(&contents, "web"));
+    assert_eq!(HOSTS, hosts::without_entry(HOSTS, "missing"));
+}
+
+#[test]
+fn test_without_entry_keeps_similar_names() {
+    let contents = hosts::with_entry(HOSTS, "db2", "10.0.0.8".parse().unwrap());
+
+    assert_eq!(HOSTS, hosts::without_entry(&contents, "db2"));
+    assert!(hosts::without_entry(&contents, "db").contains("jail=db2"));
+}
+
+#[test]
+fn test_seed() {
+    assert_eq!(
+        "192.0.2.1\thost.example.com host\t# iocage-provision host\n\
+         10.0.0.6\tdb\t# iocage-provision jail=db\n",
+        hosts::seed(HOSTS, "host.example.com", "web")
+    );
+}
+
+#[test]
+fn test_seed_skips_own_entry() {
+    assert_eq!(
+        "192.0.2.1\thost.example.com host\t# iocage-provision host\n",
+        hosts::seed(HOSTS, "host", "db")
+    );
+}
+
+#[test]
+fn test_seed_unknown_hostname() {
+    assert_eq!(
+        "10.0.0.6\tdb\t# iocage-provision jail=db\n",
+        hosts::seed(HOSTS, "", "web")
+    );
+}
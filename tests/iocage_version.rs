@@ -0,0 +1,42 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::IocageVersion;
+
+fn version(s: &str) -> IocageVersion {
+    s.parse().expect("version should parse")
+}
+
+#[test]
+fn test_parse_version_output() {
+    assert_eq!(
+        IocageVersion { major: 1, minor: 2 },
+        version("1.2 RELEASE\n")
+    );
+    assert_eq!(
+        IocageVersion { major: 1, minor: 7 },
+        version("Version\t1.7 RELEASE 2023/06/01")
+    );
+    assert_eq!(IocageVersion { major: 2, minor: 0 }, version("2"));
+    assert!("iocage: command not found"
+        .parse::<IocageVersion>()
+        .is_err());
+    assert!("x.y".parse::<IocageVersion>().is_err());
+}
+
+#[test]
+fn test_supported_versions() {
+    assert!(!version("1.1").is_supported());
+    assert!(version("1.2").is_supported());
+    assert!(version("1.10").is_supported());
+    assert!(!version("2.0").is_supported());
+    assert!(!version("0.9").is_supported());
+}
+
+#[test]
+fn test_version_flags() {
+    assert!(!version("1.1").has_thickjail());
+    assert!(version("1.2").has_thickjail());
+    assert!(version("1.7").has_thickjail());
+}
@@ -0,0 +1,320 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![cfg(feature = "manifest")]
+
+use iocage_provision::{JailSpec, Manifest, ManifestError, SysvIpc, MANIFEST_SCHEMA};
+use std::collections::BTreeSet;
+use std::net::{IpAddr, Ipv4Addr};
+
+fn gateway() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))
+}
+
+fn release() -> String {
+    String::from("13.0-RELEASE")
+}
+
+fn parse(src: &str) -> Result<Manifest, ManifestError> {
+    Manifest::from_toml(src, gateway, release)
+}
+
+#[test]
+fn test_parse_jails_with_defaults() {
+    let manifest = parse(
+        r#"
+[[jail]]
+name = "db"
+ip = "10.0.0.20/24"
+sysvipc = "new"
+
+[[jail]]
+name = "web"
+ip = "10.0.0.21/24"
+gateway = "10.0.0.254"
+release = "12.2-RELEASE"
+depends = ["db"]
+"#,
+    )
+    .expect("manifest should be valid");
+
+    assert_eq!(2, manifest.jails.len());
+    let db = &manifest.jails[0];
+    assert_eq!("db", db.name);
+    assert_eq!(gateway(), db.gateway);
+    assert_eq!(release(), db.release);
+    assert_eq!(Some(SysvIpc::New), db.sysvipc);
+    assert!(db.create_home);
+    let web = &manifest.jails[1];
+    assert_eq!("10.0.0.254".parse::<IpAddr>().unwrap(), web.gateway);
+    assert_eq!("12.2-RELEASE", web.release);
+    assert_eq!(vec![String::from("db")], web.depends);
+}
+
+#[test]
+fn test_defaults_not_computed_when_set() {
+    let manifest = Manifest::from_toml(
+        r#"
+[[jail]]
+name = "db"
+ip = "10.0.0.20/24"
+gateway = "10.0.0.1"
+release = "13.0-RELEASE"
+"#,
+        || panic!("gateway default should not be computed"),
+        || panic!("release default should not be computed"),
+    )
+    .expect("manifest should be valid");
+
+    assert_eq!(1, manifest.jails.len());
+}
+
+#[test]
+fn test_syntax_error_line_and_column() {
+    match parse("[[jail]]\nname = \"db\"\nip = \n") {
+        Err(ManifestError::Syntax { line, column, .. }) => {
+            assert_eq!(3, line);
+            assert_eq!(6, column);
+        }
+        other => panic!("expected syntax error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_unknown_key_suggestion() {
+    let err = parse(
+        r#"
+[[jail]]
+name = "db"
+ip = "10.0.0.20/24"
+
+[[jail]]
+name = "web"
+ip = "10.0.0.21/24"
+ssh_servce = true
+"#,
+    )
+    .unwrap_err();
+
+    match err {
+        ManifestError::Invalid {
+            ref key,
+            line,
+            ref suggestion,
+            ..
+        } => {
+            assert_eq!("jail[1].ssh_servce", key);
+            assert_eq!(9, line);
+            assert_eq!(Some("ssh_service"), suggestion.as_deref());
+        }
+        ref other => panic!("expected invalid value error, got {:?}", other),
+    }
+    assert!(err.to_string().contains("did you mean `ssh_service`?"));
+}
+
+#[test]
+fn test_unknown_variant_suggestion() {
+    match parse("[[jail]]\nname = \"db\"\nip = \"10.0.0.20/24\"\nsysvipc = \"nwe\"\n") {
+        Err(ManifestError::Invalid {
+            key,
+            line,
+            suggestion,
+            ..
+        }) => {
+            assert_eq!("jail[0].sysvipc", key);
+            assert_eq!(4, line);
+            assert_eq!(Some("new"), suggestion.as_deref());
+        }
+        other => panic!("expected invalid value error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_missing_required_key() {
+    match parse("[[jail]]\nname = \"db\"\n") {
+        Err(ManifestError::Invalid {
+            key, line, message, ..
+        }) => {
+            assert_eq!("jail[0]", key);
+            assert_eq!(1, line);
+            assert!(message.contains("`ip`"), "message={}", message);
+        }
+        other => panic!("expected invalid value error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_duplicate_names() {
+    match parse(
+        "[[jail]]\nname = \"db\"\nip = \"10.0.0.20/24\"\n\n\
+        [[jail]]\nname = \"db\"\nip = \"10.0.0.21/24\"\n",
+    ) {
+        Err(ManifestError::DuplicateName { name, line }) => {
+            assert_eq!("db", name);
+            assert_eq!(6, line);
+        }
+        other => panic!("expected duplicate name error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_template_variables() {
+    std::env::set_var("IOCAGE_PROVISION_TEST_SITE", "example.com");
+    let manifest = parse(
+        r#"
+[[jail]]
+name = "web-{{ env.IOCAGE_PROVISION_TEST_SITE }}"
+ip = "10.0.0.21/24"
+description = "{{ name }} at {{ ip.host }}/{{ ip.prefix }} via {{gateway}} on {{ release }}"
+cron_entries = ["@daily /usr/local/bin/backup {{ ip }}"]
+"#,
+    )
+    .expect("manifest should be valid");
+
+    let web = &manifest.jails[0];
+    assert_eq!("web-example.com", web.name);
+    assert_eq!(
+        Some("web-example.com at 10.0.0.21/24 via 10.0.0.1 on 13.0-RELEASE"),
+        web.description.as_deref()
+    );
+    assert_eq!(
+        vec![String::from("@daily /usr/local/bin/backup 10.0.0.21/24")],
+        web.cron_entries
+    );
+}
+
+#[test]
+fn test_template_unknown_variable() {
+    match parse("[[jail]]\nname = \"db\"\nip = \"10.0.0.20/24\"\ndescription = \"{{ nmae }}\"\n") {
+        Err(ManifestError::Template { key, line, message }) => {
+            assert_eq!("jail[0].description", key);
+            assert_eq!(4, line);
+            assert!(message.contains("`nmae`"), "message={}", message);
+        }
+        other => panic!("expected template error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_template_identity_only_env() {
+    match parse("[[jail]]\nname = \"db-{{ ip.host }}\"\nip = \"10.0.0.20/24\"\n") {
+        Err(ManifestError::Template { key, .. }) => assert_eq!("jail[0].name", key),
+        other => panic!("expected template error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_extends_profile_and_jail() {
+    let manifest = parse(
+        r#"
+[profile.base]
+cron_entries = ["@daily a"]
+ssh_service = true
+
+[profile.base-web]
+extends = "base"
+cron_entries = ["@daily b"]
+zfs_props = ["compression=lz4"]
+description = "{{ name }} web server"
+base_props = { allow_raw_sockets = "1" }
+
+[[jail]]
+name = "web"
+ip = "10.0.0.21/24"
+extends = "base-web"
+cron_entries = ["@daily c"]
+base_props = { allow_mlock = "1" }
+
+[[jail]]
+name = "web2"
+ip = "10.0.0.22/24"
+extends = "web"
+ssh_service = false
+"#,
+    )
+    .expect("manifest should be valid");
+
+    let web = &manifest.jails[0];
+    assert_eq!(vec!["@daily a", "@daily b", "@daily c"], web.cron_entries);
+    assert_eq!(vec!["compression=lz4"], web.zfs_props);
+    assert!(web.ssh_service);
+    assert_eq!(Some("web web server"), web.description.as_deref());
+    assert_eq!(2, web.base_props.len());
+    let web2 = &manifest.jails[1];
+    assert_eq!("web2", web2.name);
+    assert_eq!("10.0.0.22/24", web2.ip.to_string());
+    assert_eq!(vec!["@daily a", "@daily b", "@daily c"], web2.cron_entries);
+    assert!(!web2.ssh_service);
+    assert_eq!(Some("web2 web server"), web2.description.as_deref());
+}
+
+#[test]
+fn test_extends_unknown_suggestion() {
+    match parse(
+        "[profile.base-web]\nssh_service = true\n\n\
+        [[jail]]\nname = \"web\"\nip = \"10.0.0.21/24\"\nextends = \"base-wbe\"\n",
+    ) {
+        Err(ManifestError::Invalid {
+            key,
+            line,
+            suggestion,
+            ..
+        }) => {
+            assert_eq!("jail[0].extends", key);
+            assert_eq!(7, line);
+            assert_eq!(Some("base-web"), suggestion.as_deref());
+        }
+        other => panic!("expected invalid value error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_extends_circular() {
+    match parse(
+        "[profile.a]\nextends = \"b\"\n\n[profile.b]\nextends = \"a\"\n\n\
+        [[jail]]\nname = \"web\"\nip = \"10.0.0.21/24\"\nextends = \"a\"\n",
+    ) {
+        Err(ManifestError::Invalid {
+            key, line, message, ..
+        }) => {
+            assert_eq!("profile.b.extends", key);
+            assert_eq!(5, line);
+            assert!(
+                message.contains("web -> a -> b -> a"),
+                "message={}",
+                message
+            );
+        }
+        other => panic!("expected invalid value error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_schema_covers_jail_spec() {
+    let schema: serde_json::Value =
+        serde_json::from_str(MANIFEST_SCHEMA).expect("schema should be valid JSON");
+    let schema_keys = schema["definitions"]["jail"]["properties"]
+        .as_object()
+        .expect("jail properties should be an object")
+        .keys()
+        .filter(|key| *key != "extends")
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    let spec = JailSpec::new(
+        String::from("db"),
+        "10.0.0.20/24".parse().unwrap(),
+        gateway(),
+        release(),
+    );
+    let spec_keys = serde_json::to_value(&spec)
+        .unwrap()
+        .as_object()
+        .unwrap()
+        .keys()
+        .cloned()
+        .collect::<BTreeSet<_>>();
+
+    assert_eq!(spec_keys, schema_keys);
+}
@@ -0,0 +1,18 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![cfg(feature = "mock-host")]
+
+use iocage_provision::{default_release, ensure_privileged, ensure_root};
+
+#[test]
+fn test_mock_host_is_root() {
+    assert!(ensure_root().is_ok());
+    assert!(ensure_privileged().is_ok());
+}
+
+#[test]
+fn test_mock_host_default_release() {
+    assert_eq!("13.0-RELEASE", default_release());
+}
@@ -0,0 +1,372 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::{parse, JailInfo, PropertyChange, StaleState};
+use std::fs;
+use std::path::Path;
+
+fn fixture(name: &str) -> String {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("fixture {} should be readable: {}", path.display(), err))
+}
+
+fn jail(jid: Option<u32>, name: &str, state: &str, release: &str, ip4: &str) -> JailInfo {
+    JailInfo {
+        jid,
+        name: name.to_string(),
+        state: state.to_string(),
+        release: release.to_string(),
+        ip4: ip4.to_string(),
+        description: None,
+    }
+}
+
+#[test]
+fn test_list_short_and_long_formats_agree() {
+    for version in &["iocage-1.2", "iocage-1.7"] {
+        let short = parse::list(&fixture(&format!("{}/list.txt", version)));
+        let long = parse::list(&fixture(&format!("{}/list-long.txt", version)));
+
+        assert_eq!(2, short.len(), "version={}", version);
+        assert_eq!(short, long, "version={}", version);
+    }
+}
+
+#[test]
+fn test_list_fields() {
+    assert_eq!(
+        vec![
+            jail(Some(1), "db", "up", "13.0-RELEASE-p4", "vnet0|10.0.0.20/24"),
+            jail(None, "web", "down", "13.0-RELEASE-p4", "vnet0|10.0.0.21/24"),
+        ],
+        parse::list(&fixture("iocage-1.2/list-long.txt"))
+    );
+    assert_eq!(
+        jail(
+            None,
+            "dhcp",
+            "down",
+            "13.2-RELEASE-p1",
+            "DHCP (not running)"
+        ),
+        parse::list(&fixture("iocage-1.7/list.txt"))[1]
+    );
+}
+
+#[test]
+fn test_list_skips_malformed_lines() {
+    assert!(parse::list("").is_empty());
+    assert!(parse::list("not a jail\n1\tdb\n").is_empty());
+}
+
+#[test]
+fn test_properties() {
+    let props = parse::properties(&fixture("iocage-1.7/get-all.txt"));
+
+    assert_eq!(10, props.len());
+    assert_eq!("10.0.0.1", props["defaultrouter"]);
+    assert_eq!("vnet0|fe80::1/64", props["ip6_addr"]);
+    assert_eq!("billing DB, owner: platform", props["notes"]);
+}
+
+#[test]
+fn test_property() {
+    assert_eq!(None, parse::property("none\n"));
+    assert_eq!(None, parse::property(""));
+    assert_eq!(
+        Some(String::from("13.2-RELEASE")),
+        parse::property("13.2-RELEASE\n")
+    );
+}
+
+#[test]
+fn test_created_name() {
+    let stdout = "Fetching: 13.0-RELEASE\n\
+        ab12cd34 successfully created!\n\
+        Installing packages\n";
+
+    assert_eq!(Some(String::from("ab12cd34")), parse::created_name(stdout));
+    assert_eq!(None, parse::created_name("Creating jail\n"));
+}
+
+#[test]
+fn test_percentage() {
+    assert_eq!(
+        Some(45),
+        parse::percentage("base.txz  45% of 180 MB   12 MBps 00:08")
+    );
+    assert_eq!(
+        Some(100),
+        parse::percentage("[1/3] Fetching curl-7.77.0.txz: .......... 100%")
+    );
+    assert_eq!(Some(12), parse::percentage("12.5% done"));
+    assert_eq!(Some(80), parse::percentage("step 1 at 20%, step 2 at 80%"));
+    assert_eq!(None, parse::percentage("disk usage 250%"));
+    assert_eq!(None, parse::percentage("Installing packages"));
+    assert_eq!(None, parse::percentage("% of nothing"));
+}
+
+#[test]
+fn test_cpu_list() {
+    assert_eq!(Some(vec![0, 1, 2, 3]), parse::cpu_list("0-3"));
+    assert_eq!(Some(vec![0, 2, 4, 5, 6]), parse::cpu_list("4-6,0,2"));
+    assert_eq!(Some(vec![1, 2]), parse::cpu_list("1, 1-2"));
+    assert_eq!(Some(vec![7]), parse::cpu_list("7"));
+    assert_eq!(None, parse::cpu_list(""));
+    assert_eq!(None, parse::cpu_list("3-1"));
+    assert_eq!(None, parse::cpu_list("0,,1"));
+    assert_eq!(None, parse::cpu_list("all"));
+}
+
+#[test]
+fn test_bridge_members() {
+    let stdout = "bridge0: flags=8843<UP,BROADCAST,RUNNING,SIMPLEX,MULTICAST> metric 0 mtu 1500
+\tether 58:9c:fc:10:ff:c1
+\tid 00:00:00:00:00:00 priority 32768 hellotime 2 fwddelay 15
+\tmember: vnet0.1 flags=143<LEARNING,DISCOVER,AUTOEDGE,AUTOPTP>
+\t        ifmaxaddr 0 port 4 priority 128 path cost 2000
+\tmember: em0 flags=143<LEARNING,DISCOVER,AUTOEDGE,AUTOPTP>
+\t        ifmaxaddr 0 port 1 priority 128 path cost 20000
+\tgroups: bridge
+";
+
+    assert_eq!(vec!["vnet0.1", "em0"], parse::bridge_members(stdout));
+    assert!(parse::bridge_members("bridge0: flags=8802<BROADCAST,SIMPLEX,MULTICAST>\n").is_empty());
+}
+
+#[test]
+fn test_interface_addrs() {
+    let stdout = "em0: flags=8863<UP,BROADCAST,RUNNING,SIMPLEX,MULTICAST> metric 0 mtu 1500
+\tether 58:9c:fc:10:ff:c1
+\tinet 192.168.1.10 netmask 0xffffff00 broadcast 192.168.1.255
+\tinet6 fe80::5a9c:fcff:fe10:ffc1%em0 prefixlen 64 scopeid 0x1
+";
+
+    assert_eq!(
+        vec![
+            "192.168.1.10".parse::<std::net::IpAddr>().unwrap(),
+            "fe80::5a9c:fcff:fe10:ffc1".parse().unwrap()
+        ],
+        parse::interface_addrs(stdout)
+    );
+}
+
+#[test]
+fn test_pkglist() {
+    assert_eq!(
+        Ok(vec!["nginx".to_string(), "git".to_string()]),
+        parse::pkglist(r#"{"pkgs": ["nginx", "git"], "other": 1}"#)
+    );
+    assert_eq!(Ok(Vec::new()), parse::pkglist(r#"{"pkgs": []}"#));
+
+    assert_eq!(
+        Err("key 'pkgs' is missing".to_string()),
+        parse::pkglist(r#"{"packages": ["nginx"]}"#)
+    );
+    assert_eq!(
+        Err("key 'pkgs' must be an array".to_string()),
+        parse::pkglist(r#"{"pkgs": "nginx"}"#)
+    );
+    assert_eq!(
+        Err("key 'pkgs[1]' must be a package name".to_string()),
+        parse::pkglist(r#"{"pkgs": ["nginx", 2]}"#)
+    );
+    assert_eq!(
+        Err("top level must be an object".to_string()),
+        parse::pkglist(r#"["nginx"]"#)
+    );
+    assert_eq!(
+        Err("invalid JSON at line 2, column 1".to_string()),
+        parse::pkglist("{\"pkgs\": [\"nginx\",\n}")
+    );
+}
+
+#[test]
+fn test_iocage_package() {
+    assert_eq!(
+        Some("py311-iocage".to_string()),
+        parse::iocage_package("py39-iocage\npy311-iocage\npy310-iocage\n")
+    );
+    assert_eq!(None, parse::iocage_package(""));
+    assert_eq!(None, parse::iocage_package("py311-iocage-devel\n"));
+}
+
+#[test]
+fn test_stale_state() {
+    assert_eq!(
+        Some(StaleState::DyingJail),
+        parse::stale_state("jail: ioc-web: jail_set: Resource temporarily unavailable\n")
+    );
+    assert_eq!(
+        Some(StaleState::MissingConfig),
+        parse::stale_state("web is missing its configuration, please destroy web and recreate\n")
+    );
+    assert_eq!(
+        Some(StaleState::StaleInterface),
+        parse::stale_state("ifconfig: SIOCIFCREATE2: File exists\n")
+    );
+    assert_eq!(
+        None,
+        parse::stale_state("RELEASE 13.0-RELEASE not found!\n")
+    );
+}
+
+#[test]
+fn test_create_pkg_failure() {
+    assert!(parse::create_pkg_failure(
+        "Testing Host DNS response to pkg.FreeBSD.org\nInstalling pkg... \npkg: No packages available to install matching 'sudo'\n"
+    ));
+    assert!(!parse::create_pkg_failure(
+        "RELEASE 13.0-RELEASE not found!\n"
+    ));
+}
+
+#[test]
+fn test_stale_vnet_interfaces() {
+    assert_eq!(
+        vec!["vnet0.7".to_string()],
+        parse::stale_vnet_interfaces(
+            "em0 lo0 bridge0 vnet0.3 vnet0.7 vnet0.x epair0a\n",
+            "1\n3\n"
+        )
+    );
+}
+
+#[test]
+fn test_tmpfs() {
+    assert_eq!(
+        Ok(("/tmp".to_string(), Some("512m".to_string()))),
+        parse::tmpfs("/tmp=512m")
+    );
+    assert_eq!(
+        Ok(("/var/run".to_string(), None)),
+        parse::tmpfs("/var/run/")
+    );
+    assert_eq!(
+        Ok(("/scratch".to_string(), Some("1048576".to_string()))),
+        parse::tmpfs("/scratch=1048576")
+    );
+
+    assert!(parse::tmpfs("tmp=512m").is_err());
+    assert!(parse::tmpfs("/my tmp").is_err());
+    assert!(parse::tmpfs("/tmp=").is_err());
+    assert!(parse::tmpfs("/tmp=512mb").is_err());
+    assert!(parse::tmpfs("/tmp=big").is_err());
+}
+
+#[test]
+fn test_is_zfs_size() {
+    for size in &["20G", "5g", "1.5T", "512M", "100GB", "1048576"] {
+        assert!(parse::is_zfs_size(size), "{} should be a size", size);
+    }
+    for size in &["", "G", "20X", "-1G", ".5G", "20 G", "20GG"] {
+        assert!(!parse::is_zfs_size(size), "{} should not be a size", size);
+    }
+}
+
+#[test]
+fn test_duration() {
+    assert_eq!(Ok(90), parse::duration("90s"));
+    assert_eq!(Ok(30 * 60), parse::duration("30m"));
+    assert_eq!(Ok(72 * 60 * 60), parse::duration("72h"));
+    assert_eq!(Ok(7 * 24 * 60 * 60), parse::duration("7d"));
+    assert_eq!(Ok(2 * 7 * 24 * 60 * 60), parse::duration("2w"));
+}
+
+#[test]
+fn test_host_port() {
+    assert_eq!(
+        ("mail.example.com", None),
+        parse::host_port("mail.example.com")
+    );
+    assert_eq!(
+        ("mail.example.com", Some("587")),
+        parse::host_port("mail.example.com:587")
+    );
+    assert_eq!(("10.0.0.25", Some("25")), parse::host_port("10.0.0.25:25"));
+    assert_eq!(("2001:db8::25", None), parse::host_port("2001:db8::25"));
+    assert_eq!(("2001:db8::25", None), parse::host_port("[2001:db8::25]"));
+    assert_eq!(
+        ("2001:db8::25", Some("587")),
+        parse::host_port("[2001:db8::25]:587")
+    );
+    assert_eq!(("mail:smtp", None), parse::host_port("mail:smtp"));
+}
+
+#[test]
+fn test_duration_invalid() {
+    for duration in &["", "72", "h", "0h", "-1h", "1.5h", "72 h", "72hours", "3y"] {
+        assert!(parse::duration(duration).is_err(), "{}", duration);
+    }
+}
+
+#[test]
+fn test_duration_too_large() {
+    assert_eq!(Ok(parse::MAX_DURATION), parse::duration("36500d"));
+    assert!(parse::duration("36501d").is_err());
+    assert!(parse::duration("18000000000000000000s").is_err());
+}
+
+#[test]
+fn test_address_interface() {
+    assert_eq!(Some("vnet0"), parse::address_interface("vnet0|10.0.0.5/24"));
+    assert_eq!(
+        Some("em0"),
+        parse::address_interface("em0|10.0.0.5/24,em0|10.0.0.6/24")
+    );
+    assert_eq!(None, parse::address_interface("DHCP (not running)"));
+    assert_eq!(None, parse::address_interface("-"));
+}
+
+#[test]
+fn test_is_identity_property() {
+    assert!(iocage_provision::is_identity_property("ip4_addr"));
+    assert!(iocage_provision::is_identity_property("vnet0_mac"));
+    assert!(!iocage_provision::is_identity_property("resolver"));
+    assert!(!iocage_provision::is_identity_property("defaultrouter"));
+}
+
+#[test]
+fn test_property_changes() {
+    let before = parse::properties("boot:on\njid:1\nresolver:none\nsysvshm:disable\n");
+    let after = parse::properties("boot:on\njid:7\nresolver:nameserver 10.0.0.53\nallow_tun:1\n");
+
+    let changes = iocage_provision::property_changes(&before, &after);
+
+    assert_eq!(
+        vec![
+            "allow_tun: (unset) -> 1",
+            "resolver: none -> nameserver 10.0.0.53",
+            "sysvshm: disable -> (unset)",
+        ],
+        changes.iter().map(ToString::to_string).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        PropertyChange {
+            property: String::from("allow_tun"),
+            before: None,
+            after: Some(String::from("1")),
+        },
+        changes[0]
+    );
+}
+
+#[test]
+fn test_enabled() {
+    assert!(parse::enabled("1\n"));
+    assert!(parse::enabled("on\n"));
+    assert!(!parse::enabled("0\n"));
+    assert!(!parse::enabled("off\n"));
+    assert!(!parse::enabled(""));
+}
+
+#[test]
+fn test_loadavg() {
+    assert_eq!(Some(0.52), parse::loadavg("{ 0.52 0.41 0.38 }\n"));
+    assert_eq!(Some(12.0), parse::loadavg("{ 12.00 9.75 4.10 }"));
+    assert_eq!(None, parse::loadavg(""));
+}
@@ -0,0 +1,24 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+
+#[test]
+fn test_pkglist_json() {
+    assert_eq!(
+        r#"{"pkgs":["sudo","bash"]}"#,
+        iocage_provision::pkglist_json(&["sudo", "bash"], &BTreeMap::new())
+    );
+
+    let mut extra = BTreeMap::new();
+    extra.insert("pkgs".to_string(), serde_json::json!(["ignored"]));
+    extra.insert(
+        "repo".to_string(),
+        serde_json::json!({ "url": "https://pkg.example.com" }),
+    );
+    assert_eq!(
+        r#"{"pkgs":["a \"quoted\" name"],"repo":{"url":"https://pkg.example.com"}}"#,
+        iocage_provision::pkglist_json(&["a \"quoted\" name"], &extra)
+    );
+}
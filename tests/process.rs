@@ -0,0 +1,19 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::{CmdError, Error};
+
+// Terminating the children is process-wide, so this is the only test in this file
+#[test]
+fn test_no_commands_spawned_after_terminate() {
+    assert!(!iocage_provision::cancelled());
+
+    iocage_provision::terminate_children();
+
+    assert!(iocage_provision::cancelled());
+    match iocage_provision::iocage_version() {
+        Err(Error::IocageVersion(CmdError::Cancelled(program))) => assert_eq!("iocage", program),
+        other => panic!("expected a cancelled command, got {:?}", other),
+    }
+}
@@ -0,0 +1,50 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::recipe::Recipe;
+
+#[test]
+fn test_from_str() {
+    assert_eq!(Recipe::CiRunner, "ci-runner".parse().unwrap());
+    assert_eq!("ci-runner", Recipe::CiRunner.as_str());
+    assert_eq!(Recipe::Postgres, "postgres".parse().unwrap());
+    assert_eq!("postgres", Recipe::Postgres.as_str());
+    assert!("mysql".parse::<Recipe>().is_err());
+}
+
+#[test]
+fn test_ci_runner() {
+    let recipe = Recipe::CiRunner;
+
+    assert!(recipe.properties().is_empty());
+    assert!(recipe.packages().contains(&"git"));
+    assert!(recipe.packages().contains(&"gmake"));
+    assert_eq!(None, recipe.dataset());
+}
+
+#[test]
+fn test_postgres() {
+    let recipe = Recipe::Postgres;
+
+    assert_eq!(&["sysvshm=new", "sysvsem=new"], recipe.properties());
+    assert_eq!(&["postgresql16-server"], recipe.packages());
+
+    let data = recipe.dataset().unwrap();
+    assert_eq!("data/postgres", data.name);
+    assert_eq!("/var/db/postgres", data.mountpoint);
+    assert!(data.props.contains(&"recordsize=16K"));
+    assert!(data.props.contains(&"compression=lz4"));
+}
+
+#[test]
+fn test_deserialize() {
+    #[derive(serde::Deserialize)]
+    struct Jail {
+        recipes: Vec<Recipe>,
+    }
+
+    let jail: Jail = serde_json::from_str(r#"{"recipes": ["postgres"]}"#).unwrap();
+
+    assert_eq!(vec![Recipe::Postgres], jail.recipes);
+}
@@ -0,0 +1,46 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::{redact, register_secret, JailSpec, REDACTED};
+
+#[test]
+fn test_redact_registered_secrets() {
+    register_secret("s3cr3t-token");
+    register_secret("-----BEGIN KEY-----\nAAAAkeydata\n-----END KEY-----\n");
+
+    assert_eq!(
+        format!("https://{}@example.com", REDACTED),
+        redact("https://s3cr3t-token@example.com")
+    );
+    assert_eq!(format!("line: {}", REDACTED), redact("line: AAAAkeydata"));
+    assert_eq!("nothing secret", redact("nothing secret"));
+}
+
+#[test]
+fn test_redact_escaped_secrets() {
+    register_secret("pa\"ss\\word");
+
+    let debug = format!(
+        "{:?}",
+        std::process::Command::new("login").arg("pa\"ss\\word")
+    );
+    assert_eq!(format!("\"login\" \"{}\"", REDACTED), redact(&debug));
+    let json = serde_json::to_string("pa\"ss\\word").unwrap();
+    assert_eq!(format!("\"{}\"", REDACTED), redact(&json));
+}
+
+#[test]
+fn test_spec_secrets_serialized_masked() {
+    let mut spec = JailSpec::new(
+        "db",
+        "10.0.0.20/24".parse().unwrap(),
+        "10.0.0.1".parse().unwrap(),
+        "13.0-RELEASE",
+    );
+    spec.secrets.push(String::from("hunter2"));
+
+    let json = serde_json::to_string(&spec).unwrap();
+    assert!(!json.contains("hunter2"), "json={}", json);
+    assert!(json.contains(REDACTED), "json={}", json);
+}
@@ -0,0 +1,62 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::release;
+use std::path::Path;
+
+#[test]
+fn test_local_path() {
+    assert_eq!(
+        Some(Path::new("/usr/local/my-release")),
+        release::local_path("path:/usr/local/my-release")
+    );
+    assert_eq!(None, release::local_path("13.0-RELEASE"));
+}
+
+#[test]
+fn test_local_name() {
+    assert_eq!(
+        Some("my-release".to_string()),
+        release::local_name(Path::new("/usr/local/my-release"))
+    );
+    assert_eq!(
+        Some("13-STABLE-custom".to_string()),
+        release::local_name(Path::new("/tmp/13-STABLE-custom.txz"))
+    );
+    assert_eq!(
+        Some("world".to_string()),
+        release::local_name(Path::new("world.tar.gz"))
+    );
+    assert_eq!(None, release::local_name(Path::new("/")));
+    assert_eq!(None, release::local_name(Path::new("/tmp/.txz")));
+}
+
+#[test]
+fn test_pkgbase_branch() {
+    assert_eq!(
+        Some((13, "base_release_2".to_string())),
+        release::pkgbase_branch("13.2-RELEASE")
+    );
+    assert_eq!(
+        Some((15, "base_latest".to_string())),
+        release::pkgbase_branch("15.0-CURRENT")
+    );
+    assert_eq!(None, release::pkgbase_branch("path:/usr/local/my-release"));
+    assert_eq!(None, release::pkgbase_branch("13-RELEASE"));
+}
+
+#[test]
+fn test_pkgbase_name() {
+    assert_eq!(
+        "14.0-RELEASE-pkgbase",
+        release::pkgbase_name("14.0-RELEASE", &[])
+    );
+
+    let packages = vec!["FreeBSD-runtime".to_string(), "FreeBSD-rc".to_string()];
+    let reordered = vec!["FreeBSD-rc".to_string(), "FreeBSD-runtime".to_string()];
+    let name = release::pkgbase_name("14.0-RELEASE", &packages);
+    assert!(name.starts_with("14.0-RELEASE-pkgbase-"));
+    assert_eq!(name, release::pkgbase_name("14.0-RELEASE", &reordered));
+    assert_ne!(name, release::pkgbase_name("14.0-RELEASE", &packages[..1]));
+}
@@ -0,0 +1,58 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::{fake_program, prepend_path};
+use iocage_provision::JailSpec;
+use std::fs;
+
+// The program search path is process-wide, so this is the only test in this file
+#[test]
+fn test_run_log_records_commands_and_failure() {
+    let dir = tempfile::tempdir().unwrap();
+    fake_program(dir.path(), "iocage", "echo zroot\n");
+    fake_program(
+        dir.path(),
+        "zfs",
+        r#"case "$*" in
+  *"-t filesystem"*) printf 'zroot/iocage\nzroot/iocage/jails\n';;
+esac
+"#,
+    );
+    prepend_path(dir.path());
+    let run_log = dir.path().join("log/run.json");
+
+    iocage_provision::start_run_log();
+    let err = iocage_provision::rollback_checkpoint("missing").unwrap_err();
+    let spec = JailSpec::new(
+        "ferris",
+        "10.0.0.25/24".parse().unwrap(),
+        "10.0.0.1".parse().unwrap(),
+        "13.2-RELEASE",
+    );
+    iocage_provision::write_run_log(&run_log, &spec, Err(&err)).unwrap();
+
+    let record: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&run_log).unwrap()).unwrap();
+    assert_eq!("ferris", record["spec"]["name"]);
+    assert_eq!(60, record["spec"]["network_timeout"]);
+    let commands = record["commands"].as_array().unwrap();
+    assert_eq!(
+        vec!["iocage", "zfs", "zfs"],
+        commands
+            .iter()
+            .map(|command| command["argv"][0].as_str().unwrap())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!("zroot\n", commands[0]["output"]);
+    assert_eq!(0, commands[0]["exit_code"]);
+    assert_eq!("success", commands[0]["result"]);
+    assert!(commands[0]["started"].as_str().unwrap().ends_with('Z'));
+    assert_eq!("failure", record["result"]["status"]);
+    assert_eq!("not-found", record["result"]["kind"]);
+    assert_eq!(err.to_string(), record["result"]["error"]);
+}
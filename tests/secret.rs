@@ -0,0 +1,67 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::secret::{self, EnvSource, FileSource, SecretSource};
+use std::env;
+use std::fs;
+
+#[test]
+fn test_env_source() {
+    env::set_var("IOCAGE_PROVISION_TEST_TOKEN", "s3cret-token");
+
+    assert_eq!(
+        Some(String::from("s3cret-token")),
+        EnvSource.secret("IOCAGE_PROVISION_TEST_TOKEN").unwrap()
+    );
+    assert_eq!(
+        None,
+        EnvSource.secret("IOCAGE_PROVISION_TEST_MISSING").unwrap()
+    );
+}
+
+#[test]
+fn test_file_source() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("db-password"), "hunter22\n").unwrap();
+    let source = FileSource::new(dir.path());
+
+    assert_eq!(
+        Some(String::from("hunter22")),
+        source.secret("db-password").unwrap()
+    );
+    assert_eq!(None, source.secret("missing").unwrap());
+    assert_eq!(None, source.secret("../db-password").unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec_source() {
+    use iocage_provision::secret::ExecSource;
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let program = dir.path().join("vault-read");
+    fs::write(
+        &program,
+        "#!/bin/sh\n[ \"$1\" = api-token ] || exit 1\necho token-for-$1\n",
+    )
+    .unwrap();
+    fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+    let source = ExecSource::new(&program);
+
+    assert_eq!(
+        Some(String::from("token-for-api-token")),
+        source.secret("api-token").unwrap()
+    );
+    assert_eq!(None, source.secret("missing").unwrap());
+}
+
+#[test]
+fn test_parse_source() {
+    assert!(secret::parse_source("env").is_ok());
+    assert!(secret::parse_source("file:/usr/local/etc/secrets").is_ok());
+    assert!(secret::parse_source("exec:/usr/local/bin/vault-read").is_ok());
+    assert!(secret::parse_source("file:").is_err());
+    assert!(secret::parse_source("vault").is_err());
+}
@@ -0,0 +1,16 @@
+pw useradd -n deploy -c 'Automation user' -m -s /bin/sh -h -
+ssh_dir="$(pw usershow -n deploy | cut -d: -f9)/.ssh"
+mkdir -p "$ssh_dir"
+cd "$ssh_dir"
+cat <<'_IOCAGE_PROVISION_EOF_' >authorized_keys
+ssh-ed25519 AAAAC3Nza deploy@example.com
+_IOCAGE_PROVISION_EOF_
+chmod 0700 .
+chmod 0600 authorized_keys
+chown -R deploy: .
+cd /
+cat <<'_IOCAGE_PROVISION_EOF_' >/usr/local/etc/sudoers.d/deploy.tmp
+deploy ALL=(root) NOPASSWD: /usr/sbin/service nginx reload, /bin/echo a\,b\:c\=d\\e
+_IOCAGE_PROVISION_EOF_
+chmod 0440 /usr/local/etc/sudoers.d/deploy.tmp
+if visudo -cf /usr/local/etc/sudoers.d/deploy.tmp >/dev/null; then mv /usr/local/etc/sudoers.d/deploy.tmp /usr/local/etc/sudoers.d/deploy; else rm -f /usr/local/etc/sudoers.d/deploy.tmp; exit 1; fi
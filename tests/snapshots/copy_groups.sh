@@ -0,0 +1,3 @@
+pw groupshow -n video >/dev/null 2>&1 || if pw groupshow -g 44 >/dev/null 2>&1; then pw groupadd -n video; else pw groupadd -n video -g 44; fi
+pw groupshow -n 'o'\''brien' >/dev/null 2>&1 || if pw groupshow -g 1003 >/dev/null 2>&1; then pw groupadd -n 'o'\''brien'; else pw groupadd -n 'o'\''brien' -g 1003; fi
+pw groupshow -n '$(id)' >/dev/null 2>&1 || if pw groupshow -g 1004 >/dev/null 2>&1; then pw groupadd -n '$(id)'; else pw groupadd -n '$(id)' -g 1004; fi
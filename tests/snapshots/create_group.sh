@@ -0,0 +1,3 @@
+pw groupadd -n staff -g 1001
+pw groupadd -n 'o'\''brien'
+pw groupadd -n 'dev ops' -g 1002
\ No newline at end of file
@@ -0,0 +1,2 @@
+pw useradd -n jdoe -g jdoe -G wheel -s /bin/sh -u 1001
+pw useradd -n 'o'\''brien' -g 'o'\''brien' -G 'wheel,video,dev ops' -s '/usr/local/bin/my shell' -d '/usr/home/o'\''brien' -m -M 750
\ No newline at end of file
@@ -0,0 +1,43 @@
+mkdir -p /usr/local/etc/iocage-provision/firstboot.d
+cat <<'_IOCAGE_PROVISION_EOF_' >/usr/local/etc/iocage-provision/firstboot.d/01
+#!/bin/sh
+service nginx reload
+_IOCAGE_PROVISION_EOF_
+chmod 0700 /usr/local/etc/iocage-provision/firstboot.d/01
+cat <<'_IOCAGE_PROVISION_EOF_' >/usr/local/etc/iocage-provision/firstboot.d/02
+echo 'first boot' >/var/log/firstboot.log
+_IOCAGE_PROVISION_EOF_
+chmod 0700 /usr/local/etc/iocage-provision/firstboot.d/02
+cat <<'_IOCAGE_PROVISION_EOF_' >/usr/local/etc/rc.d/iocage_provision_firstboot
+#!/bin/sh
+
+# PROVIDE: iocage_provision_firstboot
+# REQUIRE: LOGIN
+# KEYWORD: firstboot
+
+. /etc/rc.subr
+
+name="iocage_provision_firstboot"
+rcvar="iocage_provision_firstboot_enable"
+start_cmd="${name}_start"
+stop_cmd=":"
+
+iocage_provision_firstboot_start()
+{
+	for script in /usr/local/etc/iocage-provision/firstboot.d/*; do
+		[ -f "$script" ] || continue
+		echo "Running first boot script $script"
+		if head -c 2 "$script" | grep -q '^#!'; then
+			"$script"
+		else
+			sh "$script"
+		fi
+	done
+}
+
+load_rc_config $name
+run_rc_command "$1"
+_IOCAGE_PROVISION_EOF_
+chmod 0555 /usr/local/etc/rc.d/iocage_provision_firstboot
+sysrc -f /etc/rc.conf iocage_provision_firstboot_enable="YES"
+touch /firstboot
@@ -0,0 +1,5 @@
+sed -i '' '/# iocage-provision/d' /etc/hosts
+cat <<'_IOCAGE_PROVISION_EOF_' >>/etc/hosts
+192.0.2.1	host.example.com host	# iocage-provision host
+10.0.0.6	db	# iocage-provision jail=db
+_IOCAGE_PROVISION_EOF_
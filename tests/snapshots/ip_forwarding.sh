@@ -0,0 +1,2 @@
+sysrc -f /etc/rc.conf gateway_enable="YES" ipv6_gateway_enable="YES"
+sysctl net.inet.ip.forwarding=1 net.inet6.ip6.forwarding=1
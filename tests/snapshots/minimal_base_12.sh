@@ -0,0 +1,20 @@
+rm -rf \
+  /libexec/ld-elf32.so.1 \
+  /usr/lib/debug \
+  /usr/lib32 \
+  /usr/libexec/ld-elf32.so.1 \
+  /usr/share/doc \
+  /usr/share/examples \
+  /usr/tests \
+  /usr/bin/c++ \
+  /usr/bin/cc \
+  /usr/bin/clang \
+  /usr/bin/clang++ \
+  /usr/bin/clang-cpp \
+  /usr/bin/cpp \
+  /usr/bin/ld.bfd \
+  /usr/bin/ld.lld \
+  /usr/bin/lldb \
+  /usr/lib/clang
+sysrc -f /etc/rc.conf sendmail_enable="NONE"
+service sendmail onestop >/dev/null 2>&1 || true
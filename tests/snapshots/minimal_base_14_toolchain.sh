@@ -0,0 +1,10 @@
+rm -rf \
+  /libexec/ld-elf32.so.1 \
+  /usr/lib/debug \
+  /usr/lib32 \
+  /usr/libexec/ld-elf32.so.1 \
+  /usr/share/doc \
+  /usr/share/examples \
+  /usr/tests
+sysrc -f /etc/rc.conf sendmail_enable="NONE"
+service sendmail onestop >/dev/null 2>&1 || true
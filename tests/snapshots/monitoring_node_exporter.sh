@@ -0,0 +1,2 @@
+sysrc -f /etc/rc.conf node_exporter_enable="YES"
+service node_exporter start
@@ -0,0 +1,11 @@
+mkdir -p /var/log/zabbix /var/run/zabbix
+chown zabbix:zabbix /var/log/zabbix /var/run/zabbix
+cat <<'_IOCAGE_PROVISION_EOF_' >/usr/local/etc/zabbix6/zabbix_agentd.conf
+PidFile=/var/run/zabbix/zabbix_agentd.pid
+LogFile=/var/log/zabbix/zabbix_agentd.log
+Server=zabbix.internal
+ServerActive=zabbix.internal
+HostnameItem=system.hostname
+_IOCAGE_PROVISION_EOF_
+sysrc -f /etc/rc.conf zabbix_agentd_enable="YES"
+service zabbix_agentd start
@@ -0,0 +1,9 @@
+mirror="$(sed -n 's|^[[:space:]]*url:[[:space:]]*"[a-z+]*://\([^/:"]*\).*|\1|p' /etc/pkg/FreeBSD.conf | head -n 1)"
+mirror="${mirror:-pkg.FreeBSD.org}"
+if ! host "$mirror" >/dev/null 2>&1; then
+  echo "resolve $mirror"
+elif ! nc -z -w 10 "$mirror" 443 >/dev/null 2>&1; then
+  echo "connect $mirror"
+else
+  echo "ok $mirror"
+fi
@@ -0,0 +1,18 @@
+mirror="$(sed -n 's|^[[:space:]]*url:[[:space:]]*"[a-z+]*://\([^/:"]*\).*|\1|p' /etc/pkg/FreeBSD.conf | head -n 1)"
+mirror="${mirror:-pkg.FreeBSD.org}"
+deadline=$(($(date +%s) + 60))
+while :; do
+  if ! route -n get default >/dev/null 2>&1; then
+    waiting=route
+  elif ! host "$mirror" >/dev/null 2>&1; then
+    waiting=resolve
+  else
+    echo "ok $mirror"
+    break
+  fi
+  if [ "$(date +%s)" -ge "$deadline" ]; then
+    echo "$waiting $mirror"
+    break
+  fi
+  sleep 1
+done
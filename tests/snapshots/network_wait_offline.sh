@@ -0,0 +1,16 @@
+mirror="$(sed -n 's|^[[:space:]]*url:[[:space:]]*"[a-z+]*://\([^/:"]*\).*|\1|p' /etc/pkg/FreeBSD.conf | head -n 1)"
+mirror="${mirror:-pkg.FreeBSD.org}"
+deadline=$(($(date +%s) + 30))
+while :; do
+  if ! route -n get default >/dev/null 2>&1; then
+    waiting=route
+  else
+    echo "ok $mirror"
+    break
+  fi
+  if [ "$(date +%s)" -ge "$deadline" ]; then
+    echo "$waiting $mirror"
+    break
+  fi
+  sleep 1
+done
@@ -0,0 +1 @@
+sysrc -f /etc/rc.conf ntpd_enable="YES" ntpd_sync_on_start="YES" && service ntpd start
\ No newline at end of file
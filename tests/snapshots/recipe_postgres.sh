@@ -0,0 +1,6 @@
+chown postgres:postgres /var/db/postgres
+sysrc -f /etc/rc.conf postgresql_enable="YES"
+if [ -z "$(ls -A /var/db/postgres)" ]; then
+  service postgresql initdb
+fi
+service postgresql start
@@ -0,0 +1 @@
+pw usermod -n 'o'\''brien' -G 'wheel,$(id)'
\ No newline at end of file
@@ -0,0 +1,2 @@
+echo 'ListenAddress 10.0.0.5' >>/etc/ssh/sshd_config
+sysrc -f /etc/rc.conf sshd_enable="YES" && service sshd start
\ No newline at end of file
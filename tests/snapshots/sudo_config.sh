@@ -0,0 +1 @@
+echo '%wheel ALL=(ALL) NOPASSWD: ALL' >/usr/local/etc/sudoers.d/wheel
\ No newline at end of file
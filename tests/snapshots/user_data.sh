@@ -0,0 +1,32 @@
+pw useradd -n deploy -m -c 'Deploy User' -G www -h -
+ssh_dir="$(pw usershow -n deploy | cut -d: -f9)/.ssh"
+mkdir -p "$ssh_dir"
+cd "$ssh_dir"
+cat <<'_IOCAGE_PROVISION_EOF_' >authorized_keys
+ssh-ed25519 AAAAC3Nza deploy@example.com
+_IOCAGE_PROVISION_EOF_
+chmod 0700 .
+chmod 0600 authorized_keys
+chown -R deploy: .
+cd /
+cat <<'_IOCAGE_PROVISION_EOF_' >/usr/local/etc/sudoers.d/deploy.tmp
+deploy ALL=(ALL) NOPASSWD:ALL
+_IOCAGE_PROVISION_EOF_
+chmod 0440 /usr/local/etc/sudoers.d/deploy.tmp
+if visudo -cf /usr/local/etc/sudoers.d/deploy.tmp >/dev/null; then mv /usr/local/etc/sudoers.d/deploy.tmp /usr/local/etc/sudoers.d/deploy; else rm -f /usr/local/etc/sudoers.d/deploy.tmp; exit 1; fi
+mkdir -p /usr/local/www
+cat <<'_IOCAGE_PROVISION_EOF_' >/usr/local/www/index.html
+<h1>It works</h1>
+_IOCAGE_PROVISION_EOF_
+chmod 0640 /usr/local/www/index.html
+chown deploy:www /usr/local/www/index.html
+mkdir -p /etc
+cat <<'_IOCAGE_PROVISION_EOF_' >>/etc/motd
+Provisioned by iocage-provision
+_IOCAGE_PROVISION_EOF_
+cat <<'_IOCAGE_PROVISION_EOF_' >/var/tmp/iocage-provision-runcmd
+sysrc 'nginx_enable=YES'
+service nginx start
+_IOCAGE_PROVISION_EOF_
+sh -e /var/tmp/iocage-provision-runcmd
+rm -f /var/tmp/iocage-provision-runcmd
@@ -0,0 +1,20 @@
+pw usermod -n jdoe -L russian
+home="$(pw usershow -n jdoe | cut -d: -f9)"
+if [ -d "$home" ]; then
+cd "$home"
+cat <<'_IOCAGE_PROVISION_EOF_' >.login_conf
+me:\
+	:charset=UTF-8:\
+	:lang=ru_RU.UTF-8:
+_IOCAGE_PROVISION_EOF_
+chown jdoe: .login_conf
+fi
+home="$(pw usershow -n 'o'\''brien' | cut -d: -f9)"
+if [ -d "$home" ]; then
+cd "$home"
+cat <<'_IOCAGE_PROVISION_EOF_' >.login_conf
+me:\
+	:lang=C:
+_IOCAGE_PROVISION_EOF_
+chown 'o'\''brien:' .login_conf
+fi
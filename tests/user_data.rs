@@ -0,0 +1,117 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::user_data::{UserData, UserDataUser, WriteFile};
+
+const CLOUD_CONFIG: &str = r#"#cloud-config
+package_update: true
+packages:
+  - nginx
+  - "py39-certbot"
+
+users:
+  - default
+  - name: deploy
+    gecos: Deploy User
+    groups: wheel, www
+    shell: /bin/sh
+    sudo: ALL=(ALL) NOPASSWD:ALL  # no password
+    ssh_authorized_keys:
+      - ssh-ed25519 AAAAC3Nza deploy@example.com
+
+write_files:
+- path: /usr/local/etc/nginx/conf.d/site.conf
+  permissions: '0644'
+  owner: deploy:www
+  content: |
+    server {
+      listen 80;
+    }
+- path: /etc/motd
+  append: true
+  content: >
+    Provisioned by
+    iocage-provision
+
+runcmd:
+  - [sysrc, "nginx_enable=YES"]
+  - service nginx start
+  - 'echo "done: it''s up" >/var/log/done'
+"#;
+
+#[test]
+fn test_parse() {
+    let data = UserData::parse(CLOUD_CONFIG).unwrap();
+
+    assert_eq!(vec!["nginx", "py39-certbot"], data.packages);
+    assert_eq!(
+        vec![UserDataUser {
+            name: "deploy".to_string(),
+            gecos: Some("Deploy User".to_string()),
+            groups: vec!["wheel".to_string(), "www".to_string()],
+            shell: Some("/bin/sh".to_string()),
+            ssh_authorized_keys: vec!["ssh-ed25519 AAAAC3Nza deploy@example.com".to_string()],
+            sudo: vec!["ALL=(ALL) NOPASSWD:ALL".to_string()],
+        }],
+        data.users
+    );
+    assert_eq!(
+        vec![
+            WriteFile {
+                path: "/usr/local/etc/nginx/conf.d/site.conf".to_string(),
+                content: "server {\n  listen 80;\n}\n".to_string(),
+                permissions: Some(0o644),
+                owner: Some("deploy:www".to_string()),
+                append: false,
+            },
+            WriteFile {
+                path: "/etc/motd".to_string(),
+                content: "Provisioned by iocage-provision\n".to_string(),
+                permissions: None,
+                owner: None,
+                append: true,
+            },
+        ],
+        data.write_files
+    );
+    assert_eq!(
+        vec![
+            "sysrc 'nginx_enable=YES'",
+            "service nginx start",
+            "echo \"done: it's up\" >/var/log/done",
+        ],
+        data.runcmd
+    );
+}
+
+#[test]
+fn test_parse_empty() {
+    assert_eq!(
+        UserData::default(),
+        UserData::parse("#cloud-config\n").unwrap()
+    );
+}
+
+#[test]
+fn test_parse_unsupported() {
+    let err =
+        UserData::parse("#cloud-config\npackages: [git]\nbootcmd:\n  - echo hi\n").unwrap_err();
+    assert_eq!(
+        "unsupported cloud-config key 'bootcmd'; line=4",
+        err.to_string()
+    );
+
+    let err = UserData::parse("users:\n  - name: deploy\n    passwd: secret\n").unwrap_err();
+    assert_eq!("unsupported user key 'passwd'; line=3", err.to_string());
+
+    let err = UserData::parse("users:\n  - name: ../deploy\n").unwrap_err();
+    assert_eq!("invalid user name; name=../deploy; line=2", err.to_string());
+
+    let err = UserData::parse("write_files:\n  - path: /a\n    encoding: b64\n    content: aGk=\n")
+        .unwrap_err();
+    assert_eq!("unsupported file encoding 'b64'; line=3", err.to_string());
+
+    assert!(UserData::parse("packages:\n  - git\n    - vim\n").is_err());
+    assert!(UserData::parse("write_files:\n  - content: hi\n").is_err());
+}
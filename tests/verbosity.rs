@@ -0,0 +1,28 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at https://mozilla.org/MPL/2.0/.
+
+use iocage_provision::Verbosity;
+use log::LevelFilter;
+
+#[test]
+fn test_from_flags() {
+    assert_eq!(Verbosity::Normal, Verbosity::from_flags(0, 0));
+    assert_eq!(Verbosity::Quiet, Verbosity::from_flags(0, 1));
+    assert_eq!(Verbosity::Silent, Verbosity::from_flags(0, 2));
+    assert_eq!(Verbosity::Silent, Verbosity::from_flags(0, 5));
+    assert_eq!(Verbosity::Detail, Verbosity::from_flags(1, 0));
+    assert_eq!(Verbosity::Commands, Verbosity::from_flags(2, 0));
+    assert_eq!(Verbosity::Scripts, Verbosity::from_flags(3, 0));
+    assert_eq!(Verbosity::Scripts, Verbosity::from_flags(7, 0));
+}
+
+#[test]
+fn test_level_filter() {
+    assert_eq!(LevelFilter::Error, Verbosity::Silent.level_filter());
+    assert_eq!(LevelFilter::Warn, Verbosity::Quiet.level_filter());
+    assert_eq!(LevelFilter::Info, Verbosity::Normal.level_filter());
+    assert_eq!(LevelFilter::Debug, Verbosity::Detail.level_filter());
+    assert_eq!(LevelFilter::Trace, Verbosity::Commands.level_filter());
+    assert_eq!(LevelFilter::Trace, Verbosity::Scripts.level_filter());
+}